@@ -0,0 +1,342 @@
+use std::collections::BTreeSet;
+use std::fs::OpenOptions;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use age::secrecy::ExposeSecret;
+use age::x25519::{Identity, Recipient};
+use base64::Engine;
+use serde::{Serialize, Deserialize};
+
+use crate::crypto::{Aes256GcmEngine, Kdf};
+use crate::entry::{self, Entry, Metadata};
+use crate::error::{Error, Result};
+use crate::global::{ACTIVITY_FILE, ACTIVITY_KEY_FILE};
+use crate::stats::Stats;
+use crate::vault::{self, Vault};
+use crate::verify::{RepairReport, Report};
+
+/// The associated data the activity log's private key is encrypted with
+const IDENTITY_AAD: &[u8] = b"yap/activity";
+
+/// Activity is an operation on the vault, as recorded in its activity log
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub struct Activity {
+    /// When the operation was done, in seconds since the Unix epoch
+    pub time: i64,
+    /// The command which was run, such as get, set, rm, or sync
+    pub operation: String,
+    /// The entry the operation was done on, or empty for operations on the whole vault
+    pub name: String,
+    /// The name of the machine the operation was done on
+    pub host: String,
+}
+
+impl Activity {
+    /// Checks whether the operation was done on the entry with the given name, including renames
+    /// to or from it
+    pub fn concerns(&self, name: &str) -> bool {
+        self.name == name || self.name.split_once(" -> ").is_some_and(|(from, to)| from == name || to == name)
+    }
+}
+
+/// ActivityKey is the key pair the activity log of a vault is encrypted with, kept in the
+/// vault's `.activity.key`. Records are encrypted to the public key, so they can be added
+/// without unlocking the vault, such as when syncing, while the private key is encrypted with
+/// the vault's key, so the log can only be read by someone who can unlock the vault.
+#[derive(Serialize, Deserialize)]
+struct ActivityKey {
+    recipient: String,
+    identity: Vec<u8>,
+}
+
+impl ActivityKey {
+    fn read(vault_dir: &Path) -> Result<Option<ActivityKey>> {
+        let p = vault_dir.join(ACTIVITY_KEY_FILE);
+        if !p.exists() {
+            return Ok(None);
+        }
+        Ok(Some(serde_yaml::from_slice(std::fs::read(p)?.as_slice())?))
+    }
+
+    fn write(&self, dir: &Path) -> Result<()> {
+        vault::atomic_write(dir.join(ACTIVITY_KEY_FILE).as_path(), serde_yaml::to_string(self)?.as_bytes())
+    }
+
+    fn identity(&self, engine: &Aes256GcmEngine) -> Result<Identity> {
+        let identity = engine.decrypt_bytes(self.identity.as_slice(), IDENTITY_AAD)?;
+        let identity = std::str::from_utf8(identity.as_slice()).map_err(|_| Error::BadActivityLog { line: 0 })?;
+        Identity::from_str(identity).map_err(|_| Error::BadActivityLog { line: 0 })
+    }
+}
+
+/// Creates the key pair for the activity log of the vault in `vault_dir`, unless it has one,
+/// with its private key encrypted by `engine`, the vault's key
+pub(crate) fn init(vault_dir: &Path, engine: &Aes256GcmEngine) -> Result<()> {
+    if vault_dir.join(ACTIVITY_KEY_FILE).exists() {
+        return Ok(());
+    }
+
+    let identity = Identity::generate();
+    let encrypted = engine.encrypt_bytes(identity.to_string().expose_secret().as_bytes(), IDENTITY_AAD)?;
+    ActivityKey { recipient: identity.to_public().to_string(), identity: encrypted }.write(vault_dir)
+}
+
+/// Re-encrypts the private key of the activity log of the vault in `vault_dir` from the vault's
+/// old key, `from`, to its new key, `to`, writing it into `staging`, see `vault::rewrite`
+pub(crate) fn rewrap(vault_dir: &Path, from: &Aes256GcmEngine, to: &Aes256GcmEngine, staging: &Path) -> Result<()> {
+    let Some(key) = ActivityKey::read(vault_dir)? else { return Ok(()) };
+    // A private key which doesn't decrypt with the old key couldn't be read anyway, and is left
+    // as it is rather than failing the rekey
+    let Ok(identity) = key.identity(from) else { return Ok(()) };
+    let encrypted = to.encrypt_bytes(identity.to_string().expose_secret().as_bytes(), IDENTITY_AAD)?;
+    ActivityKey { recipient: key.recipient, identity: encrypted }.write(staging)
+}
+
+/// Appends an operation to the activity log of the vault in `vault_dir`, as a line holding the
+/// record encrypted to the log's public key. Nothing is recorded for a vault which hasn't been
+/// unlocked since the log was added, since it doesn't have a key pair yet, see `init`.
+pub(crate) fn record(vault_dir: &Path, operation: &str, name: &str) -> Result<()> {
+    let Some(key) = ActivityKey::read(vault_dir)? else { return Ok(()) };
+    let recipient = Recipient::from_str(key.recipient.as_str()).map_err(|_| Error::BadActivityLog { line: 0 })?;
+
+    let host = gethostname::gethostname().to_string_lossy().to_string();
+    let activity = Activity { time: entry::now(), operation: operation.to_string(), name: name.to_string(), host };
+    let encrypted = age::encrypt(&recipient, serde_yaml::to_string(&activity)?.as_bytes())
+        .map_err(|_| Error::BadActivityLog { line: 0 })?;
+    let line = format!("{}\n", base64::engine::general_purpose::STANDARD.encode(encrypted));
+
+    // The whole line is written at once, so records appended by two processes don't interleave
+    let mut f = OpenOptions::new().create(true).append(true).open(vault_dir.join(ACTIVITY_FILE))?;
+    f.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+/// Reads the activity log of the vault in `vault_dir`, oldest first, decrypting its private key
+/// with `engine`, the vault's key
+pub(crate) fn read(vault_dir: &Path, engine: &Aes256GcmEngine) -> Result<Vec<Activity>> {
+    let Some(key) = ActivityKey::read(vault_dir)? else { return Ok(vec![]) };
+    let p = vault_dir.join(ACTIVITY_FILE);
+    if !p.exists() {
+        return Ok(vec![]);
+    }
+
+    let identity = key.identity(engine)?;
+    let mut log = vec![];
+    for (i, line) in std::fs::read_to_string(p)?.lines().enumerate().filter(|(_, line)| !line.is_empty()) {
+        let activity = base64::engine::general_purpose::STANDARD.decode(line).ok()
+            .and_then(|encrypted| age::decrypt(&identity, encrypted.as_slice()).ok())
+            .and_then(|plaintext| serde_yaml::from_slice(plaintext.as_slice()).ok())
+            .ok_or(Error::BadActivityLog { line: i + 1 })?;
+        log.push(activity);
+    }
+    Ok(log)
+}
+
+/// ActivityVault wraps a vault opened from a directory, recording the operations done through it
+/// in the vault's activity log, see `record`. Reads are recorded when an entry's secrets are
+/// shown, see `Vault::access_entry`, and changes once they have been made.
+pub(crate) struct ActivityVault {
+    vault: Box<dyn Vault>,
+    vault_dir: PathBuf,
+}
+
+impl ActivityVault {
+    pub(crate) fn new(vault: Box<dyn Vault>, vault_dir: &Path) -> ActivityVault {
+        ActivityVault { vault, vault_dir: vault_dir.to_path_buf() }
+    }
+
+    fn record(&self, operation: &str, name: &str) -> Result<()> {
+        record(self.vault_dir.as_path(), operation, name)
+    }
+}
+
+impl Vault for ActivityVault {
+    fn has_key(&self, key: &str) -> bool {
+        self.vault.has_key(key)
+    }
+
+    fn get_entry(&self, key: &str) -> Result<Entry> {
+        self.vault.get_entry(key)
+    }
+
+    fn access_entry(&mut self, key: &str) -> Result<Entry> {
+        let entry = self.vault.access_entry(key)?;
+        self.record("get", key)?;
+        Ok(entry)
+    }
+
+    fn set_entry(&mut self, key: &str, entry: &Entry) -> Result<()> {
+        self.vault.set_entry(key, entry)?;
+        self.record("set", key)
+    }
+
+    fn set_entries(&mut self, entries: &[(String, Entry)]) -> Result<()> {
+        self.vault.set_entries(entries)?;
+        for (key, _) in entries {
+            self.record("set", key.as_str())?;
+        }
+        Ok(())
+    }
+
+    fn delete_key(&mut self, key: &str) -> Result<()> {
+        self.vault.delete_key(key)?;
+        self.record("rm", key)
+    }
+
+    fn rename_key(&mut self, from: &str, to: &str, force: bool) -> Result<()> {
+        self.vault.rename_key(from, to, force)?;
+        self.record("mv", format!("{} -> {}", from, to).as_str())
+    }
+
+    fn list_keys(&self, prefix: &str) -> Result<Vec<String>> {
+        self.vault.list_keys(prefix)
+    }
+
+    fn attach(&mut self, key: &str, attachment: &str, reader: &mut dyn Read) -> Result<u64> {
+        let written = self.vault.attach(key, attachment, reader)?;
+        self.record("attach", key)?;
+        Ok(written)
+    }
+
+    fn read_attachment(&self, key: &str, attachment: &str, writer: &mut dyn Write) -> Result<u64> {
+        self.vault.read_attachment(key, attachment, writer)
+    }
+
+    fn list_attachments(&self, key: &str) -> Result<Vec<String>> {
+        self.vault.list_attachments(key)
+    }
+
+    fn remove_attachment(&mut self, key: &str, attachment: &str) -> Result<()> {
+        self.vault.remove_attachment(key, attachment)?;
+        self.record("detach", key)
+    }
+
+    fn history(&self, key: &str) -> Result<Vec<(u32, Metadata)>> {
+        self.vault.history(key)
+    }
+
+    fn restore(&mut self, key: &str, version: u32) -> Result<()> {
+        self.vault.restore(key, version)?;
+        self.record("restore", key)
+    }
+
+    fn list_trash(&self) -> Result<Vec<(String, i64)>> {
+        self.vault.list_trash()
+    }
+
+    fn restore_trash(&mut self, key: &str, force: bool) -> Result<()> {
+        self.vault.restore_trash(key, force)?;
+        self.record("restore", key)
+    }
+
+    fn empty_trash(&mut self, before: Option<i64>) -> Result<usize> {
+        let removed = self.vault.empty_trash(before)?;
+        self.record("empty-trash", "")?;
+        Ok(removed)
+    }
+
+    fn rebuild_index(&mut self) -> Result<usize> {
+        self.vault.rebuild_index()
+    }
+
+    fn verify(&mut self, quarantine: bool) -> Result<Report> {
+        self.vault.verify(quarantine)
+    }
+
+    fn repair(&mut self, backup: Option<&Path>) -> Result<RepairReport> {
+        let report = self.vault.repair(backup)?;
+        self.record("repair", "")?;
+        Ok(report)
+    }
+
+    fn recover(&mut self, backup: &Path, key: &str) -> Result<()> {
+        self.vault.recover(backup, key)?;
+        self.record("recover", key)
+    }
+
+    fn activity(&self) -> Result<Vec<Activity>> {
+        self.vault.activity()
+    }
+
+    fn rekey(&mut self, new_pass: &str) -> Result<()> {
+        self.vault.rekey(new_pass)?;
+        self.record("rekey", "")
+    }
+
+    fn set_kdf(&mut self, kdf: Kdf, pass: &str) -> Result<()> {
+        self.vault.set_kdf(kdf, pass)?;
+        self.record("rekey", "")
+    }
+
+    fn add_member(&mut self, name: &str, recipient: &Recipient) -> Result<()> {
+        self.vault.add_member(name, recipient)?;
+        self.record("add-member", name)
+    }
+
+    fn remove_member(&mut self, name: &str, pass: &str) -> Result<()> {
+        self.vault.remove_member(name, pass)?;
+        self.record("remove-member", name)
+    }
+
+    fn delete_folder(&mut self, folder: &str) -> Result<Vec<String>> {
+        let deleted = self.vault.delete_folder(folder)?;
+        for key in deleted.iter() {
+            self.record("rm", key.as_str())?;
+        }
+        Ok(deleted)
+    }
+
+    fn metadata(&self, key: &str) -> Result<Metadata> {
+        self.vault.metadata(key)
+    }
+
+    fn tags(&self, key: &str) -> Result<BTreeSet<String>> {
+        self.vault.tags(key)
+    }
+
+    fn stats(&self) -> Result<Stats> {
+        self.vault.stats()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use age::x25519::Identity;
+
+    use crate::activity::{self, ActivityVault};
+    use crate::crypto::Kdf;
+    use crate::global::ACTIVITY_FILE;
+    use crate::vault::{self, Backend, Vault};
+
+    #[test]
+    fn operations_are_logged_encrypted() {
+        let yap_test = String::from(".yap_test_activity");
+        let dir = Path::new(yap_test.as_str());
+        let vault = vault::create(Some(yap_test.clone()), "asdf", Kdf::default(), Backend::default(), None).unwrap();
+        let mut vault = ActivityVault::new(vault, dir);
+
+        vault.set_key("github", "password".to_string()).unwrap();
+        vault.access_entry("github").unwrap();
+        vault.get_key("github").unwrap();
+        vault.rename_key("github", "work/github", false).unwrap();
+        vault.delete_key("work/github").unwrap();
+        activity::record(dir, "sync", "").unwrap();
+
+        let log = vault.activity().unwrap();
+        let operations: Vec<(&str, &str)> = log.iter().map(|activity| (activity.operation.as_str(), activity.name.as_str())).collect();
+        assert_eq!(operations, vec![("set", "github"), ("get", "github"), ("mv", "github -> work/github"), ("rm", "work/github"), ("sync", "")]);
+        assert!(log.iter().all(|activity| !activity.host.is_empty()));
+        assert!(!std::fs::read_to_string(dir.join(ACTIVITY_FILE)).unwrap().contains("github"));
+
+        // The log stays readable once the vault's key changes
+        vault.add_member("bob", &Identity::generate().to_public()).unwrap();
+        vault.remove_member("bob", "asdf").unwrap();
+        assert_eq!(vault.activity().unwrap().len(), 7);
+
+        std::fs::remove_dir_all(yap_test).unwrap();
+    }
+}
@@ -0,0 +1,295 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use serde::{Serialize, Deserialize};
+use zeroize::Zeroizing;
+
+use crate::error::{Error, Result};
+use crate::global::{AGENT_DIR, AGENT_SOCKET};
+
+/// The hidden command which runs the agent, started in the background by `start`
+pub const AGENT_COMMAND: &str = "__agent";
+
+/// Request is sent to the agent as a single line of JSON, and is answered with a Response in the
+/// same way. Each connection carries one request.
+#[derive(Serialize, Deserialize)]
+enum Request {
+    /// Get the key cached for the vault in a directory, if it was cached for the same salt
+    Get { vault: String, salt: Vec<u8> },
+    /// Cache the key of the vault in a directory, until it expires or goes unused for `idle`
+    /// seconds, if that isn't zero
+    Put { vault: String, salt: Vec<u8>, key: Vec<u8>, idle: u64 },
+    /// Forget the key of the vault in a directory, or every key
+    Lock { vault: Option<String> },
+    /// List the vaults with cached keys
+    Status,
+    /// Forget every key and exit
+    Stop,
+}
+
+#[derive(Serialize, Deserialize)]
+enum Response {
+    Key(Vec<u8>),
+    NotCached,
+    Status(Vec<(String, u64)>),
+    Done,
+}
+
+/// CachedKey is a vault key held by the agent until it expires, or until it goes unused for too
+/// long. The salt is kept with the key, since a vault whose salt changed, such as after a rekey,
+/// needs a new key.
+struct CachedKey {
+    salt: Vec<u8>,
+    key: Zeroizing<Vec<u8>>,
+    expires: Instant,
+    idle: Option<Duration>,
+    used: Instant,
+}
+
+impl CachedKey {
+    /// Returns when the key will be forgotten, if it isn't used before then
+    fn expires(&self) -> Instant {
+        match self.idle {
+            Some(idle) => self.expires.min(self.used + idle),
+            None => self.expires,
+        }
+    }
+}
+
+/// Gets the key the agent has cached for a vault, identified by its directory and salt. None is
+/// returned if the key isn't cached, or has expired.
+pub fn get(vault: String, salt: Vec<u8>) -> Result<Option<Zeroizing<Vec<u8>>>> {
+    match request(&Request::Get { vault, salt })? {
+        Response::Key(key) => Ok(Some(Zeroizing::new(key))),
+        _ => Ok(None),
+    }
+}
+
+/// Caches the key of a vault in the agent, identified by its directory and salt. The key is
+/// forgotten if it isn't used for `idle` seconds, unless that is zero.
+pub fn put(vault: String, salt: Vec<u8>, key: Vec<u8>, idle: u64) -> Result<()> {
+    request(&Request::Put { vault, salt, key, idle }).map(|_| ())
+}
+
+/// Makes the agent forget the key of a vault, or every key if `vault` is None
+pub fn lock(vault: Option<String>) -> Result<()> {
+    request(&Request::Lock { vault }).map(|_| ())
+}
+
+/// Starts the agent in the background, which caches vault keys for `ttl` seconds after they are
+/// derived. The agent keeps running after this command exits, until it is stopped.
+pub fn start(ttl: u64) -> Result<()> {
+    if request(&Request::Status).is_ok() {
+        return Err(Error::AgentRunning);
+    }
+
+    let mut command = std::process::Command::new(std::env::current_exe()?);
+    command.arg(AGENT_COMMAND)
+        .arg(ttl.to_string())
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null());
+    #[cfg(unix)]
+    {
+        // Keep the agent out of the terminal's process group, so it survives the shell exiting
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+    command.spawn()?;
+
+    // Wait for the agent to start listening, so the next command can use it
+    for _ in 0..50 {
+        if request(&Request::Status).is_ok() {
+            return Ok(());
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    Err(Error::Agent { message: "the agent did not start".to_string() })
+}
+
+/// Stops the agent, which forgets every cached key
+pub fn stop() -> Result<()> {
+    request(&Request::Stop).map(|_| ())
+}
+
+/// Lists the vaults whose keys are cached by the agent, with the seconds left until each expires
+pub fn status() -> Result<Vec<(String, u64)>> {
+    match request(&Request::Status)? {
+        Response::Status(vaults) => Ok(vaults),
+        _ => Err(Error::Agent { message: "unexpected response".to_string() }),
+    }
+}
+
+/// Runs the agent until it is stopped, answering requests on its socket. The socket is created
+/// in a directory which only the user can access, so no other user can connect to it.
+#[cfg(unix)]
+pub fn serve(ttl: u64) -> Result<()> {
+    serve_on(socket_path()?.as_path(), ttl)
+}
+
+/// Runs the agent on the socket at `p`, see `serve`
+#[cfg(unix)]
+fn serve_on(p: &Path, ttl: u64) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::net::UnixListener;
+
+    if let Some(dir) = p.parent() {
+        std::fs::create_dir_all(dir)?;
+        std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700))?;
+    }
+    // A socket left behind by an agent which was killed would stop a new one from listening
+    if p.exists() {
+        std::fs::remove_file(p)?;
+    }
+
+    let listener = UnixListener::bind(p)?;
+    let mut keys = HashMap::new();
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else { continue };
+        if let Ok(true) = handle(stream, &mut keys, ttl) {
+            break;
+        }
+    }
+
+    Ok(std::fs::remove_file(p)?)
+}
+
+#[cfg(not(unix))]
+pub fn serve(_ttl: u64) -> Result<()> {
+    Err(Error::Agent { message: "the agent is only supported on Unix".to_string() })
+}
+
+/// Answers a single request, returning true if the agent should stop
+#[cfg(unix)]
+fn handle(stream: std::os::unix::net::UnixStream, keys: &mut HashMap<String, CachedKey>, ttl: u64) -> Result<bool> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    let request: Request = serde_json::from_str(line.as_str()).map_err(agent_error)?;
+
+    let now = Instant::now();
+    keys.retain(|_, cached| cached.expires() > now);
+    let stop = matches!(request, Request::Stop);
+
+    let response = match request {
+        Request::Get { vault, salt } => match keys.get_mut(&vault) {
+            Some(cached) if cached.salt == salt => {
+                cached.used = now;
+                Response::Key(cached.key.to_vec())
+            }
+            _ => Response::NotCached,
+        },
+        Request::Put { vault, salt, key, idle } => {
+            let idle = (idle > 0).then(|| Duration::from_secs(idle));
+            keys.insert(vault, CachedKey { salt, key: Zeroizing::new(key), expires: now + Duration::from_secs(ttl), idle, used: now });
+            Response::Done
+        }
+        Request::Lock { vault: Some(vault) } => {
+            keys.remove(&vault);
+            Response::Done
+        }
+        Request::Lock { vault: None } => {
+            keys.clear();
+            Response::Done
+        }
+        Request::Status => Response::Status(keys.iter()
+            .map(|(vault, cached)| (vault.clone(), (cached.expires() - now).as_secs()))
+            .collect()),
+        Request::Stop => {
+            keys.clear();
+            Response::Done
+        }
+    };
+
+    respond(&stream, &response)?;
+    Ok(stop)
+}
+
+#[cfg(unix)]
+fn respond(mut stream: &std::os::unix::net::UnixStream, response: &Response) -> Result<()> {
+    let mut line = serde_json::to_string(response).map_err(agent_error)?;
+    line.push('\n');
+    Ok(stream.write_all(line.as_bytes())?)
+}
+
+/// Sends a request to the agent and waits for its response
+#[cfg(unix)]
+fn request(request: &Request) -> Result<Response> {
+    request_to(socket_path()?.as_path(), request)
+}
+
+/// Sends a request to the agent listening on the socket at `p`, see `request`
+#[cfg(unix)]
+fn request_to(p: &Path, request: &Request) -> Result<Response> {
+    let mut stream = std::os::unix::net::UnixStream::connect(p)
+        .map_err(|_| Error::AgentNotRunning)?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+
+    let mut line = serde_json::to_string(request).map_err(agent_error)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())?;
+
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    serde_json::from_str(line.as_str()).map_err(agent_error)
+}
+
+#[cfg(not(unix))]
+fn request(_request: &Request) -> Result<Response> {
+    Err(Error::AgentNotRunning)
+}
+
+/// Returns the path of the agent's socket, in the user's runtime directory if there is one
+fn socket_path() -> Result<PathBuf> {
+    let dir = match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir).join("yap"),
+        None => home::home_dir().ok_or(Error::NoHomeDir)?.join(AGENT_DIR),
+    };
+    Ok(dir.join(AGENT_SOCKET))
+}
+
+fn agent_error(e: serde_json::Error) -> Error {
+    Error::Agent { message: e.to_string() }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::agent::{request_to, serve_on, Request, Response};
+
+    #[test]
+    fn agent_caches_keys_until_stopped() {
+        let runtime_dir = std::env::current_dir().unwrap().join(".yap_test_agent");
+        let socket = runtime_dir.join("yap").join("agent.sock");
+        let agent = {
+            let socket = socket.clone();
+            std::thread::spawn(move || serve_on(socket.as_path(), 60).unwrap())
+        };
+        let request = |request: &Request| request_to(socket.as_path(), request);
+        while request(&Request::Status).is_err() {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        let get = |salt: &[u8]| request(&Request::Get { vault: "vault".to_string(), salt: salt.to_vec() }).unwrap();
+        assert!(matches!(get(b"salt"), Response::NotCached));
+        request(&Request::Put { vault: "vault".to_string(), salt: b"salt".to_vec(), key: vec![1, 2, 3], idle: 0 }).unwrap();
+        assert!(matches!(get(b"salt"), Response::Key(key) if key == vec![1, 2, 3]));
+        assert!(matches!(get(b"other salt"), Response::NotCached));
+
+        // A key which goes unused for longer than its idle timeout is forgotten
+        request(&Request::Put { vault: "vault".to_string(), salt: b"salt".to_vec(), key: vec![1, 2, 3], idle: 1 }).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(600));
+        assert!(matches!(get(b"salt"), Response::Key(_)));
+        std::thread::sleep(std::time::Duration::from_millis(600));
+        assert!(matches!(get(b"salt"), Response::Key(_)));
+        std::thread::sleep(std::time::Duration::from_millis(1100));
+        assert!(matches!(get(b"salt"), Response::NotCached));
+
+        request(&Request::Stop).unwrap();
+        agent.join().unwrap();
+        assert!(request(&Request::Status).is_err());
+
+        std::fs::remove_dir_all(runtime_dir).unwrap();
+    }
+}
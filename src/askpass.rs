@@ -0,0 +1,67 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+use crate::vault::Vault;
+
+/// AskpassRule maps the prompts of programs such as ssh and sudo to the entry holding the secret
+/// they ask for. The entry name can refer to groups captured by the pattern as `$1` or `${name}`,
+/// so one rule can cover every host, such as `(\S+)@(\S+)'s password` mapped to `ssh/$2/$1`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct AskpassRule {
+    pub pattern: String,
+    pub entry: String,
+}
+
+impl AskpassRule {
+    /// Creates a rule, checking that the pattern is a valid regular expression
+    pub fn new(pattern: &str, entry: &str) -> Result<AskpassRule> {
+        Regex::new(pattern)?;
+        Ok(AskpassRule { pattern: pattern.to_string(), entry: entry.to_string() })
+    }
+
+    /// Returns the name of the entry for the prompt, if the rule matches it
+    fn entry_for(&self, prompt: &str) -> Result<Option<String>> {
+        let Some(captures) = Regex::new(self.pattern.as_str())?.captures(prompt) else { return Ok(None) };
+        let mut entry = String::new();
+        captures.expand(self.entry.as_str(), &mut entry);
+        Ok(Some(entry))
+    }
+}
+
+/// Returns the name of the entry for a prompt, using the first rule which matches it
+pub fn entry_for(rules: &[AskpassRule], prompt: &str) -> Result<String> {
+    for rule in rules {
+        if let Some(entry) = rule.entry_for(prompt)? {
+            return Ok(entry);
+        }
+    }
+    Err(Error::NoAskpassRule { prompt: prompt.to_string() })
+}
+
+/// Returns the secret of the entry a prompt maps to, to be printed for the program which asked
+pub fn secret(vault: &mut dyn Vault, rules: &[AskpassRule], prompt: &str) -> Result<String> {
+    let name = entry_for(rules, prompt.trim())?;
+    let entry = vault.access_entry(name.as_str())?;
+    Ok(entry.field(entry.secret_field()).unwrap_or_default().to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::askpass::{self, AskpassRule};
+    use crate::error::Error;
+
+    #[test]
+    fn prompts_map_to_entries_by_the_first_matching_rule() {
+        let rules = vec![
+            AskpassRule::new(r"^\[sudo\] password for (\w+)", "sudo/$1").unwrap(),
+            AskpassRule::new(r"(?P<user>\S+)@(?P<host>[^']+)'s password", "ssh/${host}/${user}").unwrap(),
+            AskpassRule::new(r"passphrase for key '.*/(\S+)'", "ssh/keys/$1").unwrap(),
+        ];
+        assert_eq!(askpass::entry_for(&rules, "[sudo] password for alice: ").unwrap(), "sudo/alice");
+        assert_eq!(askpass::entry_for(&rules, "bob@example.com's password: ").unwrap(), "ssh/example.com/bob");
+        assert_eq!(askpass::entry_for(&rules, "Enter passphrase for key '/home/bob/.ssh/id_ed25519': ").unwrap(), "ssh/keys/id_ed25519");
+        assert!(matches!(askpass::entry_for(&rules, "Are you sure you want to continue connecting?"), Err(Error::NoAskpassRule { .. })));
+        assert!(AskpassRule::new("(unclosed", "entry").is_err());
+    }
+}
@@ -0,0 +1,109 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use crate::editor;
+use crate::error::{Error, Result};
+use crate::vault::Vault;
+
+/// Attaches the file to the entry with the given name, under `attachment` or otherwise the
+/// file's own name. The file is encrypted as it is read, see `Vault::attach`. Returns the name
+/// it was attached under and its size in bytes.
+pub fn add(vault: &mut dyn Vault, key: &str, file: &Path, attachment: Option<&str>) -> Result<(String, u64)> {
+    let attachment = match attachment {
+        Some(attachment) => attachment.to_string(),
+        None => file.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default(),
+    };
+    let len = vault.attach(key, attachment.as_str(), &mut File::open(file)?)?;
+    Ok((attachment, len))
+}
+
+/// Decrypts an attachment of the entry with the given name into a file at `out`, which only the
+/// user can read. An existing file is only replaced if `force` is set. The attachment is written
+/// to a hidden file next to `out` first, which is removed if the attachment turns out to have
+/// been tampered with, so no partly decrypted file is left behind.
+pub fn save(vault: &dyn Vault, key: &str, attachment: &str, out: &Path, force: bool) -> Result<u64> {
+    if out.exists() && !force {
+        return Err(Error::FileExists { path: out.display().to_string() });
+    }
+
+    let name = out.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+    let partial = out.with_file_name(format!(".{}.partial", name));
+    let mut writer = BufWriter::new(editor::create_private(partial.as_path())?);
+    let written = vault.read_attachment(key, attachment, &mut writer)
+        .and_then(|len| Ok(writer.into_inner().map_err(|e| e.into_error())?.sync_all().map(|_| len)?));
+    match written {
+        Ok(len) => {
+            std::fs::rename(partial, out)?;
+            Ok(len)
+        }
+        Err(e) => {
+            std::fs::remove_file(partial)?;
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use crate::attachment;
+    use crate::crypto::Kdf;
+    use crate::error::Error;
+    use crate::global::ATTACHMENTS_DIR;
+    use crate::share;
+    use crate::vault::{self, Backend};
+
+    #[test]
+    fn attachments_follow_their_entry() {
+        let yap_test = String::from(".yap_test_attachment");
+        let dir = Path::new(yap_test.as_str());
+        let mut vault = vault::create(Some(yap_test.clone()), "asdf", Kdf::default(), Backend::default(), None).unwrap();
+        vault.set_key("ssh/host", "password".to_string()).unwrap();
+
+        let key: Vec<u8> = (0..200_000).map(|i| (i * 7) as u8).collect();
+        let file = dir.join("id_ed25519");
+        std::fs::write(file.as_path(), key.as_slice()).unwrap();
+        assert_eq!(attachment::add(vault.as_mut(), "ssh/host", file.as_path(), None).unwrap(), ("id_ed25519".to_string(), 200_000));
+        assert!(attachment::add(vault.as_mut(), "missing", file.as_path(), None).is_err());
+        assert!(attachment::add(vault.as_mut(), "ssh/host", file.as_path(), Some(".hidden")).is_err());
+        assert_eq!(vault.list_keys("").unwrap(), vec!["ssh/host"]);
+        assert_eq!(vault.list_attachments("ssh/host").unwrap(), vec!["id_ed25519"]);
+
+        // Attachments are bound to their entry, and are moved along with it
+        let out = dir.join("restored");
+        assert!(matches!(attachment::save(vault.as_ref(), "ssh/host", "missing", out.as_path(), false), Err(Error::AttachmentNotFound { .. })));
+        assert!(!out.exists());
+        vault.rename_key("ssh/host", "ssh/server", false).unwrap();
+        assert!(vault.list_attachments("ssh/host").unwrap().is_empty());
+        assert_eq!(attachment::save(vault.as_ref(), "ssh/server", "id_ed25519", out.as_path(), false).unwrap(), 200_000);
+        assert_eq!(std::fs::read(out.as_path()).unwrap(), key);
+        assert!(matches!(attachment::save(vault.as_ref(), "ssh/server", "id_ed25519", out.as_path(), false), Err(Error::FileExists { .. })));
+
+        // A tampered attachment isn't written out
+        let stored = dir.join(ATTACHMENTS_DIR).join("ssh/server/.files/id_ed25519");
+        let mut contents = std::fs::read(stored.as_path()).unwrap();
+        let middle = contents.len() / 2;
+        contents[middle] ^= 1;
+        std::fs::write(stored.as_path(), contents).unwrap();
+        std::fs::remove_file(out.as_path()).unwrap();
+        assert!(attachment::save(vault.as_ref(), "ssh/server", "id_ed25519", out.as_path(), false).is_err());
+        assert!(!out.exists() && !dir.join(".restored.partial").exists());
+
+        // Attachments are kept when the vault is re-encrypted with a new master key
+        vault.remove_attachment("ssh/server", "id_ed25519").unwrap();
+        vault.attach("ssh/server", "notes.txt", &mut "notes".as_bytes()).unwrap();
+        let member = share::public_key(dir.join("member.key").as_path()).unwrap();
+        vault.add_member("bob", &share::recipient(member.as_str()).unwrap()).unwrap();
+        vault.remove_member("bob", "asdf").unwrap();
+        let mut notes = vec![];
+        vault.read_attachment("ssh/server", "notes.txt", &mut notes).unwrap();
+        assert_eq!(notes, b"notes");
+
+        vault.delete_key("ssh/server").unwrap();
+        assert!(!dir.join(ATTACHMENTS_DIR).join("ssh").exists());
+
+        std::fs::remove_dir_all(yap_test).unwrap();
+    }
+}
@@ -0,0 +1,298 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use ring::hmac;
+use serde::Serialize;
+
+use crate::breach;
+use crate::crypto;
+use crate::entry::{self, Entry};
+use crate::error::Result;
+use crate::vault::Vault;
+
+/// Passwords shorter than this are reported as short
+pub const MIN_LENGTH: usize = 12;
+
+/// Passwords with a strength score below this are reported as weak
+pub const MIN_SCORE: u8 = 3;
+
+/// Some of the most common passwords and words found in passwords, most common first. A match
+/// is guessed after as many attempts as its rank.
+const COMMON: &[&str] = &[
+    "password", "123456", "qwerty", "abc123", "letmein", "monkey", "dragon", "111111", "baseball",
+    "iloveyou", "trustno1", "sunshine", "master", "welcome", "shadow", "ashley", "football",
+    "jesus", "michael", "ninja", "mustang", "password1", "admin", "login", "princess", "starwars",
+    "hello", "freedom", "whatever", "qazwsx", "charlie", "secret", "summer", "winter", "spring",
+    "autumn", "love", "god", "batman", "superman", "pokemon", "computer", "internet", "access",
+    "flower", "hunter", "soccer", "hockey", "killer", "george", "jordan", "harley", "ranger",
+    "buster", "thomas", "tigger", "robert", "andrew", "pepper", "daniel", "matrix",
+    "cheese", "orange", "banana", "apple", "cookie", "coffee", "money", "secure", "pass", "root",
+    "user", "test", "guest", "changeme", "default", "yankees", "dallas", "london", "paris",
+];
+
+/// Keyboard rows, which are as easy to guess as sequences of letters or digits
+const KEYBOARD_ROWS: &[&str] = &["qwertyuiop", "asdfghjkl", "zxcvbnm", "1234567890"];
+
+/// Problem is a reason a password should be changed
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Problem {
+    /// The password is easy to guess
+    Weak,
+    /// The password has fewer than `MIN_LENGTH` characters
+    Short,
+    /// The same password is used by another entry
+    Reused,
+    /// The password hasn't been changed for longer than the maximum age
+    Old,
+    /// The password appears in known breaches
+    Breached,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let problem = match self {
+            Problem::Weak => "weak",
+            Problem::Short => "short",
+            Problem::Reused => "reused",
+            Problem::Old => "old",
+            Problem::Breached => "breached",
+        };
+        f.write_str(problem)
+    }
+}
+
+/// Finding is the result of auditing the password of a single entry
+#[derive(Serialize)]
+pub struct Finding {
+    pub name: String,
+    pub score: u8,
+    pub problems: Vec<Problem>,
+    /// The number of times the password appears in known breaches, if they were checked
+    pub breaches: u64,
+}
+
+/// Report holds the findings for every audited entry, in the order of their names
+#[derive(Serialize)]
+pub struct Report {
+    pub findings: Vec<Finding>,
+    /// Whether the passwords were checked against known breaches
+    pub breaches_checked: bool,
+    /// The names of entries sharing the same password, largest group first
+    pub duplicates: Vec<Vec<String>>,
+}
+
+impl Report {
+    /// Returns the findings for entries with at least one problem
+    pub fn problems(&self) -> impl Iterator<Item = &Finding> {
+        self.findings.iter().filter(|finding| !finding.problems.is_empty())
+    }
+
+    /// Counts the entries with the given problem
+    pub fn count(&self, problem: Problem) -> usize {
+        self.findings.iter().filter(|finding| finding.problems.contains(&problem)).count()
+    }
+}
+
+/// Decrypts every entry with a password and reports the passwords which are weak, short, reused
+/// by another entry, or haven't been changed for `max_age` days. Passwords older than entry
+/// timestamps are never reported as old, and a `max_age` of zero turns the age check off.
+/// Nothing leaves the machine, the passwords are only compared with each other, unless `breach`
+/// is set. Then passwords are also checked against known breaches, see `breach::breaches`.
+pub fn audit(vault: &dyn Vault, max_age: u64, breach: bool) -> Result<Report> {
+    let mut entries = vec![];
+    for name in vault.list_keys("")? {
+        let entry = vault.get_entry(name.as_str())?;
+        if !entry.password.is_empty() {
+            entries.push((name, entry));
+        }
+    }
+
+    let duplicates = duplicates(&entries)?;
+
+    let breaches = match breach {
+        true => breach::breaches(&entries.iter().map(|(_, entry)| entry.password.as_str()).collect::<Vec<_>>())?,
+        false => vec![0; entries.len()],
+    };
+
+    let oldest = entry::now() - (max_age as i64).saturating_mul(24 * 60 * 60);
+    let findings = entries.iter().zip(breaches)
+        .map(|((name, entry), breaches)| {
+            let password = entry.password.as_str();
+            let score = score(password);
+            let modified = entry.metadata.modified;
+
+            let problems = [
+                (score < MIN_SCORE, Problem::Weak),
+                (password.chars().count() < MIN_LENGTH, Problem::Short),
+                (duplicates.iter().flatten().any(|duplicate| duplicate == name), Problem::Reused),
+                (max_age > 0 && modified > 0 && modified < oldest, Problem::Old),
+                (breaches > 0, Problem::Breached),
+            ];
+            let problems = problems.into_iter().filter(|(found, _)| *found).map(|(_, problem)| problem).collect();
+            Finding { name: name.clone(), score, problems, breaches }
+        })
+        .collect();
+
+    Ok(Report { findings, breaches_checked: breach, duplicates })
+}
+
+/// Groups the names of entries sharing the same password, with the largest groups first since
+/// they are the most important to change. Passwords are compared by their HMAC under a random
+/// key which is thrown away afterwards, so no plaintext password is held on to, and the hashes
+/// can't be compared with those of any other run.
+fn duplicates(entries: &[(String, Entry)]) -> Result<Vec<Vec<String>>> {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, crypto::new_key()?.as_slice());
+
+    let mut groups: HashMap<Vec<u8>, Vec<String>> = HashMap::new();
+    for (name, entry) in entries {
+        let tag = hmac::sign(&key, entry.password.as_bytes());
+        groups.entry(tag.as_ref().to_vec()).or_default().push(name.clone());
+    }
+
+    let mut duplicates: Vec<Vec<String>> = groups.into_values().filter(|names| names.len() > 1).collect();
+    duplicates.sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+    Ok(duplicates)
+}
+
+/// Rates how hard a password is to guess from 0, which is guessed almost at once, to 4, which
+/// takes more than 10^10 guesses, in the same way as zxcvbn.
+pub fn score(password: &str) -> u8 {
+    score_guesses(guesses_log10(password))
+}
+
+/// Rates a password which takes 10^`guesses_log10` guesses to find, in the same way as `score`
+pub fn score_guesses(guesses_log10: f64) -> u8 {
+    match guesses_log10 {
+        g if g < 3.0 => 0,
+        g if g < 6.0 => 1,
+        g if g < 8.0 => 2,
+        g if g < 10.0 => 3,
+        _ => 4,
+    }
+}
+
+/// Estimates the number of guesses needed to find a password, as a power of ten. The password is
+/// split into common words, sequences, repeated characters, and years, which are cheap to guess,
+/// and the characters between them, which are guessed one at a time. The split needing the
+/// fewest guesses is the estimate.
+pub fn guesses_log10(password: &str) -> f64 {
+    let chars: Vec<char> = password.chars().collect();
+    let lower: Vec<char> = password.to_lowercase().chars().collect();
+    if chars.is_empty() || lower.len() != chars.len() {
+        return brute_force(&chars, chars.len());
+    }
+
+    // The fewest guesses, as a power of ten, needed for the first i characters
+    let char_guesses = brute_force(&chars, 1);
+    let mut best = vec![0.0; chars.len() + 1];
+    for end in 1..=chars.len() {
+        best[end] = best[end - 1] + char_guesses;
+        for start in 0..end {
+            if let Some(guesses) = pattern_guesses(&chars[start..end], &lower[start..end]) {
+                best[end] = f64::min(best[end], best[start] + guesses);
+            }
+        }
+    }
+    best[chars.len()]
+}
+
+/// Returns the guesses needed for characters matching a pattern, as a power of ten, or None if
+/// they don't match one.
+fn pattern_guesses(chars: &[char], lower: &[char]) -> Option<f64> {
+    let word: String = lower.iter().collect();
+    if let Some(rank) = COMMON.iter().position(|common| *common == word) {
+        // Capitalizing some of the letters only doubles the guesses
+        let capitalized = chars.iter().any(|c| c.is_uppercase());
+        return Some(((rank + 1) as f64).log10() + if capitalized { 2f64.log10() } else { 0.0 });
+    }
+    if chars.len() < 3 {
+        return None;
+    }
+
+    let len = (chars.len() as f64).log10();
+    if chars.iter().all(|c| *c == chars[0]) {
+        return Some(brute_force(&chars[..1], 1) + len);
+    }
+    if is_sequence(lower) {
+        return Some(1.0 + len);
+    }
+    if KEYBOARD_ROWS.iter().any(|row| row.contains(word.as_str()) || row.chars().rev().collect::<String>().contains(word.as_str())) {
+        return Some(1.5 + len);
+    }
+    if chars.len() == 4 && matches!(word.parse::<u32>(), Ok(1900..=2099)) {
+        return Some(2f64.log10() + 2.0);
+    }
+    None
+}
+
+/// Checks whether characters go up or down one at a time, such as abcd or 4321
+fn is_sequence(chars: &[char]) -> bool {
+    let step = chars[1] as i64 - chars[0] as i64;
+    step.abs() == 1 && chars.windows(2).all(|pair| pair[1] as i64 - pair[0] as i64 == step)
+}
+
+/// Returns the guesses needed to find `len` characters one at a time, as a power of ten, where
+/// each could be any character from the classes used in the password.
+fn brute_force(chars: &[char], len: usize) -> f64 {
+    let classes = [
+        (chars.iter().any(|c| c.is_ascii_lowercase()), 26),
+        (chars.iter().any(|c| c.is_ascii_uppercase()), 26),
+        (chars.iter().any(|c| c.is_ascii_digit()), 10),
+        (chars.iter().any(|c| c.is_ascii_punctuation() || *c == ' '), 33),
+        (chars.iter().any(|c| !c.is_ascii()), 100),
+    ];
+    let cardinality: u32 = classes.iter().filter(|(used, _)| *used).map(|(_, size)| size).sum();
+    len as f64 * (cardinality.max(10) as f64).log10()
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use crate::audit::{audit, score, Problem};
+    use crate::crypto::Kdf;
+    use crate::vault::{self, Backend};
+
+    #[test]
+    fn scores_passwords() {
+        assert_eq!(score("password"), 0);
+        assert!(score("Password1234") < 2);
+        assert_eq!(score("qwertyuiop"), 0);
+        assert_eq!(score("aaaaaaaaaaaa"), 0);
+        assert!(score("summer1987") < 3);
+        assert_eq!(score("7rB#q9!vLm2@xZ"), 4);
+        assert_eq!(score("correct horse battery staple"), 4);
+    }
+
+    #[test]
+    fn audit_reports_problems() {
+        let yap_test = String::from(".yap_test_audit");
+        std::fs::create_dir_all(Path::new(yap_test.as_str())).unwrap();
+
+        let mut vault = vault::create(Some(yap_test.clone()), "asdf", Kdf::default(), Backend::default(), None).unwrap();
+        vault.set_key("email", "password".to_string()).unwrap();
+        vault.set_key("bank", "7rB#q9!vLm2@xZ".to_string()).unwrap();
+        vault.set_key("work/github", "7rB#q9!vLm2@xZ".to_string()).unwrap();
+        vault.set_key("work/aws", "Gj4$kPq!2wZr#8nV".to_string()).unwrap();
+        vault.set_key("work/gitlab", "7rB#q9!vLm2@xZ".to_string()).unwrap();
+        vault.set_key("work/jira", "hunter2".to_string()).unwrap();
+        vault.set_key("work/wiki", "hunter2".to_string()).unwrap();
+
+        let report = audit(vault.as_ref(), 365, false).unwrap();
+        assert_eq!(report.findings.len(), 7);
+        let problems: Vec<(&str, &Vec<Problem>)> = report.problems().map(|f| (f.name.as_str(), &f.problems)).collect();
+        assert_eq!(problems[..3], vec![
+            ("bank", &vec![Problem::Reused]),
+            ("email", &vec![Problem::Weak, Problem::Short]),
+            ("work/github", &vec![Problem::Reused]),
+        ]);
+        assert_eq!(report.count(Problem::Reused), 5);
+        assert_eq!(report.duplicates, vec![
+            vec!["bank".to_string(), "work/github".to_string(), "work/gitlab".to_string()],
+            vec!["work/jira".to_string(), "work/wiki".to_string()],
+        ]);
+
+        std::fs::remove_dir_all(yap_test).unwrap();
+    }
+}
@@ -0,0 +1,300 @@
+use std::collections::BTreeSet;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use age::x25519::Recipient;
+use serde::Serialize;
+
+use crate::activity::Activity;
+use crate::crypto::Kdf;
+use crate::entry::{self, Entry, Metadata};
+use crate::error::{Error, Result};
+use crate::global::{BACKUPS_DIR, HEADER_FILE};
+use crate::stats::Stats;
+use crate::vault::{self, Vault};
+use crate::verify::{RepairReport, Report};
+use crate::wipe;
+
+/// AutoBackup is a copy of the entries a destructive write was about to change, taken
+/// automatically and kept in the vault's `.backups` directory as `<taken>-<operation>`. The
+/// entries are copied as they are stored, still encrypted, along with the vault header they were
+/// encrypted under.
+#[derive(Serialize, PartialEq, Eq, Debug)]
+pub struct AutoBackup {
+    pub id: String,
+    /// When the backup was taken, in seconds since the Unix epoch
+    pub taken: i64,
+    /// The command which was about to change the entries, such as set or rm
+    pub operation: String,
+    /// The names of the entries which were backed up
+    pub entries: Vec<String>,
+}
+
+/// Lists the automatic backups of the vault in `vault_dir`, oldest first
+pub fn list(vault_dir: &Path) -> Result<Vec<AutoBackup>> {
+    let dir = vault_dir.join(BACKUPS_DIR);
+    if !dir.is_dir() {
+        return Ok(vec![]);
+    }
+
+    let mut backups = vec![];
+    for file in std::fs::read_dir(dir.as_path())? {
+        let file = file?;
+        let id = file.file_name().to_string_lossy().to_string();
+        let Some((taken, operation)) = id.split_once('-').and_then(|(taken, operation)| Some((taken.parse().ok()?, operation.to_string()))) else {
+            continue;
+        };
+        let mut entries = vec![];
+        vault::collect_files(file.path().as_path(), "", false, &mut entries)?;
+        entries.sort();
+        backups.push(AutoBackup { id, taken, operation, entries });
+    }
+    backups.sort_by_key(|backup| backup.taken);
+    Ok(backups)
+}
+
+/// Returns the directory holding the automatic backup with the given id
+pub fn path(vault_dir: &Path, id: &str) -> Result<PathBuf> {
+    let p = vault_dir.join(BACKUPS_DIR).join(id);
+    if id.is_empty() || id.contains(['/', '\\']) || !p.is_dir() {
+        return Err(Error::BackupNotFound { id: id.to_string() });
+    }
+    Ok(p)
+}
+
+/// Copies the files of the entries with the given names which exist into a new automatic backup,
+/// then removes the oldest backups so only `keep` are left. Nothing is backed up if none of the
+/// entries exist. Names which aren't valid entry names are left for the vault to reject, rather
+/// than copying files from outside the vault.
+fn take(vault_dir: &Path, operation: &str, keys: &[String], keep: usize) -> Result<()> {
+    let keys: Vec<&String> = keys.iter()
+        .filter(|key| vault::validate_key(key.as_str()).is_ok() && vault_dir.join(Path::new(key.as_str())).is_file())
+        .collect();
+    if keys.is_empty() {
+        return Ok(());
+    }
+
+    // Backups taken within a second of each other are kept apart, and in the order they were taken
+    let root = vault_dir.join(BACKUPS_DIR);
+    let newest = list(vault_dir)?.last().map(|backup| backup.taken + 1).unwrap_or_default();
+    let taken = entry::now().max(newest);
+    let dir = root.join(format!("{}-{}", taken, operation));
+    std::fs::create_dir_all(dir.as_path())?;
+    std::fs::copy(vault_dir.join(HEADER_FILE), dir.join(HEADER_FILE))?;
+    for key in keys {
+        let to = dir.join(Path::new(key.as_str()));
+        if let Some(parent) = to.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::copy(vault_dir.join(Path::new(key.as_str())), to)?;
+    }
+
+    let backups = list(vault_dir)?;
+    for backup in backups.iter().take(backups.len().saturating_sub(keep)) {
+        wipe::wipe_dir(root.join(backup.id.as_str()).as_path())?;
+    }
+    Ok(())
+}
+
+/// BackupVault wraps a vault opened from a directory, taking an automatic backup of the entries
+/// a write is about to replace or delete, see `AutoBackup`. Only the newest backups are kept, as
+/// many as the backup_count setting asks. Entries are kept in their history as well, but a
+/// backup also outlives deleting the entry, emptying the trash, or a botched rekey.
+pub(crate) struct BackupVault {
+    vault: Box<dyn Vault>,
+    vault_dir: PathBuf,
+    keep: usize,
+}
+
+impl BackupVault {
+    pub(crate) fn new(vault: Box<dyn Vault>, vault_dir: &Path, keep: usize) -> BackupVault {
+        BackupVault { vault, vault_dir: vault_dir.to_path_buf(), keep }
+    }
+
+    fn backup(&self, operation: &str, keys: &[String]) -> Result<()> {
+        take(self.vault_dir.as_path(), operation, keys, self.keep)
+    }
+
+    /// Backs up every entry, before the whole vault is re-encrypted
+    fn backup_all(&self, operation: &str) -> Result<()> {
+        self.backup(operation, self.vault.list_keys("")?.as_slice())
+    }
+}
+
+impl Vault for BackupVault {
+    fn has_key(&self, key: &str) -> bool {
+        self.vault.has_key(key)
+    }
+
+    fn get_entry(&self, key: &str) -> Result<Entry> {
+        self.vault.get_entry(key)
+    }
+
+    fn access_entry(&mut self, key: &str) -> Result<Entry> {
+        self.vault.access_entry(key)
+    }
+
+    fn set_entry(&mut self, key: &str, entry: &Entry) -> Result<()> {
+        self.backup("set", &[key.to_string()])?;
+        self.vault.set_entry(key, entry)
+    }
+
+    fn set_entries(&mut self, entries: &[(String, Entry)]) -> Result<()> {
+        let keys: Vec<String> = entries.iter().map(|(key, _)| key.clone()).collect();
+        self.backup("set", keys.as_slice())?;
+        self.vault.set_entries(entries)
+    }
+
+    fn delete_key(&mut self, key: &str) -> Result<()> {
+        self.backup("rm", &[key.to_string()])?;
+        self.vault.delete_key(key)
+    }
+
+    fn rename_key(&mut self, from: &str, to: &str, force: bool) -> Result<()> {
+        if force {
+            self.backup("mv", &[to.to_string()])?;
+        }
+        self.vault.rename_key(from, to, force)
+    }
+
+    fn list_keys(&self, prefix: &str) -> Result<Vec<String>> {
+        self.vault.list_keys(prefix)
+    }
+
+    fn attach(&mut self, key: &str, attachment: &str, reader: &mut dyn Read) -> Result<u64> {
+        self.vault.attach(key, attachment, reader)
+    }
+
+    fn read_attachment(&self, key: &str, attachment: &str, writer: &mut dyn Write) -> Result<u64> {
+        self.vault.read_attachment(key, attachment, writer)
+    }
+
+    fn list_attachments(&self, key: &str) -> Result<Vec<String>> {
+        self.vault.list_attachments(key)
+    }
+
+    fn remove_attachment(&mut self, key: &str, attachment: &str) -> Result<()> {
+        self.vault.remove_attachment(key, attachment)
+    }
+
+    fn history(&self, key: &str) -> Result<Vec<(u32, Metadata)>> {
+        self.vault.history(key)
+    }
+
+    fn restore(&mut self, key: &str, version: u32) -> Result<()> {
+        self.backup("restore", &[key.to_string()])?;
+        self.vault.restore(key, version)
+    }
+
+    fn list_trash(&self) -> Result<Vec<(String, i64)>> {
+        self.vault.list_trash()
+    }
+
+    fn restore_trash(&mut self, key: &str, force: bool) -> Result<()> {
+        if force {
+            self.backup("restore", &[key.to_string()])?;
+        }
+        self.vault.restore_trash(key, force)
+    }
+
+    fn empty_trash(&mut self, before: Option<i64>) -> Result<usize> {
+        self.vault.empty_trash(before)
+    }
+
+    fn rebuild_index(&mut self) -> Result<usize> {
+        self.vault.rebuild_index()
+    }
+
+    fn verify(&mut self, quarantine: bool) -> Result<Report> {
+        self.vault.verify(quarantine)
+    }
+
+    fn repair(&mut self, backup: Option<&Path>) -> Result<RepairReport> {
+        self.vault.repair(backup)
+    }
+
+    fn recover(&mut self, backup: &Path, key: &str) -> Result<()> {
+        self.backup("recover", &[key.to_string()])?;
+        self.vault.recover(backup, key)
+    }
+
+    fn activity(&self) -> Result<Vec<Activity>> {
+        self.vault.activity()
+    }
+
+    fn rekey(&mut self, new_pass: &str) -> Result<()> {
+        self.backup_all("rekey")?;
+        self.vault.rekey(new_pass)
+    }
+
+    fn set_kdf(&mut self, kdf: Kdf, pass: &str) -> Result<()> {
+        self.backup_all("rekey")?;
+        self.vault.set_kdf(kdf, pass)
+    }
+
+    fn add_member(&mut self, name: &str, recipient: &Recipient) -> Result<()> {
+        self.vault.add_member(name, recipient)
+    }
+
+    fn remove_member(&mut self, name: &str, pass: &str) -> Result<()> {
+        self.backup_all("rekey")?;
+        self.vault.remove_member(name, pass)
+    }
+
+    fn delete_folder(&mut self, folder: &str) -> Result<Vec<String>> {
+        let prefix = format!("{}/", folder.trim_end_matches('/'));
+        self.backup("rm", self.vault.list_keys(prefix.as_str())?.as_slice())?;
+        self.vault.delete_folder(folder)
+    }
+
+    fn metadata(&self, key: &str) -> Result<Metadata> {
+        self.vault.metadata(key)
+    }
+
+    fn tags(&self, key: &str) -> Result<BTreeSet<String>> {
+        self.vault.tags(key)
+    }
+
+    fn stats(&self) -> Result<Stats> {
+        self.vault.stats()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use crate::autobackup::{self, BackupVault};
+    use crate::crypto::Kdf;
+    use crate::vault::{self, Backend, Vault};
+
+    #[test]
+    fn overwritten_entries_can_be_recovered() {
+        let yap_test = String::from(".yap_test_autobackup");
+        let dir = Path::new(yap_test.as_str());
+        let vault = vault::create(Some(yap_test.clone()), "asdf", Kdf::default(), Backend::default(), None).unwrap();
+        let mut vault = BackupVault::new(vault, dir, 2);
+
+        // New entries have nothing to back up
+        vault.set_key("bank", "first".to_string()).unwrap();
+        assert!(autobackup::list(dir).unwrap().is_empty());
+
+        vault.set_key("bank", "second".to_string()).unwrap();
+        vault.set_key("bank", "third".to_string()).unwrap();
+        vault.delete_key("bank").unwrap();
+        vault.empty_trash(None).unwrap();
+        let backups = autobackup::list(dir).unwrap();
+        assert_eq!(backups.iter().map(|backup| backup.operation.as_str()).collect::<Vec<_>>(), vec!["set", "rm"]);
+        assert_eq!(backups[1].entries, vec!["bank"]);
+
+        // The deleted entry comes back from the newest backup, which is itself backed up
+        let newest = autobackup::path(dir, backups[1].id.as_str()).unwrap();
+        vault.set_key("bank", "new".to_string()).unwrap();
+        vault.recover(newest.as_path(), "bank").unwrap();
+        assert_eq!(vault.get_key("bank").unwrap(), "third");
+        assert_eq!(autobackup::list(dir).unwrap().last().unwrap().operation, "recover");
+        assert!(autobackup::path(dir, "../bank").is_err());
+
+        std::fs::remove_dir_all(yap_test).unwrap();
+    }
+}
@@ -0,0 +1,292 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+use base64::Engine;
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use serde::{Serialize, Deserialize};
+
+use crate::crypto::{self, Aes256GcmEngine, Kdf};
+use crate::editor;
+use crate::error::{Error, Result};
+use crate::global::{ACTIVITY_FILE, ACTIVITY_KEY_FILE, BACKUPS_DIR, BATCH_DIR, HEADER_FILE, INDEX_FILE, LOCK_FILE, REKEY_DIR, SNAPSHOTS_DIR};
+use crate::header::VaultHeader;
+use crate::vault;
+
+/// The magic bytes at the start of a backup, followed by its format version
+const MAGIC: &[u8] = b"YAPB";
+
+/// The current backup format, which is encrypted as a stream
+const BACKUP_VERSION: u8 = 1;
+
+/// The longest path or header a backup may hold, so a corrupt length can't exhaust memory
+const MAX_PATH_LEN: usize = 4096;
+
+/// BackupHeader is the plaintext part of a backup, holding what's needed to derive the key from
+/// the backup password, like a vault header. It follows the magic bytes and version, prefixed by
+/// its length, and is bound to the data key of the encrypted stream after it.
+#[derive(Serialize, Deserialize)]
+struct BackupHeader {
+    salt: Vec<u8>,
+    kdf: Kdf,
+}
+
+/// Backup is a backup written before backups were encrypted as a stream, as YAML with the whole
+/// encrypted archive in base64. These can still be restored.
+#[derive(Deserialize)]
+struct Backup {
+    salt: Vec<u8>,
+    kdf: Kdf,
+    data: String,
+}
+
+/// Writes a backup of the vault in `vault_dir` to a new file at `out`, returning the number of
+/// files backed up. The files are archived and compressed, then encrypted with a key derived from
+/// the backup password, so the backup can be copied through untrusted storage. Each file is
+/// streamed through into the backup, so large attachments are never held in memory. The git
+/// repository used for syncing is not included, nor is a rekey or batch which has not finished,
+/// nor the automatic backups, snapshots, or activity log, which belong to this copy of the vault,
+/// nor the index, which is rebuilt from the restored entries.
+pub fn create(vault_dir: &Path, out: &Path, pass: &str, kdf: Kdf) -> Result<usize> {
+    VaultHeader::read(vault_dir)?;
+
+    let mut files = vec![];
+    vault::collect_files(vault_dir, "", true, &mut files)?;
+    files.retain(|file| {
+        ![".git", REKEY_DIR, BATCH_DIR, BACKUPS_DIR, SNAPSHOTS_DIR].iter().any(|dir| file.starts_with(dir))
+            && ![INDEX_FILE, LOCK_FILE, ACTIVITY_FILE, ACTIVITY_KEY_FILE].contains(&file.as_str())
+    });
+    files.sort();
+
+    let salt = crypto::new_salt()?.to_vec();
+    let engine = Aes256GcmEngine::new(pass, &salt, &kdf)?;
+    let header = serde_yaml::to_string(&BackupHeader { salt, kdf })?;
+
+    let mut writer = BufWriter::new(editor::create_private(out)?);
+    let written = write_backup(&engine, header.as_bytes(), vault_dir, files.as_slice(), &mut writer)
+        .and_then(|_| Ok(writer.into_inner().map_err(|e| e.into_error())?.sync_all()?));
+    if let Err(e) = written {
+        std::fs::remove_file(out)?;
+        return Err(e);
+    }
+    Ok(files.len())
+}
+
+/// Writes the header and the encrypted archive of the files, see `create`
+fn write_backup(engine: &Aes256GcmEngine, header: &[u8], vault_dir: &Path, files: &[String], writer: &mut dyn Write) -> Result<()> {
+    writer.write_all(MAGIC)?;
+    writer.write_all(&[BACKUP_VERSION])?;
+    writer.write_all(&(header.len() as u32).to_le_bytes())?;
+    writer.write_all(header)?;
+
+    let mut archive = GzEncoder::new(engine.stream_writer(writer, header)?, Compression::default());
+    for file in files.iter() {
+        let mut f = File::open(vault_dir.join(file))?;
+        let len = f.metadata()?.len();
+        archive.write_all(&(file.len() as u32).to_le_bytes())?;
+        archive.write_all(file.as_bytes())?;
+        archive.write_all(&len.to_le_bytes())?;
+        if std::io::copy(&mut (&mut f).take(len), &mut archive)? != len {
+            return Err(std::io::Error::new(ErrorKind::UnexpectedEof, format!("{} changed while it was backed up", file)).into());
+        }
+    }
+    archive.finish()?.finish()?;
+    Ok(())
+}
+
+/// Restores the backup at `p` into `vault_dir`, returning the number of files restored. The
+/// directory must not already hold a vault, so a restore can never mix two vaults together. The
+/// backup is decrypted as it is read, so if it turns out to be corrupt or tampered with partway
+/// through, the files restored so far are removed again.
+pub fn restore(p: &Path, vault_dir: &Path, pass: &str) -> Result<usize> {
+    if vault_dir.join(HEADER_FILE).exists() {
+        return Err(Error::VaultExists { path: vault_dir.display().to_string() });
+    }
+
+    let mut reader = BufReader::new(File::open(p)?);
+    let mut magic = [0u8; 5];
+    let is_stream = read_full(&mut reader, &mut magic)? == magic.len() && magic.starts_with(MAGIC);
+    if !is_stream {
+        let mut contents = magic.to_vec();
+        reader.read_to_end(&mut contents)?;
+        return restore_legacy(contents.as_slice(), vault_dir, pass);
+    }
+    if magic[MAGIC.len()] != BACKUP_VERSION {
+        return Err(bad_backup(format!("the backup was written by a newer version of yap (format {})", magic[MAGIC.len()]).as_str()));
+    }
+
+    let mut len = [0u8; 4];
+    reader.read_exact(&mut len).map_err(|_| bad_backup("the backup is truncated"))?;
+    let len = u32::from_le_bytes(len) as usize;
+    if len > MAX_PATH_LEN {
+        return Err(bad_backup("the backup is corrupt"));
+    }
+    let mut header = vec![0u8; len];
+    reader.read_exact(&mut header).map_err(|_| bad_backup("the backup is truncated"))?;
+    let backup: BackupHeader = serde_yaml::from_slice(header.as_slice()).map_err(|_| bad_backup("the backup is corrupt"))?;
+
+    let engine = Aes256GcmEngine::new(pass, &backup.salt, &backup.kdf)?;
+    let stream = engine.stream_reader(reader, header.as_slice()).map_err(|_| bad_backup("the password is incorrect"))?;
+    restore_archive(&mut GzDecoder::new(stream), vault_dir)
+}
+
+/// Restores a backup written in the format before backups were encrypted as a stream
+fn restore_legacy(contents: &[u8], vault_dir: &Path, pass: &str) -> Result<usize> {
+    let backup: Backup = serde_yaml::from_slice(contents).map_err(|_| bad_backup("the file is not a backup"))?;
+    let data = base64::engine::general_purpose::STANDARD.decode(backup.data)
+        .map_err(|_| bad_backup("the backup is corrupt"))?;
+    let engine = Aes256GcmEngine::new(pass, &backup.salt, &backup.kdf)?;
+    let archive = engine.decrypt_bytes(&data, &[]).map_err(|_| bad_backup("the password is incorrect"))?;
+    restore_archive(&mut GzDecoder::new(archive.as_slice()), vault_dir)
+}
+
+/// Writes each file in the decompressed archive into `vault_dir`, removing them all again if
+/// the archive can't be read to the end
+fn restore_archive(archive: &mut dyn Read, vault_dir: &Path) -> Result<usize> {
+    let mut restored = vec![];
+    let result = restore_files(archive, vault_dir, &mut restored);
+    if result.is_err() {
+        for p in restored.iter() {
+            std::fs::remove_file(p)?;
+            vault::remove_empty_parents(vault_dir, p)?;
+        }
+    }
+    result.map(|_| restored.len())
+}
+
+/// Writes each file in the archive into `vault_dir`, adding its path to `restored` once it has
+/// been created
+fn restore_files(archive: &mut dyn Read, vault_dir: &Path, restored: &mut Vec<PathBuf>) -> Result<()> {
+    loop {
+        let mut len = [0u8; 4];
+        match read_full(archive, &mut len).map_err(corrupt)? {
+            0 => return Ok(()),
+            4 => {}
+            _ => return Err(bad_backup("the backup is truncated")),
+        }
+        let len = u32::from_le_bytes(len) as usize;
+        if len > MAX_PATH_LEN {
+            return Err(bad_backup("the backup is corrupt"));
+        }
+        let mut name = vec![0u8; len];
+        archive.read_exact(&mut name).map_err(corrupt)?;
+        let mut len = [0u8; 8];
+        archive.read_exact(&mut len).map_err(corrupt)?;
+        let len = u64::from_le_bytes(len);
+
+        let name = String::from_utf8(name)?;
+        if !name.split('/').all(|part| !part.is_empty() && part != "." && part != ".." && !part.contains('\\')) {
+            return Err(bad_backup(format!("the backup holds an invalid path {}", name).as_str()));
+        }
+
+        let p = vault_dir.join(name);
+        if let Some(parent) = p.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut f = File::create(p.as_path())?;
+        restored.push(p);
+        if std::io::copy(&mut archive.take(len), &mut f).map_err(corrupt)? != len {
+            return Err(bad_backup("the backup is truncated"));
+        }
+    }
+}
+
+/// Fills `buf` from the reader, returning fewer bytes only at the end of it
+fn read_full(reader: &mut dyn Read, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok(filled)
+}
+
+/// Turns an error reading the archive into an error about the backup, since the archive is only
+/// authenticated as it is read
+fn corrupt(e: std::io::Error) -> Error {
+    match e.kind() {
+        ErrorKind::InvalidData | ErrorKind::UnexpectedEof => bad_backup("the backup is corrupt or was tampered with"),
+        _ => e.into(),
+    }
+}
+
+fn bad_backup(message: &str) -> Error {
+    Error::BadBackup { message: message.to_string() }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+    use std::io::Write;
+    use std::path::Path;
+
+    use base64::Engine;
+    use base64::engine::general_purpose::STANDARD;
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+
+    use crate::backup;
+    use crate::crypto::{self, Aes256GcmEngine, Kdf};
+    use crate::error::Error;
+    use crate::global::HEADER_FILE;
+    use crate::vault::{self, Backend};
+
+    #[test]
+    fn backup_and_restore_vault() {
+        let yap_test = String::from(".yap_test_backup");
+        let restored = String::from(".yap_test_backup_restored");
+        std::fs::create_dir_all(Path::new(yap_test.as_str())).unwrap();
+
+        let mut simple_vault = vault::create(Some(yap_test.clone()), "asdf", Kdf::default(), Backend::default(), None).unwrap();
+        simple_vault.set_key("work/github", "first".to_string()).unwrap();
+        simple_vault.set_key("work/github", "second".to_string()).unwrap();
+
+        let out = Path::new(yap_test.as_str()).join("vault.backup");
+        assert_eq!(backup::create(Path::new(yap_test.as_str()), out.as_path(), "backup", Kdf::default()).unwrap(), 3);
+
+        assert!(backup::restore(out.as_path(), Path::new(yap_test.as_str()), "backup").is_err());
+        assert!(backup::restore(out.as_path(), Path::new(restored.as_str()), "wrong").is_err());
+        backup::restore(out.as_path(), Path::new(restored.as_str()), "backup").unwrap();
+
+        let restored_vault = vault::load(Some(restored.clone()), "asdf").unwrap();
+        assert_eq!(restored_vault.get_key("work/github").unwrap(), "second");
+        assert_eq!(restored_vault.history("work/github").unwrap().len(), 1);
+
+        // A backup tampered with partway through restores nothing
+        let tampered = Path::new(yap_test.as_str()).join("tampered.backup");
+        let mut contents = std::fs::read(out.as_path()).unwrap();
+        let last = contents.len() - 20;
+        contents[last] ^= 1;
+        std::fs::write(tampered.as_path(), contents).unwrap();
+        let empty = Path::new(restored.as_str()).join("empty");
+        assert!(matches!(backup::restore(tampered.as_path(), empty.as_path(), "backup"), Err(Error::BadBackup { .. })));
+        assert!(!empty.join(HEADER_FILE).exists());
+
+        // Backups written as a single YAML document can still be restored
+        let mut archive = GzEncoder::new(vec![], Compression::default());
+        for file in [HEADER_FILE, "work/github"] {
+            let contents = std::fs::read(Path::new(yap_test.as_str()).join(file)).unwrap();
+            archive.write_all(&(file.len() as u32).to_le_bytes()).unwrap();
+            archive.write_all(file.as_bytes()).unwrap();
+            archive.write_all(&(contents.len() as u64).to_le_bytes()).unwrap();
+            archive.write_all(&contents).unwrap();
+        }
+        let salt = crypto::new_salt().unwrap().to_vec();
+        let data = Aes256GcmEngine::new("backup", &salt, &Kdf::default()).unwrap().encrypt_bytes(&archive.finish().unwrap(), &[]).unwrap();
+        let legacy = BTreeMap::from([
+            ("salt", serde_yaml::to_value(salt).unwrap()),
+            ("kdf", serde_yaml::to_value(Kdf::default()).unwrap()),
+            ("data", serde_yaml::to_value(STANDARD.encode(data)).unwrap()),
+        ]);
+        std::fs::write(tampered.as_path(), serde_yaml::to_string(&legacy).unwrap()).unwrap();
+        assert_eq!(backup::restore(tampered.as_path(), empty.as_path(), "backup").unwrap(), 2);
+        assert_eq!(vault::load(Some(empty.display().to_string()), "asdf").unwrap().get_key("work/github").unwrap(), "second");
+
+        std::fs::remove_dir_all(yap_test).unwrap();
+        std::fs::remove_dir_all(restored).unwrap();
+    }
+}
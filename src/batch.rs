@@ -0,0 +1,95 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::Deserialize;
+
+use crate::entry::{self, Entry};
+use crate::error::{Error, Result};
+
+/// Update is a change to one entry read from a batch. Only the password is required, the other
+/// parts of an existing entry are kept unless the update replaces them.
+#[derive(Deserialize, Default, PartialEq, Eq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Update {
+    pub password: String,
+    pub username: Option<String>,
+    pub url: Option<String>,
+    pub notes: Option<String>,
+    #[serde(default)]
+    pub fields: BTreeMap<String, String>,
+    #[serde(default)]
+    pub tags: BTreeSet<String>,
+}
+
+impl Update {
+    /// Applies the update to an entry, which is the existing entry or a default one
+    pub fn apply(self, mut entry: Entry) -> Entry {
+        entry.password = self.password;
+        entry.username = self.username.or(entry.username);
+        entry.url = self.url.or(entry.url);
+        entry.notes = self.notes.or(entry.notes);
+        entry.fields.extend(self.fields);
+        entry.tags.extend(self.tags);
+        entry
+    }
+}
+
+/// Value is an entry in a JSON batch, given either as its password alone or as an object
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Value {
+    Password(String),
+    Update(Update),
+}
+
+/// Parses a batch of entries to set, returning each entry's name and update. A batch is either
+/// a JSON object from names to passwords or to objects with a `password` and optionally a
+/// `username`, `url`, `notes`, `fields`, and `tags`, or lines with a name and a password separated by a
+/// tab. Blank lines are skipped.
+pub fn parse(contents: &str) -> Result<Vec<(String, Update)>> {
+    if contents.trim_start().starts_with('{') {
+        let batch: BTreeMap<String, Value> = serde_json::from_str(contents)
+            .map_err(|e| Error::BadBatch { message: e.to_string() })?;
+        let updates: Vec<_> = batch.into_iter().map(|(name, value)| match value {
+            Value::Password(password) => (name, Update { password, ..Update::default() }),
+            Value::Update(update) => (name, update),
+        }).collect();
+        for tag in updates.iter().flat_map(|(_, update)| update.tags.iter()) {
+            entry::parse_tag(tag)?;
+        }
+        return Ok(updates);
+    }
+
+    let mut updates = vec![];
+    for (n, line) in contents.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+        let Some((name, password)) = line.split_once('\t') else {
+            return Err(Error::BadBatch { message: format!("line {} is not a name and a password separated by a tab", n + 1) });
+        };
+        updates.push((name.trim().to_string(), Update { password: password.to_string(), ..Update::default() }));
+    }
+    Ok(updates)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::batch::{self, Update};
+    use crate::entry::Entry;
+
+    #[test]
+    fn batches_are_read_from_json_or_tab_separated_lines() {
+        let lines = batch::parse("github\ts3cret\n\nwork/vpn\tpass\tword\n").unwrap();
+        assert_eq!(lines.len(), 2);
+        assert_eq!((lines[1].0.as_str(), lines[1].1.password.as_str()), ("work/vpn", "pass\tword"));
+        assert!(batch::parse("github s3cret").is_err());
+
+        let json = r#"{"github": "s3cret", "mail": {"password": "hunter2", "username": "bob", "fields": {"pin": "1234"}}}"#;
+        let mut updates = batch::parse(json).unwrap();
+        assert_eq!(updates[0], ("github".to_string(), Update { password: "s3cret".to_string(), ..Update::default() }));
+        assert!(batch::parse(r#"{"mail": {"pasword": "typo"}}"#).is_err());
+        assert!(batch::parse(r#"{"mail": {"password": "p", "tags": ["two words"]}}"#).is_err());
+
+        let existing = Entry { url: Some("https://mail.example".to_string()), ..Entry::new("old".to_string()) };
+        let entry = updates.remove(1).1.apply(existing);
+        assert_eq!((entry.password.as_str(), entry.username.as_deref()), ("hunter2", Some("bob")));
+        assert_eq!((entry.url.as_deref(), entry.fields.get("pin").map(String::as_str)), (Some("https://mail.example"), Some("1234")));
+    }
+}
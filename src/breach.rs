@@ -0,0 +1,80 @@
+use std::collections::HashMap;
+
+use ring::digest::{self, SHA1_FOR_LEGACY_USE_ONLY};
+
+use crate::error::{Error, Result};
+
+/// The Have I Been Pwned range API, which lists the suffixes of the SHA-1 hashes of breached
+/// passwords sharing a five character prefix
+const RANGE_API: &str = "https://api.pwnedpasswords.com/range/";
+
+/// The length of the hash prefix sent to the range API
+const PREFIX_LEN: usize = 5;
+
+/// Counts how many times each password appears in known breaches, using the Have I Been Pwned
+/// range API. Only the first five characters of each password's SHA-1 hash leave the machine,
+/// which are shared by hundreds of breached passwords, so the API never learns which password
+/// was checked. Responses are padded with fake entries so their size doesn't give it away either.
+pub fn breaches(passwords: &[&str]) -> Result<Vec<u64>> {
+    let hashes: Vec<String> = passwords.iter().map(|password| sha1_hex(password)).collect();
+
+    // Each prefix is only fetched once, however many passwords share it
+    let mut ranges: HashMap<&str, String> = HashMap::new();
+    for hash in &hashes {
+        let prefix = &hash[..PREFIX_LEN];
+        if !ranges.contains_key(prefix) {
+            ranges.insert(prefix, fetch_range(prefix)?);
+        }
+    }
+
+    Ok(hashes.iter()
+        .map(|hash| count(ranges[&hash[..PREFIX_LEN]].as_str(), &hash[PREFIX_LEN..]))
+        .collect())
+}
+
+/// Fetches the hash suffixes sharing a prefix from the range API
+fn fetch_range(prefix: &str) -> Result<String> {
+    let url = format!("{}{}", RANGE_API, prefix);
+    ureq::get(url.as_str())
+        .set("User-Agent", "yap")
+        .set("Add-Padding", "true")
+        .call()
+        .map_err(|e| Error::Breach { message: e.to_string() })?
+        .into_string()
+        .map_err(|e| Error::Breach { message: e.to_string() })
+}
+
+/// Returns the number of breaches listed for a hash suffix in a range API response, where each
+/// line is a suffix and a count separated by a colon. Padding is listed with a count of zero.
+fn count(range: &str, suffix: &str) -> u64 {
+    range.lines()
+        .filter_map(|line| line.trim().split_once(':'))
+        .find(|(listed, _)| listed.eq_ignore_ascii_case(suffix))
+        .and_then(|(_, count)| count.parse().ok())
+        .unwrap_or_default()
+}
+
+/// Returns the SHA-1 hash of a password as uppercase hex, as used by the range API
+fn sha1_hex(password: &str) -> String {
+    digest::digest(&SHA1_FOR_LEGACY_USE_ONLY, password.as_bytes()).as_ref().iter()
+        .map(|b| format!("{:02X}", b))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::breach::{count, sha1_hex, PREFIX_LEN};
+
+    #[test]
+    fn counts_breaches_in_range() {
+        let hash = sha1_hex("password");
+        assert_eq!(hash, "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8");
+
+        let (prefix, suffix) = hash.split_at(PREFIX_LEN);
+        assert_eq!(prefix, "5BAA6");
+        let range = "003D68EB55068C33ACE09247EE4C639306B:3\r\n1E4C9B93F3F0682250B6CF8331B7EE68FD8:9545824\r\n011053FD0102E94D6AE2F8B83D76FAF94F6:0";
+        assert_eq!(count(range, suffix), 9545824);
+        assert_eq!(count(range, "011053FD0102E94D6AE2F8B83D76FAF94F6"), 0);
+        assert_eq!(count(range, "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF"), 0);
+    }
+}
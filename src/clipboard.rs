@@ -0,0 +1,139 @@
+use std::io::{ErrorKind, Read, Write};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use serde::{Serialize, Deserialize};
+
+use crate::error::{Error, Result};
+
+/// Name of the hidden subcommand which clears the clipboard after a timeout
+pub const CLEAR_COMMAND: &str = "__clear-clipboard";
+
+/// ClearRequest is sent to the process which clears the clipboard over its stdin, rather than
+/// as arguments, so the secret does not show up in the process list.
+#[derive(Serialize, Deserialize)]
+struct ClearRequest {
+    secret: String,
+    previous: String,
+}
+
+/// ClipboardTool is a command line program which can copy to and paste from the system
+/// clipboard. These are used instead of talking to the clipboard directly, since on X11 and
+/// Wayland the clipboard contents only live as long as the process which set them, and these
+/// tools take care of keeping the contents available after yap exits.
+struct ClipboardTool {
+    copy: &'static [&'static str],
+    paste: &'static [&'static str],
+}
+
+/// Returns the clipboard tools to try on this platform, in order of preference
+fn tools() -> Vec<ClipboardTool> {
+    if cfg!(target_os = "macos") {
+        vec![ClipboardTool { copy: &["pbcopy"], paste: &["pbpaste"] }]
+    } else if cfg!(windows) {
+        vec![ClipboardTool { copy: &["clip.exe"], paste: &["powershell", "-NoProfile", "-Command", "Get-Clipboard"] }]
+    } else {
+        let mut tools = vec![
+            ClipboardTool { copy: &["xclip", "-selection", "clipboard"], paste: &["xclip", "-selection", "clipboard", "-o"] },
+            ClipboardTool { copy: &["xsel", "--clipboard", "--input"], paste: &["xsel", "--clipboard", "--output"] },
+        ];
+        if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+            tools.insert(0, ClipboardTool { copy: &["wl-copy"], paste: &["wl-paste", "--no-newline"] });
+        }
+        tools
+    }
+}
+
+/// Returns the name of the clipboard tool secrets are copied with, or None if none are installed
+pub fn tool() -> Option<&'static str> {
+    let path = std::env::var_os("PATH")?;
+    tools().into_iter()
+        .map(|tool| tool.copy[0])
+        .find(|program| std::env::split_paths(&path).any(|dir| dir.join(program).is_file()))
+}
+
+/// Copies text to the system clipboard using the first clipboard tool which is installed
+pub fn copy(text: &str) -> Result<()> {
+    for tool in tools() {
+        let child = Command::new(tool.copy[0])
+            .args(&tool.copy[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+
+        let mut child = match child {
+            Ok(child) => child,
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(text.as_bytes())?;
+        }
+
+        return if child.wait()?.success() { Ok(()) } else { Err(Error::NoClipboard) };
+    }
+
+    Err(Error::NoClipboard)
+}
+
+/// Reads the current text contents of the system clipboard
+pub fn paste() -> Result<String> {
+    for tool in tools() {
+        let output = match Command::new(tool.paste[0]).args(&tool.paste[1..]).stderr(Stdio::null()).output() {
+            Ok(output) => output,
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+
+        return if output.status.success() { Ok(String::from_utf8(output.stdout)?) } else { Err(Error::NoClipboard) };
+    }
+
+    Err(Error::NoClipboard)
+}
+
+/// Copies text to the system clipboard and, if `timeout` is not zero, starts a detached process
+/// which clears it again after `timeout` seconds. The clipboard contents from before the copy are
+/// restored when possible.
+pub fn copy_with_timeout(text: &str, timeout: u64) -> Result<()> {
+    let previous = paste().unwrap_or_default();
+    copy(text)?;
+
+    if timeout == 0 {
+        return Ok(());
+    }
+
+    let mut child = Command::new(std::env::current_exe()?)
+        .arg(CLEAR_COMMAND)
+        .arg(timeout.to_string())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+
+    // Restoring the secret itself would defeat the purpose of clearing the clipboard
+    let previous = if previous == text { String::new() } else { previous };
+    let request = ClearRequest { secret: text.to_string(), previous };
+    if let Some(stdin) = child.stdin.take() {
+        serde_yaml::to_writer(stdin, &request)?;
+    }
+
+    Ok(())
+}
+
+/// Waits for `timeout` seconds and then restores the previous clipboard contents, as sent by
+/// `copy_with_timeout` over stdin. If the clipboard no longer holds the secret, the user has
+/// copied something else since, and the clipboard is left alone.
+pub fn clear_after(timeout: u64) -> Result<()> {
+    let mut input = String::new();
+    std::io::stdin().read_to_string(&mut input)?;
+    let request: ClearRequest = serde_yaml::from_str(input.as_str())?;
+
+    std::thread::sleep(Duration::from_secs(timeout));
+
+    if paste()? == request.secret {
+        copy(request.previous.as_str())?;
+    }
+    Ok(())
+}
@@ -0,0 +1,89 @@
+use clap::Command;
+use clap_complete::Shell;
+
+use crate::error::{Error, Result};
+
+/// The hidden command which lists entry names for shell completion
+pub const COMPLETE_COMMAND: &str = "__complete";
+
+/// The commands whose first argument is the name of an entry
+const ENTRY_COMMANDS: &[&str] = &["get", "rm", "edit"];
+
+/// Generates the completion script for a shell. The script completes subcommands and flags, and
+/// for bash, zsh, and fish it also completes entry names for the commands which take one, by
+/// running `yap __complete` with the word being completed. Entry names aren't encrypted, so this
+/// never asks for the master password.
+pub fn script(shell: Shell, command: &mut Command) -> Result<String> {
+    let name = command.get_name().to_string();
+    let mut script = vec![];
+    clap_complete::generate(shell, command, name.as_str(), &mut script);
+    let mut script = String::from_utf8(script)?;
+
+    let commands = ENTRY_COMMANDS.join(" ");
+    let dynamic = match shell {
+        Shell::Bash => format!(r#"
+_{name}_entries() {{
+    local i
+    for ((i = 1; i < COMP_CWORD; i++)); do
+        case " {commands} " in
+            *" ${{COMP_WORDS[i]}} "*)
+                if [[ ${{COMP_WORDS[COMP_CWORD]}} != -* ]]; then
+                    COMPREPLY=($(compgen -W "$({name} {complete} "${{COMP_WORDS[COMP_CWORD]}}" 2>/dev/null)" -- "${{COMP_WORDS[COMP_CWORD]}}"))
+                    return
+                fi
+                ;;
+        esac
+    done
+    _{name} "$@"
+}}
+complete -F _{name}_entries -o bashdefault -o default {name}
+"#, name = name, commands = commands, complete = COMPLETE_COMMAND),
+        Shell::Zsh => format!(r#"
+_{name}_entries() {{
+    local word
+    for word in ${{words[2,CURRENT-1]}}; do
+        if [[ " {commands} " == *" $word "* && ${{words[CURRENT]}} != -* ]]; then
+            compadd -- ${{(f)"$({name} {complete} ${{words[CURRENT]}} 2>/dev/null)"}}
+            return
+        fi
+    done
+    _{name} "$@"
+}}
+compdef _{name}_entries {name}
+"#, name = name, commands = commands, complete = COMPLETE_COMMAND),
+        Shell::Fish => format!(r#"
+complete -c {name} -n "__fish_seen_subcommand_from {commands}" -f -a "({name} {complete} (commandline -ct) 2>/dev/null)"
+"#, name = name, commands = commands, complete = COMPLETE_COMMAND),
+        Shell::PowerShell | Shell::Elvish => String::new(),
+        _ => return Err(Error::UnsupportedShell { shell: shell.to_string() }),
+    };
+
+    script.push_str(dynamic.as_str());
+    Ok(script)
+}
+
+#[cfg(test)]
+mod test {
+    use clap::{Arg, Command};
+    use clap_complete::Shell;
+
+    use crate::completions::script;
+
+    #[test]
+    fn scripts_complete_entry_names() {
+        let mut command = Command::new("yap")
+            .subcommand(Command::new("get").arg(Arg::new("name")))
+            .subcommand(Command::new("list"));
+
+        let bash = script(Shell::Bash, &mut command).unwrap();
+        assert!(bash.contains("_yap()"));
+        assert!(bash.contains("complete -F _yap_entries"));
+        assert!(bash.contains("yap __complete"));
+
+        let fish = script(Shell::Fish, &mut command).unwrap();
+        assert!(fish.contains("__fish_seen_subcommand_from get rm edit"));
+
+        let powershell = script(Shell::PowerShell, &mut command).unwrap();
+        assert!(!powershell.contains("__complete"));
+    }
+}
@@ -1,92 +1,769 @@
+use std::collections::{BTreeMap, HashMap};
 use std::path::{Path, PathBuf};
 use std::fs::File;
+use std::sync::OnceLock;
 
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
 use serde::{Serialize, Deserialize};
 use clap::Subcommand;
 
-use crate::global::{YAP_DIR, CONFIG_FILE};
+use crate::askpass::AskpassRule;
+use crate::audit;
+use crate::crypto::Kdf;
+use crate::generate::PasswordPolicy;
+use crate::session::SessionStore;
+use crate::vault::{self, Backend};
+use crate::global::{YAP_DIR, CONFIG_FILE, LOCAL_CONFIG_FILE, SETTING_VAR_PREFIX, VAULT_CONFIG_FILE};
 use crate::error::{Error, Result};
 use crate::ExecutableCommand;
 
 
+/// The settings overridden by command line flags such as --read-only, which take precedence over
+/// everything else, see `override_with_flags`
+static FLAG_OVERRIDES: OnceLock<Vec<(SettingKey, String)>> = OnceLock::new();
+
+/// ConfigFile is the config file as it is written to disk, with each setting as a string. Settings
+/// missing from the file take their default value, so config files written by older versions can
+/// still be read.
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+struct ConfigFile {
+    remote_url: String,
+    auto_sync: String,
+    session: String,
+    kdf: String,
+    clip_timeout: String,
+    backend: String,
+    agent_ttl: String,
+    lock_timeout: String,
+    lock_wait: String,
+    keyfile: String,
+    min_strength: String,
+    read_only: String,
+    sign_as: String,
+    trash_days: String,
+    backup_count: String,
+    compress: String,
+    generate: GenerateFile,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    stores: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    askpass: Vec<AskpassRule>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    authors: BTreeMap<String, String>,
+}
+
+impl Default for ConfigFile {
+    fn default() -> Self {
+        ConfigSettings::default().into()
+    }
+}
+
+/// GenerateFile is the `generate` section of the config file, the defaults for generated passwords
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+struct GenerateFile {
+    length: String,
+    symbols: String,
+    charset: String,
+}
+
+impl Default for GenerateFile {
+    fn default() -> Self {
+        ConfigFile::default().generate
+    }
+}
+
 /// ConfigSettings are global settings for the program which should persist between command
 /// invocations. These are saved to a file in the user's home directory and loaded every time that
-/// yap is used.
-#[derive(Serialize, Deserialize, Default)]
+/// yap is used. Each setting is validated when it is set or read from the file, so a bad value is
+/// reported straight away rather than when the setting is next used.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(try_from = "ConfigFile", into = "ConfigFile")]
 struct ConfigSettings {
     remote_url: String,
-    session: String,
+    auto_sync: bool,
+    session: SessionStore,
+    kdf: Kdf,
+    /// Seconds
+    clip_timeout: u64,
+    backend: Backend,
+    /// Seconds
+    agent_ttl: u64,
+    /// Minutes
+    lock_timeout: u64,
+    /// Seconds
+    lock_wait: u64,
+    keyfile: Option<PathBuf>,
+    /// From 0 to 4, see audit::score
+    min_strength: u8,
+    read_only: bool,
+    sign_as: Option<String>,
+    /// Days
+    trash_days: u64,
+    backup_count: usize,
+    compress: bool,
+    generate_length: usize,
+    generate_symbols: bool,
+    generate_charset: Option<String>,
+    stores: BTreeMap<String, String>,
+    askpass: Vec<AskpassRule>,
+    /// The public keys of trusted authors, in base64, by name, see `sign::author`
+    authors: BTreeMap<String, String>,
+}
+
+impl Default for ConfigSettings {
+    fn default() -> Self {
+        ConfigSettings {
+            remote_url: String::new(),
+            auto_sync: false,
+            session: SessionStore::Agent,
+            kdf: Kdf::default(),
+            clip_timeout: 45,
+            backend: Backend::Files,
+            agent_ttl: 900,
+            lock_timeout: 5,
+            lock_wait: 10,
+            keyfile: None,
+            min_strength: audit::MIN_SCORE,
+            read_only: false,
+            sign_as: None,
+            trash_days: 30,
+            backup_count: 10,
+            compress: true,
+            generate_length: PasswordPolicy::default().length,
+            generate_symbols: PasswordPolicy::default().symbols,
+            generate_charset: None,
+            stores: BTreeMap::new(),
+            askpass: vec![],
+            authors: BTreeMap::new(),
+        }
+    }
+}
+
+impl TryFrom<ConfigFile> for ConfigSettings {
+    type Error = Error;
+
+    fn try_from(file: ConfigFile) -> Result<Self> {
+        let mut settings = ConfigSettings { stores: file.stores, askpass: file.askpass, authors: file.authors, ..ConfigSettings::default() };
+        let values = [
+            (SettingKey::RemoteURL, file.remote_url), (SettingKey::AutoSync, file.auto_sync),
+            (SettingKey::Session, file.session),
+            (SettingKey::Kdf, file.kdf), (SettingKey::ClipTimeout, file.clip_timeout),
+            (SettingKey::Backend, file.backend), (SettingKey::AgentTtl, file.agent_ttl),
+            (SettingKey::LockTimeout, file.lock_timeout), (SettingKey::LockWait, file.lock_wait),
+            (SettingKey::Keyfile, file.keyfile),
+            (SettingKey::MinStrength, file.min_strength), (SettingKey::ReadOnly, file.read_only),
+            (SettingKey::SignAs, file.sign_as), (SettingKey::TrashDays, file.trash_days),
+            (SettingKey::BackupCount, file.backup_count), (SettingKey::Compress, file.compress),
+            (SettingKey::GenerateLength, file.generate.length), (SettingKey::GenerateSymbols, file.generate.symbols),
+            (SettingKey::GenerateCharset, file.generate.charset),
+        ];
+        // Older versions wrote empty values for settings they didn't use, which take the default
+        for (key, value) in values.into_iter().filter(|(_, value)| !value.is_empty()) {
+            settings.set(key, value.as_str())?;
+        }
+        Ok(settings)
+    }
+}
+
+impl From<ConfigSettings> for ConfigFile {
+    fn from(settings: ConfigSettings) -> Self {
+        ConfigFile {
+            remote_url: settings.get(SettingKey::RemoteURL),
+            auto_sync: settings.get(SettingKey::AutoSync),
+            session: settings.get(SettingKey::Session),
+            kdf: settings.get(SettingKey::Kdf),
+            clip_timeout: settings.get(SettingKey::ClipTimeout),
+            backend: settings.get(SettingKey::Backend),
+            agent_ttl: settings.get(SettingKey::AgentTtl),
+            lock_timeout: settings.get(SettingKey::LockTimeout),
+            lock_wait: settings.get(SettingKey::LockWait),
+            keyfile: settings.get(SettingKey::Keyfile),
+            min_strength: settings.get(SettingKey::MinStrength),
+            read_only: settings.get(SettingKey::ReadOnly),
+            sign_as: settings.get(SettingKey::SignAs),
+            trash_days: settings.get(SettingKey::TrashDays),
+            backup_count: settings.get(SettingKey::BackupCount),
+            compress: settings.get(SettingKey::Compress),
+            generate: GenerateFile {
+                length: settings.get(SettingKey::GenerateLength),
+                symbols: settings.get(SettingKey::GenerateSymbols),
+                charset: settings.get(SettingKey::GenerateCharset),
+            },
+            stores: settings.stores,
+            askpass: settings.askpass,
+            authors: settings.authors,
+        }
+    }
+}
+
+impl ConfigSettings {
+    /// Get the value of a setting, as it is written in the config file
+    fn get(&self, key: SettingKey) -> String {
+        match key {
+            SettingKey::RemoteURL => self.remote_url.clone(),
+            SettingKey::AutoSync => self.auto_sync.to_string(),
+            SettingKey::Session => self.session.name().to_string(),
+            SettingKey::Kdf => self.kdf.name().to_string(),
+            SettingKey::ClipTimeout => self.clip_timeout.to_string(),
+            SettingKey::Backend => self.backend.name().to_string(),
+            SettingKey::AgentTtl => self.agent_ttl.to_string(),
+            SettingKey::LockTimeout => self.lock_timeout.to_string(),
+            SettingKey::LockWait => self.lock_wait.to_string(),
+            SettingKey::Keyfile => self.keyfile.as_ref().map(|p| p.display().to_string()).unwrap_or_default(),
+            SettingKey::MinStrength => self.min_strength.to_string(),
+            SettingKey::ReadOnly => self.read_only.to_string(),
+            SettingKey::SignAs => self.sign_as.clone().unwrap_or_default(),
+            SettingKey::TrashDays => self.trash_days.to_string(),
+            SettingKey::BackupCount => self.backup_count.to_string(),
+            SettingKey::Compress => self.compress.to_string(),
+            SettingKey::GenerateLength => self.generate_length.to_string(),
+            SettingKey::GenerateSymbols => self.generate_symbols.to_string(),
+            SettingKey::GenerateCharset => self.generate_charset.clone().unwrap_or_default(),
+        }
+    }
+
+    /// Set the value of a setting from a string, returning an error which describes the values
+    /// the setting accepts if it isn't valid. The setting is left as it was on error.
+    fn set(&mut self, key: SettingKey, value: &str) -> Result<()> {
+        let bad_value = || Error::BadConfigValue {
+            key: key.name().to_string(),
+            value: value.to_string(),
+            expected: key.expected(),
+        };
+
+        match key {
+            SettingKey::RemoteURL => self.remote_url = value.to_string(),
+            SettingKey::AutoSync => self.auto_sync = parse_bool(value).ok_or_else(bad_value)?,
+            SettingKey::Session => self.session = SessionStore::parse(value).ok_or_else(bad_value)?,
+            SettingKey::Kdf => self.kdf = Kdf::parse(value).ok_or_else(bad_value)?,
+            SettingKey::ClipTimeout => self.clip_timeout = parse_duration(value, 1).ok_or_else(bad_value)?,
+            SettingKey::Backend => self.backend = Backend::parse(value).ok_or_else(bad_value)?,
+            SettingKey::AgentTtl => self.agent_ttl = parse_duration(value, 1).ok_or_else(bad_value)?,
+            SettingKey::LockTimeout => self.lock_timeout = parse_duration(value, 60).ok_or_else(bad_value)?,
+            SettingKey::LockWait => self.lock_wait = parse_duration(value, 1).ok_or_else(bad_value)?,
+            SettingKey::Keyfile => self.keyfile = Some(value).filter(|p| !p.is_empty()).map(PathBuf::from),
+            SettingKey::MinStrength => {
+                self.min_strength = value.trim().parse().ok().filter(|score| *score <= 4).ok_or_else(bad_value)?
+            }
+            SettingKey::ReadOnly => self.read_only = parse_bool(value).ok_or_else(bad_value)?,
+            SettingKey::SignAs => self.sign_as = Some(value.trim()).filter(|name| !name.is_empty()).map(String::from),
+            SettingKey::TrashDays => self.trash_days = parse_duration(value, 24 * 60 * 60).ok_or_else(bad_value)?,
+            SettingKey::BackupCount => self.backup_count = value.trim().parse().ok().ok_or_else(bad_value)?,
+            SettingKey::Compress => self.compress = parse_bool(value).ok_or_else(bad_value)?,
+            SettingKey::GenerateLength => {
+                self.generate_length = value.trim().parse().ok().filter(|length| *length > 0).ok_or_else(bad_value)?
+            }
+            SettingKey::GenerateSymbols => self.generate_symbols = parse_bool(value).ok_or_else(bad_value)?,
+            SettingKey::GenerateCharset => self.generate_charset = Some(value).filter(|c| !c.is_empty()).map(String::from),
+        }
+        Ok(())
+    }
+}
+
+/// Parses a duration into a whole number of units, each `unit` seconds long. A plain number is
+/// already in units, and a number followed by s, m, h, or d is converted, so "1h" is 60 when the
+/// unit is a minute. Durations which aren't a whole number of units are rejected.
+fn parse_duration(value: &str, unit: u64) -> Option<u64> {
+    let value = value.trim();
+    let (number, multiplier) = match value.char_indices().last()? {
+        (i, 's') => (&value[..i], 1),
+        (i, 'm') => (&value[..i], 60),
+        (i, 'h') => (&value[..i], 60 * 60),
+        (i, 'd') => (&value[..i], 24 * 60 * 60),
+        _ => (value, unit),
+    };
+
+    let seconds = number.trim().parse::<u64>().ok()?.checked_mul(multiplier)?;
+    Some(seconds / unit).filter(|_| seconds % unit == 0)
+}
+
+/// Flattens the settings in a YAML mapping into their dotted names and values as strings, so
+/// nested sections and dotted names can be mixed
+fn flatten(prefix: &str, mapping: serde_yaml::Mapping, values: &mut Vec<(String, String)>) -> Result<()> {
+    for (name, value) in mapping {
+        let name = match name {
+            serde_yaml::Value::String(name) => format!("{}{}", prefix, name),
+            name => format!("{}{}", prefix, serde_yaml::to_string(&name)?.trim()),
+        };
+        match value {
+            serde_yaml::Value::Mapping(section) => flatten(format!("{}.", name).as_str(), section, values)?,
+            serde_yaml::Value::String(value) => values.push((name, value)),
+            serde_yaml::Value::Null => values.push((name, String::new())),
+            value => values.push((name, serde_yaml::to_string(&value)?.trim().to_string())),
+        }
+    }
+    Ok(())
+}
+
+/// Parses a boolean, accepting true, yes, or on and false, no, or off
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.trim().to_lowercase().as_str() {
+        "true" | "yes" | "on" => Some(true),
+        "false" | "no" | "off" => Some(false),
+        _ => None,
+    }
 }
 
 /// SettingKeys represent valid settings that can be updated by the user. These are parsed from a
 /// string in the ExecutableCommand implementation for ConfigCommand.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub enum SettingKey {
     RemoteURL,
+    AutoSync,
     Session,
+    Kdf,
+    ClipTimeout,
+    Backend,
+    AgentTtl,
+    LockTimeout,
+    LockWait,
+    Keyfile,
+    MinStrength,
+    ReadOnly,
+    SignAs,
+    TrashDays,
+    BackupCount,
+    Compress,
+    GenerateLength,
+    GenerateSymbols,
+    GenerateCharset,
 }
 
 impl SettingKey {
+    /// Every setting, in the order they appear in the config file
+    pub const ALL: [SettingKey; 19] = [
+        SettingKey::RemoteURL, SettingKey::AutoSync, SettingKey::Session, SettingKey::Kdf,
+        SettingKey::ClipTimeout, SettingKey::Backend, SettingKey::AgentTtl, SettingKey::LockTimeout,
+        SettingKey::LockWait, SettingKey::Keyfile, SettingKey::MinStrength, SettingKey::ReadOnly, SettingKey::SignAs,
+        SettingKey::TrashDays, SettingKey::BackupCount, SettingKey::Compress, SettingKey::GenerateLength, SettingKey::GenerateSymbols, SettingKey::GenerateCharset,
+    ];
+
     /// parse will create a SettingKey if the given string is valid. If the string does not
     /// correspond to a key, then a None option is returned.
     pub fn parse(setting: &str) -> Option<Self> {
-        match setting {
-            "remote_url" => Some(SettingKey::RemoteURL),
-            "session" => Some(SettingKey::Session),
-            _ => None
+        SettingKey::ALL.into_iter().find(|key| key.name() == setting)
+    }
+
+    /// Returns the name of the setting, as used in the config file and on the command line
+    pub fn name(&self) -> &'static str {
+        match self {
+            SettingKey::RemoteURL => "remote_url",
+            SettingKey::AutoSync => "auto_sync",
+            SettingKey::Session => "session",
+            SettingKey::Kdf => "kdf",
+            SettingKey::ClipTimeout => "clip_timeout",
+            SettingKey::Backend => "backend",
+            SettingKey::AgentTtl => "agent_ttl",
+            SettingKey::LockTimeout => "lock_timeout",
+            SettingKey::LockWait => "lock_wait",
+            SettingKey::Keyfile => "keyfile",
+            SettingKey::MinStrength => "min_strength",
+            SettingKey::ReadOnly => "read_only",
+            SettingKey::SignAs => "sign_as",
+            SettingKey::TrashDays => "trash_days",
+            SettingKey::BackupCount => "backup_count",
+            SettingKey::Compress => "compress",
+            SettingKey::GenerateLength => "generate.length",
+            SettingKey::GenerateSymbols => "generate.symbols",
+            SettingKey::GenerateCharset => "generate.charset",
+        }
+    }
+
+    /// Checks whether the setting holds something which differs between machines, such as a
+    /// path, so it can't be set in a vault's synced config file, see `Configuration::with_vault`
+    pub fn is_local(&self) -> bool {
+        matches!(self, SettingKey::Keyfile)
+    }
+
+    /// Describes the values the setting accepts, for errors about invalid values
+    pub fn expected(&self) -> &'static str {
+        match self {
+            SettingKey::RemoteURL => "a git remote URL",
+            SettingKey::Session => "agent, keychain, or none",
+            SettingKey::Kdf => "pbkdf2 or argon2id",
+            SettingKey::ClipTimeout | SettingKey::AgentTtl | SettingKey::LockWait => "a number of seconds, or a duration such as 30s, 15m, or 1h",
+            SettingKey::Backend => "files or sqlite",
+            SettingKey::LockTimeout => "a number of minutes, or a duration such as 15m or 1h",
+            SettingKey::Keyfile => "a path to a keyfile",
+            SettingKey::MinStrength => "a strength score from 0 to 4",
+            SettingKey::SignAs => "the name to sign entries as",
+            SettingKey::TrashDays => "a number of days, or 0 to keep deleted entries until the trash is emptied",
+            SettingKey::BackupCount => "a number of backups, or 0 to turn automatic backups off",
+            SettingKey::GenerateLength => "a number of characters greater than zero",
+            SettingKey::AutoSync | SettingKey::ReadOnly | SettingKey::Compress | SettingKey::GenerateSymbols => "true or false",
+            SettingKey::GenerateCharset => "the characters generated passwords are made of",
         }
     }
+
+    /// Returns the environment variable which overrides the setting, such as YAP_REMOTE_URL or
+    /// YAP_GENERATE_LENGTH
+    pub fn env_var(&self) -> String {
+        format!("{}{}", SETTING_VAR_PREFIX, self.name().replace('.', "_").to_uppercase())
+    }
 }
 
 /// Configuration contains the config object (ConfigSettings) and a path to the location that the
 /// configuration was loaded from. This enables multiple key vaults since each vault requires its
 /// own settings, which may be different from the user's default.
+///
+/// Settings can be overridden by a vault's own config file, see `with_vault`, by environment
+/// variables, so CI jobs and containers can configure yap without a config file, and by command
+/// line flags. Overrides are read along with the file, but never saved to it.
 pub struct Configuration {
+    /// The settings as they are saved to the file
     settings: ConfigSettings,
+    /// The settings with overrides applied
+    current: ConfigSettings,
+    /// Where each overridden setting was overridden, either a vault config file, a variable, or a
+    /// flag
+    sources: HashMap<SettingKey, String>,
+    /// The settings overridden by environment variables, as they were when the config was read
+    env: Vec<(SettingKey, String)>,
     store: PathBuf,
 }
 
 impl Configuration {
-    /// Sets up required files and settings in the specified directory.
+    /// Creates a Configuration from the settings saved in a file, applying the overrides from
+    /// environment variables, see `env_overrides`, and from command line flags.
+    fn new(settings: ConfigSettings, store: PathBuf, env: Vec<(SettingKey, String)>) -> Result<Configuration> {
+        let mut config = Configuration { current: settings.clone(), settings, sources: HashMap::new(), env, store };
+        config.override_from_env()?;
+        Ok(config)
+    }
+
+    /// Merges the settings in a vault's own config file, `.config.yaml` in the vault directory,
+    /// so each store can have its own remote, clip timeout, and so on. The file only lists the
+    /// settings it overrides, and takes precedence over the user's config file. It is synced along
+    /// with the vault, so settings which differ between machines, see `SettingKey::is_local`,
+    /// go in the vault's local config file, `.local.yaml`, instead, which is never synced and
+    /// takes precedence over both. Environment variables and flags still take precedence over
+    /// all of them. Sections such as `generate` may be nested, or written as dotted names such as
+    /// `generate.length`.
+    pub fn with_vault(mut self, vault_dir: &Path) -> Result<Configuration> {
+        let mut values = vec![];
+        for (file, local) in [(VAULT_CONFIG_FILE, false), (LOCAL_CONFIG_FILE, true)] {
+            let path = vault_dir.join(file);
+            let file: serde_yaml::Mapping = match File::open(path.as_path()) {
+                Ok(f) => serde_yaml::from_reader(f)?,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+
+            let mut file_values = vec![];
+            flatten("", file, &mut file_values)?;
+            for (name, value) in file_values {
+                let key = SettingKey::parse(name.as_str()).ok_or(Error::BadConfigKey { key: name })?;
+                if key.is_local() && !local {
+                    return Err(Error::LocalSetting { key: key.name().to_string(), path: path.display().to_string() });
+                }
+                values.push((key, value, path.display().to_string()));
+            }
+        }
+        if values.is_empty() {
+            return Ok(self);
+        }
+
+        self.current = self.settings.clone();
+        self.sources.clear();
+        for (key, value, source) in values {
+            self.apply(key, value.as_str(), source)?;
+        }
+        self.override_from_env()?;
+        Ok(self)
+    }
+
+    /// Applies the settings overridden by environment variables, see `SettingKey::env_var`, and
+    /// then those overridden by command line flags, see `override_with_flags`
+    fn override_from_env(&mut self) -> Result<()> {
+        for (key, value) in self.env.clone() {
+            self.apply(key, value.as_str(), key.env_var())?;
+        }
+        for (key, value) in FLAG_OVERRIDES.get().into_iter().flatten() {
+            self.apply(*key, value.as_str(), format!("--{}", key.name().replace('_', "-")))?;
+        }
+        Ok(())
+    }
+
+    /// Overrides a setting without saving it, recording where the value came from. An invalid
+    /// value is reported along with where it came from.
+    fn apply(&mut self, key: SettingKey, value: &str, source: String) -> Result<()> {
+        self.current.set(key, value).map_err(|_| Error::BadConfigValue {
+            key: format!("{} ({})", key.name(), source),
+            value: value.to_string(),
+            expected: key.expected(),
+        })?;
+        self.sources.insert(key, source);
+        Ok(())
+    }
+
+    /// Sets up required files and settings in the specified directory. An existing config file
+    /// is kept, so settings such as `kdf` can be chosen before initializing a vault.
     pub fn init(p: PathBuf) -> Result<()> {
         if !p.as_path().exists() {
             std::fs::create_dir_all(p.as_path())?;
         }
 
-        let f = File::create(p.join(CONFIG_FILE).as_path())?;
-        serde_yaml::to_writer(f, &ConfigSettings::default())?;
+        let config_file = p.join(CONFIG_FILE);
+        if !config_file.as_path().exists() {
+            vault::atomic_write(config_file.as_path(), serde_yaml::to_string(&ConfigSettings::default())?.as_bytes())?;
+        }
         Ok(())
     }
 
     /// read will look for a config file in the specified directory and parse it into a Configuration
     pub fn read(dir: PathBuf) -> Result<Configuration> {
+        Configuration::read_with_env(dir, env_overrides())
+    }
+
+    /// Reads the config file in the specified directory, overriding it with the given settings
+    /// instead of the environment variables
+    fn read_with_env(dir: PathBuf, env: Vec<(SettingKey, String)>) -> Result<Configuration> {
         let store = dir.join(CONFIG_FILE);
         let f = File::open(store.as_path())?;
 
         let settings: ConfigSettings = serde_yaml::from_reader(f)?;
 
-        Ok(Configuration { settings, store })
+        Configuration::new(settings, store, env)
     }
 
-    /// Get the config value for the given key
-    pub fn get_key(&self, key: SettingKey) -> &str {
-        match key {
-            SettingKey::RemoteURL => self.settings.remote_url.as_str(),
-            SettingKey::Session => self.settings.session.as_str()
+    /// Get the config value for the given key, taking environment variable overrides into
+    /// account
+    pub fn get_key(&self, key: SettingKey) -> String {
+        self.current.get(key)
+    }
+
+    /// Set the config value for the given key to the given value, if it is valid for the key.
+    /// The value is saved to the file even if the setting is overridden, and is used once the
+    /// override is removed.
+    pub fn set_key(&mut self, key: SettingKey, value: &str) -> Result<()> {
+        self.settings.set(key, value)?;
+        self.current.set(key, value)?;
+        self.sources.remove(&key);
+        Ok(())
+    }
+
+    /// Revert the config value for the given key to its default
+    pub fn unset_key(&mut self, key: SettingKey) {
+        let default = ConfigSettings::default();
+        self.set_key(key, default.get(key).as_str()).expect("default settings are valid");
+    }
+
+    /// Get the default value for the given key
+    pub fn default_key(key: SettingKey) -> String {
+        ConfigSettings::default().get(key)
+    }
+
+    /// Get where the given key is overridden, either the path of a vault config file or the name
+    /// of an environment variable. None means the value is from the user's config file.
+    pub fn source(&self, key: SettingKey) -> Option<&str> {
+        self.sources.get(&key).map(|source| source.as_str())
+    }
+
+    /// Lists every setting with its current value and default, marking the ones which are
+    /// overridden by a vault config file or an environment variable
+    pub fn listing(&self) -> String {
+        SettingKey::ALL.into_iter()
+            .map(|key| {
+                let mut line = format!("{} = {:?} (default {:?})", key.name(), self.get_key(key), Configuration::default_key(key));
+                if let Some(source) = self.source(key) {
+                    line.push_str(format!(", set by {}", source).as_str());
+                }
+                line
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Get the URL of the git remote which vaults are synced with.
+    pub fn remote_url(&self) -> &str {
+        self.current.remote_url.as_str()
+    }
+
+    /// Get whether commands which change the vault push it to the remote afterwards.
+    pub fn auto_sync(&self) -> bool {
+        self.current.auto_sync
+    }
+
+    /// Get the key derivation function to use for new vaults, with its default parameters.
+    pub fn kdf(&self) -> Kdf {
+        self.current.kdf
+    }
+
+    /// Get the backend to use for new vaults.
+    pub fn backend(&self) -> Backend {
+        self.current.backend
+    }
+
+    /// Get where the keys of unlocked vaults are cached between commands.
+    pub fn session(&self) -> SessionStore {
+        self.current.session
+    }
+
+    /// Get the number of seconds after which copied secrets are cleared from the clipboard. Zero
+    /// means that the clipboard is never cleared.
+    pub fn clip_timeout(&self) -> u64 {
+        self.current.clip_timeout
+    }
+
+    /// Get the number of seconds the agent caches a vault's key after it is derived.
+    pub fn agent_ttl(&self) -> u64 {
+        self.current.agent_ttl
+    }
+
+    /// Get the number of minutes a vault can go unused before its session is locked. Zero means
+    /// that sessions are never locked for inactivity.
+    pub fn lock_timeout(&self) -> u64 {
+        self.current.lock_timeout
+    }
+
+    /// Get the number of seconds to wait for another yap which is writing to the vault before
+    /// giving up, see `lock::lock`
+    pub fn lock_wait(&self) -> u64 {
+        self.current.lock_wait
+    }
+
+    /// Get the path of the keyfile which protects the vault along with the master password, if
+    /// one is set. Each vault records its own in its local config file, see `set_local_key`.
+    pub fn keyfile(&self) -> Option<PathBuf> {
+        self.current.keyfile.clone()
+    }
+
+    /// Get the strength score from 0 to 4 which passwords should have, below which the user is
+    /// warned before a password is saved.
+    pub fn min_strength(&self) -> u8 {
+        self.current.min_strength
+    }
+
+    /// Get whether vaults are opened read-only, so entries can be read but not changed. A vault's
+    /// own config file can set this for just that store, see `with_vault`.
+    pub fn read_only(&self) -> bool {
+        self.current.read_only
+    }
+
+    /// Get the name entries are signed as, or None if they aren't signed. Entries are signed with
+    /// the key in ~/.yap-sign.key, see `sign::SigningVault`.
+    pub fn sign_as(&self) -> Option<&str> {
+        self.current.sign_as.as_deref()
+    }
+
+    /// Get the number of days deleted entries are kept in the trash before they are removed for
+    /// good. Zero means that the trash is only emptied by hand.
+    pub fn trash_days(&self) -> u64 {
+        self.current.trash_days
+    }
+
+    /// Get the number of automatic backups kept in the vault's .backups directory, taken before
+    /// entries are overwritten, deleted, or re-encrypted. Zero turns automatic backups off, see
+    /// `autobackup::BackupVault`.
+    pub fn backup_count(&self) -> usize {
+        self.current.backup_count
+    }
+
+    /// Get whether large entries and attachments are compressed before they are encrypted, see
+    /// `Aes256GcmEngine::with_compression`. Compressed entries can be read either way.
+    pub fn compress(&self) -> bool {
+        self.current.compress
+    }
+
+    /// Get the policy for generated passwords. Flags given to the generate command take precedence
+    /// over it.
+    pub fn password_policy(&self) -> PasswordPolicy {
+        PasswordPolicy {
+            length: self.current.generate_length,
+            symbols: self.current.generate_symbols,
+            charset: self.current.generate_charset.clone(),
+            ..PasswordPolicy::default()
         }
     }
 
-    /// Set the config value for the given key to the given value.
-    pub fn set_key(&mut self, key: SettingKey, value: String) {
-        match key {
-            SettingKey::RemoteURL => self.settings.remote_url = value.clone(),
-            SettingKey::Session => self.settings.session = value.clone(),
+    /// Get the path of the store registered under a name, if there is one.
+    pub fn store_path(&self, name: &str) -> Option<PathBuf> {
+        self.settings.stores.get(name).map(PathBuf::from)
+    }
+
+    /// Get the names of the registered stores along with their paths, sorted by name.
+    pub fn stores(&self) -> &BTreeMap<String, String> {
+        &self.settings.stores
+    }
+
+    /// Registers a store under a name, so it can be given to --store instead of its path. The
+    /// path is made absolute, so the name works from any directory. Names can't contain path
+    /// separators, so they are never mistaken for paths.
+    pub fn add_store(&mut self, name: &str, path: &str) -> Result<()> {
+        if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+            return Err(Error::BadStoreName { name: name.to_string() });
         }
+
+        let path = match path.strip_prefix("~/") {
+            Some(rest) => home::home_dir().ok_or(Error::NoHomeDir)?.join(rest),
+            None => std::env::current_dir()?.join(path),
+        };
+        self.settings.stores.insert(name.to_string(), path.display().to_string());
+        Ok(())
+    }
+
+    /// Removes the store registered under a name. The vault in the store is left as it is.
+    pub fn remove_store(&mut self, name: &str) -> Result<()> {
+        self.settings.stores.remove(name)
+            .map(|_| ())
+            .ok_or_else(|| Error::StoreNotFound { name: name.to_string() })
+    }
+
+    /// Get the rules mapping askpass prompts to entries, in the order they are tried.
+    pub fn askpass_rules(&self) -> &[AskpassRule] {
+        &self.settings.askpass
+    }
+
+    /// Adds a rule mapping askpass prompts which match the pattern to an entry. Rules are tried
+    /// in the order they were added, and a rule with the same pattern is replaced in place.
+    pub fn add_askpass_rule(&mut self, pattern: &str, entry: &str) -> Result<()> {
+        let rule = AskpassRule::new(pattern, entry)?;
+        match self.settings.askpass.iter_mut().find(|rule| rule.pattern == pattern) {
+            Some(existing) => *existing = rule,
+            None => self.settings.askpass.push(rule),
+        }
+        Ok(())
+    }
+
+    /// Removes the askpass rule with the pattern.
+    pub fn remove_askpass_rule(&mut self, pattern: &str) -> Result<()> {
+        let count = self.settings.askpass.len();
+        self.settings.askpass.retain(|rule| rule.pattern != pattern);
+        if self.settings.askpass.len() == count {
+            return Err(Error::AskpassRuleNotFound { pattern: pattern.to_string() });
+        }
+        Ok(())
+    }
+
+    /// Get the public keys of the authors whose signatures are trusted, in base64, by name.
+    pub fn authors(&self) -> &BTreeMap<String, String> {
+        &self.settings.authors
+    }
+
+    /// Trusts entries signed by the author with the public key, as printed by yap sign-key on
+    /// their machine. An author with the same name is replaced.
+    pub fn add_author(&mut self, name: &str, key: &str) -> Result<()> {
+        let key = key.trim();
+        if name.is_empty() || !STANDARD.decode(key).is_ok_and(|bytes| bytes.len() == 32) {
+            return Err(Error::BadAuthorKey { name: name.to_string() });
+        }
+        self.settings.authors.insert(name.to_string(), key.to_string());
+        Ok(())
+    }
+
+    /// Stops trusting the author's signatures
+    pub fn remove_author(&mut self, name: &str) -> Result<()> {
+        self.settings.authors.remove(name)
+            .map(|_| ())
+            .ok_or_else(|| Error::AuthorNotFound { name: name.to_string() })
     }
 
     /// Saves the Configuration into the default location.
     pub fn save(&self) -> Result<()> {
-        let f = File::create(self.store.as_path())?;
-        Ok(serde_yaml::to_writer(f, &self.settings)?)
+        vault::atomic_write(self.store.as_path(), serde_yaml::to_string(&self.settings)?.as_bytes())
     }
 }
 
@@ -100,8 +777,51 @@ pub fn read() -> Result<Configuration> {
     Configuration::read(get_default_path()?)
 }
 
+/// Reads a Configuration from the default directory, using the default settings if there is no
+/// config file. Vaults can be used without one, such as with --store.
+pub fn read_or_default() -> Result<Configuration> {
+    let dir = get_default_path()?;
+    match Configuration::read(dir.clone()) {
+        Err(Error::StdIO(e)) if e.kind() == std::io::ErrorKind::NotFound => {
+            Configuration::new(ConfigSettings::default(), dir.join(CONFIG_FILE), env_overrides())
+        }
+        result => result,
+    }
+}
+
+/// Returns the settings which are overridden by environment variables, along with their values,
+/// see `SettingKey::env_var`
+fn env_overrides() -> Vec<(SettingKey, String)> {
+    SettingKey::ALL.into_iter()
+        .filter_map(|key| std::env::var(key.env_var()).ok().map(|value| (key, value)))
+        .collect()
+}
+
+/// Overrides settings in every config read afterwards, as asked for by command line flags such as
+/// --read-only. Unlike environment variables, these aren't passed on to the programs yap runs.
+/// Only the first call has any effect, since flags are only parsed once.
+pub fn override_with_flags(overrides: Vec<(SettingKey, String)>) {
+    let _ = FLAG_OVERRIDES.set(overrides);
+}
+
+/// Saves a setting in the local config file of the vault in `vault_dir`, see
+/// `Configuration::with_vault`, so it only applies to this copy of that store. The file's other
+/// settings are kept as they are.
+pub fn set_local_key(vault_dir: &Path, key: SettingKey, value: &str) -> Result<()> {
+    ConfigSettings::default().set(key, value)?;
+
+    let path = vault_dir.join(LOCAL_CONFIG_FILE);
+    let mut file: serde_yaml::Mapping = match File::open(path.as_path()) {
+        Ok(f) => serde_yaml::from_reader(f)?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => serde_yaml::Mapping::new(),
+        Err(e) => return Err(e.into()),
+    };
+    file.insert(key.name().into(), value.into());
+    vault::atomic_write(path.as_path(), serde_yaml::to_string(&file)?.as_bytes())
+}
+
 /// Creates a PathBuf to the default config location
-fn get_default_path() -> Result<PathBuf> {
+pub(crate) fn get_default_path() -> Result<PathBuf> {
     let home_dir = home::home_dir().ok_or(Error::NoHomeDir)?;
     let p = home_dir.join(Path::new(YAP_DIR));
     Ok(p)
@@ -119,26 +839,173 @@ pub enum ConfigCommand {
         key: String,
         value: String,
     },
+
+    /// Revert the given setting to its default value
+    Unset {
+        key: String,
+    },
+
+    /// List every setting with its current and default values
+    List,
+
+    /// Register stores by name, so --store can be given the name instead of a path
+    Store {
+        #[command(subcommand)]
+        command: StoreCommand,
+    },
+
+    /// Map the prompts yap askpass is given to the entries holding their secrets
+    Askpass {
+        #[command(subcommand)]
+        command: AskpassCommand,
+    },
+
+    /// Choose whose signatures are trusted, by the public keys they print with yap sign-key
+    Author {
+        #[command(subcommand)]
+        command: AuthorCommand,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum StoreCommand {
+    /// Register the store at a path under a name
+    Add {
+        name: String,
+        path: String,
+    },
+
+    /// Remove a registered store, leaving its vault as it is
+    Rm {
+        name: String,
+    },
+
+    /// List the registered stores with their paths
+    List,
+}
+
+#[derive(Subcommand)]
+pub enum AskpassCommand {
+    /// Map prompts matching a regular expression to an entry. The entry name can use groups
+    /// captured by the pattern, such as $1
+    Add {
+        pattern: String,
+        entry: String,
+    },
+
+    /// Remove the rule with a pattern
+    Rm {
+        pattern: String,
+    },
+
+    /// List the rules in the order they are tried
+    List,
+}
+
+#[derive(Subcommand)]
+pub enum AuthorCommand {
+    /// Trust entries signed by an author with a public key
+    Add {
+        name: String,
+        key: String,
+    },
+
+    /// Stop trusting an author's signatures
+    Rm {
+        name: String,
+    },
+
+    /// List the trusted authors with their public keys
+    List,
 }
 
 impl ExecutableCommand for ConfigCommand {
-    fn execute(self) -> std::result::Result<String, String> {
+    fn execute(self) -> Result<String> {
         match self {
             ConfigCommand::Get { key } => {
                 let config = read()?;
                 let setting_key = SettingKey::parse(key.as_str())
-                    .ok_or(String::from(Error::BadConfigKey { key }))?;
+                    .ok_or(Error::BadConfigKey { key })?;
 
                 Ok(config.get_key(setting_key).to_string())
             }
             ConfigCommand::Set { key, value } => {
                 let mut config = read()?;
                 let setting_key = SettingKey::parse(key.as_str())
-                    .ok_or(String::from(Error::BadConfigKey { key }))?;
+                    .ok_or(Error::BadConfigKey { key })?;
 
-                config.set_key(setting_key, value);
+                config.set_key(setting_key, value.as_str())?;
 
-                Ok(config.save().map(|_| "Successfully updated config.\n".to_string())?)
+                config.save()?;
+                Ok("Successfully updated config.\n".to_string())
+            }
+            ConfigCommand::Unset { key } => {
+                let mut config = read()?;
+                let setting_key = SettingKey::parse(key.as_str())
+                    .ok_or(Error::BadConfigKey { key })?;
+
+                config.unset_key(setting_key);
+
+                config.save()?;
+                Ok(format!("Reverted {} to its default.", setting_key.name()))
+            }
+            ConfigCommand::List => Ok(read_or_default()?.listing()),
+            ConfigCommand::Store { command: StoreCommand::Add { name, path } } => {
+                let mut config = read()?;
+                config.add_store(name.as_str(), path.as_str())?;
+                config.save()?;
+                Ok(format!("Successfully added store {}", name))
+            }
+            ConfigCommand::Store { command: StoreCommand::Rm { name } } => {
+                let mut config = read()?;
+                config.remove_store(name.as_str())?;
+                config.save()?;
+                Ok(format!("Successfully removed store {}", name))
+            }
+            ConfigCommand::Store { command: StoreCommand::List } => {
+                let config = read_or_default()?;
+                Ok(config.stores().iter()
+                    .map(|(name, path)| format!("{}\t{}", name, path))
+                    .collect::<Vec<_>>()
+                    .join("\n"))
+            }
+            ConfigCommand::Askpass { command: AskpassCommand::Add { pattern, entry } } => {
+                let mut config = read()?;
+                config.add_askpass_rule(pattern.as_str(), entry.as_str())?;
+                config.save()?;
+                Ok(format!("Successfully added askpass rule for {}", pattern))
+            }
+            ConfigCommand::Askpass { command: AskpassCommand::Rm { pattern } } => {
+                let mut config = read()?;
+                config.remove_askpass_rule(pattern.as_str())?;
+                config.save()?;
+                Ok(format!("Successfully removed askpass rule for {}", pattern))
+            }
+            ConfigCommand::Askpass { command: AskpassCommand::List } => {
+                let config = read_or_default()?;
+                Ok(config.askpass_rules().iter()
+                    .map(|rule| format!("{}\t{}", rule.pattern, rule.entry))
+                    .collect::<Vec<_>>()
+                    .join("\n"))
+            }
+            ConfigCommand::Author { command: AuthorCommand::Add { name, key } } => {
+                let mut config = read()?;
+                config.add_author(name.as_str(), key.as_str())?;
+                config.save()?;
+                Ok(format!("Successfully added author {}", name))
+            }
+            ConfigCommand::Author { command: AuthorCommand::Rm { name } } => {
+                let mut config = read()?;
+                config.remove_author(name.as_str())?;
+                config.save()?;
+                Ok(format!("Successfully removed author {}", name))
+            }
+            ConfigCommand::Author { command: AuthorCommand::List } => {
+                let config = read_or_default()?;
+                Ok(config.authors().iter()
+                    .map(|(name, key)| format!("{}\t{}", name, key))
+                    .collect::<Vec<_>>()
+                    .join("\n"))
             }
         }
     }
@@ -146,30 +1013,168 @@ impl ExecutableCommand for ConfigCommand {
 
 #[cfg(test)]
 mod test {
-    use std::path::Path;
-    use crate::global::CONFIG_FILE;
+    use std::path::{Path, PathBuf};
+    use crate::error::Error;
+    use crate::global::{CONFIG_FILE, LOCAL_CONFIG_FILE, VAULT_CONFIG_FILE};
     use crate::config::{Configuration, SettingKey};
+    use crate::session::SessionStore;
 
     #[test]
-    fn init_set_get_save_values() {
-        let yap_test = Path::new(".yap_test");
+    fn settings_are_saved_and_read_back() {
+        let yap_test = Path::new(".yap_test_config_saved");
         Configuration::init(yap_test.to_path_buf()).unwrap();
         assert!(yap_test.join(CONFIG_FILE).exists());
 
-        let mut test_config = Configuration::read(yap_test.to_path_buf()).unwrap();
+        let mut test_config = Configuration::read_with_env(yap_test.to_path_buf(), vec![]).unwrap();
+        test_config.set_key(SettingKey::Session, "none").unwrap();
+        assert_eq!(test_config.get_key(SettingKey::Session), "none");
+        test_config.set_key(SettingKey::RemoteURL, "test remote url").unwrap();
+        assert_eq!(test_config.get_key(SettingKey::RemoteURL), "test remote url");
+        test_config.set_key(SettingKey::LockTimeout, "1h").unwrap();
+        test_config.set_key(SettingKey::GenerateLength, "32").unwrap();
+        test_config.set_key(SettingKey::GenerateCharset, "abc").unwrap();
+
+        test_config.save().unwrap();
+        let test_config = Configuration::read_with_env(yap_test.to_path_buf(), vec![]).unwrap();
+        assert_eq!(test_config.get_key(SettingKey::Session), "none");
+        assert_eq!(test_config.session(), SessionStore::None);
+        assert_eq!(test_config.lock_timeout(), 60);
+        assert_eq!(test_config.password_policy().length, 32);
+        assert_eq!(test_config.password_policy().charset.as_deref(), Some("abc"));
+        assert_eq!(test_config.get_key(SettingKey::RemoteURL), "test remote url");
+
+        std::fs::remove_dir_all(yap_test).unwrap();
+    }
+
+    #[test]
+    fn values_are_validated() {
+        let yap_test = Path::new(".yap_test_config_validated");
+        Configuration::init(yap_test.to_path_buf()).unwrap();
+        let mut test_config = Configuration::read_with_env(yap_test.to_path_buf(), vec![]).unwrap();
+
+        // Durations can be given with units, as long as they are a whole number of units
+        assert!(test_config.set_key(SettingKey::Session, "sometimes").is_err());
+        assert!(test_config.set_key(SettingKey::ClipTimeout, "soon").is_err());
+        assert!(test_config.set_key(SettingKey::LockTimeout, "90s").is_err());
+        test_config.set_key(SettingKey::LockTimeout, "1h").unwrap();
+        assert_eq!(test_config.lock_timeout(), 60);
+        test_config.set_key(SettingKey::AgentTtl, "15m").unwrap();
+        assert_eq!(test_config.get_key(SettingKey::AgentTtl), "900");
+        assert!(test_config.set_key(SettingKey::GenerateLength, "0").is_err());
+        assert!(test_config.set_key(SettingKey::MinStrength, "5").is_err());
+        assert_eq!(SettingKey::GenerateLength.env_var(), "YAP_GENERATE_LENGTH");
+
+        std::fs::remove_dir_all(yap_test).unwrap();
+    }
+
+    #[test]
+    fn settings_are_reverted_to_their_defaults() {
+        let yap_test = Path::new(".yap_test_config_reverted");
+        Configuration::init(yap_test.to_path_buf()).unwrap();
+        let mut test_config = Configuration::read_with_env(yap_test.to_path_buf(), vec![]).unwrap();
+
+        test_config.set_key(SettingKey::Session, "none").unwrap();
+        test_config.set_key(SettingKey::RemoteURL, "test remote url").unwrap();
+        test_config.unset_key(SettingKey::Session);
+        assert_eq!(test_config.session(), SessionStore::Agent);
+        assert!(test_config.listing().contains("remote_url = \"test remote url\" (default \"\")"));
+
+        std::fs::remove_dir_all(yap_test).unwrap();
+    }
+
+    #[test]
+    fn environment_variables_override_the_file() {
+        let yap_test = Path::new(".yap_test_config_env");
+        Configuration::init(yap_test.to_path_buf()).unwrap();
+
+        // Overrides aren't saved to the file
+        let env = vec![(SettingKey::ClipTimeout, "10".to_string())];
+        let test_config = Configuration::read_with_env(yap_test.to_path_buf(), env.clone()).unwrap();
+        assert_eq!(test_config.clip_timeout(), 10);
+        assert_eq!(test_config.source(SettingKey::ClipTimeout), Some("YAP_CLIP_TIMEOUT"));
+        test_config.save().unwrap();
+        assert_eq!(Configuration::read_with_env(yap_test.to_path_buf(), vec![]).unwrap().clip_timeout(), 45);
+
+        // Invalid overrides are reported along with the variable
+        let env = vec![(SettingKey::ClipTimeout, "soon".to_string())];
+        assert!(Configuration::read_with_env(yap_test.to_path_buf(), env).is_err());
+
+        std::fs::remove_dir_all(yap_test).unwrap();
+    }
+
+    #[test]
+    fn vault_config_overrides_the_users() {
+        let yap_test = Path::new(".yap_test_config_vault");
+        Configuration::init(yap_test.to_path_buf()).unwrap();
+        let mut test_config = Configuration::read_with_env(yap_test.to_path_buf(), vec![]).unwrap();
+        test_config.set_key(SettingKey::LockTimeout, "1h").unwrap();
+        test_config.save().unwrap();
 
-        let test_session = String::from("test session");
-        test_config.set_key(SettingKey::Session, test_session.clone());
-        assert_eq!(test_config.get_key(SettingKey::Session), test_session);
+        let vault_dir = yap_test.join("vault");
+        std::fs::create_dir_all(vault_dir.as_path()).unwrap();
+        std::fs::write(vault_dir.join(VAULT_CONFIG_FILE), "remote_url: git@example.com:vault.git\nauto_sync: yes\nclip_timeout: 5\ngenerate:\n  symbols: false\n").unwrap();
+        let vault_config = Configuration::read_with_env(yap_test.to_path_buf(), vec![]).unwrap().with_vault(vault_dir.as_path()).unwrap();
+        assert_eq!(vault_config.remote_url(), "git@example.com:vault.git");
+        assert_eq!(vault_config.clip_timeout(), 5);
+        assert!(vault_config.auto_sync());
+        assert_eq!(vault_config.lock_timeout(), 60);
+        assert!(!vault_config.password_policy().symbols);
+        assert!(vault_config.listing().contains("clip_timeout = \"5\" (default \"45\"), set by "));
 
-        let test_url = String::from("test remote url");
-        test_config.set_key(SettingKey::RemoteURL, test_url.clone());
-        assert_eq!(test_config.get_key(SettingKey::RemoteURL), test_url);
+        // Environment variables take precedence over the vault's config file
+        let env = vec![(SettingKey::ClipTimeout, "10".to_string())];
+        let vault_config = Configuration::read_with_env(yap_test.to_path_buf(), env).unwrap().with_vault(vault_dir.as_path()).unwrap();
+        assert_eq!(vault_config.clip_timeout(), 10);
+
+        // Paths differ between machines, so they are only read from the local file, which isn't synced
+        std::fs::write(vault_dir.join(LOCAL_CONFIG_FILE), "keyfile: /keys/vault\nclip_timeout: 20\n").unwrap();
+        let vault_config = Configuration::read_with_env(yap_test.to_path_buf(), vec![]).unwrap().with_vault(vault_dir.as_path()).unwrap();
+        assert_eq!(vault_config.keyfile(), Some(PathBuf::from("/keys/vault")));
+        assert_eq!(vault_config.clip_timeout(), 20);
+        assert!(vault_config.auto_sync());
+        std::fs::write(vault_dir.join(VAULT_CONFIG_FILE), "keyfile: /keys/vault\n").unwrap();
+        let synced = Configuration::read_with_env(yap_test.to_path_buf(), vec![]).unwrap().with_vault(vault_dir.as_path());
+        assert!(matches!(synced, Err(Error::LocalSetting { .. })));
+
+        std::fs::write(vault_dir.join(VAULT_CONFIG_FILE), "clip_timeout: soon\n").unwrap();
+        assert!(Configuration::read_with_env(yap_test.to_path_buf(), vec![]).unwrap().with_vault(vault_dir.as_path()).is_err());
+
+        std::fs::remove_dir_all(yap_test).unwrap();
+    }
+
+    #[test]
+    fn stores_are_registered_by_name() {
+        let yap_test = Path::new(".yap_test_config_stores");
+        Configuration::init(yap_test.to_path_buf()).unwrap();
+        let mut test_config = Configuration::read_with_env(yap_test.to_path_buf(), vec![]).unwrap();
+
+        test_config.add_store("work", "vaults/work").unwrap();
+        assert!(test_config.add_store("a/b", "vaults/work").is_err());
+        test_config.save().unwrap();
+        let mut test_config = Configuration::read_with_env(yap_test.to_path_buf(), vec![]).unwrap();
+        assert_eq!(test_config.store_path("work"), Some(std::env::current_dir().unwrap().join("vaults/work")));
+        test_config.remove_store("work").unwrap();
+        assert!(test_config.remove_store("work").is_err());
+        assert_eq!(test_config.store_path("work"), None);
+
+        std::fs::remove_dir_all(yap_test).unwrap();
+    }
+
+    #[test]
+    fn askpass_rules_keep_their_order() {
+        let yap_test = Path::new(".yap_test_config_askpass");
+        Configuration::init(yap_test.to_path_buf()).unwrap();
+        let mut test_config = Configuration::read_with_env(yap_test.to_path_buf(), vec![]).unwrap();
 
+        test_config.add_askpass_rule(r"\[sudo\]", "sudo").unwrap();
+        test_config.add_askpass_rule("password", "other").unwrap();
+        test_config.add_askpass_rule(r"\[sudo\]", "root").unwrap();
+        assert!(test_config.add_askpass_rule("(", "broken").is_err());
         test_config.save().unwrap();
-        let test_config = Configuration::read(yap_test.to_path_buf()).unwrap();
-        assert_eq!(test_config.get_key(SettingKey::Session), test_session);
-        assert_eq!(test_config.get_key(SettingKey::RemoteURL), test_url);
+        let mut test_config = Configuration::read_with_env(yap_test.to_path_buf(), vec![]).unwrap();
+        assert_eq!(test_config.askpass_rules()[0].entry, "root");
+        test_config.remove_askpass_rule("password").unwrap();
+        assert_eq!(test_config.askpass_rules().len(), 1);
 
         std::fs::remove_dir_all(yap_test).unwrap();
     }
@@ -0,0 +1,141 @@
+use std::io::BufRead;
+
+use clap::ValueEnum;
+
+use crate::entry::Entry;
+use crate::error::Result;
+use crate::vault::Vault;
+
+/// The folder credentials for git are kept in, as `git/<host>`
+const GIT_FOLDER: &str = "git";
+
+/// Operation is the action git asks a credential helper to take
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Operation {
+    /// Print the credential for the host, if the vault has one
+    Get,
+    /// Save a credential git used successfully
+    Store,
+    /// Delete a credential the host rejected
+    Erase,
+}
+
+/// Credential is the description of a credential git sends to helpers on stdin, as `key=value`
+/// lines ending with a blank line. Only the attributes yap uses are kept.
+#[derive(Default, Debug, PartialEq, Eq)]
+pub struct Credential {
+    pub protocol: Option<String>,
+    pub host: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+impl Credential {
+    /// Reads a credential in git's format, ignoring attributes yap doesn't use
+    pub fn read(reader: impl BufRead) -> Result<Credential> {
+        let mut credential = Credential::default();
+        for line in reader.lines() {
+            let line = line?;
+            if line.is_empty() {
+                break;
+            }
+
+            let Some((key, value)) = line.split_once('=') else { continue };
+            let value = Some(value.to_string());
+            match key {
+                "protocol" => credential.protocol = value,
+                "host" => credential.host = value,
+                "username" => credential.username = value,
+                "password" => credential.password = value,
+                _ => {}
+            }
+        }
+        Ok(credential)
+    }
+
+    /// Returns the name of the entry holding the credential for the host
+    fn entry_name(&self) -> Option<String> {
+        self.host.as_deref()
+            .filter(|host| !host.is_empty())
+            .map(|host| format!("{}/{}", GIT_FOLDER, host))
+    }
+}
+
+/// Runs a credential helper operation against the vault, returning what git should read on
+/// stdout. Credentials are kept in login entries named after the host, such as `git/github.com`.
+/// Nothing is printed when the vault has no credential for the host, so git asks the next helper
+/// or the user.
+pub fn run(vault: &mut dyn Vault, operation: Operation, credential: &Credential) -> Result<String> {
+    let Some(name) = credential.entry_name() else { return Ok(String::new()) };
+    let exists = vault.has_key(name.as_str());
+
+    match operation {
+        Operation::Get if exists => {
+            let entry = vault.access_entry(name.as_str())?;
+            let mut lines = vec![];
+            if let Some(username) = entry.username.as_deref().filter(|username| !username.is_empty()) {
+                lines.push(format!("username={}", username));
+            }
+            lines.push(format!("password={}", entry.password));
+            Ok(lines.join("\n"))
+        }
+        Operation::Store => {
+            let Some(password) = credential.password.clone() else { return Ok(String::new()) };
+            let mut entry = match exists {
+                true => vault.get_entry(name.as_str())?,
+                false => Entry::new(String::new()),
+            };
+            entry.password = password;
+            entry.username = credential.username.clone().or(entry.username);
+            if let (None, Some(protocol), Some(host)) = (&entry.url, &credential.protocol, &credential.host) {
+                entry.url = Some(format!("{}://{}", protocol, host));
+            }
+            vault.set_entry(name.as_str(), &entry)?;
+            Ok(String::new())
+        }
+        // Only the credential git rejected is erased, not one for another user of the same host
+        Operation::Erase if exists => {
+            let entry = vault.get_entry(name.as_str())?;
+            if credential.username.is_none() || credential.username == entry.username {
+                vault.delete_key(name.as_str())?;
+            }
+            Ok(String::new())
+        }
+        Operation::Get | Operation::Erase => Ok(String::new()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::credential::{self, Credential, Operation};
+    use crate::crypto::Kdf;
+    use crate::vault::{self, Backend};
+
+    #[test]
+    fn credentials_are_stored_by_host() {
+        let yap_test = String::from(".yap_test_credential");
+        let mut vault = vault::create(Some(yap_test.clone()), "asdf", Kdf::default(), Backend::default(), None).unwrap();
+
+        let input = "protocol=https\nhost=github.com\nusername=alice\npassword=s3cret\nwwwauth[]=Basic\n\nignored=1\n";
+        let credential = Credential::read(input.as_bytes()).unwrap();
+        assert_eq!(credential.host.as_deref(), Some("github.com"));
+        assert_eq!(credential.password.as_deref(), Some("s3cret"));
+
+        credential::run(vault.as_mut(), Operation::Store, &credential).unwrap();
+        let entry = vault.get_entry("git/github.com").unwrap();
+        assert_eq!(entry.url.as_deref(), Some("https://github.com"));
+
+        let query = Credential::read("protocol=https\nhost=github.com\n".as_bytes()).unwrap();
+        assert_eq!(credential::run(vault.as_mut(), Operation::Get, &query).unwrap(), "username=alice\npassword=s3cret");
+        let other = Credential::read("protocol=https\nhost=gitlab.com\n".as_bytes()).unwrap();
+        assert_eq!(credential::run(vault.as_mut(), Operation::Get, &other).unwrap(), "");
+
+        let bob = Credential { username: Some("bob".to_string()), ..Credential::read("host=github.com\n".as_bytes()).unwrap() };
+        credential::run(vault.as_mut(), Operation::Erase, &bob).unwrap();
+        assert!(vault.has_key("git/github.com"));
+        credential::run(vault.as_mut(), Operation::Erase, &credential).unwrap();
+        assert!(!vault.has_key("git/github.com"));
+
+        std::fs::remove_dir_all(yap_test).unwrap();
+    }
+}
@@ -1,20 +1,190 @@
+use std::fmt::{Display, Formatter};
+use std::io::{ErrorKind, Read, Write};
 use std::num::NonZeroU32;
+use std::time::{Duration, Instant};
 
 use ring::aead::{
     Aad,
     AES_256_GCM,
     BoundKey,
+    LessSafeKey,
     Nonce,
     NONCE_LEN,
+    MAX_TAG_LEN,
     NonceSequence,
     OpeningKey,
     SealingKey,
     UnboundKey,
 };
+use ring::digest::{self, SHA256};
+use ring::hmac;
 use ring::pbkdf2::{derive, PBKDF2_HMAC_SHA256};
 use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Serialize, Deserialize};
+use tracing::debug;
+use zeroize::Zeroizing;
 
-const SALT: &[u8] = &[0u8, 1, 2, 3, 4, 5, 6, 7, 8, 9];
+use crate::error::{self, Error};
+
+/// Length in bytes of the random salt generated for each vault
+pub const SALT_LEN: usize = 16;
+
+/// Length in bytes of the keys derived by a Kdf
+pub const KEY_LEN: usize = 32;
+
+/// Number of bytes added to a payload when it is encrypted, for the nonce and tag
+pub const OVERHEAD: usize = NONCE_LEN + MAX_TAG_LEN;
+
+/// Length in bytes of an encrypted data key, see `Aes256GcmEngine::seal_enveloped`
+pub const WRAPPED_KEY_LEN: usize = KEY_LEN + OVERHEAD;
+
+/// Appended to the associated data of payloads which were compressed before they were sealed,
+/// so whether a payload is compressed is authenticated without changing its format
+const COMPRESSED_AAD: &[u8] = b"/zstd";
+
+/// The size in bytes from which payloads are compressed, such as long notes. Smaller payloads,
+/// like most passwords and every key, barely shrink.
+const COMPRESS_MIN_LEN: usize = 512;
+
+/// The zstd level payloads are compressed at, which is zstd's default
+const COMPRESS_LEVEL: i32 = 3;
+
+/// Kdf is the key derivation function used to turn a master password into
+/// an encryption key, along with the parameters it was used with. The Kdf
+/// is chosen when a vault is created and stored in the vault header, so
+/// that existing vaults keep deriving the same key even if the default
+/// parameters or the configured Kdf change later.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(tag = "algorithm", rename_all = "lowercase")]
+pub enum Kdf {
+    /// PBKDF2 with HMAC-SHA256
+    Pbkdf2 { iterations: u32 },
+
+    /// Argon2id, with memory given in KiB
+    Argon2id { memory: u32, iterations: u32, parallelism: u32 },
+}
+
+impl Kdf {
+    /// parse creates a Kdf with default parameters from its name, as used in
+    /// the `kdf` config setting. If the name is not a known Kdf, then a None
+    /// option is returned.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "pbkdf2" => Some(Kdf::Pbkdf2 { iterations: 310000 }),
+            "argon2id" => Some(Kdf::Argon2id { memory: 19456, iterations: 2, parallelism: 1 }),
+            _ => None
+        }
+    }
+
+    /// Returns the name of the Kdf, as used in the `kdf` config setting
+    pub fn name(&self) -> &'static str {
+        match self {
+            Kdf::Pbkdf2 { .. } => "pbkdf2",
+            Kdf::Argon2id { .. } => "argon2id",
+        }
+    }
+
+    /// Derives a key from the password and salt using this Kdf's algorithm
+    /// and parameters. The key is wiped from memory when it is dropped.
+    pub fn derive_key(&self, pass: &str, salt: &[u8]) -> error::Result<Zeroizing<[u8; KEY_LEN]>> {
+        // Byte buffer to store derived bytes
+        let mut key = Zeroizing::new([0u8; KEY_LEN]);
+        let start = Instant::now();
+
+        match *self {
+            Kdf::Pbkdf2 { iterations } => {
+                let iterations = NonZeroU32::new(iterations).ok_or(Error::BadKdfParams)?;
+                derive(PBKDF2_HMAC_SHA256, iterations, salt, pass.as_bytes(), key.as_mut_slice());
+            }
+            Kdf::Argon2id { memory, iterations, parallelism } => {
+                let params = argon2::Params::new(memory, iterations, parallelism, Some(KEY_LEN))
+                    .map_err(|_| Error::BadKdfParams)?;
+                argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+                    .hash_password_into(pass.as_bytes(), salt, key.as_mut_slice())
+                    .map_err(|_| Error::BadKdfParams)?;
+            }
+        }
+
+        debug!("derived a key with {} in {:?}", self.name(), start.elapsed());
+        Ok(key)
+    }
+}
+
+impl Kdf {
+    /// Measures how long deriving a key takes with this Kdf on this machine
+    pub fn time(&self) -> error::Result<Duration> {
+        let start = Instant::now();
+        self.derive_key("yap benchmark", &[0u8; SALT_LEN])?;
+        Ok(start.elapsed())
+    }
+
+    /// Returns this Kdf with its cost scaled so deriving a key takes about `target` on this
+    /// machine, but never less than the default cost. PBKDF2's iterations are scaled, while
+    /// Argon2id's memory is scaled up to `MAX_ARGON2_MEMORY`, and then its iterations.
+    pub fn calibrate(&self, target: Duration) -> error::Result<Kdf> {
+        let scale = |cost: u32, took: Duration, step: u32| {
+            let scaled = cost as f64 * target.as_secs_f64() / took.as_secs_f64().max(1e-6);
+            (scaled.min(u32::MAX as f64) as u32 / step).max(1) * step
+        };
+
+        match Kdf::parse(self.name()).unwrap_or_default() {
+            Kdf::Pbkdf2 { iterations } => {
+                let took = Kdf::Pbkdf2 { iterations }.time()?;
+                Ok(Kdf::Pbkdf2 { iterations: scale(iterations, took, 10000).max(iterations) })
+            }
+            Kdf::Argon2id { memory, iterations, parallelism } => {
+                let took = Kdf::Argon2id { memory, iterations, parallelism }.time()?;
+                let scaled_memory = scale(memory, took, 1024).clamp(memory, MAX_ARGON2_MEMORY);
+
+                let took = Kdf::Argon2id { memory: scaled_memory, iterations, parallelism }.time()?;
+                let scaled_iterations = scale(iterations, took, 1).max(iterations);
+                Ok(Kdf::Argon2id { memory: scaled_memory, iterations: scaled_iterations, parallelism })
+            }
+        }
+    }
+}
+
+/// The most memory, in KiB, which calibrating Argon2id gives it, so unlocking a vault doesn't
+/// exhaust the memory of smaller machines the vault is synced to
+const MAX_ARGON2_MEMORY: u32 = 1024 * 1024;
+
+impl Display for Kdf {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Kdf::Pbkdf2 { iterations } => write!(f, "pbkdf2 with {} iterations", iterations),
+            Kdf::Argon2id { memory, iterations, parallelism } => {
+                write!(f, "argon2id with {} KiB of memory, {} iterations, and parallelism {}", memory, iterations, parallelism)
+            }
+        }
+    }
+}
+
+impl Default for Kdf {
+    fn default() -> Self {
+        Kdf::Pbkdf2 { iterations: 310000 }
+    }
+}
+
+
+/// Mixes the contents of a keyfile into a key derived from the master password, so that both
+/// the password and the keyfile are needed to recreate the key.
+pub fn mix_keyfile(key: &[u8; KEY_LEN], keyfile: &[u8]) -> Zeroizing<[u8; KEY_LEN]> {
+    let tag = hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, key), digest::digest(&SHA256, keyfile).as_ref());
+    let mut mixed = Zeroizing::new([0u8; KEY_LEN]);
+    mixed.copy_from_slice(tag.as_ref());
+    mixed
+}
+
+/// Hashes a keyfile so the right keyfile can be recognized before any entry is decrypted. The
+/// hash is salted with the vault's salt, so it can't be matched between vaults, and is separate
+/// from the hash mixed into the key.
+pub fn keyfile_hash(salt: &[u8], keyfile: &[u8]) -> Vec<u8> {
+    let mut context = digest::Context::new(&SHA256);
+    context.update(b"yap keyfile\0");
+    context.update(salt);
+    context.update(keyfile);
+    context.finish().as_ref().to_vec()
+}
 
 
 /// Aes256GcmEngine is a high-level encryption engine. Once created, it
@@ -22,55 +192,395 @@ const SALT: &[u8] = &[0u8, 1, 2, 3, 4, 5, 6, 7, 8, 9];
 /// `encrypt_bytes` or `decrypt_bytes`, providing a conceptually simple
 /// model of symmetric encryption.
 ///
-/// An Aes256GcmEngine must be initialized with a plaintext password and
-/// salt byte slice. The encryption key passed to the SealingKey and
-/// OpeningKey is derived from the password using PBKDF2_HMAC_SHA256, in
-/// `derive_key_from_pass`. `new` may panic under catastrophic
-/// circumstances, namely if 100000 is not a valid u32 or if the system
-/// is unable to fill bytes with random values.
+/// An Aes256GcmEngine must be initialized with a plaintext password,
+/// salt byte slice, and Kdf. The salt is generated once per vault by
+/// `new_salt` and stored in the vault header along with the Kdf. The
+/// encryption key passed to the SealingKey and OpeningKey is derived from
+/// the password using the Kdf, either PBKDF2_HMAC_SHA256 or Argon2id.
+/// `new` fails if the Kdf parameters are invalid, and `with_key` may panic
+/// under catastrophic circumstances, namely if the system is unable to
+/// fill bytes with random values.
+///
+/// The key and any decrypted plaintext are wiped from memory when they
+/// are dropped, so secrets don't linger on the heap after they are used.
+///
+/// Payloads can be compressed with zstd before they are sealed, see
+/// `with_compression`. Compressed payloads are sealed with `COMPRESSED_AAD`
+/// appended to their associated data, and are decompressed whenever they
+/// are opened, whether or not compression is on.
 pub struct Aes256GcmEngine {
-    key: [u8; 32],
+    key: Zeroizing<[u8; KEY_LEN]>,
     counter: InitializedNonceSequence,
+    compress: bool,
 }
 
 impl Aes256GcmEngine {
-    pub fn new(pass: String) -> Self {
+    pub fn new(pass: &str, salt: &[u8], kdf: &Kdf) -> error::Result<Self> {
+        Ok(Self::with_key(kdf.derive_key(pass, salt)?))
+    }
+
+    /// Creates an engine from an already derived key.
+    pub fn with_key(key: Zeroizing<[u8; KEY_LEN]>) -> Self {
         Self {
-            key: derive_key_from_pass(pass, SALT),
+            key,
             counter: InitializedNonceSequence::new(new_iv().unwrap()),
+            compress: false,
         }
     }
 
-    pub fn encrypt_bytes(&self, payload: &[u8]) -> Result<Vec<u8>, ring::error::Unspecified> {
+    /// Sets whether payloads are compressed before they are encrypted, which only happens for
+    /// payloads large enough to shrink, see `COMPRESS_MIN_LEN`. Streams are always compressed
+    /// when this is set, since their length isn't known up front.
+    pub fn with_compression(mut self, compress: bool) -> Self {
+        self.compress = compress;
+        self
+    }
+
+    /// Encrypts the payload, authenticating `aad` along with it. The same associated data must
+    /// be given to decrypt the result, so ciphertext can be bound to where it is used. Large
+    /// payloads are compressed first if compression is on.
+    pub fn encrypt_bytes(&self, payload: &[u8], aad: &[u8]) -> error::Result<Vec<u8>> {
+        let compressed = self.compress(payload)?;
+        let aad = flagged(aad, compressed.is_some());
+        Ok(self.seal(compressed.as_deref().map_or(payload, Vec::as_slice), aad.as_slice())?)
+    }
+
+    /// Encrypts the payload as it is, see `encrypt_bytes`
+    fn seal(&self, payload: &[u8], aad: &[u8]) -> Result<Vec<u8>, ring::error::Unspecified> {
         let nonce_bytes = self.counter.current();
 
-        let mut sealing_key = SealingKey::new(UnboundKey::new(&AES_256_GCM, &self.key)?, self.counter);
-        let mut raw = payload.to_owned();
-        sealing_key.seal_in_place_append_tag(Aad::empty(), &mut raw)?;
+        let mut sealing_key = SealingKey::new(UnboundKey::new(&AES_256_GCM, self.key.as_slice())?, self.counter);
 
-        // Append the nonce to the beginning of the encrypted bytes
-        let mut data = nonce_bytes.to_vec();
-        data.append(&mut raw);
+        // Seal the payload in place after the nonce. The buffer is large enough to never be
+        // reallocated, so no copy of the plaintext is left behind in freed memory.
+        let mut data = Vec::with_capacity(NONCE_LEN + payload.len() + AES_256_GCM.tag_len());
+        data.extend_from_slice(&nonce_bytes);
+        data.extend_from_slice(payload);
+        let tag = sealing_key.seal_in_place_separate_tag(Aad::from(aad), &mut data[NONCE_LEN..])?;
+        data.extend_from_slice(tag.as_ref());
 
         Ok(data)
     }
 
-    pub fn decrypt_bytes(&self, bytes: &[u8]) -> Result<Vec<u8>, ring::error::Unspecified> {
+    /// Encrypts the payload with a new random data key, using envelope encryption. The data key
+    /// is encrypted with this engine's key and put in front of the ciphertext, so the engine's
+    /// key can later be changed by re-encrypting it alone, without touching the payload. Both
+    /// are bound to `aad`, and large payloads are compressed first if compression is on.
+    pub fn seal_enveloped(&self, payload: &[u8], aad: &[u8]) -> error::Result<Vec<u8>> {
+        let compressed = self.compress(payload)?;
+        let aad = flagged(aad, compressed.is_some());
+        let data_key = new_key()?;
+        let wrapped = self.seal(data_key.as_slice(), aad.as_slice())?;
+        let ciphertext = Aes256GcmEngine::with_key(data_key).seal(compressed.as_deref().map_or(payload, Vec::as_slice), aad.as_slice())?;
+        Ok([wrapped, ciphertext].concat())
+    }
+
+    /// Decrypts bytes from `seal_enveloped`, by decrypting the data key and then the payload
+    pub fn open_enveloped(&self, bytes: &[u8], aad: &[u8]) -> error::Result<Zeroizing<Vec<u8>>> {
+        if bytes.len() < WRAPPED_KEY_LEN {
+            return Err(ring::error::Unspecified.into());
+        }
+        let (wrapped, ciphertext) = bytes.split_at(WRAPPED_KEY_LEN);
+
+        let (wrapped_key, compressed) = self.open_flagged(wrapped, aad)?;
+        let mut data_key = Zeroizing::new([0u8; KEY_LEN]);
+        data_key.copy_from_slice(wrapped_key.as_slice());
+        let payload = Aes256GcmEngine::with_key(data_key).open(ciphertext, flagged(aad, compressed).as_slice())?;
+        match compressed {
+            true => decompress(payload.as_slice()),
+            false => Ok(payload),
+        }
+    }
+
+    /// Decrypts bytes from `encrypt_bytes`, failing if they were not encrypted with the same
+    /// associated data. Compressed payloads are decompressed.
+    pub fn decrypt_bytes(&self, bytes: &[u8], aad: &[u8]) -> error::Result<Zeroizing<Vec<u8>>> {
+        match self.open_flagged(bytes, aad)? {
+            (payload, true) => decompress(payload.as_slice()),
+            (payload, false) => Ok(payload),
+        }
+    }
+
+    /// Decrypts bytes from `encrypt_bytes` as they are, returning whether the payload was
+    /// compressed, which is told by the associated data it opens with
+    fn open_flagged(&self, bytes: &[u8], aad: &[u8]) -> Result<(Zeroizing<Vec<u8>>, bool), ring::error::Unspecified> {
+        match self.open(bytes, aad) {
+            Ok(payload) => Ok((payload, false)),
+            Err(_) => Ok((self.open(bytes, flagged(aad, true).as_slice())?, true)),
+        }
+    }
+
+    /// Decrypts bytes from `seal`, with exactly the given associated data
+    fn open(&self, bytes: &[u8], aad: &[u8]) -> Result<Zeroizing<Vec<u8>>, ring::error::Unspecified> {
         // Split the incoming bytes at the nonce length
+        if bytes.len() < OVERHEAD {
+            return Err(ring::error::Unspecified);
+        }
         let (nonce_bytes, bytes) = bytes.split_at(NONCE_LEN);
 
         let mut opening_key = OpeningKey::new(
-            UnboundKey::new(&AES_256_GCM, &self.key)?,
+            UnboundKey::new(&AES_256_GCM, self.key.as_slice())?,
             InitializedNonceSequence::new(nonce_bytes.try_into()?),
         );
 
-        let mut raw = bytes.to_owned();
-        let plaintext = opening_key.open_in_place(Aad::empty(), &mut raw)?;
+        // Decrypt in place and drop the tag, rather than copying the plaintext out
+        let mut raw = Zeroizing::new(bytes.to_vec());
+        let len = opening_key.open_in_place(Aad::from(aad), raw.as_mut_slice())?.len();
+        raw.truncate(len);
 
-        Ok(plaintext.to_owned())
+        Ok(raw)
+    }
+
+    /// Compresses the payload if compression is on, or returns None if it is off, the payload
+    /// is too small to be worth compressing, or it doesn't shrink
+    fn compress(&self, payload: &[u8]) -> error::Result<Option<Zeroizing<Vec<u8>>>> {
+        if !self.compress || payload.len() < COMPRESS_MIN_LEN {
+            return Ok(None);
+        }
+        let compressed = Zeroizing::new(zstd::bulk::compress(payload, COMPRESS_LEVEL)?);
+        Ok(Some(compressed).filter(|compressed| compressed.len() < payload.len()))
     }
 }
 
+/// Returns the associated data a payload is sealed with, flagged if it was compressed
+fn flagged(aad: &[u8], compressed: bool) -> Vec<u8> {
+    match compressed {
+        true => [aad, COMPRESSED_AAD].concat(),
+        false => aad.to_vec(),
+    }
+}
+
+/// Decompresses a payload which was compressed before it was sealed. The payload has already
+/// been authenticated, so it can only fail to decompress if it was written by a broken version.
+fn decompress(compressed: &[u8]) -> error::Result<Zeroizing<Vec<u8>>> {
+    let mut payload = Zeroizing::new(vec![]);
+    zstd::stream::read::Decoder::new(compressed)?.read_to_end(&mut payload)?;
+    Ok(payload)
+}
+
+impl Aes256GcmEngine {
+    /// Returns a writer which encrypts everything written to it into `writer` in chunks, such as
+    /// a large file, without holding more than a chunk of it in memory. Like `seal_enveloped`,
+    /// a new random data key is encrypted with this engine's key and bound to `aad`, then
+    /// written in front of the chunks. Each chunk is sealed with the data key and numbered, and
+    /// the last one is marked, so chunks can't be reordered, dropped, or cut off without it being
+    /// noticed. This is the STREAM construction of Hoang, Reyhanitabar, Rogaway, and Vizár.
+    /// The stream is never compressed, see `encrypt_stream`.
+    pub fn stream_writer<W: Write>(&self, mut writer: W, aad: &[u8]) -> error::Result<StreamWriter<W>> {
+        let data_key = new_key()?;
+        writer.write_all(self.seal(data_key.as_slice(), aad)?.as_slice())?;
+        Ok(StreamWriter {
+            inner: writer,
+            key: LessSafeKey::new(UnboundKey::new(&AES_256_GCM, data_key.as_slice())?),
+            chunk: Zeroizing::new(Vec::with_capacity(STREAM_CHUNK_LEN + MAX_TAG_LEN)),
+            index: 0,
+            written: 0,
+        })
+    }
+
+    /// Returns a reader which decrypts a stream written by `stream_writer` from `reader`. Each
+    /// chunk is only returned once it has been authenticated, but a stream which has been cut
+    /// off or tampered with is only noticed when its reader gets there, so anything already
+    /// read from it must be discarded if it fails. Compressed streams are left to
+    /// `decrypt_stream`, so they fail to open here.
+    pub fn stream_reader<R: Read>(&self, mut reader: R, aad: &[u8]) -> error::Result<StreamReader<R>> {
+        match self.open_stream_key(&mut reader, aad)? {
+            (data_key, false) => Self::stream_reader_with_key(reader, &data_key),
+            (_, true) => Err(ring::error::Unspecified.into()),
+        }
+    }
+
+    /// Returns a reader which decrypts the chunks of a stream from `reader`, whose data key has
+    /// already been read
+    fn stream_reader_with_key<R: Read>(mut reader: R, data_key: &[u8; KEY_LEN]) -> error::Result<StreamReader<R>> {
+        let key = LessSafeKey::new(UnboundKey::new(&AES_256_GCM, data_key.as_slice())?);
+        let mut next = Zeroizing::new(vec![0u8; STREAM_CHUNK_LEN + MAX_TAG_LEN]);
+        let next_len = read_full(&mut reader, next.as_mut_slice())?;
+        Ok(StreamReader {
+            inner: reader,
+            key,
+            chunk: Zeroizing::new(vec![0u8; STREAM_CHUNK_LEN + MAX_TAG_LEN]),
+            next,
+            next_len,
+            start: 0,
+            end: 0,
+            index: 0,
+            done: false,
+        })
+    }
+
+    /// Encrypts everything read from `reader` into `writer`, see `stream_writer`, compressing
+    /// it first if compression is on. Returns the number of bytes read.
+    pub fn encrypt_stream(&self, reader: &mut dyn Read, writer: &mut dyn Write, aad: &[u8]) -> error::Result<u64> {
+        let mut stream = self.stream_writer(writer, flagged(aad, self.compress).as_slice())?;
+        if !self.compress {
+            let len = std::io::copy(reader, &mut stream)?;
+            stream.finish()?;
+            return Ok(len);
+        }
+
+        let mut encoder = zstd::stream::write::Encoder::new(stream, COMPRESS_LEVEL)?;
+        let len = std::io::copy(reader, &mut encoder)?;
+        encoder.finish()?.finish()?;
+        Ok(len)
+    }
+
+    /// Decrypts everything written by `encrypt_stream` from `reader` into `writer`, see
+    /// `stream_reader`, decompressing it if it was compressed. Returns the number of bytes
+    /// written.
+    pub fn decrypt_stream(&self, reader: &mut dyn Read, writer: &mut dyn Write, aad: &[u8]) -> error::Result<u64> {
+        let (data_key, compressed) = self.open_stream_key(reader, aad)?;
+        let mut stream = Self::stream_reader_with_key(reader, &data_key)?;
+        match compressed {
+            true => Ok(std::io::copy(&mut zstd::stream::read::Decoder::new(stream)?, writer)?),
+            false => Ok(std::io::copy(&mut stream, writer)?),
+        }
+    }
+
+    /// Copies a stream written by `encrypt_stream` from `reader` into `writer`, encrypting its
+    /// data key with another engine's key and bound to `to_aad` instead. The chunks themselves
+    /// are copied as they are, so nothing else needs to be decrypted, and a compressed stream
+    /// stays compressed.
+    pub fn rewrap_stream(&self, to: &Aes256GcmEngine, reader: &mut dyn Read, writer: &mut dyn Write, aad: &[u8], to_aad: &[u8]) -> error::Result<()> {
+        let (data_key, compressed) = self.open_stream_key(reader, aad)?;
+        writer.write_all(to.seal(data_key.as_slice(), flagged(to_aad, compressed).as_slice())?.as_slice())?;
+        std::io::copy(reader, writer)?;
+        Ok(())
+    }
+
+    /// Reads and decrypts the data key in front of a stream written by `stream_writer`,
+    /// returning whether the stream was compressed
+    fn open_stream_key(&self, reader: &mut dyn Read, aad: &[u8]) -> error::Result<(Zeroizing<[u8; KEY_LEN]>, bool)> {
+        let mut wrapped = [0u8; WRAPPED_KEY_LEN];
+        reader.read_exact(&mut wrapped)?;
+        let (wrapped_key, compressed) = self.open_flagged(&wrapped, aad)?;
+        let mut data_key = Zeroizing::new([0u8; KEY_LEN]);
+        data_key.copy_from_slice(wrapped_key.as_slice());
+        Ok((data_key, compressed))
+    }
+}
+
+/// StreamWriter encrypts everything written to it in chunks, see `Aes256GcmEngine::stream_writer`.
+/// A full chunk is only sealed once more is written, since the last chunk is sealed differently,
+/// so `finish` must be called once everything has been written.
+pub struct StreamWriter<W: Write> {
+    inner: W,
+    key: LessSafeKey,
+    chunk: Zeroizing<Vec<u8>>,
+    index: u64,
+    written: u64,
+}
+
+impl<W: Write> StreamWriter<W> {
+    /// Seals the last chunk, returning the inner writer and the number of bytes encrypted
+    pub fn finish(mut self) -> std::io::Result<(W, u64)> {
+        self.seal(true)?;
+        self.inner.flush()?;
+        Ok((self.inner, self.written))
+    }
+
+    /// Seals the buffered chunk and writes it to the inner writer
+    fn seal(&mut self, last: bool) -> std::io::Result<()> {
+        let tag = self.key.seal_in_place_separate_tag(chunk_nonce(self.index, last), Aad::from(STREAM_AAD), self.chunk.as_mut_slice())
+            .map_err(|_| std::io::Error::other("unable to encrypt the stream"))?;
+        self.inner.write_all(self.chunk.as_slice())?;
+        self.inner.write_all(tag.as_ref())?;
+        self.written += self.chunk.len() as u64;
+        self.chunk.clear();
+        self.index += 1;
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for StreamWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.chunk.len() == STREAM_CHUNK_LEN && !buf.is_empty() {
+            self.seal(false)?;
+        }
+        let len = buf.len().min(STREAM_CHUNK_LEN - self.chunk.len());
+        self.chunk.extend_from_slice(&buf[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// StreamReader decrypts a stream written by a `StreamWriter` a chunk at a time, see
+/// `Aes256GcmEngine::stream_reader`. The chunk after the current one is read ahead, to tell
+/// whether the current one should be the last.
+pub struct StreamReader<R: Read> {
+    inner: R,
+    key: LessSafeKey,
+    chunk: Zeroizing<Vec<u8>>,
+    next: Zeroizing<Vec<u8>>,
+    next_len: usize,
+    /// The part of `chunk` which has been decrypted but not read yet
+    start: usize,
+    end: usize,
+    index: u64,
+    done: bool,
+}
+
+impl<R: Read> StreamReader<R> {
+    /// Decrypts the chunk which was read ahead, and reads the one after it
+    fn open_next(&mut self) -> std::io::Result<()> {
+        std::mem::swap(&mut self.chunk, &mut self.next);
+        let len = self.next_len;
+        self.next_len = read_full(&mut self.inner, self.next.as_mut_slice())?;
+
+        let last = self.next_len == 0;
+        let plaintext = self.key.open_in_place(chunk_nonce(self.index, last), Aad::from(STREAM_AAD), &mut self.chunk[..len])
+            .map_err(|_| std::io::Error::new(ErrorKind::InvalidData, "the encrypted stream is corrupt or was tampered with"))?;
+        (self.start, self.end) = (0, plaintext.len());
+        self.index += 1;
+        self.done = last;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for StreamReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.start == self.end && !self.done {
+            self.open_next()?;
+        }
+        let len = buf.len().min(self.end - self.start);
+        buf[..len].copy_from_slice(&self.chunk[self.start..self.start + len]);
+        self.start += len;
+        Ok(len)
+    }
+}
+
+/// The number of bytes in each chunk of a stream, other than the last
+const STREAM_CHUNK_LEN: usize = 64 * 1024;
+
+/// The associated data of every chunk of a stream. Each stream has its own data
+/// key, so chunks are bound to their stream by the key rather than by associated data.
+const STREAM_AAD: &[u8] = b"yap stream";
+
+/// Returns the nonce of a chunk of a stream, made of its number and whether it is
+/// the last chunk. Every stream has its own data key, so the nonces are never reused.
+fn chunk_nonce(index: u64, last: bool) -> Nonce {
+    let mut nonce = [0u8; NONCE_LEN];
+    nonce[3..11].copy_from_slice(&index.to_be_bytes());
+    nonce[11] = last as u8;
+    Nonce::assume_unique_for_key(nonce)
+}
+
+/// Reads from `reader` until `buf` is full or there is nothing left, returning the number of
+/// bytes read
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        match reader.read(&mut buf[len..])? {
+            0 => break,
+            n => len += n,
+        }
+    }
+    Ok(len)
+}
+
 /// InitializedNonceSequence represents a NonceSequence initialized with
 /// a random sequence of 12 bytes. These bytes are interpreted as a u128
 /// for quick advancement of the counter.
@@ -123,28 +633,153 @@ fn new_iv() -> Result<[u8; NONCE_LEN], ring::error::Unspecified> {
     Ok(nonce_buf)
 }
 
-fn derive_key_from_pass(pass: String, salt: &[u8]) -> [u8; 32] {
-    // Byte buffer to store derived bytes
-    let mut key = [0u8; 32];
-    // Derive the key and store in `key`
-    derive(PBKDF2_HMAC_SHA256, NonZeroU32::new(310000u32).unwrap(), salt, &pass.as_bytes(), &mut key);
+/// Create a new random key, such as a vault's master key or an entry's data key
+pub fn new_key() -> Result<Zeroizing<[u8; KEY_LEN]>, ring::error::Unspecified> {
+    let mut key = Zeroizing::new([0u8; KEY_LEN]);
+    SystemRandom::new().fill(key.as_mut_slice())?;
+    Ok(key)
+}
 
-    key
+/// Create a new random salt to use when deriving a vault's key
+pub fn new_salt() -> Result<[u8; SALT_LEN], ring::error::Unspecified> {
+    let mut salt = [0u8; SALT_LEN];
+    SystemRandom::new().fill(&mut salt)?;
+    Ok(salt)
 }
 
 #[cfg(test)]
 mod test {
-    use crate::crypto::Aes256GcmEngine;
+    use std::io::{Read, Write};
+    use std::time::Duration;
+
+    use ring::aead::MAX_TAG_LEN;
+
+    use crate::crypto::{Aes256GcmEngine, Kdf, OVERHEAD, STREAM_CHUNK_LEN, WRAPPED_KEY_LEN, keyfile_hash, mix_keyfile, new_key, new_salt};
 
     #[test]
     fn can_encrypt_and_decrypt_bytes() {
-        let engine = Aes256GcmEngine::new("key".to_string());
+        let engine = Aes256GcmEngine::new("key", &new_salt().unwrap(), &Kdf::default()).unwrap();
         let message = "some message".as_bytes();
 
-        let encrypted = engine.encrypt_bytes(message).unwrap();
-        let decrypted = engine.decrypt_bytes(encrypted.as_slice()).unwrap();
+        let encrypted = engine.encrypt_bytes(message, b"name").unwrap();
+        let decrypted = engine.decrypt_bytes(encrypted.as_slice(), b"name").unwrap();
 
         assert_eq!(message, decrypted.as_slice());
+        assert!(engine.decrypt_bytes(encrypted.as_slice(), b"other name").is_err());
+
+        let sealed = engine.seal_enveloped(message, b"name").unwrap();
+        assert_eq!(message, engine.open_enveloped(sealed.as_slice(), b"name").unwrap().as_slice());
+        assert!(engine.open_enveloped(sealed.as_slice(), b"other name").is_err());
     }
-}
 
+    #[test]
+    fn streams_are_encrypted_in_chunks() {
+        let engine = Aes256GcmEngine::with_key(new_key().unwrap());
+        let other = Aes256GcmEngine::with_key(new_key().unwrap());
+        for len in [0, 100, STREAM_CHUNK_LEN, 2 * STREAM_CHUNK_LEN + 1] {
+            let message: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            let mut sealed = vec![];
+            assert_eq!(engine.encrypt_stream(&mut message.as_slice(), &mut sealed, b"name").unwrap(), len as u64);
+
+            let mut opened = vec![];
+            engine.decrypt_stream(&mut sealed.as_slice(), &mut opened, b"name").unwrap();
+            assert_eq!(opened, message);
+            assert!(engine.decrypt_stream(&mut sealed.as_slice(), &mut vec![], b"other name").is_err());
+
+            let mut rewrapped = vec![];
+            engine.rewrap_stream(&other, &mut sealed.as_slice(), &mut rewrapped, b"name", b"moved").unwrap();
+            let mut opened = vec![];
+            other.decrypt_stream(&mut rewrapped.as_slice(), &mut opened, b"moved").unwrap();
+            assert_eq!(opened, message);
+        }
+
+        // Writes and reads of any size line up with the chunks
+        let message: Vec<u8> = (0..3 * STREAM_CHUNK_LEN).map(|i| (i / 3) as u8).collect();
+        let mut stream = engine.stream_writer(vec![], b"name").unwrap();
+        message.chunks(1000).for_each(|part| stream.write_all(part).unwrap());
+        let (sealed, len) = stream.finish().unwrap();
+        assert_eq!(len, message.len() as u64);
+        let mut reader = engine.stream_reader(sealed.as_slice(), b"name").unwrap();
+        let mut opened = vec![0u8; message.len()];
+        opened.chunks_mut(777).for_each(|part| reader.read_exact(part).unwrap());
+        assert_eq!((opened, reader.read(&mut [0u8; 1]).unwrap()), (message, 0));
+
+        // Cutting off the last chunk is noticed, even where a chunk ends
+        let message = vec![7u8; 2 * STREAM_CHUNK_LEN];
+        let mut sealed = vec![];
+        engine.encrypt_stream(&mut message.as_slice(), &mut sealed, b"name").unwrap();
+        sealed.truncate(WRAPPED_KEY_LEN + STREAM_CHUNK_LEN + MAX_TAG_LEN);
+        assert!(engine.decrypt_stream(&mut sealed.as_slice(), &mut vec![], b"name").is_err());
+    }
+
+    #[test]
+    fn large_payloads_are_compressed() {
+        let key = new_key().unwrap();
+        let engine = Aes256GcmEngine::with_key(key.clone()).with_compression(true);
+        let uncompressed = Aes256GcmEngine::with_key(key);
+        let message = "a long note which repeats itself\n".repeat(100);
+
+        // Compressed payloads are read back whether or not compression is on, and the flag is
+        // authenticated, so it can't be dropped
+        let encrypted = engine.encrypt_bytes(message.as_bytes(), b"name").unwrap();
+        assert!(encrypted.len() < message.len() / 4);
+        assert_eq!(uncompressed.decrypt_bytes(encrypted.as_slice(), b"name").unwrap().as_slice(), message.as_bytes());
+        assert!(uncompressed.open(encrypted.as_slice(), b"name").is_err());
+        let sealed = engine.seal_enveloped(message.as_bytes(), b"name").unwrap();
+        assert!(sealed.len() < message.len() / 4);
+        assert_eq!(uncompressed.open_enveloped(sealed.as_slice(), b"name").unwrap().as_slice(), message.as_bytes());
+        assert!(engine.open_enveloped(sealed.as_slice(), b"other name").is_err());
+
+        // Small payloads aren't worth compressing
+        assert_eq!(engine.encrypt_bytes(b"password", b"name").unwrap().len(), 8 + OVERHEAD);
+
+        // Attachments are compressed the same way, and stay compressed when rewrapped
+        let mut sealed = vec![];
+        engine.encrypt_stream(&mut message.as_bytes(), &mut sealed, b"name").unwrap();
+        assert!(sealed.len() < message.len() / 4);
+        assert!(uncompressed.stream_reader(sealed.as_slice(), b"name").is_err());
+        let mut rewrapped = vec![];
+        engine.rewrap_stream(&uncompressed, &mut sealed.as_slice(), &mut rewrapped, b"name", b"moved").unwrap();
+        let mut opened = vec![];
+        uncompressed.decrypt_stream(&mut rewrapped.as_slice(), &mut opened, b"moved").unwrap();
+        assert_eq!(opened, message.as_bytes());
+    }
+
+    #[test]
+    fn kdfs_derive_distinct_keys() {
+        let salt = new_salt().unwrap();
+        let argon2id = Kdf::Argon2id { memory: 64, iterations: 1, parallelism: 1 };
+
+        let pbkdf2_key = Kdf::Pbkdf2 { iterations: 1000 }.derive_key("key", &salt).unwrap();
+        let argon2id_key = argon2id.derive_key("key", &salt).unwrap();
+
+        assert_ne!(*pbkdf2_key, *argon2id_key);
+        assert_eq!(*argon2id_key, *argon2id.derive_key("key", &salt).unwrap());
+        assert!(Kdf::Pbkdf2 { iterations: 0 }.derive_key("key", &salt).is_err());
+    }
+
+    #[test]
+    fn calibrated_kdfs_are_never_weaker_than_the_default() {
+        let pbkdf2 = Kdf::default();
+        assert_eq!(pbkdf2.calibrate(Duration::ZERO).unwrap(), pbkdf2);
+
+        let took = pbkdf2.time().unwrap();
+        let Kdf::Pbkdf2 { iterations } = pbkdf2.calibrate(took * 3).unwrap() else { panic!("calibrating changed the algorithm") };
+        assert!(iterations >= 310000 && iterations % 10000 == 0);
+
+        let argon2id = Kdf::parse("argon2id").unwrap();
+        assert_eq!(argon2id.calibrate(Duration::ZERO).unwrap(), argon2id);
+        assert_eq!(argon2id.to_string(), "argon2id with 19456 KiB of memory, 2 iterations, and parallelism 1");
+    }
+
+    #[test]
+    fn keyfile_changes_key() {
+        let salt = new_salt().unwrap();
+        let key = Kdf::Pbkdf2 { iterations: 1000 }.derive_key("key", &salt).unwrap();
+
+        assert_ne!(*mix_keyfile(&key, b"keyfile"), *key);
+        assert_ne!(*mix_keyfile(&key, b"keyfile"), *mix_keyfile(&key, b"other keyfile"));
+        assert_eq!(keyfile_hash(&salt, b"keyfile"), keyfile_hash(&salt, b"keyfile"));
+        assert_ne!(keyfile_hash(&salt, b"keyfile"), keyfile_hash(&new_salt().unwrap(), b"keyfile"));
+    }
+}
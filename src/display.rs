@@ -0,0 +1,351 @@
+use std::collections::BTreeMap;
+
+use chrono::{Local, TimeZone};
+
+use crate::audit::{Problem, Report};
+use crate::activity::Activity;
+use crate::autobackup::AutoBackup;
+use crate::doctor::{Check, Status};
+use crate::snapshot::Snapshot;
+use crate::stats::Stats;
+use crate::status::VaultStatus;
+use crate::verify;
+use crate::entry::Metadata;
+use crate::generate::EntryPolicy;
+use crate::sign::Author;
+
+/// Node is a level in the tree of hierarchical key names, mapping each name at this level to the
+/// names nested below it.
+#[derive(Default)]
+struct Node(BTreeMap<String, Node>);
+
+/// Renders `/`-separated key names as a tree, with names at each level sorted and nested names
+/// indented below their parent.
+pub fn tree(keys: &[String]) -> String {
+    let mut root = Node::default();
+    for key in keys {
+        key.split('/')
+            .filter(|part| !part.is_empty())
+            .fold(&mut root, |node, part| node.0.entry(part.to_string()).or_default());
+    }
+
+    let mut lines = vec![];
+    render(&root, "", &mut lines);
+    lines.join("\n")
+}
+
+/// Appends a line for each child of the node, followed by the lines for its own children.
+fn render(node: &Node, indent: &str, lines: &mut Vec<String>) {
+    for (i, (name, child)) in node.0.iter().enumerate() {
+        let last = i + 1 == node.0.len();
+        let (branch, nested) = if last { ("└── ", "    ") } else { ("├── ", "│   ") };
+
+        lines.push(format!("{}{}{}", indent, branch, name));
+        render(child, format!("{}{}", indent, nested).as_str(), lines);
+    }
+}
+
+/// Renders key names in columns along with their created, modified, and accessed times, and who
+/// last changed them.
+pub fn long_listing(entries: &[(String, Metadata, Author)]) -> String {
+    let width = entries.iter().map(|(key, _, _)| key.len()).max().unwrap_or_default().max(4);
+    let row = |key: &str, created: &str, modified: &str, accessed: &str, author: &str| {
+        format!("{:width$}  {:16}  {:16}  {:16}  {}", key, created, modified, accessed, author)
+    };
+
+    let mut lines = vec![row("NAME", "CREATED", "MODIFIED", "ACCESSED", "AUTHOR")];
+    for (key, metadata, author) in entries {
+        let (created, modified, accessed) = (date(metadata.created), date(metadata.modified), date(metadata.accessed));
+        lines.push(row(key, created.as_str(), modified.as_str(), accessed.as_str(), author.to_string().as_str()));
+    }
+    lines.join("\n")
+}
+
+/// Renders the versions in an entry's history along with when each version was last modified.
+pub fn history_listing(versions: &[(u32, Metadata)]) -> String {
+    let mut lines = vec![format!("{:7}  {}", "VERSION", "MODIFIED")];
+    for (version, metadata) in versions {
+        lines.push(format!("{:7}  {}", version, date(metadata.modified)));
+    }
+    lines.join("\n")
+}
+
+/// Renders the status of a vault as a list of labelled values. Values over several lines, such as
+/// the changes a sync would make, are indented to line up.
+pub fn status_summary(status: &VaultStatus) -> String {
+    let last_sync = status.last_sync.map(date).unwrap_or_else(|| "never".to_string());
+    let pending = match (&status.pending, &status.sync_error) {
+        (Some(pending), _) => pending.to_string(),
+        (None, Some(e)) => format!("unknown, the remote can't be reached: {}", e),
+        (None, None) => "no remote is set".to_string(),
+    };
+
+    [
+        ("Store", status.store.clone()),
+        ("Backend", status.backend.to_string()),
+        ("Entries", status.entries.to_string()),
+        ("Session", status.session.to_string()),
+        ("Last sync", last_sync),
+        ("Sync", pending),
+    ]
+        .into_iter()
+        .map(|(label, value)| format!("{:11}{}", format!("{}:", label), value.replace('\n', &format!("\n{:11}", ""))))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Renders the checks made by yap doctor one per line, each followed by how to fix it if it
+/// isn't ok, and a summary of the warnings and failures
+pub fn doctor_report(checks: &[Check]) -> String {
+    let width = checks.iter().map(|check| check.name.len()).max().unwrap_or_default();
+    let mut lines = vec![];
+    for check in checks {
+        let status = match check.status {
+            Status::Ok => "ok",
+            Status::Warning => "warning",
+            Status::Failed => "failed",
+        };
+        lines.push(format!("{:7}  {:width$}  {}", status, check.name, check.message));
+        if let Some(fix) = check.fix.as_ref() {
+            lines.push(format!("{:7}  {:width$}  fix: {}", "", "", fix));
+        }
+    }
+
+    let count = |status| checks.iter().filter(|check| check.status == status).count();
+    lines.push(String::new());
+    lines.push(format!("{} checks, {} warnings, {} failed", checks.len(), count(Status::Warning), count(Status::Failed)));
+    lines.join("\n")
+}
+
+/// Renders the activity log of a vault, one operation per line with when and where it was done.
+pub fn activity_listing(log: &[Activity]) -> String {
+    let width = log.iter().map(|activity| activity.host.len()).max().unwrap_or_default().max(4);
+    let mut lines = vec![format!("{:16}  {:width$}  {:13}  {}", "TIME", "HOST", "OPERATION", "NAME")];
+    for activity in log {
+        lines.push(format!("{:16}  {:width$}  {:13}  {}", date(activity.time), activity.host, activity.operation, activity.name));
+    }
+    lines.join("\n")
+}
+
+/// Renders the names of the entries in the trash along with when each was deleted.
+pub fn trash_listing(trashed: &[(String, i64)]) -> String {
+    let width = trashed.iter().map(|(name, _)| name.len()).max().unwrap_or_default().max(4);
+    let mut lines = vec![format!("{:width$}  {}", "NAME", "DELETED")];
+    for (name, deleted) in trashed {
+        lines.push(format!("{:width$}  {}", name, date(*deleted)));
+    }
+    lines.join("\n")
+}
+
+/// Renders the automatic backups of a vault along with when each was taken, by which command,
+/// and the entries it holds.
+pub fn backup_listing(backups: &[AutoBackup]) -> String {
+    let width = backups.iter().map(|backup| backup.id.len()).max().unwrap_or_default().max(2);
+    let mut lines = vec![format!("{:width$}  {:16}  {}", "ID", "TAKEN", "ENTRIES")];
+    for backup in backups {
+        lines.push(format!("{:width$}  {:16}  {}", backup.id, date(backup.taken), backup.entries.join(", ")));
+    }
+    lines.join("\n")
+}
+
+/// Renders the snapshots of a vault along with when each was taken, the number of files in it,
+/// and its label.
+pub fn snapshot_listing(snapshots: &[Snapshot]) -> String {
+    let width = snapshots.iter().map(|snapshot| snapshot.id.len()).max().unwrap_or_default().max(2);
+    let mut lines = vec![format!("{:width$}  {:16}  {:5}  {}", "ID", "TAKEN", "FILES", "LABEL")];
+    for snapshot in snapshots {
+        let label = snapshot.label.as_deref().unwrap_or_default();
+        lines.push(format!("{:width$}  {:16}  {:5}  {}", snapshot.id, date(snapshot.taken), snapshot.files.len(), label));
+    }
+    lines.join("\n")
+}
+
+/// Renders the entries with problems found by an audit in columns, along with the strength score
+/// of each password, followed by a summary of the problems found.
+pub fn audit_table(report: &Report) -> String {
+    let width = report.problems().map(|finding| finding.name.len()).max().unwrap_or_default().max(4);
+
+    let mut lines = vec![format!("{:width$}  {:8}  {}", "NAME", "STRENGTH", "PROBLEMS")];
+    for finding in report.problems() {
+        let problems = finding.problems.iter()
+            .map(|problem| match problem {
+                Problem::Breached => format!("breached {} times", finding.breaches),
+                problem => problem.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        lines.push(format!("{:width$}  {:8}  {}", finding.name, format!("{}/4", finding.score), problems));
+    }
+
+    let problems = report.problems().count();
+    let mut counts = vec![Problem::Weak, Problem::Short, Problem::Reused, Problem::Old];
+    if report.breaches_checked {
+        counts.push(Problem::Breached);
+    }
+    let counts = counts.into_iter()
+        .map(|problem| format!("{} {}", report.count(problem), problem))
+        .collect::<Vec<_>>()
+        .join(", ");
+    lines.push(String::new());
+    for names in &report.duplicates {
+        lines.push(format!("Shared by {} entries: {}", names.len(), names.join(", ")));
+    }
+    if !report.duplicates.is_empty() {
+        lines.push(String::new());
+    }
+    lines.push(format!("{} of {} passwords have problems: {}", problems, report.findings.len(), counts));
+    lines.join("\n")
+}
+
+/// Renders the findings of verifying a vault as a table of paths and problems, followed by a
+/// summary of the problems found and what was quarantined
+pub fn verify_table(report: &verify::Report) -> String {
+    let width = report.findings.iter().map(|finding| finding.path.len()).max().unwrap_or_default().max(4);
+
+    let mut lines = vec![format!("{:width$}  {:24}  {}", "PATH", "PROBLEM", "DETAIL")];
+    for finding in report.findings.iter() {
+        let problem = match finding.quarantined {
+            true => format!("{}, quarantined", finding.problem),
+            false => finding.problem.to_string(),
+        };
+        lines.push(format!("{:width$}  {:24}  {}", finding.path, problem, finding.detail));
+    }
+
+    let counts = [verify::Problem::Header, verify::Problem::Corrupt, verify::Problem::Unindexed, verify::Problem::Missing, verify::Problem::Stale]
+        .into_iter()
+        .map(|problem| format!("{} {}", report.count(problem), problem))
+        .collect::<Vec<_>>()
+        .join(", ");
+    lines.push(String::new());
+    lines.push(format!("Checked {} files, found {} problems: {}", report.checked, report.findings.len(), counts));
+    let quarantined = report.findings.iter().filter(|finding| finding.quarantined).count();
+    if quarantined > 0 {
+        lines.push(format!("Moved {} corrupted files into the quarantine", quarantined));
+    }
+    lines.join("\n")
+}
+
+/// Renders the repairs made to a vault as a table of paths and what was done to them, followed by
+/// a summary
+pub fn repair_table(report: &verify::RepairReport) -> String {
+    if report.repairs.is_empty() {
+        return format!("Nothing needed repairing, rebuilt the index of {} entries", report.indexed);
+    }
+
+    let width = report.repairs.iter().map(|repair| repair.path.len()).max().unwrap_or_default().max(4);
+    let mut lines = vec![format!("{:width$}  {}", "PATH", "REPAIR")];
+    for repair in report.repairs.iter() {
+        let action = match repair.detail.is_empty() {
+            true => repair.action.to_string(),
+            false => format!("{} {}", repair.action, repair.detail),
+        };
+        lines.push(format!("{:width$}  {}", repair.path, action));
+    }
+
+    let counts = [verify::Action::Removed, verify::Action::Moved, verify::Action::Restored, verify::Action::Reverted, verify::Action::Quarantined]
+        .into_iter()
+        .map(|action| format!("{} {}", report.count(action), action))
+        .collect::<Vec<_>>()
+        .join(", ");
+    lines.push(String::new());
+    lines.push(format!("Made {} repairs: {}", report.repairs.len(), counts));
+    lines.push(format!("Rebuilt the index of {} entries", report.indexed));
+    lines.join("\n")
+}
+
+/// Renders the rules of an entry's password policy, one per line, with the generate settings
+/// used for any rule which isn't set.
+pub fn policy(name: &str, policy: &EntryPolicy) -> String {
+    if policy.is_empty() {
+        return format!("{} has no password policy, the generate settings are used", name);
+    }
+
+    let length = policy.length.map(|length| length.to_string());
+    let symbols = match policy.symbols.as_deref() {
+        Some("") => Some("none".to_string()),
+        symbols => symbols.map(str::to_string),
+    };
+    let require: Vec<String> = policy.require.iter()
+        .map(|class| format!("{:?}", class).to_lowercase())
+        .collect();
+
+    let default = || "from the generate settings".to_string();
+    [
+        format!("length:  {}", length.unwrap_or_else(default)),
+        format!("symbols: {}", symbols.unwrap_or_else(default)),
+        format!("require: {}", if require.is_empty() { "-".to_string() } else { require.join(", ") }),
+    ].join("\n")
+}
+
+/// Renders the stats of a vault: its size, then tables of the entries in each folder, with each
+/// tag, and in each age group, and finally how much history is kept
+pub fn stats_report(stats: &Stats) -> String {
+    let sizes = &stats.sizes;
+    let mut lines = vec![format!("{} entries, taking {} encrypted: {} of entries, {} of history, {} of attachments, and {} in the trash",
+        stats.entries, size(sizes.total()), size(sizes.entries), size(sizes.history), size(sizes.attachments), size(sizes.trash))];
+
+    let folders: Vec<(String, usize)> = stats.folders.iter()
+        .map(|(folder, count)| (if folder.is_empty() { "(none)".to_string() } else { folder.clone() }, *count))
+        .collect();
+    let tags: Vec<(String, usize)> = stats.tags.iter().map(|(tag, count)| (tag.clone(), *count)).collect();
+    for (heading, counts) in [("FOLDER", folders.as_slice()), ("TAG", tags.as_slice()), ("CHANGED", stats.ages.as_slice())] {
+        if counts.is_empty() {
+            continue;
+        }
+        let width = counts.iter().map(|(name, _)| name.len()).max().unwrap_or_default().max(heading.len());
+        lines.push(String::new());
+        lines.push(format!("{:width$}  {}", heading, "ENTRIES"));
+        lines.extend(counts.iter().map(|(name, count)| format!("{:width$}  {}", name, count)));
+    }
+
+    lines.push(String::new());
+    let history = &stats.history;
+    let most = history.most.iter().map(|(name, count)| format!("{} ({})", name, count)).collect::<Vec<_>>().join(", ");
+    lines.push(match history.versions {
+        0 => "No previous versions are kept".to_string(),
+        versions => format!("{} previous versions are kept of {} entries, the most of {}", versions, history.entries, most),
+    });
+    lines.join("\n")
+}
+
+/// Formats a number of bytes in the largest unit it is at least one of
+pub fn size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// Formats seconds since the Unix epoch as a local date and time, or `-` if the time is unknown.
+pub fn date(timestamp: i64) -> String {
+    match Local.timestamp_opt(timestamp, 0).single() {
+        Some(time) if timestamp > 0 => time.format("%Y-%m-%d %H:%M").to_string(),
+        _ => "-".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::display::{size, tree};
+
+    #[test]
+    fn renders_keys_as_tree() {
+        let keys: Vec<String> = ["email", "work/aws/root", "work/github"].iter().map(|k| k.to_string()).collect();
+        let expected = "├── email\n└── work\n    ├── aws\n    │   └── root\n    └── github";
+        assert_eq!(tree(&keys), expected);
+    }
+
+    #[test]
+    fn formats_sizes() {
+        assert_eq!(size(512), "512 B");
+        assert_eq!(size(1536), "1.5 KiB");
+        assert_eq!(size(3 * 1024 * 1024 * 1024), "3.0 GiB");
+    }
+}
@@ -0,0 +1,63 @@
+use std::io::{ErrorKind, Read, Write};
+use std::process::{Command, Stdio};
+
+use clap::ValueEnum;
+
+use crate::error::{Error, Result};
+
+/// Engine is the container engine which stores the secret
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Engine {
+    #[default]
+    Docker,
+    Podman,
+}
+
+impl Engine {
+    fn program(&self) -> &'static str {
+        match self {
+            Engine::Docker => "docker",
+            Engine::Podman => "podman",
+        }
+    }
+}
+
+/// Creates a secret in the container engine, such as a Docker swarm secret, with `docker secret
+/// create <name> -`. The value is written to the engine's stdin, so it never touches the disk or
+/// shows up in the process list.
+pub fn create(engine: Engine, name: &str, value: &str) -> Result<()> {
+    let child = Command::new(engine.program())
+        .args(["secret", "create", name, "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            return Err(Error::Docker { message: format!("{} is not installed", engine.program()) })
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(value.as_bytes())?;
+    }
+
+    let mut stderr = String::new();
+    if let Some(mut err) = child.stderr.take() {
+        err.read_to_string(&mut stderr)?;
+    }
+    match child.wait()?.success() {
+        true => Ok(()),
+        false => Err(Error::Docker { message: stderr.trim().to_string() }),
+    }
+}
+
+/// Writes the value to stdout exactly as it is, without a trailing newline, so it can be piped
+/// into other tools which create secrets
+pub fn write_raw(value: &str) -> Result<()> {
+    let mut stdout = std::io::stdout().lock();
+    stdout.write_all(value.as_bytes())?;
+    Ok(stdout.flush()?)
+}
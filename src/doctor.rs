@@ -0,0 +1,237 @@
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::agent;
+use crate::clipboard;
+use crate::config::{self, Configuration};
+use crate::error::Error;
+use crate::global::{CONFIG_FILE, HEADER_FILE, LOCAL_CONFIG_FILE, VAULT_CONFIG_FILE};
+use crate::header::VaultHeader;
+use crate::session::SessionStore;
+use crate::sync;
+use crate::vault;
+
+/// Status is the outcome of one of the checks made by `run`
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok,
+    /// Yap works, but not as well as it could
+    Warning,
+    /// Yap, or some of its commands, won't work until this is fixed
+    Failed,
+}
+
+/// Check is the outcome of checking one part of yap's setup, with how to fix it if it isn't ok
+#[derive(Serialize, Clone, PartialEq, Eq, Debug)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: Status,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fix: Option<String>,
+}
+
+impl Check {
+    fn ok(name: &'static str, message: impl Into<String>) -> Check {
+        Check { name, status: Status::Ok, message: message.into(), fix: None }
+    }
+
+    fn warning(name: &'static str, message: impl Into<String>, fix: impl Into<String>) -> Check {
+        Check { name, status: Status::Warning, message: message.into(), fix: Some(fix.into()) }
+    }
+
+    fn failed(name: &'static str, message: impl Into<String>, fix: impl Into<String>) -> Check {
+        Check { name, status: Status::Failed, message: message.into(), fix: Some(fix.into()) }
+    }
+}
+
+/// Checks the setup of yap and of the vault in the given store, or the default store if None:
+/// the config, the vault directory and its header, the remote, the agent, and the clipboard.
+/// Nothing is changed, and no master password is needed, so this works even when the vault
+/// can't be opened.
+pub fn run(store: Option<String>) -> Vec<Check> {
+    let mut checks = vec![];
+    let config = check_config(&mut checks);
+    let vault_dir = match vault::get_path_or_default(store) {
+        Ok(vault_dir) => vault_dir,
+        Err(e) => {
+            checks.push(Check::failed("vault", e.to_string(), "pass the path of a vault with --store"));
+            return checks;
+        }
+    };
+
+    let config = config.and_then(|config| match config.with_vault(vault_dir.as_path()) {
+        Ok(config) => Some(config),
+        Err(e) => {
+            let path = vault_dir.join(VAULT_CONFIG_FILE);
+            checks.push(Check::failed("config", format!("{} is invalid: {}", path.display(), e), format!("fix or remove {}", path.display())));
+            None
+        }
+    });
+    let header = check_vault(vault_dir.as_path(), config.as_ref(), &mut checks);
+    if let Some(config) = config.as_ref() {
+        if header.is_some() {
+            checks.push(check_remote(vault_dir.as_path(), config));
+        }
+        checks.push(check_agent(config));
+    }
+    checks.push(check_clipboard());
+    checks
+}
+
+/// Checks that the user's config file can be read, returning the config if it can
+fn check_config(checks: &mut Vec<Check>) -> Option<Configuration> {
+    let path = config::get_default_path().map(|dir| dir.join(CONFIG_FILE).display().to_string()).unwrap_or_default();
+    match config::read() {
+        Ok(config) => {
+            checks.push(Check::ok("config", format!("{} is valid", path)));
+            Some(config)
+        }
+        Err(Error::StdIO(e)) if e.kind() == std::io::ErrorKind::NotFound => {
+            checks.push(Check::warning("config", format!("there is no config file at {}, so the default settings are used", path), "run yap init to create one"));
+            config::read_or_default().ok()
+        }
+        Err(e) => {
+            checks.push(Check::failed("config", format!("{} is invalid: {}", path, e), format!("fix the setting, or remove {} to go back to the defaults", path)));
+            None
+        }
+    }
+}
+
+/// Checks that the vault directory exists with permissions which keep it private, and that its
+/// header can be read, returning the header if it can
+fn check_vault(vault_dir: &Path, config: Option<&Configuration>, checks: &mut Vec<Check>) -> Option<VaultHeader> {
+    let metadata = match std::fs::metadata(vault_dir) {
+        Ok(metadata) if metadata.is_dir() => metadata,
+        Ok(_) => {
+            checks.push(Check::failed("vault", format!("{} is not a directory", vault_dir.display()), "pass the path of a vault with --store"));
+            return None;
+        }
+        Err(e) => {
+            checks.push(Check::failed("vault", format!("{} can't be read: {}", vault_dir.display(), e), "run yap init to create a vault, or pass the path of one with --store"));
+            return None;
+        }
+    };
+
+    if metadata.permissions().readonly() && !config.is_some_and(|config| config.read_only()) {
+        checks.push(Check::warning("vault", format!("{} is read-only, so only commands which don't change the vault work", vault_dir.display()),
+            "make the directory writable, or set read_only to true to say this is on purpose"));
+    } else {
+        checks.push(private(vault_dir));
+    }
+
+    let header = match VaultHeader::read(vault_dir) {
+        Ok(header) => header,
+        Err(e @ Error::MissingHeader { .. }) => {
+            checks.push(Check::failed("header", e.to_string(), "run yap init to create a vault here, or restore the header from a backup or snapshot"));
+            return None;
+        }
+        Err(e) => {
+            checks.push(Check::failed("header", format!("{} can't be read: {}", HEADER_FILE, e), "restore the header with yap repair --backup or yap snapshot rollback"));
+            return None;
+        }
+    };
+    let problems = header.problems();
+    if problems.is_empty() {
+        checks.push(Check::ok("header", format!("the header is valid, with a {} byte salt and {} keys", header.salt.len(), header.kdf.name())));
+    } else {
+        checks.push(Check::failed("header", problems.join(", "), "restore the header with yap repair --backup or yap snapshot rollback"));
+    }
+
+    if header.keyfile.is_some() {
+        match config.and_then(|config| config.keyfile()) {
+            Some(keyfile) if keyfile.is_file() => checks.push(Check::ok("keyfile", format!("the keyfile is at {}", keyfile.display()))),
+            Some(keyfile) => checks.push(Check::failed("keyfile", format!("the vault needs a keyfile, but {} doesn't exist", keyfile.display()), format!("set keyfile in {} to the path of the vault's keyfile", vault_dir.join(LOCAL_CONFIG_FILE).display()))),
+            None => checks.push(Check::failed("keyfile", "the vault needs a keyfile, but the keyfile setting isn't set", format!("set keyfile in {} to the path of the vault's keyfile", vault_dir.join(LOCAL_CONFIG_FILE).display()))),
+        }
+    }
+    Some(header)
+}
+
+/// Checks that only the user can access the vault directory
+#[cfg(unix)]
+fn private(vault_dir: &Path) -> Check {
+    use std::os::unix::fs::PermissionsExt;
+
+    match std::fs::metadata(vault_dir).map(|metadata| metadata.permissions().mode() & 0o777) {
+        Ok(mode) if mode & 0o077 == 0 => Check::ok("vault", format!("{} is only accessible by you", vault_dir.display())),
+        Ok(mode) => Check::warning("vault", format!("{} has mode {:o}, so other users can list its entries", vault_dir.display(), mode),
+            format!("run chmod -R go-rwx {}", vault_dir.display())),
+        Err(e) => Check::failed("vault", format!("{} can't be read: {}", vault_dir.display(), e), "check the permissions of the vault directory"),
+    }
+}
+
+/// Checks that only the user can access the vault directory. Windows keeps a user's profile
+/// private already.
+#[cfg(not(unix))]
+fn private(vault_dir: &Path) -> Check {
+    Check::ok("vault", format!("{} is writable", vault_dir.display()))
+}
+
+/// Checks that the remote can be reached, by finding what syncing would do. The remote URL is left
+/// out of the message, since it may hold a password.
+fn check_remote(vault_dir: &Path, config: &Configuration) -> Check {
+    if config.remote_url().is_empty() {
+        return Check::ok("remote", "no remote is set, so the vault isn't synced");
+    }
+    match sync::status(vault_dir.to_path_buf(), config.remote_url(), false, false) {
+        Ok(status) => Check::ok("remote", format!("the remote is reachable, with {} entries to pull and {} to push", status.pull.len(), status.push.len())),
+        Err(e) => Check::failed("remote", format!("the remote can't be reached: {}", e), "check the remote_url setting, your network, and your credentials for the remote"),
+    }
+}
+
+/// Checks whether the agent is running, which only matters if the session setting uses it
+fn check_agent(config: &Configuration) -> Check {
+    let uses_agent = config.session() == SessionStore::Agent;
+    match agent::status() {
+        Ok(vaults) => Check::ok("agent", format!("the agent is running, with {} keys cached", vaults.len())),
+        Err(Error::AgentNotRunning) if uses_agent => Check::warning("agent", "the agent isn't running, so every command asks for the master password",
+            "run yap agent start"),
+        Err(Error::AgentNotRunning) => Check::ok("agent", format!("the agent isn't running, and isn't used since session is {}", config.session().name())),
+        Err(e) if uses_agent => Check::failed("agent", format!("the agent can't be reached: {}", e), "run yap agent stop, then yap agent start"),
+        Err(e) => Check::ok("agent", format!("the agent can't be reached, but isn't used since session is {}: {}", config.session().name(), e)),
+    }
+}
+
+/// Checks that a clipboard tool is installed, which copying secrets needs
+fn check_clipboard() -> Check {
+    match clipboard::tool() {
+        Some(tool) => Check::ok("clipboard", format!("secrets are copied with {}", tool)),
+        None => Check::warning("clipboard", "no clipboard tool is installed, so secrets can't be copied",
+            "install wl-clipboard on Wayland, or xclip or xsel on X11"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use crate::crypto::Kdf;
+    use crate::doctor::{self, Status};
+    use crate::global::HEADER_FILE;
+    use crate::vault::{self, Backend};
+
+    #[test]
+    fn vaults_are_checked_without_the_password() {
+        let yap_test = String::from(".yap_test_doctor");
+        let dir = Path::new(yap_test.as_str());
+        vault::create(Some(yap_test.clone()), "asdf", Kdf::default(), Backend::default(), None).unwrap();
+
+        let mut checks = vec![];
+        assert!(doctor::check_vault(dir, None, &mut checks).is_some());
+        let header = checks.iter().find(|check| check.name == "header").unwrap();
+        assert_eq!(header.status, Status::Ok);
+
+        // A missing header fails, with a fix
+        std::fs::remove_file(dir.join(HEADER_FILE)).unwrap();
+        checks.clear();
+        assert!(doctor::check_vault(dir, None, &mut checks).is_none());
+        let header = checks.iter().find(|check| check.name == "header").unwrap();
+        assert_eq!(header.status, Status::Failed);
+        assert!(header.fix.is_some());
+
+        std::fs::remove_dir_all(yap_test).unwrap();
+    }
+}
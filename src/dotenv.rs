@@ -0,0 +1,81 @@
+use zeroize::Zeroizing;
+
+use crate::error::{Error, Result};
+use crate::vault::Vault;
+
+/// Renders the secrets of every entry in a folder as `KEY=value` lines, in the format read by
+/// dotenv libraries and shells. Each key is the name of the entry within the folder, upper cased
+/// with every other character replaced by an underscore, so `app/db/password` in the folder `app`
+/// is `DB_PASSWORD`.
+pub fn render(vault: &mut dyn Vault, folder: &str) -> Result<Zeroizing<String>> {
+    let prefix = format!("{}/", folder.trim_end_matches('/'));
+    let names = vault.list_keys(prefix.as_str())?;
+    if names.is_empty() {
+        return Err(Error::PasswordNotFound { name: prefix });
+    }
+
+    let mut lines = Zeroizing::new(String::new());
+    for name in names {
+        let entry = vault.access_entry(name.as_str())?;
+        let value = entry.field(entry.secret_field()).unwrap_or_default();
+        lines.push_str(format!("{}={}\n", key(&name[prefix.len()..]), quote(value)).as_str());
+    }
+    Ok(lines)
+}
+
+/// Returns the variable name for an entry, such as DB_PASSWORD for db/password
+fn key(name: &str) -> String {
+    let key: String = name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect();
+    match key.starts_with(|c: char| c.is_ascii_digit()) {
+        true => format!("_{}", key),
+        false => key,
+    }
+}
+
+/// Quotes a value if it has characters a shell or dotenv parser would treat specially. Double
+/// quotes are used, with backslashes, quotes, dollar signs, backticks, and newlines escaped.
+fn quote(value: &str) -> String {
+    if !value.is_empty() && value.chars().all(|c| c.is_ascii_alphanumeric() || "_-.,:/@%+".contains(c)) {
+        return value.to_string();
+    }
+
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '\\' | '"' | '$' | '`' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod test {
+    use crate::crypto::Kdf;
+    use crate::dotenv;
+    use crate::vault::{self, Backend};
+
+    #[test]
+    fn entries_in_a_folder_render_as_quoted_variables() {
+        let yap_test = String::from(".yap_test_dotenv");
+        let mut vault = vault::create(Some(yap_test.clone()), "asdf", Kdf::default(), Backend::default(), None).unwrap();
+        vault.set_key("app/db/password", "pa$$ \"word\"\nline".to_string()).unwrap();
+        vault.set_key("app/api-key", "abc123".to_string()).unwrap();
+        vault.set_key("app/2fa", "".to_string()).unwrap();
+        vault.set_key("other/secret", "hidden".to_string()).unwrap();
+
+        let rendered = dotenv::render(vault.as_mut(), "app/").unwrap();
+        assert_eq!(rendered.as_str(), "_2FA=\"\"\nAPI_KEY=abc123\nDB_PASSWORD=\"pa\\$\\$ \\\"word\\\"\\nline\"\n");
+        assert!(dotenv::render(vault.as_mut(), "missing").is_err());
+
+        std::fs::remove_dir_all(yap_test).unwrap();
+    }
+}
@@ -0,0 +1,89 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use ring::rand::{SecureRandom, SystemRandom};
+
+use crate::error::{Error, Result};
+use crate::wipe;
+
+/// Opens the user's editor on a temporary file containing `initial`, and returns the contents of
+/// the file once the editor exits. The editor is taken from $VISUAL or $EDITOR, falling back to
+/// vi.
+///
+/// Since the file holds decrypted secrets, it is created readable only by the user, on a tmpfs
+/// such as /dev/shm when one is available so it never reaches the disk, and it is overwritten
+/// before being removed.
+pub fn edit(initial: &str) -> Result<String> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    edit_with(editor.as_str(), initial)
+}
+
+/// Opens `editor`, a command followed by any arguments, on a temporary file containing
+/// `initial`, see `edit`
+fn edit_with(editor: &str, initial: &str) -> Result<String> {
+    let mut args = editor.split_whitespace();
+    let program = args.next().ok_or(Error::NoEditor)?;
+
+    let p = temp_path()?;
+    let result = write_private(p.as_path(), initial.as_bytes()).and_then(|_| {
+        let status = Command::new(program).args(args).arg(p.as_path()).status()?;
+        if !status.success() {
+            return Err(Error::NoEditor);
+        }
+
+        let mut contents = String::new();
+        File::open(p.as_path())?.read_to_string(&mut contents)?;
+        Ok(contents)
+    });
+
+    wipe::wipe_file(p.as_path())?;
+    result
+}
+
+/// Returns a new random path for a temporary file, preferring a directory in memory
+fn temp_path() -> Result<PathBuf> {
+    let shm = Path::new("/dev/shm");
+    let dir = if shm.is_dir() { shm.to_path_buf() } else { std::env::temp_dir() };
+
+    let mut random = [0u8; 8];
+    SystemRandom::new().fill(&mut random)?;
+    let name: String = random.iter().map(|b| format!("{:02x}", b)).collect();
+
+    Ok(dir.join(format!("yap-{}.txt", name)))
+}
+
+/// Creates a new file which only the user can read and write, failing if it already exists
+pub(crate) fn write_private(p: &Path, contents: &[u8]) -> Result<()> {
+    let mut f = create_private(p)?;
+    f.write_all(contents)?;
+    Ok(f.sync_all()?)
+}
+
+/// Creates a new, empty file which only the user can read and write, failing if it already
+/// exists
+pub(crate) fn create_private(p: &Path) -> Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    Ok(options.open(p)?)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::editor::edit_with;
+
+    #[test]
+    fn edit_returns_contents_and_removes_file() {
+        // `true` leaves the file as-is, standing in for an editor the user saved without changes
+        assert_eq!(edit_with("true", "some note\nsecond line").unwrap(), "some note\nsecond line");
+        assert!(edit_with("false", "some note").is_err());
+    }
+}
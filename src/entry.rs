@@ -0,0 +1,209 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Serialize, Deserialize};
+use zeroize::Zeroizing;
+
+use crate::error::{Error, Result};
+use crate::generate::EntryPolicy;
+use crate::sign::Signature;
+
+/// EntryKind distinguishes the different kinds of entries which can be stored in a vault.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    #[default]
+    Login,
+    /// A secure note, whose text is kept in the notes field
+    Note,
+    /// A payment card, whose details are kept in the `number`, `cardholder`, `expiry`, and `code`
+    /// fields
+    Card,
+}
+
+/// Metadata records when an entry was created, last modified, and last accessed, as seconds since
+/// the Unix epoch. Entries written before timestamps were recorded have zero for unknown times.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(default)]
+pub struct Metadata {
+    pub created: i64,
+    pub modified: i64,
+    pub accessed: i64,
+}
+
+/// Returns the current time in seconds since the Unix epoch
+pub fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or_default()
+}
+
+/// Entry is a single item stored in a vault. It is serialized into a small YAML document which is
+/// encrypted as a whole, so the username, URL, notes, and custom fields are as protected as the
+/// password itself.
+///
+/// Entries written by older versions of yap were bare passwords rather than documents. These are
+/// still read as entries which only have a password, see `from_bytes`.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug, Default)]
+pub struct Entry {
+    pub kind: EntryKind,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub password: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub fields: BTreeMap<String, String>,
+    /// Tags for grouping entries across folders, such as `work` or `aws`
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub tags: BTreeSet<String>,
+    /// The rules for passwords generated for this entry, such as by rotate
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub policy: Option<EntryPolicy>,
+    /// Who last wrote the entry, if they signed it, see `sign::SigningVault`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<Signature>,
+    #[serde(default)]
+    pub metadata: Metadata,
+}
+
+impl Entry {
+    /// Creates a login entry with only a password
+    pub fn new(password: String) -> Self {
+        Entry { password, ..Default::default() }
+    }
+
+    /// Creates a secure note entry holding the text
+    pub fn note(text: String) -> Self {
+        Entry { kind: EntryKind::Note, notes: Some(text), ..Default::default() }
+    }
+
+    /// Returns the name of the field holding this entry's secret, which is shown when no other
+    /// field is asked for.
+    pub fn secret_field(&self) -> &'static str {
+        match self.kind {
+            EntryKind::Login => "password",
+            EntryKind::Note => "notes",
+            EntryKind::Card => "number",
+        }
+    }
+
+    /// Gets the value of a field by name. The built in fields are `password`, `username`, `url`,
+    /// and `notes`, and any other name refers to a custom field.
+    pub fn field(&self, name: &str) -> Option<&str> {
+        match name {
+            "password" => Some(self.password.as_str()),
+            "username" => self.username.as_deref(),
+            "url" => self.url.as_deref(),
+            "notes" => self.notes.as_deref(),
+            _ => self.fields.get(name).map(|value| value.as_str()),
+        }
+    }
+
+    /// Sets the value of a field by name, using the same names as `field`.
+    pub fn set_field(&mut self, name: &str, value: String) {
+        match name {
+            "password" => self.password = value,
+            "username" => self.username = Some(value),
+            "url" => self.url = Some(value),
+            "notes" => self.notes = Some(value),
+            _ => {
+                self.fields.insert(name.to_string(), value);
+            }
+        }
+    }
+
+    /// Renders the entry as text for the user to edit. Notes are edited as plain text, while other
+    /// entries are edited as a YAML document of their fields. Metadata is left out since it is
+    /// managed by the vault.
+    pub fn to_editable(&self) -> Result<String> {
+        if self.kind == EntryKind::Note {
+            return Ok(self.notes.clone().unwrap_or_default());
+        }
+
+        let mut doc = serde_yaml::to_value(self)?;
+        if let Some(mapping) = doc.as_mapping_mut() {
+            mapping.remove("metadata");
+        }
+        Ok(serde_yaml::to_string(&doc)?)
+    }
+
+    /// Parses text edited by the user back into an entry, see `to_editable`. The entry keeps this
+    /// entry's metadata.
+    pub fn from_editable(&self, text: &str) -> Result<Entry> {
+        let mut entry = if self.kind == EntryKind::Note {
+            Entry::note(text.to_string())
+        } else {
+            serde_yaml::from_str(text)?
+        };
+
+        entry.metadata = self.metadata;
+        Ok(entry)
+    }
+
+    /// Serializes the entry into the plaintext that is encrypted in the vault
+    pub fn to_bytes(&self) -> Result<Zeroizing<Vec<u8>>> {
+        Ok(Zeroizing::new(serde_yaml::to_string(self)?.into_bytes()))
+    }
+
+    /// Parses decrypted plaintext into an entry. Plaintext which is not an entry document was
+    /// written by an older version of yap, and is the password itself.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Entry> {
+        match serde_yaml::from_slice(bytes) {
+            Ok(entry) => Ok(entry),
+            Err(_) => Ok(Entry::new(String::from_utf8(bytes.to_vec())?)),
+        }
+    }
+}
+
+/// Parses a custom field given on the command line as `name=value`
+pub fn parse_field(field: &str) -> Result<(String, String)> {
+    field.split_once('=')
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .ok_or_else(|| Error::BadField { field: field.to_string() })
+}
+
+/// Parses a tag given on the command line. Tags can't be empty or hold whitespace or commas, so
+/// they can be listed on one line.
+pub fn parse_tag(tag: &str) -> Result<String> {
+    let tag = tag.trim();
+    if tag.is_empty() || tag.contains(|c: char| c.is_whitespace() || c == ',') {
+        return Err(Error::BadTag { tag: tag.to_string() });
+    }
+    Ok(tag.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::entry::{Entry, parse_field, parse_tag};
+
+    #[test]
+    fn entries_round_trip_and_read_bare_passwords() {
+        let mut entry = Entry::new("password".to_string());
+        entry.set_field("username", "user".to_string());
+        entry.set_field("pin", "1234".to_string());
+
+        let read = Entry::from_bytes(entry.to_bytes().unwrap().as_slice()).unwrap();
+        assert_eq!(read, entry);
+        assert_eq!(read.field("username"), Some("user"));
+        assert_eq!(read.field("pin"), Some("1234"));
+        assert_eq!(read.field("url"), None);
+
+        let bare = Entry::from_bytes("password: not a document".as_bytes()).unwrap();
+        assert_eq!(bare.password, "password: not a document");
+
+        let edited = read.to_editable().unwrap();
+        assert!(!edited.contains("metadata"));
+        assert_eq!(read.from_editable(edited.replace("username: user", "username: other").as_str()).unwrap().field("username"), Some("other"));
+
+        let note = Entry::note("line one\nline two".to_string());
+        assert_eq!(note.to_editable().unwrap(), "line one\nline two");
+        assert_eq!(note.from_editable("new text").unwrap().notes.as_deref(), Some("new text"));
+
+        assert_eq!(parse_field("a=b=c").unwrap(), ("a".to_string(), "b=c".to_string()));
+        assert!(parse_field("abc").is_err());
+        assert_eq!(parse_tag(" aws ").unwrap(), "aws");
+        assert!(parse_tag("work,aws").is_err() && parse_tag("").is_err());
+    }
+}
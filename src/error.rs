@@ -5,18 +5,291 @@ pub enum Error {
     #[error("Key named {name} not found in this vault")]
     PasswordNotFound { name: String },
 
+    #[error("Key named {name} not found in this vault, did you mean {candidates}? Pass --first to use the closest match")]
+    NoExactMatch { name: String, candidates: String },
+
+    #[error("Invalid name {name}, names can't be empty or contain empty parts, parts can't start with '.', and config.yaml is reserved")]
+    BadKeyName { name: String },
+
+    #[error("Version {version} of {name} not found in its history")]
+    VersionNotFound { name: String, version: u32 },
+
+    #[error("Key named {name} already exists in this vault, pass --force to replace it")]
+    PasswordExists { name: String },
+
+    #[error("Entry {name} has no attachment named {attachment}")]
+    AttachmentNotFound { name: String, attachment: String },
+
+    #[error("Invalid attachment name {attachment}, names can't be empty, start with '.', or contain path separators")]
+    BadAttachmentName { attachment: String },
+
+    #[error("Field {field} should be given as name=value")]
+    BadField { field: String },
+
+    #[error("Tag {tag:?} can't be empty or hold spaces or commas")]
+    BadTag { tag: String },
+
+    #[error("{name} is not in the trash")]
+    NotInTrash { name: String },
+
+    #[error("Entry {name} has no field named {field}")]
+    FieldNotFound { name: String, field: String },
+
+    #[error("Entry {name} is not a login, only the passwords of logins can be rotated")]
+    NotALogin { name: String },
+
+    #[error("Unable to access the clipboard, install xclip, xsel, or wl-clipboard")]
+    NoClipboard,
+
+    #[error("Unable to run a picker, install rofi, wofi, or dmenu, or pass --picker")]
+    NoPicker,
+
+    #[error("Unable to type the secret, install xdotool or wtype")]
+    NoTyper,
+
+    #[error("Unable to run an editor, set $EDITOR to the editor to use")]
+    NoEditor,
+
     #[error("No home directory was found, could not process request")]
     NoHomeDir,
 
+    #[error("No vault header found in {path}, has the vault been initialized?")]
+    MissingHeader { path: String },
+
+    #[error("The vault in {path} is locked by another yap which is writing to it, try again once it has finished or raise the lock_wait setting")]
+    VaultLocked { path: String },
+
+    #[error("Incorrect master password")]
+    WrongPassword,
+
+    #[error("The vault is protected by a keyfile, set the keyfile setting to its path")]
+    MissingKeyfile,
+
+    #[error("{path} is not the keyfile for this vault")]
+    WrongKeyfile { path: String },
+
+    #[error("{name} is not a yap entry")]
+    NotAnEntry { name: String },
+
+    #[error("{name} is stored in format version {version}, which needs a newer version of yap")]
+    UnsupportedEntry { name: String, version: u8 },
+
+    #[error("No master password given, set the {var} environment variable when using --non-interactive")]
+    MissingPassword { var: String },
+
+    #[error("No value given, pipe it in with --stdin when using --non-interactive")]
+    MissingValue,
+
+    #[error("Passwords do not match")]
+    PasswordMismatch,
+
+    #[error("Invalid key derivation parameters in vault header")]
+    BadKdfParams,
+
+    #[error("Confirmation is required, pass --force to skip it when using --non-interactive")]
+    ConfirmationRequired,
+
+    #[error("Cannot generate a password from an empty set of characters")]
+    EmptyCharset,
+
+    #[error("No password can follow this policy, it requires characters which aren't allowed")]
+    UnsatisfiablePolicy,
+
+    #[error("A pattern can't end with a backslash, use \\\\ for a backslash in the password")]
+    BadPasswordPattern,
+
+    #[error("No remote_url is configured, set one with `yap config set remote_url <url>`")]
+    NoRemote,
+
+    #[error("Git error: {message}")]
+    Git { message: String },
+
+    #[error("Unable to decrypt with gpg: {message}")]
+    Gpg { message: String },
+
+    #[error("Unable to read the imported file: {message}")]
+    BadImport { message: String },
+
+    #[error("Unable to export the vault: {message}")]
+    BadExport { message: String },
+
+    #[error("Unable to restore the backup: {message}")]
+    BadBackup { message: String },
+
+    #[error("A vault already exists at {path}, restore the backup into another store")]
+    VaultExists { path: String },
+
+    #[error("The agent is not running, start it with yap agent start")]
+    AgentNotRunning,
+
+    #[error("The agent is already running")]
+    AgentRunning,
+
+    #[error("Sessions are disabled, set the session setting to agent or keychain to enable them")]
+    SessionsDisabled,
+
+    #[error("Unable to use the keychain: {message}")]
+    Keychain { message: String },
+
+    #[error("The keychain is not supported by this build, rebuild yap with the keyring feature")]
+    KeychainUnsupported,
+
+    #[error("Agent error: {message}")]
+    Agent { message: String },
+
+    #[error("Local and remote changes conflict for {}, choose which to keep with --resolve", names.join(", "))]
+    SyncConflict { names: Vec<String> },
+
+    #[error("WebDAV error: {message}")]
+    WebDav { message: String },
+
+    #[error("Refusing to serve {address} without TLS, pass --cert and --key or listen on a loopback address")]
+    InsecureServer { address: String },
+
+    #[error("Server error: {message}")]
+    Server { message: String },
+
+    #[error("Remote vault error: {message}")]
+    Remote { message: String },
+
+    #[error("{name} was changed on the server by another client, run the command again to use the latest version")]
+    RemoteChanged { name: String },
+
+    #[error("No token given for the remote vault, set the {var} environment variable")]
+    MissingToken { var: String },
+
+    #[error("A token named {name} already exists, revoke it first to replace it")]
+    TokenExists { name: String },
+
+    #[error("No token named {name}")]
+    TokenNotFound { name: String },
+
+    #[error("No askpass rule matches the prompt {prompt:?}, add one with yap config askpass add")]
+    NoAskpassRule { prompt: String },
+
+    #[error("No askpass rule has the pattern {pattern}")]
+    AskpassRuleNotFound { pattern: String },
+
+    #[error("Invalid variable {var}, variables should be given as NAME=entry or NAME=entry#field")]
+    BadEnvVar { var: String },
+
+    #[error("No command given to run")]
+    MissingCommand,
+
+    #[error("{path} already exists, pass --force to replace it")]
+    FileExists { path: String },
+
+    #[error("Unable to create the container secret: {message}")]
+    Docker { message: String },
+
+    #[error("Unable to share the entry: {message}")]
+    Share { message: String },
+
+    #[error("No age key was found in {path}")]
+    BadShareKey { path: String },
+
+    #[error("{name} is not a member of the vault")]
+    MemberNotFound { name: String },
+
+    #[error("Unable to add the member: {message}")]
+    Member { message: String },
+
+    #[error("Invalid recovery share {share}")]
+    BadRecoveryShare { share: String },
+
+    #[error("{needed} recovery shares are needed, but only {given} were given")]
+    NotEnoughShares { needed: usize, given: usize },
+
+    #[error("The recovery shares don't belong together, or don't belong to this vault")]
+    WrongRecoveryShares,
+
+    #[error("The threshold must be at least 2, and no more than the number of shares")]
+    BadThreshold,
+
+    #[error("The signature of {name} doesn't match {author}'s key, it was changed or forged after it was signed")]
+    BadSignature { name: String, author: String },
+
+    #[error("Invalid key for author {name}, keys are the base64 public keys printed by yap sign-key")]
+    BadAuthorKey { name: String },
+
+    #[error("No author named {name} is trusted")]
+    AuthorNotFound { name: String },
+
+    #[error("The signing key in {path} can't be read")]
+    BadSignKey { path: String },
+
+    #[error("Invalid batch, {message}")]
+    BadBatch { message: String },
+
+    #[error("The vault is read-only, leave out --read-only or turn off the read_only setting to change it")]
+    ReadOnly,
+
+    #[error("The vault has no master key to share with members, change the master password with yap rekey first")]
+    NoMasterKey,
+
+    #[error("Secret Service error: {message}")]
+    SecretService { message: String },
+
+    #[error("The Secret Service is not supported by this build, rebuild yap with the secret-service feature")]
+    SecretServiceUnsupported,
+
+    #[error("Unable to check for breached passwords: {message}")]
+    Breach { message: String },
+
+    #[error("The audit found problems with {count} passwords")]
+    AuditFailed { count: usize },
+
+    #[error("Verifying the vault found {count} problems")]
+    VerifyFailed { count: usize },
+
+    #[error("yap doctor found {count} problems")]
+    DoctorFailed { count: usize },
+
+    #[error("No automatic backup {id} was found, see yap backup list")]
+    BackupNotFound { id: String },
+
+    #[error("No snapshot {id} was found, see yap snapshot list")]
+    SnapshotNotFound { id: String },
+
+    #[error("Snapshot {id} is missing or has a corrupted copy of {name}, nothing was rolled back")]
+    BadSnapshot { id: String, name: String },
+
+    #[error("The activity log can't be read at line {line}, it may be corrupt")]
+    BadActivityLog { line: usize },
+
+    #[error("The terminal interface is not supported by this build, rebuild yap with the tui feature")]
+    TuiUnsupported,
+
+    #[error("Completions can't be generated for {shell}")]
+    UnsupportedShell { shell: String },
+
+    #[error("Invalid store name {name}, names can't be empty, start with '.', or contain path separators")]
+    BadStoreName { name: String },
+
+    #[error("No store is registered as {name}")]
+    StoreNotFound { name: String },
+
     #[error("Config key {key} does not exist")]
     BadConfigKey { key: String },
 
+    #[error("Config value {value:?} is not valid for {key}, expected {expected}")]
+    BadConfigValue { key: String, value: String, expected: &'static str },
+
+    #[error("{key} differs between machines, so it can't be set in {path}, which is synced. Set it in the vault's .local.yaml instead")]
+    LocalSetting { key: String, path: String },
+
+    #[error("Invalid search pattern: {0}")]
+    BadPattern(#[from] regex::Error),
+
     #[error("Cryptographic error")]
     CryptoError(#[from] ring::error::Unspecified),
 
     #[error("IO Error: {0}")]
     StdIO(#[from] std::io::Error),
 
+    #[error("Database error: {0}")]
+    Database(#[from] rusqlite::Error),
+
     #[error("Unable to serialize or deserialize: {0}")]
     SerdeYaml(#[from] serde_yaml::Error),
 
@@ -24,6 +297,47 @@ pub enum Error {
     UTF8Error(#[from] std::string::FromUtf8Error)
 }
 
+/// The exit code for errors without a more specific code
+pub const EXIT_FAILURE: i32 = 1;
+/// The exit code when an entry, field, or version doesn't exist
+pub const EXIT_NOT_FOUND: i32 = 3;
+/// The exit code when the master password or keyfile is wrong or missing
+pub const EXIT_AUTH: i32 = 4;
+/// The exit code when a file can't be read or written
+pub const EXIT_IO: i32 = 5;
+/// The exit code when syncing with the remote repository fails
+pub const EXIT_SYNC: i32 = 6;
+/// The exit code when an audit finds problems
+pub const EXIT_AUDIT: i32 = 7;
+/// The exit code when a vault file can't be decrypted or isn't understood
+pub const EXIT_CORRUPT: i32 = 8;
+/// The exit code when a confirmation or value is needed but can't be asked for
+pub const EXIT_INPUT: i32 = 9;
+
+impl Error {
+    /// Returns the code yap exits with for the error, so scripts can tell failures apart. Exit
+    /// code 2 is left for invalid arguments, which clap reports before any command runs.
+    pub fn code(&self) -> i32 {
+        match self {
+            Error::PasswordNotFound { .. } | Error::FieldNotFound { .. } | Error::VersionNotFound { .. }
+            | Error::NoExactMatch { .. } | Error::TokenNotFound { .. }
+            | Error::NoAskpassRule { .. } | Error::MemberNotFound { .. } | Error::AttachmentNotFound { .. }
+            | Error::NotInTrash { .. } | Error::BackupNotFound { .. } | Error::SnapshotNotFound { .. } => EXIT_NOT_FOUND,
+            Error::WrongPassword | Error::MissingKeyfile | Error::WrongKeyfile { .. } | Error::MissingPassword { .. }
+            | Error::PasswordMismatch | Error::MissingToken { .. } | Error::WrongRecoveryShares => EXIT_AUTH,
+            Error::StdIO(_) | Error::Database(_) | Error::NoHomeDir => EXIT_IO,
+            Error::SyncConflict { .. } | Error::Git { .. } | Error::WebDav { .. }
+            | Error::NoRemote | Error::Remote { .. } | Error::RemoteChanged { .. } => EXIT_SYNC,
+            Error::AuditFailed { .. } => EXIT_AUDIT,
+            Error::CryptoError(_) | Error::BadSignature { .. } | Error::NotAnEntry { .. } | Error::UnsupportedEntry { .. } | Error::MissingHeader { .. }
+            | Error::BadKdfParams | Error::VerifyFailed { .. } | Error::BadSnapshot { .. }
+            | Error::BadActivityLog { .. } => EXIT_CORRUPT,
+            Error::ConfirmationRequired | Error::MissingValue => EXIT_INPUT,
+            _ => EXIT_FAILURE,
+        }
+    }
+}
+
 impl From<Error> for String {
     fn from(e: Error) -> Self {
         e.to_string()
@@ -31,3 +345,16 @@ impl From<Error> for String {
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod test {
+    use crate::error::{Error, EXIT_AUTH, EXIT_FAILURE, EXIT_IO, EXIT_NOT_FOUND};
+
+    #[test]
+    fn errors_have_exit_codes() {
+        assert_eq!(Error::PasswordNotFound { name: "github".to_string() }.code(), EXIT_NOT_FOUND);
+        assert_eq!(Error::WrongPassword.code(), EXIT_AUTH);
+        assert_eq!(Error::from(std::io::Error::other("disk full")).code(), EXIT_IO);
+        assert_eq!(Error::EmptyCharset.code(), EXIT_FAILURE);
+    }
+}
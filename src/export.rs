@@ -0,0 +1,110 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::editor;
+use crate::entry::Entry;
+use crate::error::{Error, Result};
+use crate::vault::Vault;
+
+/// ExportFormat is the plaintext format entries are exported in
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ExportFormat {
+    /// A JSON array holding every entry with its name, fields, and metadata
+    Json,
+    /// A CSV file with a row for each entry, which browsers and most password managers can import
+    Csv,
+}
+
+#[derive(Serialize)]
+struct ExportedEntry {
+    name: String,
+    #[serde(flatten)]
+    entry: Entry,
+}
+
+/// Decrypts every entry in the vault and renders them in plaintext, for moving to another password
+/// manager or auditing a vault. The result must be handled with care, since anyone who can read
+/// it can read every password.
+pub fn export(vault: &dyn Vault, format: ExportFormat) -> Result<String> {
+    let entries = vault.list_keys("")?.into_iter()
+        .map(|name| vault.get_entry(name.as_str()).map(|entry| ExportedEntry { name, entry }))
+        .collect::<Result<Vec<_>>>()?;
+
+    match format {
+        ExportFormat::Json => serde_json::to_string_pretty(&entries).map_err(|e| Error::BadExport { message: e.to_string() }),
+        ExportFormat::Csv => to_csv(&entries),
+    }
+}
+
+/// Writes an export to a new file which only the user can read. Existing files are never
+/// overwritten.
+pub fn write(p: &Path, contents: &str) -> Result<()> {
+    editor::write_private(p, contents.as_bytes())
+}
+
+/// Renders entries as CSV. The first columns match the names used by browser exports, so the
+/// file can be imported again with `yap import csv`, followed by a column for each custom field.
+fn to_csv(entries: &[ExportedEntry]) -> Result<String> {
+    let fields: BTreeSet<&String> = entries.iter().flat_map(|e| e.entry.fields.keys()).collect();
+    let bad_export = |e: csv::Error| Error::BadExport { message: e.to_string() };
+
+    let mut writer = csv::Writer::from_writer(vec![]);
+    let header = ["name", "kind", "url", "username", "password", "notes"].into_iter()
+        .chain(fields.iter().map(|field| field.as_str()));
+    writer.write_record(header).map_err(bad_export)?;
+
+    for exported in entries {
+        let entry = &exported.entry;
+        let kind = serde_yaml::to_string(&entry.kind)?;
+        let row = [exported.name.as_str(), kind.trim(), entry.url.as_deref().unwrap_or_default(),
+            entry.username.as_deref().unwrap_or_default(), entry.password.as_str(), entry.notes.as_deref().unwrap_or_default()];
+        let custom = fields.iter().map(|field| entry.fields.get(*field).map(|value| value.as_str()).unwrap_or_default());
+        writer.write_record(row.into_iter().chain(custom)).map_err(bad_export)?;
+    }
+
+    let bytes = writer.into_inner().map_err(|e| Error::BadExport { message: e.to_string() })?;
+    Ok(String::from_utf8(bytes)?)
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use crate::crypto::Kdf;
+    use crate::entry::Entry;
+    use crate::export::{export, ExportFormat};
+    use crate::import::ImportCommand;
+    use crate::vault::{self, Backend};
+
+    #[test]
+    fn export_and_import_csv() {
+        let yap_test = String::from(".yap_test_export");
+        std::fs::create_dir_all(Path::new(yap_test.as_str())).unwrap();
+
+        let mut vault = vault::create(Some(yap_test.clone()), "asdf", Kdf::default(), Backend::default(), None).unwrap();
+        let mut entry = Entry::new("hunter2".to_string());
+        entry.set_field("username", "alice".to_string());
+        entry.set_field("pin", "1234".to_string());
+        vault.set_entry("work/github", &entry).unwrap();
+        vault.set_key("email", "swordfish".to_string()).unwrap();
+
+        let json = export(vault.as_ref(), ExportFormat::Json).unwrap();
+        assert!(json.contains("\"name\": \"work/github\""));
+        assert!(json.contains("\"pin\": \"1234\""));
+
+        let csv = export(vault.as_ref(), ExportFormat::Csv).unwrap();
+        assert_eq!(csv.lines().next(), Some("name,kind,url,username,password,notes,pin"));
+
+        let path = Path::new(yap_test.as_str()).join("export.csv");
+        std::fs::write(&path, csv).unwrap();
+        let imported = ImportCommand::Csv { path: path.to_string_lossy().to_string(), map: vec![] }.read(true).unwrap();
+        assert_eq!(imported[1].0, "work/github");
+        assert_eq!(imported[1].1.field("username"), Some("alice"));
+        assert_eq!(imported[1].1.password, "hunter2");
+
+        std::fs::remove_dir_all(yap_test).unwrap();
+    }
+}
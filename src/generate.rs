@@ -0,0 +1,414 @@
+use clap::ValueEnum;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+
+const LOWERCASE: &str = "abcdefghijklmnopqrstuvwxyz";
+const UPPERCASE: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const DIGITS: &str = "0123456789";
+const SYMBOLS: &str = "!@#$%^&*()-_=+[]{};:,.<>/?~";
+
+/// Letters which make up the syllables of pronounceable passwords. Consonants which sound alike
+/// or are easily misheard, such as c, q, and x, are left out.
+const CONSONANTS: &str = "bdfghjklmnprstvz";
+const VOWELS: &str = "aeiou";
+
+/// Symbols which are easy to name when reading a pronounceable password aloud
+const SPOKEN_SYMBOLS: &str = "!@#$%&*+=?";
+
+/// The EFF large wordlist, 7776 words each numbered by the five dice rolls which choose it
+const WORDLIST: &str = include_str!("eff_large_wordlist.txt");
+
+/// The most passwords generated while looking for one with every required class of character
+const MAX_ATTEMPTS: usize = 1000;
+
+/// CharClass is a class of characters which a site may require passwords to contain
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum CharClass {
+    Lowercase,
+    Uppercase,
+    Digit,
+    Symbol,
+}
+
+impl CharClass {
+    fn contains(&self, c: char) -> bool {
+        match self {
+            CharClass::Lowercase => c.is_lowercase(),
+            CharClass::Uppercase => c.is_uppercase(),
+            CharClass::Digit => c.is_ascii_digit(),
+            CharClass::Symbol => !c.is_alphanumeric(),
+        }
+    }
+}
+
+/// EntryPolicy is a site's rules for its passwords, stored with the site's entry so passwords
+/// generated for it follow them. Each rule which is set replaces the generate settings.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug, Default)]
+pub struct EntryPolicy {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub length: Option<usize>,
+    /// The symbols the site allows, or an empty string if it allows none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub symbols: Option<String>,
+    /// The classes of characters every password must contain at least one of
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub require: Vec<CharClass>,
+}
+
+impl EntryPolicy {
+    /// Returns the policy with these rules applied on top of it
+    pub fn apply(&self, policy: PasswordPolicy) -> PasswordPolicy {
+        let symbols = match &self.symbols {
+            Some(symbols) => !symbols.is_empty(),
+            None => policy.symbols,
+        };
+        PasswordPolicy {
+            length: self.length.unwrap_or(policy.length),
+            symbols,
+            symbol_set: self.symbols.clone().or(policy.symbol_set),
+            require: self.require.clone(),
+            ..policy
+        }
+    }
+
+    /// Returns whether none of the rules are set
+    pub fn is_empty(&self) -> bool {
+        self == &EntryPolicy::default()
+    }
+}
+
+/// PasswordPolicy describes the passwords that should be generated. By default, passwords are
+/// made up of upper and lowercase letters, digits, and symbols. Digits and symbols may be left
+/// out, or an explicit charset may be given which replaces the default character classes. A
+/// password may also be required to contain certain classes of characters.
+pub struct PasswordPolicy {
+    pub length: usize,
+    pub symbols: bool,
+    pub digits: bool,
+    pub charset: Option<String>,
+    /// The symbols to use instead of the default ones
+    pub symbol_set: Option<String>,
+    pub require: Vec<CharClass>,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        PasswordPolicy { length: 20, symbols: true, digits: true, charset: None, symbol_set: None, require: Vec::new() }
+    }
+}
+
+impl PasswordPolicy {
+    /// Returns the policy for PINs, such as for devices and bank cards, which are only digits.
+    /// Every digit is chosen independently, so a PIN may start with zeros.
+    pub fn pin(length: usize) -> Self {
+        PasswordPolicy { length, symbols: false, charset: Some(DIGITS.to_string()), ..Default::default() }
+    }
+
+    /// Returns the characters which may appear in a generated password, without duplicates so
+    /// that every character is equally likely to be chosen.
+    fn characters(&self) -> Vec<char> {
+        let mut chars: Vec<char> = match &self.charset {
+            Some(charset) => charset.chars().collect(),
+            None => {
+                let mut classes = vec![LOWERCASE, UPPERCASE];
+                if self.digits {
+                    classes.push(DIGITS);
+                }
+                if self.symbols {
+                    classes.push(self.symbol_set.as_deref().unwrap_or(SYMBOLS));
+                }
+                classes.concat().chars().collect()
+            }
+        };
+
+        chars.sort_unstable();
+        chars.dedup();
+        chars
+    }
+
+    /// Returns the bits of entropy in a password generated with this policy
+    pub fn entropy(&self) -> f64 {
+        self.length as f64 * (self.characters().len() as f64).log2()
+    }
+
+    /// Returns the number of digits and symbols sprinkled into a pronounceable password of this
+    /// length, about one digit for every eight characters and a single symbol
+    fn sprinkles(&self) -> (usize, usize) {
+        let digits = if self.digits && self.length >= 6 { (self.length / 8).max(1) } else { 0 };
+        let symbols = if self.symbols && self.length >= 8 { 1 } else { 0 };
+        (digits, symbols)
+    }
+
+    /// Returns the bits of entropy in a pronounceable password generated with this policy. The
+    /// positions of the digits and symbols aren't counted, so this is a lower bound.
+    pub fn pronounceable_entropy(&self) -> f64 {
+        let (digits, symbols) = self.sprinkles();
+        let letters = self.length - digits - symbols;
+        letters.div_ceil(2) as f64 * (CONSONANTS.len() as f64).log2()
+            + (letters / 2) as f64 * (VOWELS.len() as f64).log2()
+            + digits as f64 * (DIGITS.len() as f64).log2()
+            + symbols as f64 * (SPOKEN_SYMBOLS.len() as f64).log2()
+    }
+}
+
+/// PassphrasePolicy describes the diceware passphrases that should be generated, made up of
+/// words chosen from the EFF large wordlist and joined by a separator.
+pub struct PassphrasePolicy {
+    pub words: usize,
+    pub separator: String,
+    pub capitalize: bool,
+}
+
+impl Default for PassphrasePolicy {
+    fn default() -> Self {
+        PassphrasePolicy { words: 6, separator: "-".to_string(), capitalize: false }
+    }
+}
+
+impl PassphrasePolicy {
+    /// Returns the bits of entropy in a passphrase generated with this policy, about 12.9 for
+    /// each word. Capitalizing every word adds none.
+    pub fn entropy(&self) -> f64 {
+        self.words as f64 * (wordlist().len() as f64).log2()
+    }
+}
+
+/// Returns the words of the wordlist, without their dice rolls
+fn wordlist() -> Vec<&'static str> {
+    WORDLIST.lines().filter_map(|line| line.split('\t').nth(1)).collect()
+}
+
+/// Generates a password according to the policy, choosing each character uniformly at random
+/// using the system's secure random number generator.
+pub fn generate(policy: &PasswordPolicy) -> Result<String> {
+    let chars = policy.characters();
+    if chars.is_empty() {
+        return Err(Error::EmptyCharset);
+    }
+
+    // A password without a required class is thrown away, so every password which meets the
+    // policy is equally likely
+    let possible = policy.require.iter().all(|class| chars.iter().any(|c| class.contains(*c)));
+    if !possible || policy.require.len() > policy.length {
+        return Err(Error::UnsatisfiablePolicy);
+    }
+
+    let rng = SystemRandom::new();
+    for _ in 0..MAX_ATTEMPTS {
+        let pw: String = (0..policy.length)
+            .map(|_| random_index(&rng, chars.len()).map(|i| chars[i]))
+            .collect::<Result<String>>()?;
+        if policy.require.iter().all(|class| pw.chars().any(|c| class.contains(c))) {
+            return Ok(pw);
+        }
+    }
+    Err(Error::UnsatisfiablePolicy)
+}
+
+/// Returns the characters a character of a pattern may be replaced with, or None if it stands
+/// for itself. See `pattern` for the characters which stand for classes.
+fn pattern_class(c: char) -> Option<String> {
+    match c {
+        'a' => Some(LOWERCASE.to_string()),
+        'A' => Some(UPPERCASE.to_string()),
+        '9' => Some(DIGITS.to_string()),
+        's' => Some(SYMBOLS.to_string()),
+        'x' => Some([LOWERCASE, UPPERCASE, DIGITS].concat()),
+        '*' => Some([LOWERCASE, UPPERCASE, DIGITS, SYMBOLS].concat()),
+        _ => None,
+    }
+}
+
+/// Splits a pattern into the set of characters each position of the password is chosen from. A
+/// literal character is a set of one.
+fn pattern_positions(pattern: &str) -> Result<Vec<String>> {
+    let mut positions = Vec::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        let position = match c {
+            '\\' => chars.next().ok_or(Error::BadPasswordPattern)?.to_string(),
+            c => pattern_class(c).unwrap_or_else(|| c.to_string()),
+        };
+        positions.push(position);
+    }
+    Ok(positions)
+}
+
+/// Generates a password from a pwgen style pattern, for sites with very specific rules. Each
+/// character of the pattern is replaced with a random character of its class: `a` for lowercase
+/// letters, `A` for uppercase letters, `9` for digits, `s` for symbols, `x` for letters and
+/// digits, and `*` for any of them. Any other character stands for itself, and a backslash
+/// makes the next one stand for itself, so `Aaaa-9999` could be `Kbrt-4071`.
+pub fn pattern(pattern: &str) -> Result<String> {
+    let rng = SystemRandom::new();
+    pattern_positions(pattern)?.iter()
+        .map(|set| random_index(&rng, set.len()).map(|i| set.as_bytes()[i] as char))
+        .collect()
+}
+
+/// Returns the bits of entropy in a password generated from the pattern
+pub fn pattern_entropy(pattern: &str) -> Result<f64> {
+    Ok(pattern_positions(pattern)?.iter().map(|set| (set.len() as f64).log2()).sum())
+}
+
+/// Generates a password made of syllables which alternate consonants and vowels, so it can be
+/// read aloud, such as over the phone. The length, digits, and symbols of the policy are
+/// followed, with digits and symbols sprinkled between the syllables. The charset is ignored.
+pub fn pronounceable(policy: &PasswordPolicy) -> Result<String> {
+    let rng = SystemRandom::new();
+    let choose = |set: &str| random_index(&rng, set.len()).map(|i| set.as_bytes()[i] as char);
+
+    let (digits, symbols) = policy.sprinkles();
+    let letters = policy.length - digits - symbols;
+    let mut syllables = Vec::new();
+    for i in (0..letters).step_by(2) {
+        let mut syllable = String::from(choose(CONSONANTS)?);
+        if i + 1 < letters {
+            syllable.push(choose(VOWELS)?);
+        }
+        syllables.push(syllable);
+    }
+
+    let sprinkles = (0..digits).map(|_| choose(DIGITS)).chain((0..symbols).map(|_| choose(SPOKEN_SYMBOLS)));
+    for sprinkle in sprinkles {
+        let position = random_index(&rng, syllables.len() + 1)?;
+        syllables.insert(position, sprinkle?.to_string());
+    }
+    Ok(syllables.concat())
+}
+
+/// Generates a passphrase according to the policy, choosing each word uniformly at random from
+/// the wordlist using the system's secure random number generator.
+pub fn passphrase(policy: &PassphrasePolicy) -> Result<String> {
+    let words = wordlist();
+    let rng = SystemRandom::new();
+    let chosen = (0..policy.words)
+        .map(|_| random_index(&rng, words.len()).map(|i| match policy.capitalize {
+            true => capitalize(words[i]),
+            false => words[i].to_string(),
+        }))
+        .collect::<Result<Vec<String>>>()?;
+    Ok(chosen.join(policy.separator.as_str()))
+}
+
+/// Returns the word with its first letter in uppercase
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// Returns a random index less than `bound`. Random values which would bias the result towards
+/// lower indices are rejected and drawn again.
+pub(crate) fn random_index(rng: &SystemRandom, bound: usize) -> Result<usize> {
+    let bound = bound as u64;
+    let zone = (1u64 << 32) - ((1u64 << 32) % bound);
+
+    loop {
+        let mut buf = [0u8; 4];
+        rng.fill(&mut buf)?;
+
+        let value = u32::from_be_bytes(buf) as u64;
+        if value < zone {
+            return Ok((value % bound) as usize);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::generate::{generate, passphrase, pronounceable, wordlist, PassphrasePolicy, PasswordPolicy, SYMBOLS, DIGITS};
+    use crate::generate::{pattern, pattern_entropy, CharClass, EntryPolicy, CONSONANTS, SPOKEN_SYMBOLS, VOWELS};
+
+    #[test]
+    fn generates_passwords_following_policy() {
+        let pw = generate(&PasswordPolicy::default()).unwrap();
+        assert_eq!(pw.chars().count(), 20);
+
+        let policy = PasswordPolicy { length: 64, symbols: false, digits: false, ..Default::default() };
+        let pw = generate(&policy).unwrap();
+        assert_eq!(pw.len(), 64);
+        assert!(!pw.chars().any(|c| SYMBOLS.contains(c) || DIGITS.contains(c)));
+
+        let policy = PasswordPolicy { length: 32, charset: Some("ab".to_string()), ..Default::default() };
+        let pw = generate(&policy).unwrap();
+        assert!(pw.chars().all(|c| c == 'a' || c == 'b'));
+
+        let policy = PasswordPolicy { charset: Some(String::new()), ..Default::default() };
+        assert!(generate(&policy).is_err());
+
+        let pin = generate(&PasswordPolicy::pin(6)).unwrap();
+        assert!(pin.len() == 6 && pin.chars().all(|c| DIGITS.contains(c)));
+        assert_eq!(PasswordPolicy::pin(6).entropy().round(), 20.0);
+    }
+
+    #[test]
+    fn entry_policies_replace_the_defaults() {
+        let entry_policy = EntryPolicy { length: Some(12), symbols: Some("!-".to_string()), require: vec![CharClass::Digit, CharClass::Symbol] };
+        let policy = entry_policy.apply(PasswordPolicy::default());
+        for _ in 0..20 {
+            let pw = generate(&policy).unwrap();
+            assert_eq!(pw.len(), 12);
+            assert!(pw.chars().any(|c| DIGITS.contains(c)));
+            assert!(pw.chars().filter(|c| !c.is_alphanumeric()).all(|c| c == '!' || c == '-'));
+        }
+
+        let entry_policy = EntryPolicy { symbols: Some(String::new()), require: vec![CharClass::Symbol], ..Default::default() };
+        assert!(generate(&entry_policy.apply(PasswordPolicy::default())).is_err());
+        assert!(EntryPolicy::default().is_empty());
+    }
+
+    #[test]
+    fn generates_passwords_from_patterns() {
+        let pw = pattern("Aaaa-9999-ssss").unwrap();
+        let chars: Vec<char> = pw.chars().collect();
+        assert_eq!(chars.len(), 14);
+        assert!(chars[0].is_ascii_uppercase() && chars[1..4].iter().all(|c| c.is_ascii_lowercase()));
+        assert!(chars[4] == '-' && chars[9] == '-');
+        assert!(chars[5..9].iter().all(|c| DIGITS.contains(*c)) && chars[10..].iter().all(|c| SYMBOLS.contains(*c)));
+
+        let pw = pattern("\\a\\9-a").unwrap();
+        assert!(pw.starts_with("a9-") && pw.len() == 4);
+        assert_eq!(pattern_entropy("9999-").unwrap().round(), 13.0);
+        assert!(pattern("a\\").is_err());
+    }
+
+    #[test]
+    fn generates_pronounceable_passwords() {
+        let policy = PasswordPolicy { length: 16, ..Default::default() };
+        let pw = pronounceable(&policy).unwrap();
+        assert_eq!(pw.len(), 16);
+        assert_eq!(pw.chars().filter(|c| DIGITS.contains(*c)).count(), 2);
+        assert_eq!(pw.chars().filter(|c| SPOKEN_SYMBOLS.contains(*c)).count(), 1);
+
+        let letters: String = pw.chars().filter(|c| c.is_ascii_lowercase()).collect();
+        for (i, c) in letters.chars().enumerate() {
+            assert!(if i % 2 == 0 { CONSONANTS.contains(c) } else { VOWELS.contains(c) });
+        }
+
+        let policy = PasswordPolicy { length: 9, symbols: false, digits: false, ..Default::default() };
+        let pw = pronounceable(&policy).unwrap();
+        assert!(pw.len() == 9 && pw.chars().all(|c| c.is_ascii_lowercase()));
+        assert!(policy.pronounceable_entropy() < policy.entropy());
+    }
+
+    #[test]
+    fn generates_passphrases_from_the_wordlist() {
+        let words = wordlist();
+        assert_eq!(words.len(), 7776);
+        assert_eq!((words[0], words[7775]), ("abacus", "zoom"));
+
+        let policy = PassphrasePolicy { words: 5, separator: " ".to_string(), capitalize: true };
+        let phrase = passphrase(&policy).unwrap();
+        let chosen: Vec<&str> = phrase.split(' ').collect();
+        assert_eq!(chosen.len(), 5);
+        assert!(chosen.iter().all(|word| words.contains(&word.to_lowercase().as_str())));
+        assert!(chosen.iter().all(|word| word.starts_with(|c: char| c.is_ascii_uppercase())));
+        assert_eq!(policy.entropy().round(), 65.0);
+    }
+}
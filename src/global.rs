@@ -1,2 +1,39 @@
 pub const YAP_DIR: &str = ".yap";
 pub const CONFIG_FILE: &str = "config.yaml";
+pub const VAULT_CONFIG_FILE: &str = ".config.yaml";
+pub const LOCAL_CONFIG_FILE: &str = ".local.yaml";
+pub const HEADER_FILE: &str = ".header.yaml";
+pub const PASSWORD_VAR: &str = "PASS";
+pub const NEW_PASSWORD_VAR: &str = "NEW_PASS";
+pub const IMPORT_PASSWORD_VAR: &str = "IMPORT_PASS";
+pub const BACKUP_PASSWORD_VAR: &str = "BACKUP_PASS";
+pub const STORE_VAR: &str = "YAP_STORE";
+pub const WEBDAV_PASSWORD_VAR: &str = "WEBDAV_PASS";
+pub const SERVE_TOKEN_VAR: &str = "YAP_SERVE_TOKEN";
+pub const REMOTE_TOKEN_VAR: &str = "YAP_REMOTE_TOKEN";
+pub const SETTING_VAR_PREFIX: &str = "YAP_";
+pub const REKEY_DIR: &str = ".rekey";
+pub const BATCH_DIR: &str = ".batch";
+pub const REKEY_COMPLETE: &str = ".complete";
+pub const TEMP_SUFFIX: &str = ".tmp";
+pub const AGENT_DIR: &str = ".yap-agent";
+pub const AGENT_SOCKET: &str = "agent.sock";
+pub const REMOTE_CACHE_DIR: &str = ".yap-remotes";
+pub const HISTORY_DIR: &str = ".history";
+pub const ATTACHMENTS_DIR: &str = ".attachments";
+pub const ATTACHMENT_FILES_DIR: &str = ".files";
+pub const TRASH_DIR: &str = ".trash";
+pub const TRASH_ENTRY_FILE: &str = "entry";
+pub const QUARANTINE_DIR: &str = ".quarantine";
+pub const BACKUPS_DIR: &str = ".backups";
+pub const SNAPSHOTS_DIR: &str = ".snapshots";
+pub const ACTIVITY_FILE: &str = ".activity";
+pub const ACTIVITY_KEY_FILE: &str = ".activity.key";
+pub const SYNC_STATE_FILE: &str = ".sync.yaml";
+pub const LAST_SYNC_FILE: &str = ".last-sync";
+pub const TOKENS_FILE: &str = ".tokens.yaml";
+pub const INDEX_FILE: &str = ".index";
+pub const DATABASE_FILE: &str = ".entries.db";
+pub const LOCK_FILE: &str = ".lock";
+pub const SHARE_KEY_FILE: &str = ".yap-share.key";
+pub const SIGN_KEY_FILE: &str = ".yap-sign.key";
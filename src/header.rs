@@ -0,0 +1,212 @@
+use std::fs::File;
+use std::path::Path;
+
+use age::x25519::{Identity, Recipient};
+use serde::{Serialize, Deserialize};
+use zeroize::Zeroizing;
+
+use crate::crypto::{self, Aes256GcmEngine, Kdf, KEY_LEN, SALT_LEN};
+use crate::error::{Error, Result};
+use crate::global::HEADER_FILE;
+use crate::vault::{self, Backend};
+
+/// VaultHeader holds the unencrypted parameters required to open a vault, most importantly the
+/// random salt used to derive the encryption key from the master password. The header is written
+/// once when a vault is created and read back every time the vault is loaded, so each vault
+/// derives its own key even when the same master password is shared between vaults. The
+/// key derivation function and its parameters are stored too, and headers written before the
+/// Kdf was recorded are read as using the default PBKDF2 parameters. The backend used to store
+/// the vault's entries is recorded in the same way.
+///
+/// The version is the format that entries without a format marker of their own are encrypted in.
+/// Headers written before it was recorded are version 0, where entries are encrypted without
+/// associated data. In version 1, each entry is bound to its name, see
+/// `SimpleVault::associated_data`. Entries are now written with a marker, see `ENTRY_VERSION`.
+///
+/// Vaults protected by a keyfile as well as the master password record a salted hash of the
+/// keyfile, see `crypto::keyfile_hash`, so a wrong keyfile is caught before decrypting anything.
+/// Likewise, a verifier encrypted with the vault's key lets a wrong master password be reported
+/// as soon as the vault is loaded.
+///
+/// Vaults using envelope encryption have a random master key, which is stored here encrypted
+/// with the key derived from the master password. Entries are encrypted with data keys which are
+/// in turn encrypted with the master key, so changing the master password only needs the master
+/// key to be encrypted again.
+///
+/// Team vaults also list their members, who open the vault with their age key instead of the
+/// master password. Each member has their own copy of the master key, encrypted to their public
+/// key.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct VaultHeader {
+    pub salt: Vec<u8>,
+    #[serde(default)]
+    pub kdf: Kdf,
+    #[serde(default)]
+    pub backend: Backend,
+    #[serde(default)]
+    pub version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keyfile: Option<Vec<u8>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verifier: Option<Vec<u8>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub master_key: Option<Vec<u8>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub members: Vec<Member>,
+}
+
+/// Member is someone who can open a team vault with their age key, see `yap member`. The name is
+/// only used to tell members apart.
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct Member {
+    pub name: String,
+    pub recipient: String,
+    pub master_key: Vec<u8>,
+}
+
+/// The known plaintext encrypted as the verifier, which is also its associated data
+const VERIFIER: &[u8] = b"yap verifier";
+
+/// The associated data of the encrypted master key
+const MASTER_KEY: &[u8] = b"yap master key";
+
+/// The format version of newly created vaults
+pub(crate) const FORMAT_VERSION: u32 = 1;
+
+impl VaultHeader {
+    /// Creates a header for the given Kdf and backend with a newly generated random salt, using
+    /// the current format version.
+    pub fn new(kdf: Kdf, backend: Backend) -> Result<VaultHeader> {
+        Ok(VaultHeader { salt: crypto::new_salt()?.to_vec(), kdf, backend, version: FORMAT_VERSION, keyfile: None, verifier: None, master_key: None, members: vec![] })
+    }
+
+    /// Records a verifier for the vault's key, which `verify` checks keys against.
+    pub fn set_verifier(&mut self, key: &Zeroizing<[u8; KEY_LEN]>) -> Result<()> {
+        let engine = Aes256GcmEngine::with_key(key.clone());
+        self.verifier = Some(engine.encrypt_bytes(VERIFIER, VERIFIER)?);
+        Ok(())
+    }
+
+    /// Stores the vault's master key, encrypted with the key derived from the master password.
+    pub fn wrap_master_key(&mut self, key: &Zeroizing<[u8; KEY_LEN]>, master_key: &Zeroizing<[u8; KEY_LEN]>) -> Result<()> {
+        let engine = Aes256GcmEngine::with_key(key.clone());
+        self.master_key = Some(engine.encrypt_bytes(master_key.as_slice(), MASTER_KEY)?);
+        Ok(())
+    }
+
+    /// Decrypts the vault's master key with the key derived from the master password. None is
+    /// returned for vaults created before envelope encryption, which encrypt their entries with
+    /// the derived key directly.
+    pub fn unwrap_master_key(&self, key: &Zeroizing<[u8; KEY_LEN]>) -> Result<Option<Zeroizing<[u8; KEY_LEN]>>> {
+        let Some(wrapped) = self.master_key.as_ref() else { return Ok(None) };
+
+        let engine = Aes256GcmEngine::with_key(key.clone());
+        let master_key = engine.decrypt_bytes(wrapped.as_slice(), MASTER_KEY).map_err(|_| Error::WrongPassword)?;
+        let master_key: [u8; KEY_LEN] = master_key.as_slice().try_into().map_err(|_| Error::WrongPassword)?;
+        Ok(Some(Zeroizing::new(master_key)))
+    }
+
+    /// Gives a member access to the vault by encrypting its master key to their public key. A
+    /// member with the same name is replaced.
+    pub fn wrap_for_member(&mut self, name: &str, recipient: &Recipient, master_key: &Zeroizing<[u8; KEY_LEN]>) -> Result<()> {
+        let wrapped = age::encrypt(recipient, master_key.as_slice()).map_err(|e| Error::Member { message: e.to_string() })?;
+        self.members.retain(|member| member.name != name);
+        self.members.push(Member { name: name.to_string(), recipient: recipient.to_string(), master_key: wrapped });
+        self.members.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(())
+    }
+
+    /// Decrypts the vault's master key with a member's private key. None is returned if the key
+    /// doesn't belong to a member.
+    pub fn unwrap_as_member(&self, identity: &Identity) -> Result<Option<Zeroizing<[u8; KEY_LEN]>>> {
+        let recipient = identity.to_public().to_string();
+        let Some(member) = self.members.iter().find(|member| member.recipient == recipient) else { return Ok(None) };
+
+        let master_key = Zeroizing::new(age::decrypt(identity, member.master_key.as_slice()).map_err(|_| Error::WrongPassword)?);
+        let master_key: [u8; KEY_LEN] = master_key.as_slice().try_into().map_err(|_| Error::WrongPassword)?;
+        Ok(Some(Zeroizing::new(master_key)))
+    }
+
+    /// Checks that the key derived from a master password is the vault's key. Vaults created
+    /// before verifiers were recorded can't be checked until they are rekeyed, so any key passes.
+    pub fn verify(&self, key: &Zeroizing<[u8; KEY_LEN]>) -> Result<()> {
+        let Some(verifier) = self.verifier.as_ref() else { return Ok(()) };
+
+        let engine = Aes256GcmEngine::with_key(key.clone());
+        match engine.decrypt_bytes(verifier.as_slice(), VERIFIER) {
+            Ok(plaintext) if plaintext.as_slice() == VERIFIER => Ok(()),
+            _ => Err(Error::WrongPassword),
+        }
+    }
+
+    /// Describes what is wrong with the values in the header, if anything, see `Vault::verify`.
+    /// The verifier can't be checked without the master password, and the Kdf's parameters
+    /// without deriving a key.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = vec![];
+        if self.salt.len() != SALT_LEN {
+            problems.push(format!("the salt is {} bytes long rather than {}", self.salt.len(), SALT_LEN));
+        }
+        if self.version > FORMAT_VERSION {
+            problems.push(format!("format version {} is newer than this version of yap understands", self.version));
+        }
+        if !self.members.is_empty() && self.master_key.is_none() {
+            problems.push("the vault has members but no master key".to_string());
+        }
+        for member in self.members.iter().filter(|member| member.recipient.parse::<Recipient>().is_err()) {
+            problems.push(format!("member {} has an invalid public key", member.name));
+        }
+        problems
+    }
+
+    /// Reads the header stored in the given vault directory. A missing header means that the
+    /// directory was never initialized as a vault.
+    pub fn read(vault_dir: &Path) -> Result<VaultHeader> {
+        let p = vault_dir.join(HEADER_FILE);
+        if !p.as_path().exists() {
+            return Err(Error::MissingHeader { path: vault_dir.display().to_string() });
+        }
+
+        let f = File::open(p.as_path())?;
+        Ok(serde_yaml::from_reader(f)?)
+    }
+
+    /// Writes the header into the given vault directory, replacing any existing header.
+    pub fn write(&self, vault_dir: &Path) -> Result<()> {
+        vault::atomic_write(vault_dir.join(HEADER_FILE).as_path(), serde_yaml::to_string(self)?.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+    use crate::crypto::Kdf;
+    use crate::header::{VaultHeader, FORMAT_VERSION};
+    use crate::vault::Backend;
+
+    #[test]
+    fn write_and_read_header() {
+        let yap_test = Path::new(".yap_test_header");
+        std::fs::create_dir_all(yap_test).unwrap();
+
+        assert!(VaultHeader::read(yap_test).is_err());
+
+        let kdf = Kdf::parse("argon2id").unwrap();
+        let mut header = VaultHeader::new(kdf, Backend::Files).unwrap();
+        let key = kdf.derive_key("pass", header.salt.as_slice()).unwrap();
+        assert!(header.verify(&key).is_ok());
+        header.set_verifier(&key).unwrap();
+        header.write(yap_test).unwrap();
+
+        let read = VaultHeader::read(yap_test).unwrap();
+        assert_eq!(header.salt, read.salt);
+        assert_eq!(read.kdf, kdf);
+        assert_eq!(read.backend, Backend::Files);
+        assert_eq!(read.version, FORMAT_VERSION);
+        assert!(read.verify(&key).is_ok());
+        assert!(read.verify(&kdf.derive_key("wrong", header.salt.as_slice()).unwrap()).is_err());
+        assert_ne!(VaultHeader::new(kdf, Backend::Files).unwrap().salt, read.salt);
+
+        std::fs::remove_dir_all(yap_test).unwrap();
+    }
+}
@@ -0,0 +1,139 @@
+use std::collections::BTreeSet;
+
+use clap::Subcommand;
+
+use crate::entry::Entry;
+use crate::error::Result;
+use crate::prompt;
+use crate::vault::Vault;
+
+mod bitwarden;
+mod csv;
+mod kdbx;
+mod pass;
+
+/// ImportCommand selects the password manager to import entries from
+#[derive(Subcommand)]
+pub enum ImportCommand {
+    /// Import a password-store (pass) directory, decrypting each entry with gpg
+    Pass {
+        /// The password-store directory, defaults to $PASSWORD_STORE_DIR or ~/.password-store
+        path: Option<String>,
+    },
+
+    /// Import a KeePass database, prompting for its password
+    Kdbx {
+        /// The database file
+        path: String,
+
+        /// A keyfile which protects the database in addition to or instead of its password
+        #[arg(short, long)]
+        keyfile: Option<String>,
+    },
+
+    /// Import a Bitwarden JSON export, prompting for its password if it is password protected
+    Bitwarden {
+        /// The exported JSON file
+        path: String,
+    },
+
+    /// Import a CSV file with a header row, such as a password export from Chrome, Edge, or Firefox
+    Csv {
+        /// The CSV file
+        path: String,
+
+        /// Read an entry's name or field from a column, given as target=column, such as
+        /// name=title. Separate several mappings with commas
+        #[arg(short, long, value_delimiter = ',')]
+        map: Vec<String>,
+    },
+}
+
+impl ImportCommand {
+    /// Reads and decrypts the entries to import, named as they should be in the vault. Any
+    /// password protecting the imported data is prompted for, or read from IMPORT_PASS when
+    /// `non_interactive` is set.
+    pub fn read(self, non_interactive: bool) -> Result<Vec<(String, Entry)>> {
+        match self {
+            ImportCommand::Pass { path } => pass::read(path),
+            ImportCommand::Kdbx { path, keyfile } => {
+                let data = std::fs::read(path)?;
+                let keyfile = keyfile.map(std::fs::read).transpose()?;
+                let password = prompt::import_password("Database password", non_interactive)?;
+                // Databases protected only by a keyfile have no password at all
+                let password = Some(password).filter(|pass| !pass.is_empty() || keyfile.is_none());
+                kdbx::read(&data, password.as_ref().map(|pass| pass.as_str()), keyfile.as_deref())
+            }
+            ImportCommand::Bitwarden { path } => {
+                let json = std::fs::read_to_string(path)?;
+                bitwarden::read(json.as_str(), || prompt::import_password("Export password", non_interactive))
+            }
+            ImportCommand::Csv { path, map } => csv::read(std::fs::read_to_string(path)?.as_str(), map),
+        }
+    }
+}
+
+/// ImportSummary records which entries were imported into a vault and which were skipped since
+/// an entry with the same name already existed.
+#[derive(Default)]
+pub struct ImportSummary {
+    pub imported: Vec<String>,
+    pub skipped: Vec<String>,
+}
+
+impl std::fmt::Display for ImportSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Successfully imported {} entries", self.imported.len())?;
+        if !self.skipped.is_empty() {
+            write!(f, "\nSkipped {} entries which already exist, pass --force to overwrite them:", self.skipped.len())?;
+            for key in self.skipped.iter() {
+                write!(f, "\n  {}", key)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Makes a folder name or title from another password manager usable as part of an entry name
+fn key_part(name: &str) -> String {
+    let name = name.replace(['/', '\\'], "-");
+    let name = name.trim().trim_start_matches('.');
+    if name.is_empty() { "untitled".to_string() } else { name.to_string() }
+}
+
+/// Makes a path of folders from another password manager, separated by slashes, usable as an
+/// entry name or folder
+fn key_path(path: &str) -> String {
+    path.split('/').map(key_part).collect::<Vec<_>>().join("/")
+}
+
+/// Returns the name, numbered if needed to keep it distinct from the names already imported.
+/// Other password managers allow several entries with the same title, while a vault does not.
+fn unique_name(names: &mut BTreeSet<String>, name: String) -> String {
+    let mut unique = name.clone();
+    let mut n = 2;
+    while names.contains(&unique) {
+        unique = format!("{} ({})", name, n);
+        n += 1;
+    }
+    names.insert(unique.clone());
+    unique
+}
+
+/// Stores imported entries in the vault as a single batch, so either all of them are imported or
+/// none are. Existing entries are only overwritten if `force` is set, otherwise they are skipped
+/// and listed in the summary.
+pub fn store(vault: &mut dyn Vault, entries: Vec<(String, Entry)>, force: bool) -> Result<ImportSummary> {
+    let mut summary = ImportSummary::default();
+    let mut batch = vec![];
+    for (key, entry) in entries {
+        if !force && vault.has_key(key.as_str()) {
+            summary.skipped.push(key);
+        } else {
+            summary.imported.push(key.clone());
+            batch.push((key, entry));
+        }
+    }
+    vault.set_entries(batch.as_slice())?;
+    Ok(summary)
+}
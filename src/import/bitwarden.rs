@@ -0,0 +1,289 @@
+use std::collections::{BTreeSet, HashMap};
+
+use aes::cipher::{BlockDecryptMut, KeyIvInit};
+use base64::Engine;
+use ring::digest::{self, SHA256};
+use ring::{hmac, pbkdf2};
+use serde::Deserialize;
+use zeroize::Zeroizing;
+
+use crate::entry::{Entry, EntryKind};
+use crate::error::{Error, Result};
+use crate::import::{key_part, key_path, unique_name};
+
+/// Export is a Bitwarden JSON export. Password protected exports only hold the KDF parameters
+/// and the encrypted `data`, which decrypts to an unencrypted export holding the folders and
+/// items.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Export {
+    #[serde(default)]
+    encrypted: bool,
+    #[serde(default)]
+    password_protected: bool,
+    salt: Option<String>,
+    kdf_type: Option<u32>,
+    kdf_iterations: Option<u32>,
+    kdf_memory: Option<u32>,
+    kdf_parallelism: Option<u32>,
+    #[serde(rename = "encKeyValidation_DO_NOT_EDIT")]
+    key_validation: Option<String>,
+    data: Option<String>,
+    #[serde(default)]
+    folders: Vec<Folder>,
+    #[serde(default)]
+    items: Vec<Item>,
+}
+
+#[derive(Deserialize)]
+struct Folder {
+    id: String,
+    name: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Item {
+    folder_id: Option<String>,
+    #[serde(rename = "type")]
+    kind: u32,
+    name: String,
+    notes: Option<String>,
+    #[serde(default)]
+    fields: Option<Vec<Field>>,
+    login: Option<Login>,
+    card: Option<Card>,
+    identity: Option<serde_json::Map<String, serde_json::Value>>,
+    ssh_key: Option<serde_json::Map<String, serde_json::Value>>,
+}
+
+#[derive(Deserialize)]
+struct Field {
+    name: Option<String>,
+    value: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Login {
+    username: Option<String>,
+    password: Option<String>,
+    totp: Option<String>,
+    uris: Option<Vec<Uri>>,
+}
+
+#[derive(Deserialize)]
+struct Uri {
+    uri: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Card {
+    cardholder_name: Option<String>,
+    brand: Option<String>,
+    number: Option<String>,
+    exp_month: Option<String>,
+    exp_year: Option<String>,
+    code: Option<String>,
+}
+
+const LOGIN: u32 = 1;
+const CARD: u32 = 3;
+
+/// Reads every item of a Bitwarden JSON export. Folders become folders in the vault, logins and
+/// secure notes become the same kinds of entries, and cards become card entries. Identities and
+/// SSH keys are kept as notes with their details in custom fields. The export's password is
+/// only asked for if the export is password protected.
+pub fn read(json: &str, password: impl FnOnce() -> Result<Zeroizing<String>>) -> Result<Vec<(String, Entry)>> {
+    let mut export: Export = serde_json::from_str(json).map_err(|e| bad_export(e.to_string().as_str()))?;
+    if export.encrypted {
+        if !export.password_protected {
+            return Err(bad_export("only password protected encrypted exports can be imported"));
+        }
+        let data = decrypt_export(&export, password()?.as_str())?;
+        export = serde_json::from_str(data.as_str()).map_err(|e| bad_export(e.to_string().as_str()))?;
+    }
+
+    let folders: HashMap<&str, String> = export.folders.iter()
+        .map(|folder| (folder.id.as_str(), key_path(folder.name.as_str())))
+        .collect();
+
+    let mut names = BTreeSet::new();
+    Ok(export.items.iter()
+        .map(|item| {
+            let name = match item.folder_id.as_deref().and_then(|id| folders.get(id)) {
+                Some(folder) => format!("{}/{}", folder, key_part(item.name.as_str())),
+                None => key_part(item.name.as_str()),
+            };
+            (unique_name(&mut names, name), convert(item))
+        })
+        .collect())
+}
+
+/// Converts an item into an entry of the matching kind. Secure notes and any other kinds of
+/// items become notes.
+fn convert(item: &Item) -> Entry {
+    let mut entry = Entry::default();
+
+    match (item.kind, &item.login, &item.card) {
+        (LOGIN, Some(login), _) => {
+            set(&mut entry, "password", login.password.as_ref());
+            set(&mut entry, "username", login.username.as_ref());
+            set(&mut entry, "totp", login.totp.as_ref());
+
+            let uris = login.uris.iter().flatten().filter_map(|uri| uri.uri.as_ref());
+            for (n, uri) in uris.enumerate() {
+                let name = if n == 0 { "url".to_string() } else { format!("url{}", n + 1) };
+                set(&mut entry, name.as_str(), Some(uri));
+            }
+        }
+        (CARD, _, Some(card)) => {
+            entry.kind = EntryKind::Card;
+            set(&mut entry, "cardholder", card.cardholder_name.as_ref());
+            set(&mut entry, "brand", card.brand.as_ref());
+            set(&mut entry, "number", card.number.as_ref());
+            set(&mut entry, "code", card.code.as_ref());
+            if let (Some(month), Some(year)) = (&card.exp_month, &card.exp_year) {
+                set(&mut entry, "expiry", Some(&format!("{:0>2}/{}", month, year)));
+            }
+        }
+        _ => {
+            entry.kind = EntryKind::Note;
+            let details = item.identity.iter().chain(item.ssh_key.iter()).flatten();
+            for (name, value) in details.filter(|(_, value)| value.is_string()) {
+                set(&mut entry, name.as_str(), value.as_str().map(String::from).as_ref());
+            }
+        }
+    }
+
+    set(&mut entry, "notes", item.notes.as_ref());
+    for field in item.fields.iter().flatten() {
+        if let Some(name) = &field.name {
+            set(&mut entry, name.as_str(), field.value.as_ref());
+        }
+    }
+    entry
+}
+
+/// Sets a field of the entry, unless the value is missing or empty
+fn set(entry: &mut Entry, name: &str, value: Option<&String>) {
+    if let Some(value) = value.filter(|value| !value.is_empty()) {
+        entry.set_field(name, value.clone());
+    }
+}
+
+/// Decrypts the data of a password protected export. The password is stretched with the
+/// export's KDF, then expanded into separate encryption and MAC keys with HKDF. The key
+/// validation string is decrypted first to tell a wrong password apart from a corrupt export.
+fn decrypt_export(export: &Export, password: &str) -> Result<String> {
+    let salt = export.salt.as_deref().ok_or_else(|| bad_export("the salt is missing"))?;
+    let iterations = export.kdf_iterations.ok_or_else(|| bad_export("the KDF iterations are missing"))?;
+
+    let mut key = [0u8; 32];
+    match export.kdf_type {
+        Some(0) => {
+            let iterations = std::num::NonZeroU32::new(iterations).ok_or_else(|| bad_export("the KDF iterations are zero"))?;
+            pbkdf2::derive(pbkdf2::PBKDF2_HMAC_SHA256, iterations, salt.as_bytes(), password.as_bytes(), &mut key);
+        }
+        Some(1) => {
+            let memory = export.kdf_memory.ok_or_else(|| bad_export("the KDF memory is missing"))?;
+            let parallelism = export.kdf_parallelism.ok_or_else(|| bad_export("the KDF parallelism is missing"))?;
+            let params = argon2::Params::new(memory * 1024, iterations, parallelism, Some(32))
+                .map_err(|_| bad_export("the Argon2 parameters are invalid"))?;
+            argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+                .hash_password_into(password.as_bytes(), digest::digest(&SHA256, salt.as_bytes()).as_ref(), &mut key)
+                .map_err(|_| bad_export("the Argon2 parameters are invalid"))?;
+        }
+        _ => return Err(bad_export("the key derivation function is not supported")),
+    }
+
+    let enc_key = hkdf_expand(&key, b"enc");
+    let mac_key = hkdf_expand(&key, b"mac");
+
+    let validation = export.key_validation.as_deref().ok_or_else(|| bad_export("the key validation is missing"))?;
+    decrypt_string(validation, &enc_key, &mac_key)
+        .map_err(|_| bad_export("the password is incorrect"))?;
+
+    let data = export.data.as_deref().ok_or_else(|| bad_export("the encrypted data is missing"))?;
+    let data = decrypt_string(data, &enc_key, &mac_key)?;
+    Ok(String::from_utf8(data)?)
+}
+
+/// Expands a key into a single 32 byte key for the purpose given by `info`
+fn hkdf_expand(key: &[u8], info: &[u8]) -> Vec<u8> {
+    let mut message = info.to_vec();
+    message.push(1);
+    hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, key), &message).as_ref().to_vec()
+}
+
+/// Decrypts a Bitwarden encrypted string, `2.<iv>|<ciphertext>|<mac>` with each part in base64,
+/// which is AES-256-CBC encrypted and authenticated with HMAC-SHA256.
+fn decrypt_string(text: &str, enc_key: &[u8], mac_key: &[u8]) -> Result<Vec<u8>> {
+    let parts = text.strip_prefix("2.").ok_or_else(|| bad_export("unsupported encryption type"))?;
+    let parts = parts.split('|')
+        .map(|part| base64::engine::general_purpose::STANDARD.decode(part))
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|_| bad_export("an encrypted value is not base64"))?;
+    let [iv, ciphertext, mac] = parts.as_slice() else {
+        return Err(bad_export("an encrypted value is malformed"));
+    };
+
+    let mut signed = iv.clone();
+    signed.extend_from_slice(ciphertext);
+    hmac::verify(&hmac::Key::new(hmac::HMAC_SHA256, mac_key), &signed, mac)
+        .map_err(|_| bad_export("an encrypted value failed authentication"))?;
+
+    cbc::Decryptor::<aes::Aes256>::new_from_slices(enc_key, iv)
+        .map_err(|_| bad_export("an encrypted value is malformed"))?
+        .decrypt_padded_vec_mut::<cbc::cipher::block_padding::Pkcs7>(ciphertext)
+        .map_err(|_| bad_export("an encrypted value is malformed"))
+}
+
+fn bad_export(message: &str) -> Error {
+    Error::BadImport { message: message.to_string() }
+}
+
+#[cfg(test)]
+mod test {
+    use zeroize::Zeroizing;
+
+    use crate::entry::EntryKind;
+    use crate::import::bitwarden::read;
+
+    #[test]
+    fn read_plain_and_password_protected_exports() {
+        let plain = r#"{
+            "encrypted": false,
+            "folders": [{ "id": "f1", "name": "Work/Team" }],
+            "items": [
+                { "folderId": "f1", "type": 1, "name": "github", "notes": null,
+                  "fields": [{ "name": "recovery", "value": "abc", "type": 1 }],
+                  "login": { "username": "alice", "password": "hunter2", "totp": "JBSWY3DP",
+                             "uris": [{ "match": null, "uri": "https://github.com" }, { "match": null, "uri": "https://gist.github.com" }] } },
+                { "folderId": null, "type": 2, "name": "recovery codes", "notes": "1234\n5678", "secureNote": { "type": 0 } },
+                { "folderId": null, "type": 3, "name": "visa", "notes": null,
+                  "card": { "cardholderName": "Alice", "brand": "Visa", "number": "4111111111111111", "expMonth": "7", "expYear": "2030", "code": "123" } }
+            ]
+        }"#;
+        let entries = read(plain, || panic!("plain exports have no password")).unwrap();
+        let names: Vec<&str> = entries.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["Work/Team/github", "recovery codes", "visa"]);
+
+        let login = &entries[0].1;
+        assert_eq!(login.password, "hunter2");
+        assert_eq!(login.field("url"), Some("https://github.com"));
+        assert_eq!(login.field("url2"), Some("https://gist.github.com"));
+        assert_eq!(login.field("totp"), Some("JBSWY3DP"));
+        assert_eq!(login.field("recovery"), Some("abc"));
+        assert_eq!(entries[1].1.kind, EntryKind::Note);
+        assert_eq!(entries[1].1.notes.as_deref(), Some("1234\n5678"));
+        assert_eq!(entries[2].1.kind, EntryKind::Card);
+        assert_eq!(entries[2].1.field("expiry"), Some("07/2030"));
+
+        let protected = r#"{"encrypted": true, "passwordProtected": true, "salt": "enWteSyromEyvP434zU7cw==", "kdfType": 0, "kdfIterations": 1000, "encKeyValidation_DO_NOT_EDIT": "2.xYeXWdeHMZsmrFp87waduw==|21GvRZEEhee2SfSJpSw9o0GNuO1S2pU2TiQ6rWeBFoUNjbXcXSGTW3gR+bAFSFTs|ZWII1E7fojP+zesbhMPuliMpBdo3/JFp52oNjAaJOEE=", "data": "2.Wbqksy8mpuEDOBwNe+aIYg==|HlcVt1LNfGqsA0YlkGjWm02NETJd3FzOPl1gQyGi2dHgWLQwYOyHUm0/xO7UFCNH/lyFxMPT2sEOwZ+CRrTX+vtfIN/ZlfrqsYkJqr9WLyPSL/AKBqzX4oGi7eKOqg9dVmBY4MZKjmVlTlHJ32DpiIZTnR8/XqcAjhUAzwZtb2FA5qLn8MbQjcAhe30ALC39UMXlkdll60MOlHOeQsidq67yJZHuXelNIgPMkcvi/Pe7mAksrDnE0P4PHPrQ7sw98RQEairtzOErhgJEzSbmDZl8n/WDVbsapi+bkhKqyr5144+96iGvVLkbbMCJSlTxBjDtJiRBt3gpyyrpI7oPm5A8iRB3ImRvaxjDDsJ6hY/DwrvJXSBaiImEz+1TdZHWeGilVygt4c9TnRnXles9iw==|mB0azYGPdYqPsgGEQXwSe8XcjVDA/Rqs08IZ9PpUKCQ="}"#;
+        let entries = read(protected, || Ok(Zeroizing::new("export pass".to_string()))).unwrap();
+        assert_eq!(entries[0].0, "Work/GitHub");
+        assert_eq!(entries[0].1.password, "hunter2");
+        assert!(read(protected, || Ok(Zeroizing::new("wrong".to_string()))).is_err());
+    }
+}
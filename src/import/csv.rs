@@ -0,0 +1,102 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::entry::{self, Entry};
+use crate::error::{Error, Result};
+use crate::import::{key_path, unique_name};
+
+/// The columns read by default for each part of an entry. These match the exports of Chrome,
+/// Edge, and Firefox, and most other password managers use similar names.
+const DEFAULT_COLUMNS: [(&str, &[&str]); 5] = [
+    ("name", &["name", "title"]),
+    ("url", &["url", "login_uri", "website"]),
+    ("username", &["username", "login_username", "login"]),
+    ("password", &["password", "login_password"]),
+    ("notes", &["note", "notes", "comments"]),
+];
+
+/// Reads every row of a CSV file with a header row, such as a browser's password export. Each
+/// `map` item is `target=column`, where the target is `name`, a built in field, or a custom
+/// field, and overrides the column read for it by default. Slashes in names separate folders.
+/// Rows without a name are named after the host of their URL, since Firefox doesn't export names.
+pub fn read(text: &str, map: Vec<String>) -> Result<Vec<(String, Entry)>> {
+    let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(text.as_bytes());
+    let headers: Vec<String> = reader.headers().map_err(bad_csv)?.iter().map(|h| h.trim().to_lowercase()).collect();
+
+    let mut columns = BTreeMap::new();
+    for (target, names) in DEFAULT_COLUMNS {
+        if let Some(column) = headers.iter().position(|h| names.contains(&h.as_str())) {
+            columns.insert(target.to_string(), column);
+        }
+    }
+    for mapping in map {
+        let (target, column) = entry::parse_field(mapping.as_str())?;
+        let column = headers.iter().position(|h| *h == column.trim().to_lowercase())
+            .ok_or_else(|| Error::BadImport { message: format!("there is no column named {}", column) })?;
+        columns.insert(target, column);
+    }
+
+    let mut names = BTreeSet::new();
+    let mut entries = vec![];
+    for record in reader.records() {
+        let record = record.map_err(bad_csv)?;
+        let mut entry = Entry::default();
+        let mut name = None;
+
+        for (target, column) in columns.iter() {
+            let value = record.get(*column).unwrap_or_default();
+            if value.is_empty() {
+                continue;
+            }
+            if target == "name" {
+                name = Some(value.to_string());
+            } else {
+                entry.set_field(target, value.to_string());
+            }
+        }
+
+        let name = name.or_else(|| entry.url.as_deref().map(host)).unwrap_or_default();
+        entries.push((unique_name(&mut names, key_path(name.as_str())), entry));
+    }
+    Ok(entries)
+}
+
+/// Returns the host of a URL, without any `www.` prefix
+fn host(url: &str) -> String {
+    let host = url.split_once("://").map(|(_, rest)| rest).unwrap_or(url);
+    let host = host.split(['/', '?', '#']).next().unwrap_or_default();
+    let host = host.rsplit_once('@').map(|(_, host)| host).unwrap_or(host);
+    host.strip_prefix("www.").unwrap_or(host).to_string()
+}
+
+fn bad_csv(e: csv::Error) -> Error {
+    Error::BadImport { message: e.to_string() }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::import::csv::read;
+
+    #[test]
+    fn read_browser_exports() {
+        let chrome = "name,url,username,password,note\n\
+            github.com,https://github.com/login,alice,hunter2,\n\
+            github.com,https://github.com/login,bob,swordfish,\"two\nlines\"\n";
+        let entries = read(chrome, vec![]).unwrap();
+        let names: Vec<&str> = entries.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["github.com", "github.com (2)"]);
+        assert_eq!(entries[0].1.field("username"), Some("alice"));
+        assert_eq!(entries[1].1.password, "swordfish");
+        assert_eq!(entries[1].1.notes.as_deref(), Some("two\nlines"));
+
+        let firefox = "\"url\",\"username\",\"password\",\"httpRealm\",\"guid\"\n\
+            \"https://www.example.com:8080\",\"alice\",\"hunter2\",,\"{abc}\"\n";
+        let entries = read(firefox, vec![]).unwrap();
+        assert_eq!(entries[0].0, "example.com:8080");
+        assert_eq!(entries[0].1.field("guid"), None);
+
+        let entries = read(firefox, vec!["name=username".to_string(), "id=guid".to_string()]).unwrap();
+        assert_eq!(entries[0].0, "alice");
+        assert_eq!(entries[0].1.field("id"), Some("{abc}"));
+        assert!(read(firefox, vec!["name=missing".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,584 @@
+use std::collections::{BTreeSet, HashMap};
+use std::io::Read;
+
+use aes::cipher::{BlockDecryptMut, BlockEncrypt, KeyInit, KeyIvInit, StreamCipher};
+use base64::Engine;
+use ring::digest::{self, SHA256, SHA512};
+use ring::hmac;
+
+use crate::entry::Entry;
+use crate::error::{Error, Result};
+use crate::import::{key_part, unique_name};
+
+const SIGNATURE_1: u32 = 0x9AA2D903;
+const SIGNATURE_2: u32 = 0xB54BFB67;
+
+const CIPHER_AES256: &str = "31c1f2e6bf714350be5805216afc5aff";
+const CIPHER_CHACHA20: &str = "d6038a2b8b6f4cb5a524339a31dbb59a";
+
+const KDF_AES_KDBX3: &str = "c9d9f39a628a4460bf740d08c18a4fea";
+const KDF_AES_KDBX4: &str = "7c02bb8279a74ac0927d114a00648238";
+const KDF_ARGON2D: &str = "ef636ddf8c29444b91f7a9a403e30a0c";
+const KDF_ARGON2ID: &str = "9e298b1956db4773b23dfc3ec6f0a1e6";
+
+const SALSA20_NONCE: [u8; 8] = [0xE8, 0x30, 0x09, 0x4B, 0x97, 0x20, 0x5D, 0x2A];
+
+/// Reads every entry of a KeePass database. Both KDBX 3.1 and KDBX 4 databases are supported,
+/// protected with a password, a keyfile, or both. Groups become folders, so an entry titled
+/// `github` in the group `work` is named `work/github`. The recycle bin and entry history are
+/// not imported.
+pub fn read(data: &[u8], password: Option<&str>, keyfile: Option<&[u8]>) -> Result<Vec<(String, Entry)>> {
+    let mut reader = Reader { data, pos: 0 };
+    if reader.u32()? != SIGNATURE_1 || reader.u32()? != SIGNATURE_2 {
+        return Err(bad_database("not a KeePass database"));
+    }
+    let _minor = reader.u16()?;
+    let major = reader.u16()?;
+    if major != 3 && major != 4 {
+        return Err(bad_database(format!("KDBX version {} is not supported", major).as_str()));
+    }
+
+    let header = Header::read(&mut reader, major)?;
+    let composite = composite_key(password, keyfile)?;
+    let transformed = header.kdf.transform(&composite)?;
+
+    let mut seed = header.master_seed.clone();
+    seed.extend_from_slice(&transformed);
+    let key = digest::digest(&SHA256, &seed);
+
+    let (xml, mut stream) = if major == 3 {
+        read_kdbx3(&mut reader, &header, key.as_ref())?
+    } else {
+        read_kdbx4(&mut reader, &header, key.as_ref(), &seed)?
+    };
+    read_xml(xml.as_str(), &mut stream)
+}
+
+/// Reads the payload of a KDBX 3.1 database, which is encrypted as a whole and then split into
+/// hashed blocks. Returns the XML and the cipher for its protected values.
+fn read_kdbx3(reader: &mut Reader, header: &Header, key: &[u8]) -> Result<(String, InnerStream)> {
+    let payload = header.decrypt(key, reader.rest())?;
+    let mut payload = Reader { data: payload.as_slice(), pos: 0 };
+    if payload.take(32)? != header.stream_start.as_slice() {
+        return Err(bad_password());
+    }
+
+    let mut blocks = vec![];
+    loop {
+        let _index = payload.u32()?;
+        let hash = payload.take(32)?;
+        let size = payload.u32()? as usize;
+        if size == 0 {
+            break;
+        }
+        let block = payload.take(size)?;
+        if digest::digest(&SHA256, block).as_ref() != hash {
+            return Err(bad_database("a block of the database is corrupt"));
+        }
+        blocks.extend_from_slice(block);
+    }
+
+    let xml = String::from_utf8(header.decompress(blocks)?)?;
+    Ok((xml, inner_stream(header.stream_id, &header.stream_key)?))
+}
+
+/// Reads the payload of a KDBX 4 database. The header is authenticated with an HMAC, and the
+/// encrypted payload is split into blocks which are each authenticated. The decrypted payload
+/// starts with an inner header holding the key for protected values, followed by the XML.
+fn read_kdbx4(reader: &mut Reader, header: &Header, key: &[u8], seed: &[u8]) -> Result<(String, InnerStream)> {
+    let header_bytes = &reader.data[..reader.pos];
+    let header_hash = reader.take(32)?;
+    if digest::digest(&SHA256, header_bytes).as_ref() != header_hash {
+        return Err(bad_database("the header is corrupt"));
+    }
+
+    let mut hmac_seed = seed.to_vec();
+    hmac_seed.push(1);
+    let hmac_key = digest::digest(&SHA512, &hmac_seed);
+
+    let header_hmac = reader.take(32)?;
+    if hmac::verify(&block_key(u64::MAX, hmac_key.as_ref()), header_bytes, header_hmac).is_err() {
+        return Err(bad_password());
+    }
+
+    let mut blocks = vec![];
+    for index in 0u64.. {
+        let mac = reader.take(32)?;
+        let size = reader.u32()?;
+        let block = reader.take(size as usize)?;
+
+        let mut signed = index.to_le_bytes().to_vec();
+        signed.extend_from_slice(&size.to_le_bytes());
+        signed.extend_from_slice(block);
+        if hmac::verify(&block_key(index, hmac_key.as_ref()), &signed, mac).is_err() {
+            return Err(bad_database("a block of the database is corrupt"));
+        }
+
+        if size == 0 {
+            break;
+        }
+        blocks.extend_from_slice(block);
+    }
+
+    let payload = header.decompress(header.decrypt(key, &blocks)?)?;
+    let mut payload = Reader { data: payload.as_slice(), pos: 0 };
+    let mut stream_id = 0;
+    let mut stream_key = vec![];
+    loop {
+        let id = payload.u8()?;
+        let size = payload.u32()? as usize;
+        let value = payload.take(size)?;
+        match id {
+            0 => break,
+            1 => stream_id = Reader { data: value, pos: 0 }.u32()?,
+            2 => stream_key = value.to_vec(),
+            _ => {}
+        }
+    }
+
+    let xml = String::from_utf8(payload.rest().to_vec())?;
+    Ok((xml, inner_stream(stream_id, &stream_key)?))
+}
+
+/// Returns the HMAC key for a block of a KDBX 4 database
+fn block_key(index: u64, hmac_key: &[u8]) -> hmac::Key {
+    let mut key = index.to_le_bytes().to_vec();
+    key.extend_from_slice(hmac_key);
+    hmac::Key::new(hmac::HMAC_SHA256, digest::digest(&SHA512, &key).as_ref())
+}
+
+/// Combines the password and keyfile into the composite key, which the KDF transforms into the
+/// key of the database.
+fn composite_key(password: Option<&str>, keyfile: Option<&[u8]>) -> Result<Vec<u8>> {
+    let mut composite = vec![];
+    if let Some(password) = password {
+        composite.extend_from_slice(digest::digest(&SHA256, password.as_bytes()).as_ref());
+    }
+    if let Some(keyfile) = keyfile {
+        composite.extend_from_slice(&keyfile_key(keyfile)?);
+    }
+    Ok(digest::digest(&SHA256, &composite).as_ref().to_vec())
+}
+
+/// Reads the key held by a keyfile. XML keyfiles hold the key as base64 in version 1 or hex in
+/// version 2. Other files are used as a raw 32 byte key, a 64 character hex key, or otherwise
+/// hashed as a whole.
+fn keyfile_key(keyfile: &[u8]) -> Result<Vec<u8>> {
+    if let Ok(Ok(doc)) = std::str::from_utf8(keyfile).map(roxmltree::Document::parse) {
+        let root = doc.root_element();
+        if root.has_tag_name("KeyFile") {
+            let version = child_text(root, &["Meta", "Version"]).unwrap_or_default();
+            let data = child_text(root, &["Key", "Data"])
+                .ok_or_else(|| bad_database("the keyfile does not hold a key"))?;
+            return if version.starts_with("2.") {
+                let data: String = data.split_whitespace().collect();
+                unhex(data.as_str()).ok_or_else(|| bad_database("the keyfile's key is not hex"))
+            } else {
+                base64::engine::general_purpose::STANDARD.decode(data.trim())
+                    .map_err(|_| bad_database("the keyfile's key is not base64"))
+            };
+        }
+    }
+
+    if keyfile.len() == 32 {
+        return Ok(keyfile.to_vec());
+    }
+    if keyfile.len() == 64 {
+        if let Some(key) = std::str::from_utf8(keyfile).ok().and_then(unhex) {
+            return Ok(key);
+        }
+    }
+    Ok(digest::digest(&SHA256, keyfile).as_ref().to_vec())
+}
+
+/// KdbxKdf is the key derivation function of a database and its parameters
+enum KdbxKdf {
+    Aes { seed: Vec<u8>, rounds: u64 },
+    Argon2 { algorithm: argon2::Algorithm, version: argon2::Version, salt: Vec<u8>, memory: u64, iterations: u64, parallelism: u32 },
+}
+
+impl KdbxKdf {
+    /// Transforms the composite key, returning the 32 byte transformed key
+    fn transform(&self, composite: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            KdbxKdf::Aes { seed, rounds } => {
+                let cipher = aes::Aes256::new_from_slice(seed)
+                    .map_err(|_| bad_database("the AES-KDF seed is not 32 bytes"))?;
+                let mut blocks = [
+                    aes::Block::clone_from_slice(&composite[..16]),
+                    aes::Block::clone_from_slice(&composite[16..]),
+                ];
+                for _ in 0..*rounds {
+                    cipher.encrypt_blocks(&mut blocks);
+                }
+                Ok(digest::digest(&SHA256, &blocks.concat()).as_ref().to_vec())
+            }
+            KdbxKdf::Argon2 { algorithm, version, salt, memory, iterations, parallelism } => {
+                let params = argon2::Params::new((*memory / 1024) as u32, *iterations as u32, *parallelism, Some(32))
+                    .map_err(|_| bad_database("the Argon2 parameters are invalid"))?;
+                let mut key = vec![0u8; 32];
+                argon2::Argon2::new(*algorithm, *version, params)
+                    .hash_password_into(composite, salt, &mut key)
+                    .map_err(|_| bad_database("the Argon2 parameters are invalid"))?;
+                Ok(key)
+            }
+        }
+    }
+}
+
+/// Header holds the fields of a database's outer header needed to decrypt it
+struct Header {
+    cipher: String,
+    compressed: bool,
+    master_seed: Vec<u8>,
+    iv: Vec<u8>,
+    kdf: KdbxKdf,
+    stream_start: Vec<u8>,
+    stream_id: u32,
+    stream_key: Vec<u8>,
+}
+
+impl Header {
+    /// Reads the outer header. KDBX 3.1 headers store the AES-KDF parameters and inner stream
+    /// key directly, while KDBX 4 headers store a dictionary of KDF parameters.
+    fn read(reader: &mut Reader, major: u16) -> Result<Self> {
+        let mut fields = HashMap::new();
+        loop {
+            let id = reader.u8()?;
+            let size = if major == 3 { reader.u16()? as usize } else { reader.u32()? as usize };
+            let value = reader.take(size)?.to_vec();
+            if id == 0 {
+                break;
+            }
+            fields.insert(id, value);
+        }
+
+        let field = |id: u8| fields.get(&id).cloned().ok_or_else(|| bad_database("a header field is missing"));
+        let number = |id: u8| -> Result<u64> {
+            let value = field(id)?;
+            Ok(match value.len() {
+                4 => Reader { data: &value, pos: 0 }.u32()? as u64,
+                _ => Reader { data: &value, pos: 0 }.u64()?,
+            })
+        };
+
+        let kdf = if major == 3 {
+            KdbxKdf::Aes { seed: field(5)?, rounds: number(6)? }
+        } else {
+            kdf_parameters(field(11)?.as_slice())?
+        };
+
+        Ok(Header {
+            cipher: hex(&field(2)?),
+            compressed: number(3)? == 1,
+            master_seed: field(4)?,
+            iv: field(7)?,
+            kdf,
+            stream_start: if major == 3 { field(9)? } else { vec![] },
+            stream_id: if major == 3 { number(10)? as u32 } else { 0 },
+            stream_key: if major == 3 { field(8)? } else { vec![] },
+        })
+    }
+
+    /// Decrypts the payload with the database's cipher
+    fn decrypt(&self, key: &[u8], payload: &[u8]) -> Result<Vec<u8>> {
+        match self.cipher.as_str() {
+            CIPHER_AES256 => cbc::Decryptor::<aes::Aes256>::new_from_slices(key, &self.iv)
+                .map_err(|_| bad_database("the encryption IV is invalid"))?
+                .decrypt_padded_vec_mut::<cbc::cipher::block_padding::Pkcs7>(payload)
+                .map_err(|_| bad_password()),
+            CIPHER_CHACHA20 => {
+                let mut payload = payload.to_vec();
+                chacha20::ChaCha20::new_from_slices(key, &self.iv)
+                    .map_err(|_| bad_database("the encryption IV is invalid"))?
+                    .apply_keystream(&mut payload);
+                Ok(payload)
+            }
+            _ => Err(bad_database("only AES-256 and ChaCha20 encrypted databases are supported")),
+        }
+    }
+
+    /// Decompresses the payload if the database is compressed with gzip
+    fn decompress(&self, payload: Vec<u8>) -> Result<Vec<u8>> {
+        if !self.compressed {
+            return Ok(payload);
+        }
+        let mut decompressed = vec![];
+        flate2::read::GzDecoder::new(payload.as_slice()).read_to_end(&mut decompressed)?;
+        Ok(decompressed)
+    }
+}
+
+/// Reads the KDF parameters of a KDBX 4 header, which are stored as a dictionary of typed values
+fn kdf_parameters(data: &[u8]) -> Result<KdbxKdf> {
+    let mut reader = Reader { data, pos: 0 };
+    let _version = reader.u16()?;
+
+    let mut params = HashMap::new();
+    loop {
+        let kind = reader.u8()?;
+        if kind == 0 {
+            break;
+        }
+        let name_len = reader.u32()? as usize;
+        let name = String::from_utf8(reader.take(name_len)?.to_vec())?;
+        let value_len = reader.u32()? as usize;
+        params.insert(name, reader.take(value_len)?.to_vec());
+    }
+
+    let bytes = |name: &str| params.get(name).cloned().ok_or_else(|| bad_database("a KDF parameter is missing"));
+    let number = |name: &str| -> Result<u64> {
+        let value = bytes(name)?;
+        Ok(match value.len() {
+            4 => Reader { data: &value, pos: 0 }.u32()? as u64,
+            _ => Reader { data: &value, pos: 0 }.u64()?,
+        })
+    };
+
+    let algorithm = match hex(&bytes("$UUID")?).as_str() {
+        KDF_AES_KDBX3 | KDF_AES_KDBX4 => return Ok(KdbxKdf::Aes { seed: bytes("S")?, rounds: number("R")? }),
+        KDF_ARGON2D => argon2::Algorithm::Argon2d,
+        KDF_ARGON2ID => argon2::Algorithm::Argon2id,
+        _ => return Err(bad_database("the key derivation function is not supported")),
+    };
+
+    Ok(KdbxKdf::Argon2 {
+        algorithm,
+        version: if number("V")? == 0x10 { argon2::Version::V0x10 } else { argon2::Version::V0x13 },
+        salt: bytes("S")?,
+        memory: number("M")?,
+        iterations: number("I")?,
+        parallelism: number("P")? as u32,
+    })
+}
+
+/// Creates the stream cipher protecting values such as passwords within the XML
+fn inner_stream(id: u32, key: &[u8]) -> Result<InnerStream> {
+    match id {
+        0 => Ok(InnerStream::None),
+        2 => {
+            let key = digest::digest(&SHA256, key);
+            Ok(InnerStream::Salsa20(salsa20::Salsa20::new(key.as_ref().into(), &SALSA20_NONCE.into())))
+        }
+        3 => {
+            let key = digest::digest(&SHA512, key);
+            let key = key.as_ref();
+            Ok(InnerStream::ChaCha20(chacha20::ChaCha20::new(key[..32].into(), key[32..44].into())))
+        }
+        _ => Err(bad_database("the protected value cipher is not supported")),
+    }
+}
+
+/// InnerStream decrypts protected values in the XML. The stream runs over every protected value
+/// in document order, so values must be decrypted in that order.
+enum InnerStream {
+    None,
+    Salsa20(salsa20::Salsa20),
+    ChaCha20(chacha20::ChaCha20),
+}
+
+impl InnerStream {
+    fn apply(&mut self, data: &mut [u8]) {
+        match self {
+            InnerStream::None => {}
+            InnerStream::Salsa20(cipher) => cipher.apply_keystream(data),
+            InnerStream::ChaCha20(cipher) => cipher.apply_keystream(data),
+        }
+    }
+}
+
+/// Reads the entries from the XML document of a database
+fn read_xml(xml: &str, stream: &mut InnerStream) -> Result<Vec<(String, Entry)>> {
+    let doc = roxmltree::Document::parse(xml).map_err(|e| bad_database(e.to_string().as_str()))?;
+
+    let mut protected = HashMap::new();
+    for node in doc.descendants().filter(|node| node.has_tag_name("Value") && node.attribute("Protected") == Some("True")) {
+        let mut value = base64::engine::general_purpose::STANDARD.decode(node.text().unwrap_or_default())
+            .map_err(|_| bad_database("a protected value is not base64"))?;
+        stream.apply(&mut value);
+        protected.insert(node.id(), String::from_utf8(value)?);
+    }
+
+    let root = doc.root_element();
+    let recycle_bin = match child_text(root, &["Meta", "RecycleBinEnabled"]) {
+        Some("False") => None,
+        _ => child_text(root, &["Meta", "RecycleBinUUID"]),
+    };
+
+    let group = root.children()
+        .find(|node| node.has_tag_name("Root"))
+        .and_then(|node| node.children().find(|node| node.has_tag_name("Group")))
+        .ok_or_else(|| bad_database("the database has no root group"))?;
+
+    let mut entries = vec![];
+    let mut names = BTreeSet::new();
+    read_group(group, "", recycle_bin, &protected, &mut names, &mut entries);
+    Ok(entries)
+}
+
+/// Reads the entries of a group and its subgroups, naming them after the group's path. The
+/// top level group is the database itself, so it doesn't add a folder.
+fn read_group(
+    group: roxmltree::Node,
+    prefix: &str,
+    recycle_bin: Option<&str>,
+    protected: &HashMap<roxmltree::NodeId, String>,
+    names: &mut BTreeSet<String>,
+    entries: &mut Vec<(String, Entry)>,
+) {
+    for node in group.children() {
+        if node.has_tag_name("Group") {
+            if recycle_bin.is_some() && child_text(node, &["UUID"]) == recycle_bin {
+                continue;
+            }
+            let name = key_part(child_text(node, &["Name"]).unwrap_or_default());
+            read_group(node, format!("{}{}/", prefix, name).as_str(), recycle_bin, protected, names, entries);
+        } else if node.has_tag_name("Entry") {
+            let (title, entry) = read_entry(node, protected);
+            let name = unique_name(names, format!("{}{}", prefix, key_part(title.as_str())));
+            entries.push((name, entry));
+        }
+    }
+}
+
+/// Reads an entry's title and fields. One time password seeds stored by KeePassXC or the
+/// KeeTrayTOTP plugin are kept in the `totp` field, and other custom strings become fields.
+fn read_entry(node: roxmltree::Node, protected: &HashMap<roxmltree::NodeId, String>) -> (String, Entry) {
+    let mut title = String::new();
+    let mut entry = Entry::default();
+
+    for string in node.children().filter(|node| node.has_tag_name("String")) {
+        let key = child_text(string, &["Key"]).unwrap_or_default();
+        let value = match string.children().find(|node| node.has_tag_name("Value")) {
+            Some(value) => protected.get(&value.id()).cloned().unwrap_or_else(|| value.text().unwrap_or_default().to_string()),
+            None => continue,
+        };
+        if value.is_empty() {
+            continue;
+        }
+
+        match key {
+            "Title" => title = value,
+            "Password" => entry.password = value,
+            "UserName" => entry.username = Some(value),
+            "URL" => entry.url = Some(value),
+            "Notes" => entry.notes = Some(value),
+            "otp" | "TOTP Seed" => entry.set_field("totp", value),
+            _ => entry.set_field(key, value),
+        }
+    }
+    (title, entry)
+}
+
+/// Returns the text of the element found by following the path of child tag names
+fn child_text<'a>(node: roxmltree::Node<'a, 'a>, path: &[&str]) -> Option<&'a str> {
+    let mut node = node;
+    for name in path {
+        node = node.children().find(|child| child.has_tag_name(*name))?;
+    }
+    Some(node.text().unwrap_or_default())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok()).collect()
+}
+
+fn bad_database(message: &str) -> Error {
+    Error::BadImport { message: message.to_string() }
+}
+
+fn bad_password() -> Error {
+    bad_database("the password or keyfile is incorrect")
+}
+
+/// Reader reads little endian values from a byte slice
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        let end = self.pos.checked_add(n).filter(|end| *end <= self.data.len())
+            .ok_or_else(|| bad_database("the database is truncated"))?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        let bytes = &self.data[self.pos..];
+        self.pos = self.data.len();
+        bytes
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use base64::Engine;
+
+    use crate::import::kdbx::{inner_stream, read_xml};
+
+    #[test]
+    fn read_entries_from_xml() {
+        // Protected values are encrypted by one stream in document order, including history
+        let mut stream = inner_stream(3, b"stream key").unwrap();
+        let mut protect = |value: &str| {
+            let mut value = value.as_bytes().to_vec();
+            stream.apply(&mut value);
+            base64::engine::general_purpose::STANDARD.encode(value)
+        };
+        let xml = format!(r#"<KeePassFile>
+            <Meta><RecycleBinEnabled>True</RecycleBinEnabled><RecycleBinUUID>Ymlu</RecycleBinUUID></Meta>
+            <Root><Group><Name>Database</Name>
+                <Entry>
+                    <String><Key>Title</Key><Value>github</Value></String>
+                    <String><Key>UserName</Key><Value>alice</Value></String>
+                    <String><Key>otp</Key><Value>otpauth://totp/github</Value></String>
+                    <History><Entry><String><Key>Password</Key><Value Protected="True">{}</Value></String></Entry></History>
+                    <String><Key>Password</Key><Value Protected="True">{}</Value></String>
+                </Entry>
+                <Group><Name>Work/Team</Name>
+                    <Entry><String><Key>Title</Key><Value>db</Value></String><String><Key>Port</Key><Value>5432</Value></String></Entry>
+                    <Entry><String><Key>Title</Key><Value>db</Value></String></Entry>
+                </Group>
+                <Group><UUID>Ymlu</UUID><Name>Recycle Bin</Name>
+                    <Entry><String><Key>Title</Key><Value>deleted</Value></String></Entry>
+                </Group>
+            </Group></Root>
+        </KeePassFile>"#, protect("old"), protect("hunter2"));
+
+        let entries = read_xml(xml.as_str(), &mut inner_stream(3, b"stream key").unwrap()).unwrap();
+        let names: Vec<&str> = entries.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["github", "Work-Team/db", "Work-Team/db (2)"]);
+
+        let github = &entries[0].1;
+        assert_eq!(github.password, "hunter2");
+        assert_eq!(github.field("username"), Some("alice"));
+        assert_eq!(github.field("totp"), Some("otpauth://totp/github"));
+        assert_eq!(entries[1].1.field("Port"), Some("5432"));
+    }
+}
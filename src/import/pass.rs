@@ -0,0 +1,108 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::entry::Entry;
+use crate::error::{Error, Result};
+
+/// Reads every entry of a password-store. Each `.gpg` file is decrypted by running gpg, so the
+/// user's gpg-agent takes care of asking for the key's passphrase. Entries keep their path in
+/// the store as their name, such as `work/github` for `work/github.gpg`.
+pub fn read(path: Option<String>) -> Result<Vec<(String, Entry)>> {
+    let dir = match path.or_else(|| std::env::var("PASSWORD_STORE_DIR").ok()) {
+        Some(path) => PathBuf::from(path),
+        None => home::home_dir().ok_or(Error::NoHomeDir)?.join(".password-store"),
+    };
+
+    let mut files = vec![];
+    collect_gpg_files(dir.as_path(), "", &mut files)?;
+    files.sort();
+
+    files.into_iter()
+        .map(|name| {
+            let text = decrypt(dir.join(format!("{}.gpg", name)).as_path())?;
+            Ok((name, parse(text.as_str())))
+        })
+        .collect()
+}
+
+/// Recursively collects the names of `.gpg` files below `dir`, without their extension. Hidden
+/// files and directories such as `.git` are skipped.
+fn collect_gpg_files(dir: &Path, parent: &str, files: &mut Vec<String>) -> Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.starts_with('.') {
+            continue;
+        }
+
+        let name = if parent.is_empty() { name } else { format!("{}/{}", parent, name) };
+        if entry.file_type()?.is_dir() {
+            collect_gpg_files(entry.path().as_path(), name.as_str(), files)?;
+        } else if let Some(name) = name.strip_suffix(".gpg") {
+            files.push(name.to_string());
+        }
+    }
+    Ok(())
+}
+
+/// Decrypts a file with gpg, returning the plaintext
+fn decrypt(p: &Path) -> Result<String> {
+    let output = Command::new("gpg")
+        .args(["--quiet", "--batch", "--decrypt"])
+        .arg(p)
+        .output()?;
+
+    if output.status.success() {
+        Ok(String::from_utf8(output.stdout)?)
+    } else {
+        Err(Error::Gpg { message: String::from_utf8_lossy(&output.stderr).trim().to_string() })
+    }
+}
+
+/// Parses the text of a pass entry. By convention, the first line is the password and the
+/// following lines hold other information, often as `key: value` pairs. Well known keys are
+/// mapped to the username and URL, other pairs become custom fields, and any remaining lines
+/// are kept as notes.
+fn parse(text: &str) -> Entry {
+    let mut lines = text.lines();
+    let mut entry = Entry::new(lines.next().unwrap_or_default().to_string());
+
+    let mut notes = vec![];
+    for line in lines {
+        match line.split_once(':') {
+            Some((key, value)) if !key.trim().is_empty() && !key.contains(' ') && !value.starts_with("//") => {
+                let field = match key.trim().to_lowercase().as_str() {
+                    "user" | "username" | "login" => "username".to_string(),
+                    "url" | "website" => "url".to_string(),
+                    _ => key.trim().to_string(),
+                };
+                entry.set_field(field.as_str(), value.trim().to_string());
+            }
+            _ => notes.push(line),
+        }
+    }
+
+    let notes = notes.join("\n");
+    if !notes.trim().is_empty() {
+        entry.notes = Some(notes.trim().to_string());
+    }
+    entry
+}
+
+#[cfg(test)]
+mod test {
+    use crate::import::pass::parse;
+
+    #[test]
+    fn parse_pass_entries() {
+        let entry = parse("hunter2\nlogin: alice\nURL: https://example.com\npin: 1234\nsecurity question\n");
+        assert_eq!(entry.password, "hunter2");
+        assert_eq!(entry.field("username"), Some("alice"));
+        assert_eq!(entry.field("url"), Some("https://example.com"));
+        assert_eq!(entry.field("pin"), Some("1234"));
+        assert_eq!(entry.notes.as_deref(), Some("security question"));
+
+        let entry = parse("only a password");
+        assert_eq!(entry, crate::entry::Entry::new("only a password".to_string()));
+    }
+}
@@ -0,0 +1,105 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+use crate::entry::Metadata;
+use crate::error::Result;
+
+/// Index lists the entries in a vault along with their metadata and tags, so listing and searching a vault
+/// doesn't need to walk its directory or decrypt every entry. It is stored encrypted in the
+/// vault's index file, which is updated on every write. The index is local to each copy of the
+/// vault and is never synced, so it is thrown away whenever a sync changes the vault, and rebuilt
+/// from the entries the next time it is needed. Vaults written by older versions of yap have no
+/// index, and get one the same way.
+#[derive(Serialize, Deserialize, Default, PartialEq, Eq, Debug)]
+pub(crate) struct Index {
+    entries: BTreeMap<String, Metadata>,
+    /// The tags of each entry which has any, kept apart so indexes written before entries had
+    /// tags are still read
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    tags: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl Index {
+    /// Returns the names of the entries which start with `prefix`, sorted by name
+    pub fn names(&self, prefix: &str) -> Vec<String> {
+        self.entries.range(prefix.to_string()..)
+            .take_while(|(name, _)| name.starts_with(prefix))
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// Returns the metadata of the entry with the given name, if it is in the index
+    pub fn metadata(&self, name: &str) -> Option<Metadata> {
+        self.entries.get(name).copied()
+    }
+
+    /// Adds an entry to the index, or updates its metadata
+    pub fn insert(&mut self, name: &str, metadata: Metadata) {
+        self.entries.insert(name.to_string(), metadata);
+    }
+
+    /// Returns the tags of the entry with the given name
+    pub fn tags(&self, name: &str) -> BTreeSet<String> {
+        self.tags.get(name).cloned().unwrap_or_default()
+    }
+
+    /// Replaces the tags of the entry with the given name
+    pub fn set_tags(&mut self, name: &str, tags: &BTreeSet<String>) {
+        match tags.is_empty() {
+            true => self.tags.remove(name),
+            false => self.tags.insert(name.to_string(), tags.clone()),
+        };
+    }
+
+    /// Removes an entry from the index, returning its metadata
+    pub fn remove(&mut self, name: &str) -> Option<Metadata> {
+        self.tags.remove(name);
+        self.entries.remove(name)
+    }
+
+    /// Returns the number of entries in the index
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Serializes the index, which is wiped from memory when dropped
+    pub fn to_bytes(&self) -> Result<Zeroizing<Vec<u8>>> {
+        Ok(Zeroizing::new(serde_yaml::to_string(self)?.into_bytes()))
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Index> {
+        Ok(serde_yaml::from_slice(bytes)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+
+    use crate::entry::Metadata;
+    use crate::index::Index;
+
+    #[test]
+    fn names_are_listed_by_prefix() {
+        let mut index = Index::default();
+        for name in ["work/github", "mail", "work/aws", "workshop"] {
+            index.insert(name, Metadata { created: 1, ..Metadata::default() });
+        }
+        assert_eq!(index.names("work/"), vec!["work/aws", "work/github"]);
+        assert_eq!(index.names("").len(), 4);
+        assert!(index.names("zzz").is_empty());
+
+        index.insert("mail", Metadata { created: 2, ..Metadata::default() });
+        assert_eq!(index.remove("mail").map(|metadata| metadata.created), Some(2));
+        assert_eq!(index.metadata("mail"), None);
+
+        index.set_tags("work/aws", &BTreeSet::from(["work".to_string()]));
+        assert!(index.tags("work/aws").contains("work") && index.tags("work/github").is_empty());
+
+        let read = Index::from_bytes(index.to_bytes().unwrap().as_slice()).unwrap();
+        assert_eq!(read, index);
+        assert_eq!(read.len(), 3);
+    }
+}
@@ -0,0 +1,74 @@
+use std::time::SystemTime;
+
+use zeroize::Zeroizing;
+
+use crate::crypto::KEY_LEN;
+use crate::error::{Error, Result};
+
+/// The service vault keys are stored under in the keychain. Each vault is its own account,
+/// named by its directory.
+#[cfg(feature = "keyring")]
+const SERVICE: &str = "yap";
+
+/// Gets the key stored in the platform keychain for a vault, identified by its directory and
+/// salt, with the time it was last used. None is returned if no key is stored, or it was stored
+/// for another salt.
+#[cfg(feature = "keyring")]
+pub fn get(vault: &str, salt: &[u8]) -> Result<Option<(Zeroizing<[u8; KEY_LEN]>, SystemTime)>> {
+    let secret = match keyring::Entry::new(SERVICE, vault).and_then(|entry| entry.get_secret()) {
+        Ok(secret) => Zeroizing::new(secret),
+        Err(keyring::Error::NoEntry) => return Ok(None),
+        Err(e) => return Err(keychain_error(e)),
+    };
+
+    // The salt is stored in front of the key, so a key stored before a rekey isn't used, and
+    // the time it was last used in seconds since the epoch after it
+    let Some(rest) = secret.strip_prefix(salt) else { return Ok(None) };
+    if rest.len() != KEY_LEN + 8 {
+        return Ok(None);
+    }
+    let (key, used) = rest.split_at(KEY_LEN);
+    let used = u64::from_le_bytes(used.try_into().unwrap_or_default());
+    Ok(Some((Zeroizing::new(key.try_into().unwrap_or_default()), SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(used))))
+}
+
+/// Stores the key of a vault in the platform keychain, where it stays until the vault is locked,
+/// recording that it was used now. The keychain decides who can read it, which is usually anyone
+/// logged in as the user.
+#[cfg(feature = "keyring")]
+pub fn put(vault: &str, salt: &[u8], key: &[u8; KEY_LEN]) -> Result<()> {
+    let used = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs();
+    let secret = Zeroizing::new([salt, key.as_slice(), used.to_le_bytes().as_slice()].concat());
+    keyring::Entry::new(SERVICE, vault)
+        .and_then(|entry| entry.set_secret(secret.as_slice()))
+        .map_err(keychain_error)
+}
+
+/// Removes the key of a vault from the platform keychain. Nothing is done if no key is stored.
+#[cfg(feature = "keyring")]
+pub fn delete(vault: &str) -> Result<()> {
+    match keyring::Entry::new(SERVICE, vault).and_then(|entry| entry.delete_credential()) {
+        Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+        Err(e) => Err(keychain_error(e)),
+    }
+}
+
+#[cfg(feature = "keyring")]
+fn keychain_error(e: keyring::Error) -> Error {
+    Error::Keychain { message: e.to_string() }
+}
+
+#[cfg(not(feature = "keyring"))]
+pub fn get(_vault: &str, _salt: &[u8]) -> Result<Option<(Zeroizing<[u8; KEY_LEN]>, SystemTime)>> {
+    Err(Error::KeychainUnsupported)
+}
+
+#[cfg(not(feature = "keyring"))]
+pub fn put(_vault: &str, _salt: &[u8], _key: &[u8; KEY_LEN]) -> Result<()> {
+    Err(Error::KeychainUnsupported)
+}
+
+#[cfg(not(feature = "keyring"))]
+pub fn delete(_vault: &str) -> Result<()> {
+    Err(Error::KeychainUnsupported)
+}
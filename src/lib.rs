@@ -1,17 +1,68 @@
 pub mod error;
 pub mod config;
 pub mod vault;
+pub mod entry;
+pub mod prompt;
+pub mod generate;
+pub mod sync;
+pub mod display;
+pub mod clipboard;
+pub mod search;
+pub mod editor;
+pub mod import;
+pub mod export;
+pub mod backup;
+pub mod agent;
+pub mod session;
+pub mod audit;
+pub mod completions;
+pub mod credential;
+pub mod askpass;
+pub mod run;
+pub mod render;
+pub mod dotenv;
+pub mod docker;
+pub mod menu;
+pub mod share;
+pub mod sign;
+pub mod recovery;
+pub mod batch;
+pub mod attachment;
+pub mod server;
+pub mod token;
+pub mod wipe;
+pub mod verify;
+pub mod autobackup;
+pub mod snapshot;
+pub mod activity;
+pub mod doctor;
+pub mod status;
+pub mod stats;
+#[cfg(feature = "tui")]
+pub mod tui;
+#[cfg(feature = "secret-service")]
+pub mod secret_service;
 
 mod global;
 mod crypto;
+mod header;
+mod keychain;
+mod breach;
+mod webdav;
+mod remote;
+mod readonly;
+mod index;
+mod lock;
+mod sqlite;
 
 use std::path::Path;
 
 pub use config::ConfigCommand;
+pub use crypto::Kdf;
 pub use error::{Error, Result};
 
 pub trait ExecutableCommand {
-    fn execute(self) -> std::result::Result<String, String>;
+    fn execute(self) -> Result<String>;
 }
 
 /// Ensures that required directories and files exist
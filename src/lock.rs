@@ -0,0 +1,281 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use age::x25519::Recipient;
+use tracing::trace;
+
+use crate::activity::Activity;
+use crate::config;
+use crate::crypto::Kdf;
+use crate::entry::{Entry, Metadata};
+use crate::error::{Error, Result};
+use crate::global::LOCK_FILE;
+use crate::stats::Stats;
+use crate::vault::Vault;
+use crate::verify::{RepairReport, Report};
+
+/// How long to wait between attempts to take a lock which another yap is holding
+const RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The locks this process holds, by the path of their lock file, with the open file holding the
+/// lock and the number of guards sharing it. A lock which is already held is shared rather than
+/// taken again, since the process would otherwise wait on itself.
+static HELD: Mutex<BTreeMap<PathBuf, (File, usize)>> = Mutex::new(BTreeMap::new());
+
+/// VaultLock is an advisory lock on a vault directory, which keeps other yap processes from
+/// writing to the vault while it is held. The lock is released once every guard for it in this
+/// process has been dropped.
+pub(crate) struct VaultLock {
+    path: PathBuf,
+}
+
+impl Drop for VaultLock {
+    fn drop(&mut self) {
+        let mut held = HELD.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((_, count)) = held.get_mut(&self.path) {
+            *count -= 1;
+            if *count == 0 {
+                // Closing the file releases the lock
+                held.remove(&self.path);
+            }
+        }
+    }
+}
+
+/// Locks the vault in `vault_dir`, waiting for as long as the lock_wait setting allows if another
+/// yap holds the lock, see `acquire`
+pub(crate) fn lock(vault_dir: &Path) -> Result<VaultLock> {
+    let wait = config::read_or_default()?.with_vault(vault_dir)?.lock_wait();
+    acquire(vault_dir, Duration::from_secs(wait))
+}
+
+/// Locks the vault in `vault_dir` with an exclusive lock on its lock file, waiting up to `wait`
+/// for another yap to release it. The lock is advisory, so it only keeps out other processes
+/// which take it too.
+pub(crate) fn acquire(vault_dir: &Path, wait: Duration) -> Result<VaultLock> {
+    let path = vault_dir.canonicalize()?.join(LOCK_FILE);
+    let mut held = HELD.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some((_, count)) = held.get_mut(&path) {
+        *count += 1;
+        return Ok(VaultLock { path });
+    }
+
+    let f = OpenOptions::new().create(true).truncate(false).write(true).open(path.as_path())?;
+    let start = Instant::now();
+    loop {
+        match f.try_lock() {
+            Ok(()) => break,
+            Err(TryLockError::WouldBlock) if start.elapsed() < wait => {
+                trace!("waiting for another yap to release {}", path.display());
+                std::thread::sleep(RETRY_INTERVAL);
+            }
+            Err(TryLockError::WouldBlock) => return Err(Error::VaultLocked { path: vault_dir.display().to_string() }),
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+        }
+    }
+    held.insert(path.clone(), (f, 1));
+    Ok(VaultLock { path })
+}
+
+/// LockingVault wraps a vault opened from a directory, locking the directory for the length of
+/// every change, so two yap processes, such as the agent and the command line, can't interleave
+/// their writes to the same entries or index. Reads don't take the lock, since every file is
+/// replaced whole, see `vault::atomic_write`, and a vault on a read-only mount can still be read.
+pub(crate) struct LockingVault {
+    vault: Box<dyn Vault>,
+    vault_dir: PathBuf,
+    wait: Duration,
+}
+
+impl LockingVault {
+    pub(crate) fn new(vault: Box<dyn Vault>, vault_dir: &Path, wait: Duration) -> LockingVault {
+        LockingVault { vault, vault_dir: vault_dir.to_path_buf(), wait }
+    }
+
+    fn lock(&self) -> Result<VaultLock> {
+        acquire(self.vault_dir.as_path(), self.wait)
+    }
+}
+
+impl Vault for LockingVault {
+    fn has_key(&self, key: &str) -> bool {
+        self.vault.has_key(key)
+    }
+
+    fn get_entry(&self, key: &str) -> Result<Entry> {
+        self.vault.get_entry(key)
+    }
+
+    fn access_entry(&mut self, key: &str) -> Result<Entry> {
+        let _lock = self.lock()?;
+        self.vault.access_entry(key)
+    }
+
+    fn set_entry(&mut self, key: &str, entry: &Entry) -> Result<()> {
+        let _lock = self.lock()?;
+        self.vault.set_entry(key, entry)
+    }
+
+    fn set_entries(&mut self, entries: &[(String, Entry)]) -> Result<()> {
+        let _lock = self.lock()?;
+        self.vault.set_entries(entries)
+    }
+
+    fn delete_key(&mut self, key: &str) -> Result<()> {
+        let _lock = self.lock()?;
+        self.vault.delete_key(key)
+    }
+
+    fn rename_key(&mut self, from: &str, to: &str, force: bool) -> Result<()> {
+        let _lock = self.lock()?;
+        self.vault.rename_key(from, to, force)
+    }
+
+    fn list_keys(&self, prefix: &str) -> Result<Vec<String>> {
+        self.vault.list_keys(prefix)
+    }
+
+    fn attach(&mut self, key: &str, attachment: &str, reader: &mut dyn Read) -> Result<u64> {
+        let _lock = self.lock()?;
+        self.vault.attach(key, attachment, reader)
+    }
+
+    fn read_attachment(&self, key: &str, attachment: &str, writer: &mut dyn Write) -> Result<u64> {
+        self.vault.read_attachment(key, attachment, writer)
+    }
+
+    fn list_attachments(&self, key: &str) -> Result<Vec<String>> {
+        self.vault.list_attachments(key)
+    }
+
+    fn remove_attachment(&mut self, key: &str, attachment: &str) -> Result<()> {
+        let _lock = self.lock()?;
+        self.vault.remove_attachment(key, attachment)
+    }
+
+    fn history(&self, key: &str) -> Result<Vec<(u32, Metadata)>> {
+        self.vault.history(key)
+    }
+
+    fn restore(&mut self, key: &str, version: u32) -> Result<()> {
+        let _lock = self.lock()?;
+        self.vault.restore(key, version)
+    }
+
+    fn list_trash(&self) -> Result<Vec<(String, i64)>> {
+        self.vault.list_trash()
+    }
+
+    fn restore_trash(&mut self, key: &str, force: bool) -> Result<()> {
+        let _lock = self.lock()?;
+        self.vault.restore_trash(key, force)
+    }
+
+    fn empty_trash(&mut self, before: Option<i64>) -> Result<usize> {
+        let _lock = self.lock()?;
+        self.vault.empty_trash(before)
+    }
+
+    fn rebuild_index(&mut self) -> Result<usize> {
+        let _lock = self.lock()?;
+        self.vault.rebuild_index()
+    }
+
+    /// Only takes the lock if corrupted files are quarantined, so a vault on a read-only mount
+    /// can still be verified
+    fn verify(&mut self, quarantine: bool) -> Result<Report> {
+        let _lock = quarantine.then(|| self.lock()).transpose()?;
+        self.vault.verify(quarantine)
+    }
+
+    fn repair(&mut self, backup: Option<&Path>) -> Result<RepairReport> {
+        let _lock = self.lock()?;
+        self.vault.repair(backup)
+    }
+
+    fn recover(&mut self, backup: &Path, key: &str) -> Result<()> {
+        let _lock = self.lock()?;
+        self.vault.recover(backup, key)
+    }
+
+    fn activity(&self) -> Result<Vec<Activity>> {
+        self.vault.activity()
+    }
+
+    fn rekey(&mut self, new_pass: &str) -> Result<()> {
+        let _lock = self.lock()?;
+        self.vault.rekey(new_pass)
+    }
+
+    fn set_kdf(&mut self, kdf: Kdf, pass: &str) -> Result<()> {
+        let _lock = self.lock()?;
+        self.vault.set_kdf(kdf, pass)
+    }
+
+    fn add_member(&mut self, name: &str, recipient: &Recipient) -> Result<()> {
+        let _lock = self.lock()?;
+        self.vault.add_member(name, recipient)
+    }
+
+    fn remove_member(&mut self, name: &str, pass: &str) -> Result<()> {
+        let _lock = self.lock()?;
+        self.vault.remove_member(name, pass)
+    }
+
+    fn delete_folder(&mut self, folder: &str) -> Result<Vec<String>> {
+        let _lock = self.lock()?;
+        self.vault.delete_folder(folder)
+    }
+
+    fn metadata(&self, key: &str) -> Result<Metadata> {
+        self.vault.metadata(key)
+    }
+
+    fn tags(&self, key: &str) -> Result<BTreeSet<String>> {
+        self.vault.tags(key)
+    }
+
+    fn stats(&self) -> Result<Stats> {
+        self.vault.stats()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::{File, TryLockError};
+    use std::path::Path;
+    use std::time::Duration;
+
+    use crate::crypto::Kdf;
+    use crate::error::Error;
+    use crate::global::LOCK_FILE;
+    use crate::lock::{self, LockingVault};
+    use crate::vault::{self, Backend, Vault};
+
+    #[test]
+    fn writes_wait_for_the_lock() {
+        let yap_test = String::from(".yap_test_lock");
+        let dir = Path::new(yap_test.as_str());
+        let vault = vault::create(Some(yap_test.clone()), "asdf", Kdf::default(), Backend::default(), None).unwrap();
+        let mut vault = LockingVault::new(vault, dir, Duration::from_millis(200));
+
+        // The lock is shared within a process, and excludes everyone else until it is released
+        let held = lock::acquire(dir, Duration::ZERO).unwrap();
+        vault.set_key("github", "password".to_string()).unwrap();
+        let other = File::open(dir.join(LOCK_FILE)).unwrap();
+        assert!(matches!(other.try_lock(), Err(TryLockError::WouldBlock)));
+        drop(held);
+
+        other.lock().unwrap();
+        assert!(matches!(vault.set_key("github", "changed".to_string()), Err(Error::VaultLocked { .. })));
+        assert_eq!(vault.get_key("github").unwrap(), "password");
+        other.unlock().unwrap();
+        vault.set_key("github", "changed".to_string()).unwrap();
+
+        std::fs::remove_dir_all(yap_test).unwrap();
+    }
+}
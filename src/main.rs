@@ -1,7 +1,30 @@
-use clap::{Parser, Subcommand};
+use std::io::{IsTerminal, Read};
+use std::path::PathBuf;
+use std::time::Duration;
 
-use yap::{ExecutableCommand, ConfigCommand};
-use yap::vault;
+use clap::{ArgAction, CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use serde_json::json;
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::layer::SubscriberExt;
+
+use yap::{ExecutableCommand, ConfigCommand, Error, Kdf};
+use yap::{agent, askpass, audit, autobackup, backup, clipboard, completions, config, credential, display, docker, doctor, dotenv, editor, entry, export, generate, import, menu, prompt, recovery, render, run, search, server, session, share, sign, snapshot, status, sync, token, vault, wipe};
+use yap::config::{Configuration, SettingKey};
+use yap::entry::{Entry, EntryKind};
+use yap::export::ExportFormat;
+use yap::import::ImportCommand;
+use yap::generate::{CharClass, EntryPolicy, PassphrasePolicy, PasswordPolicy};
+use yap::sign::Author;
+use yap::sync::Resolution;
+use yap::vault::Vault;
+
+/// The most entries offered when no entry has the name given to get
+const MAX_CANDIDATES: usize = 10;
+
+/// The length of PINs generated when no length is given
+const PIN_LENGTH: usize = 6;
 
 #[derive(Parser)]
 #[command(about = "Yet Another Password Manager")]
@@ -12,18 +35,281 @@ struct Cli {
     /// Optional vault store to use. Useful if multiple vaults are in use.
     #[arg(short, long)]
     store: Option<String>,
+
+    /// Read the master password from the PASS environment variable instead of prompting for it
+    #[arg(long)]
+    non_interactive: bool,
+
+    /// Open the vault read-only, so any command which would change it fails. This overrides the
+    /// read_only setting
+    #[arg(long)]
+    read_only: bool,
+
+    /// The format to print results and errors in
+    #[arg(long, value_enum, default_value = "text")]
+    format: OutputFormat,
+
+    /// Print diagnostic output from syncing, encryption, and file access to stderr. Given once
+    /// for info, twice for debug, and three times for trace
+    #[arg(short, long, action = ArgAction::Count)]
+    verbose: u8,
+
+    /// The level of diagnostic output to print, overriding --verbose
+    #[arg(long, value_enum)]
+    log_level: Option<LogLevel>,
+
+    /// Print no diagnostic output, not even warnings
+    #[arg(short, long, conflicts_with_all = ["verbose", "log_level"])]
+    quiet: bool,
+}
+
+/// OutputFormat is the format results are printed in. With JSON, the get, list, config get, audit,
+/// and sync commands print their results as JSON values, other commands print an object holding
+/// their message, and errors are printed as an object holding the error.
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+/// LogLevel is the most detailed diagnostic output printed. Only warnings are printed by default.
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq)]
+enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<LogLevel> for LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Error => LevelFilter::ERROR,
+            LogLevel::Warn => LevelFilter::WARN,
+            LogLevel::Info => LevelFilter::INFO,
+            LogLevel::Debug => LevelFilter::DEBUG,
+            LogLevel::Trace => LevelFilter::TRACE,
+        }
+    }
 }
 
 #[derive(Subcommand)]
 enum Commands {
-    /// Initialize Yap. This should only be used once, as it will remove any existing passwords in the specified store
-    Init,
+    /// Initialize Yap. This should only be used once, as initializing a store which already holds
+    /// a vault makes its passwords unreadable, so it asks for confirmation first
+    Init {
+        /// Protect the vault with a keyfile as well as the master password. The keyfile setting
+        /// is set to its path in the vault's local config file, which isn't synced, so other
+        /// stores and machines keep their own keyfiles, and the file is needed to open the vault
+        /// from then on
+        #[arg(short, long)]
+        keyfile: Option<String>,
+
+        /// Replace an existing vault without asking for confirmation
+        #[arg(short, long)]
+        force: bool,
+    },
 
-    /// Sync passwords with the remote repository
+    /// Sync passwords with the remote repository. Both pulls and pushes unless one is specified
     Sync {
         /// Optional password store to sync if not default
         #[arg(short, long)]
-        store: Option<String>
+        store: Option<String>,
+
+        /// Pull changes from the remote repository
+        #[arg(long)]
+        pull: bool,
+
+        /// Push changes to the remote repository
+        #[arg(long)]
+        push: bool,
+
+        /// Which side to keep when an entry changed both locally and on the remote. Asks if
+        /// not given.
+        #[arg(long, value_enum)]
+        resolve: Option<Resolution>,
+
+        /// Show which entries would be pushed, pulled, or conflict, without syncing
+        #[arg(long, visible_alias = "dry-run", conflicts_with = "resolve")]
+        status: bool,
+    },
+
+    /// Serve the vault over HTTPS, so other tools can list, get, set, and delete entries, and other
+    /// machines can open it as a remote store by giving its URL as --store. Clients authenticate
+    /// with tokens created with yap token create, or the token in YAP_SERVE_TOKEN. Without either,
+    /// a random token is printed. Remote stores send the token in YAP_REMOTE_TOKEN.
+    Serve {
+        /// The address to listen on
+        #[arg(long, default_value = "127.0.0.1:7879")]
+        listen: String,
+
+        /// PEM file holding the TLS certificate chain. Without it, plain HTTP is served, and only
+        /// on loopback addresses
+        #[arg(long, requires = "key")]
+        cert: Option<PathBuf>,
+
+        /// PEM file holding the private key of the TLS certificate
+        #[arg(long, requires = "cert")]
+        key: Option<PathBuf>,
+
+        /// Only serve the encrypted files used by remote stores, without unlocking the vault
+        #[arg(long)]
+        encrypted_only: bool,
+    },
+
+    /// Create, revoke, and list the tokens clients of yap serve authenticate with
+    Token {
+        #[command(subcommand)]
+        command: TokenCommand
+    },
+
+    /// Act as a git credential helper, keeping HTTPS credentials in entries named git/<host>. Set
+    /// it up with `git config --global credential.helper '!yap git-credential'`
+    GitCredential {
+        /// The operation git asks for
+        #[arg(value_enum)]
+        operation: credential::Operation,
+    },
+
+    /// Print the secret for a prompt, so yap can be used as SSH_ASKPASS or SUDO_ASKPASS. The
+    /// prompt is mapped to an entry by the rules added with yap config askpass add
+    Askpass {
+        /// The prompt, as the program asking for the secret gives it
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        prompt: Vec<String>,
+    },
+
+    /// Run a command with secrets from the vault in its environment, so they never need to be
+    /// written to a file or typed into the shell
+    Run {
+        /// A variable to set, as NAME=entry for the entry's secret or NAME=entry#field for one of
+        /// its fields. Can be given more than once
+        #[arg(short, long, required = true)]
+        env: Vec<run::EnvVar>,
+
+        /// The command to run and its arguments, after --
+        #[arg(last = true, required = true)]
+        command: Vec<String>,
+    },
+
+    /// Render a template, replacing {{ yap "name" }} with the secret of an entry and
+    /// {{ yap "name" "field" }} with one of its fields, to generate files such as .pgpass
+    Render {
+        /// The template file
+        template: PathBuf,
+
+        /// Write the result to a file which only you can read, instead of printing it
+        #[arg(short, long)]
+        out: Option<PathBuf>,
+
+        /// Replace the output file if it exists
+        #[arg(short, long, requires = "out")]
+        force: bool,
+    },
+
+    /// Print the secrets of every entry in a folder as KEY=value lines, or write them to a .env
+    /// file. Keys are the entry names within the folder, such as DB_PASSWORD for db/password
+    Env {
+        /// The folder of the entries, such as myapp/dev
+        prefix: String,
+
+        /// Write the variables to a file which only you can read, instead of printing them
+        #[arg(short, long)]
+        out: Option<PathBuf>,
+
+        /// Replace the output file if it exists
+        #[arg(short, long, requires = "out")]
+        force: bool,
+    },
+
+    /// Encrypt an entry for someone else with their age public key, so only they can receive it
+    Share {
+        /// The name of the entry
+        name: String,
+
+        /// The public key to share with, as an age1... key or a file holding one, such as the
+        /// output of share-key
+        #[arg(long)]
+        to: String,
+
+        /// Write the shared entry to this file instead of printing it
+        #[arg(short, long)]
+        out: Option<PathBuf>,
+
+        /// Replace the output file if it exists
+        #[arg(short, long, requires = "out")]
+        force: bool,
+    },
+
+    /// Store an entry shared with you, decrypting it with your share key
+    Receive {
+        /// The file holding the shared entry, read from stdin if not given
+        file: Option<PathBuf>,
+
+        /// Store the entry under this name instead of the one it was shared under
+        #[arg(long)]
+        name: Option<String>,
+
+        /// Replace an existing entry without asking for confirmation
+        #[arg(short, long)]
+        force: bool,
+    },
+
+    /// Print the public key others share entries with, creating your share key the first time
+    ShareKey,
+
+    /// Print the public key entries are signed with when the sign_as setting is set, creating
+    /// your signing key the first time
+    SignKey,
+
+    /// Attach files to an entry, such as SSH keys or recovery codes, which are encrypted along
+    /// with it
+    Attach {
+        #[command(subcommand)]
+        command: AttachCommand
+    },
+
+    /// Manage the members of a team vault, who open it with their share key instead of the
+    /// master password
+    Member {
+        #[command(subcommand)]
+        command: MemberCommand
+    },
+
+    /// Split the vault's master key into recovery shares to hand out, and restore access with
+    /// them if the master password is forgotten
+    Recovery {
+        #[command(subcommand)]
+        command: RecoveryCommand
+    },
+
+    /// Create Docker or Podman secrets from entries, without writing them to disk
+    DockerSecret {
+        #[command(subcommand)]
+        command: DockerSecretCommand
+    },
+
+    /// Provide the freedesktop Secret Service on the session bus, so applications which use
+    /// GNOME Keyring or KWallet keep their secrets in the vault. Requires the secret-service feature
+    SecretService,
+
+    /// Choose an entry in a picker such as rofi or dmenu, then copy its password to the clipboard
+    /// or type it into the focused window. Bind this to a key for one keystroke password entry
+    Menu {
+        /// The picker to run instead of rofi, wofi, or dmenu, such as "fzf". It reads entry
+        /// names from stdin, one per line, and prints the chosen one
+        #[arg(long)]
+        picker: Option<String>,
+
+        /// Use this field of the entry instead of the password, such as username
+        #[arg(short, long)]
+        field: Option<String>,
+
+        /// Type the value into the focused window with xdotool, or wtype on Wayland, instead of
+        /// copying it to the clipboard
+        #[arg(short, long = "type")]
+        type_value: bool,
     },
 
     /// Set or view global settings
@@ -35,63 +321,2140 @@ enum Commands {
     /// Get a password identified by 'name'
     Get {
         /// The name of the password
-        name: String
+        name: String,
+
+        /// Get this field of the entry instead of the password, such as username, url, or notes
+        #[arg(short, long)]
+        field: Option<String>,
+
+        /// Copy the value to the clipboard instead of printing it. The clipboard is cleared after
+        /// the number of seconds in the clip_timeout setting
+        #[arg(short, long)]
+        clip: bool,
+
+        /// If no entry has exactly this name, use the closest match instead of asking which
+        /// entry was meant
+        #[arg(long)]
+        first: bool,
     },
 
-    /// Set a password to the given value. This will overwrite the password if it exists
+    /// Set a password to the given value. This asks before overwriting the password if it
+    /// exists, but keeps any other fields of the entry. If no value is given, it is prompted for
+    /// instead, which keeps it out of shell history
     Set {
         /// The name of the password
+        #[arg(required_unless_present = "batch")]
+        name: Option<String>,
+        value: Option<String>,
+
+        /// Read the value from stdin
+        #[arg(long, conflicts_with = "value")]
+        stdin: bool,
+
+        /// The username to store with the password
+        #[arg(short, long)]
+        username: Option<String>,
+
+        /// The URL to store with the password
+        #[arg(long)]
+        url: Option<String>,
+
+        /// Notes to store with the password
+        #[arg(long)]
+        notes: Option<String>,
+
+        /// Custom fields to store with the password, given as name=value
+        #[arg(short, long)]
+        field: Vec<String>,
+
+        /// Tags to add to the entry, such as work or aws. Can be given more than once
+        #[arg(long)]
+        tag: Vec<String>,
+
+        /// Overwrite an existing password without asking for confirmation
+        #[arg(long)]
+        force: bool,
+
+        /// Set every password in a file at once, given as a JSON object from names to passwords
+        /// or entries, or as lines with a name and a password separated by a tab. Use - to read
+        /// the file from stdin
+        #[arg(long, value_name = "FILE", conflicts_with_all = ["name", "value", "stdin", "username", "url", "notes", "field", "tag"])]
+        batch: Option<String>,
+    },
+
+    /// Edit the entry identified by 'name' in $EDITOR, creating it if it does not exist
+    Edit {
+        /// The name of the entry
+        name: String
+    },
+
+    /// Unlock the vault, so commands use its cached key until it is locked or the session expires
+    Unlock,
+
+    /// Lock the vault, so commands ask for the master password again
+    Lock {
+        /// Lock every vault rather than only this one
+        #[arg(short, long)]
+        all: bool,
+    },
+
+    /// Show the vault's store, backend, and number of entries, whether it is unlocked and for how
+    /// long, when it was last synced, and what a sync would change
+    Status,
+
+    /// Run an agent which caches vault keys, so commands don't ask for the master password each time
+    Agent {
+        #[command(subcommand)]
+        command: AgentCommand
+    },
+
+    /// Back up the whole vault into a single encrypted file, or restore a backup. Entries are also
+    /// backed up automatically before they are overwritten, deleted, or re-encrypted, keeping as
+    /// many backups as the backup_count setting
+    Backup {
+        #[command(subcommand)]
+        command: BackupCommand
+    },
+
+    /// Take a snapshot of the whole vault, list snapshots, or roll the vault back to one
+    Snapshot {
+        #[command(subcommand)]
+        command: SnapshotCommand
+    },
+
+    /// Export every entry in plaintext, for moving to another password manager or auditing
+    Export {
+        /// The format to export entries in
+        #[arg(long, value_enum, default_value = "json")]
+        format: ExportFormat,
+
+        /// Write the export to a new file instead of printing it
+        #[arg(short, long)]
+        out: Option<String>,
+
+        /// Export without asking for confirmation
+        #[arg(short, long)]
+        force: bool,
+    },
+
+    /// Import entries from another password manager
+    Import {
+        #[command(subcommand)]
+        source: ImportCommand,
+
+        /// Overwrite existing entries with the same names as imported entries
+        #[arg(short, long, global = true)]
+        force: bool,
+    },
+
+    /// Add or show secure notes, which hold multi-line text such as recovery codes
+    Note {
+        #[command(subcommand)]
+        command: NoteCommand
+    },
+
+    /// Delete the password identified by 'name'
+    Rm {
+        /// The name of the password, or of a folder when --recursive is given. Deleted passwords
+        /// are kept in the trash for the number of days in the trash_days setting
+        name: String,
+
+        /// Delete every password in the folder 'name', such as work/
+        #[arg(short, long)]
+        recursive: bool,
+
+        /// Delete without asking for confirmation
+        #[arg(short, long)]
+        force: bool,
+    },
+
+    /// Rename the password identified by 'old' to 'new'
+    Mv {
+        /// The current name of the password
+        old: String,
+
+        /// The new name of the password
+        new: String,
+
+        /// Replace the password named 'new' if it already exists
+        #[arg(short, long)]
+        force: bool,
+    },
+
+    /// Show the previous versions of the password identified by 'name', or with --audit, what was
+    /// done to the vault on this machine
+    History {
+        /// The name of the password, which limits the activity log to it with --audit
+        #[arg(required_unless_present = "audit")]
+        name: Option<String>,
+
+        /// Show the activity log, the operations on the vault such as get, set, rm, and sync
+        #[arg(long)]
+        audit: bool,
+    },
+
+    /// Convert the vault to another backend in place, taking a snapshot of it first to roll back to
+    Migrate {
+        /// The backend to convert the vault to
+        #[arg(long, value_parser = ["files", "sqlite"])]
+        to: String,
+    },
+
+    /// Destroy the whole vault in the store, overwriting its files before removing them, after
+    /// asking for confirmation. This can't be undone
+    Nuke {
+        /// Destroy the vault without asking for confirmation
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// List, restore, or permanently remove deleted passwords
+    Trash {
+        #[command(subcommand)]
+        command: TrashCommand
+    },
+
+    /// Restore a previous version of the password identified by 'name'
+    Restore {
+        /// The name of the password
+        name: String,
+
+        /// The version to restore, as shown by the history command
+        #[arg(long)]
+        version: u32,
+    },
+
+    /// Replace the password identified by 'name' with a newly generated one, keeping the old
+    /// password in the entry's history and its other fields as they are
+    Rotate {
+        /// The name of the password
+        name: String,
+
+        /// Number of characters in the new password, defaults to the length in the entry's
+        /// policy or the generate.length setting
+        #[arg(short, long)]
+        length: Option<usize>,
+
+        /// Copy the new password to the clipboard. The clipboard is cleared after the number of
+        /// seconds in the clip_timeout setting
+        #[arg(short, long)]
+        clip: bool,
+
+        /// Print the new password after storing it
+        #[arg(short, long)]
+        print: bool,
+    },
+
+    /// Set the rules for passwords generated for the entry identified by 'name', such as a
+    /// site's length limit or the symbols it allows. The generate and rotate commands follow
+    /// them. With no rules given, the entry's policy is shown instead
+    Policy {
+        /// The name of the entry
         name: String,
-        value: String,
+
+        /// Number of characters in the entry's passwords
+        #[arg(short, long)]
+        length: Option<usize>,
+
+        /// The only symbols the site allows in its passwords
+        #[arg(long, conflicts_with = "no_symbols")]
+        symbols: Option<String>,
+
+        /// The site doesn't allow symbols in its passwords
+        #[arg(long)]
+        no_symbols: bool,
+
+        /// A class of characters every password must contain, may be given more than once
+        #[arg(long, value_enum)]
+        require: Vec<CharClass>,
+
+        /// Remove the entry's policy
+        #[arg(long, conflicts_with_all = ["length", "symbols", "no_symbols", "require"])]
+        clear: bool,
+    },
+
+    /// Measure how fast each key derivation function is on this machine, and suggest the
+    /// parameters which make unlocking a vault take about as long as the target
+    BenchKdf {
+        /// How long unlocking a vault should take, in milliseconds
+        #[arg(long, default_value_t = 1000)]
+        target_ms: u64,
+
+        /// Derive the vault's key with the suggested parameters from now on, asking for the
+        /// master password
+        #[arg(long)]
+        apply: bool,
+
+        /// The key derivation function to apply, defaults to the one the vault already uses
+        #[arg(long, requires = "apply", value_parser = ["pbkdf2", "argon2id"])]
+        kdf: Option<String>,
+    },
+
+    /// Change the master password, re-encrypting every stored password
+    Rekey,
+
+    /// List the names of stored passwords
+    List {
+        /// Only list passwords whose names start with this prefix
+        prefix: Option<String>,
+
+        /// Show hierarchical names, such as work/github, as a tree
+        #[arg(short, long, conflicts_with = "long")]
+        tree: bool,
+
+        /// Show when each password was created, modified, and last accessed, and who last changed
+        /// it if they signed it
+        #[arg(short, long)]
+        long: bool,
+
+        /// Only list entries with this tag. Can be given more than once to only list entries
+        /// with every tag
+        #[arg(long)]
+        tag: Vec<String>,
+    },
+
+    /// Rebuild the index of entry names and timestamps which listing and searching use. This is
+    /// only needed if entries were added to the vault directory by hand
+    Reindex,
+
+    /// Find passwords whose names match a pattern
+    Find {
+        /// The pattern to search for, matched as a substring unless --regex is given. Every
+        /// entry matches if it is left out, which is useful with --tag
+        #[arg(required_unless_present = "tag")]
+        pattern: Option<String>,
+
+        /// Match the pattern as a regular expression
+        #[arg(short, long)]
+        regex: bool,
+
+        /// Ignore case when matching
+        #[arg(short, long)]
+        ignore_case: bool,
+
+        /// Also search the username, url, notes, and custom fields of each entry
+        #[arg(short, long)]
+        deep: bool,
+
+        /// Only search entries with this tag. Can be given more than once to only search entries
+        /// with every tag
+        #[arg(long)]
+        tag: Vec<String>,
+    },
+
+    /// Show how many entries are in each folder and have each tag, how long ago passwords were
+    /// changed, how much space the vault takes, and how much history it keeps
+    Stats,
+
+    /// Report weak, short, reused, and old passwords. Exits with an error if any are found, so it
+    /// can be used in scripts
+    Audit {
+        /// Report passwords which haven't been changed for this many days, or never if zero
+        #[arg(long, default_value_t = 365)]
+        max_age: u64,
+
+        /// Also check passwords against known breaches with the Have I Been Pwned API. Only
+        /// the first 5 characters of each password's SHA-1 hash are sent
+        #[arg(long)]
+        breach: bool,
+    },
+
+    /// Check that every entry, previous version, attachment, and entry in the trash decrypts with
+    /// the vault's key, that the index matches the entries on disk, and that the vault header is
+    /// well-formed. Exits with an error if any problems are found, so it can be used in scripts
+    Verify {
+        /// Move corrupted files out of the vault, into its .quarantine directory
+        #[arg(long)]
+        quarantine: bool,
+    },
+
+    /// Check the config, the vault directory and header, the remote, the agent, and the clipboard,
+    /// printing how to fix anything which is wrong. Needs no master password, so it works when
+    /// the vault can't be opened. Exits with an error if any check fails
+    Doctor,
+
+    /// Repair the vault: remove temporary files left by interrupted writes, move history and
+    /// attachments left behind by an interrupted delete or restore to their entry, restore
+    /// corrupted files from a backup or an entry's history, and rebuild the index. Files which
+    /// can't be repaired are moved into the vault's .quarantine directory
+    Repair {
+        /// A backup to restore corrupted files from, see yap backup. Its password is asked for
+        #[arg(long)]
+        backup: Option<String>,
     },
 
     /// Generate and store a password using the given name.
     Generate {
         /// The name of the password
-        name: String
+        #[arg(required_unless_present = "no_save")]
+        name: Option<String>,
+
+        /// Number of characters in the password, defaults to the generate.length setting
+        #[arg(short, long)]
+        length: Option<usize>,
+
+        /// Include symbols in the password, even if the generate.symbols setting is false
+        #[arg(long, conflicts_with = "no_symbols")]
+        symbols: bool,
+
+        /// Leave symbols out of the password
+        #[arg(long)]
+        no_symbols: bool,
+
+        /// Leave digits out of the password
+        #[arg(long)]
+        no_digits: bool,
+
+        /// Only use characters from this set, instead of letters, digits, and symbols. Defaults
+        /// to the generate.charset setting
+        #[arg(long)]
+        charset: Option<String>,
+
+        /// Generate a password from a pattern, where each `a` is replaced by a lowercase letter,
+        /// `A` by an uppercase letter, `9` by a digit, `s` by a symbol, `x` by a letter or digit,
+        /// and `*` by any of them. Other characters, or any character after a backslash, are
+        /// kept as they are, so "Aaaa-9999-ssss" could give "Kbrt-4071-#!@%"
+        #[arg(long, conflicts_with_all = ["length", "symbols", "no_symbols", "no_digits", "charset", "pin", "pronounceable", "words"])]
+        pattern: Option<String>,
+
+        /// Generate a PIN of digits only, such as for a device or bank card. Its length defaults
+        /// to 6 instead of the generate.length setting
+        #[arg(long, conflicts_with_all = ["symbols", "no_symbols", "no_digits", "charset", "pronounceable", "words"])]
+        pin: bool,
+
+        /// Generate a password of syllables which can be read aloud, such as over the phone, with
+        /// a few digits and symbols sprinkled between them
+        #[arg(long, conflicts_with_all = ["charset", "words"])]
+        pronounceable: bool,
+
+        /// Generate a diceware passphrase of this many words from the EFF large wordlist,
+        /// instead of a password of random characters
+        #[arg(short, long, value_parser = clap::value_parser!(u16).range(1..), conflicts_with_all = ["length", "symbols", "no_symbols", "no_digits", "charset"])]
+        words: Option<u16>,
+
+        /// The separator between the words of a passphrase
+        #[arg(long, default_value = "-", requires = "words")]
+        separator: String,
+
+        /// Capitalize each word of a passphrase
+        #[arg(long, requires = "words")]
+        capitalize: bool,
+
+        /// Print the password after storing it
+        #[arg(short, long)]
+        print: bool,
+
+        /// Copy the password to the clipboard. The clipboard is cleared after the number of
+        /// seconds in the clip_timeout setting
+        #[arg(short, long)]
+        clip: bool,
+
+        /// Print or copy the password without storing it in the vault, such as for a throwaway
+        /// account
+        #[arg(long, conflicts_with_all = ["name", "force"])]
+        no_save: bool,
+
+        /// Overwrite an existing password without asking for confirmation
+        #[arg(short, long)]
+        force: bool,
     },
-}
 
-impl ExecutableCommand for Cli {
-    fn execute(self) -> Result<String, String> {
-        match self.command {
+    /// Browse, search, and edit entries in a terminal interface
+    Tui,
 
-            // Initialize the yap directory and the vaults
-            Commands::Init => {
-                yap::init()?;
-                vault::create(self.store)?;
-                Ok("Succesfully initialized Yap!".to_string())
-            }
+    /// Print a script which completes yap commands in a shell, such as with
+    /// `source <(yap completions bash)`
+    Completions {
+        shell: Shell,
+    },
 
-            // Execute the config subcommands
-            Commands::Config { command } => command.execute(),
+    /// List entry names starting with a prefix, used internally by shell completions
+    #[command(name = completions::COMPLETE_COMMAND, hide = true)]
+    Complete {
+        #[arg(default_value = "")]
+        prefix: String,
+    },
 
-            // Sync the given store with a remote repository
-            Commands::Sync { .. } => Ok("TODO".to_string()),
+    /// Run the agent in the foreground, used internally by agent start
+    #[command(name = agent::AGENT_COMMAND, hide = true)]
+    RunAgent {
+        ttl: u64,
+    },
 
-            // Get a password
-            Commands::Get { name } => {
-                let vault = vault::load(self.store)?;
-                let pw = vault.get_key(name.as_str())?;
-                Ok(pw)
-            }
+    /// Clear the clipboard after a timeout, used internally by get --clip
+    #[command(name = clipboard::CLEAR_COMMAND, hide = true)]
+    ClearClipboard {
+        timeout: u64,
+    },
+}
 
-            // Set a password
-            Commands::Set { name, value } => {
-                let mut vault = vault::load(self.store)?;
-                vault.set_key(name.as_str(), value)?;
-                Ok("Successfully saved password".to_string())
-            }
+impl Commands {
+    /// Checks whether the command prints its result as a JSON value with --format json, rather
+    /// than as a message
+    fn prints_json(&self) -> bool {
+        matches!(self, Commands::Get { .. } | Commands::Policy { .. } | Commands::List { .. } | Commands::Audit { .. } | Commands::Sync { .. }
+            | Commands::Verify { .. } | Commands::Repair { .. } | Commands::Doctor | Commands::Status | Commands::Stats
+            | Commands::BenchKdf { apply: false, .. }
+            | Commands::Token { command: TokenCommand::Create { .. } | TokenCommand::List }
+            | Commands::Member { command: MemberCommand::List } | Commands::Attach { command: AttachCommand::List { .. } }
+            | Commands::Trash { command: TrashCommand::List } | Commands::Backup { command: BackupCommand::List }
+            | Commands::Snapshot { command: SnapshotCommand::List } | Commands::History { audit: true, .. }
+            | Commands::Config { command: ConfigCommand::Get { .. } | ConfigCommand::List })
+    }
 
-            // Generate and store a password
-            Commands::Generate { .. } => Ok("TODO".to_string()),
+    /// Checks whether the command changes the vault in its store, which the read_only setting
+    /// forbids, see `Cli::check_writable`. Commands which only change the user's config, such as
+    /// config set, or write outside the store, such as backup create, don't.
+    fn writes_store(&self) -> bool {
+        match self {
+            Commands::Init { .. } | Commands::Set { .. } | Commands::Edit { .. } | Commands::Receive { .. } | Commands::Import { .. }
+            | Commands::Rm { .. } | Commands::Mv { .. } | Commands::Migrate { .. } | Commands::Nuke { .. } | Commands::Restore { .. }
+            | Commands::Rotate { .. } | Commands::Rekey | Commands::Repair { .. }
+            | Commands::Verify { quarantine: true }
+            | Commands::BenchKdf { apply: true, .. }
+            | Commands::Token { command: TokenCommand::Create { .. } | TokenCommand::Revoke { .. } }
+            | Commands::Attach { command: AttachCommand::Add { .. } | AttachCommand::Remove { .. } }
+            | Commands::Member { command: MemberCommand::Add { .. } | MemberCommand::Remove { .. } }
+            | Commands::Recovery { command: RecoveryCommand::Restore { .. } }
+            | Commands::Note { command: NoteCommand::Add { .. } }
+            | Commands::Trash { command: TrashCommand::Restore { .. } | TrashCommand::Empty { .. } }
+            | Commands::Backup { command: BackupCommand::Restore { .. } | BackupCommand::Recover { .. } }
+            | Commands::Snapshot { command: SnapshotCommand::Create { .. } | SnapshotCommand::Rollback { .. } } => true,
+            Commands::Sync { status, .. } => !status,
+            Commands::Policy { length, symbols, no_symbols, require, clear, .. } => {
+                *clear || length.is_some() || symbols.is_some() || *no_symbols || !require.is_empty()
+            }
+            Commands::Generate { name, no_save, .. } => name.is_some() && !no_save,
+            _ => false,
         }
     }
 }
 
-fn main() {
-    match Cli::parse().execute() {
-        Ok(msg) => println!("{}", msg),
-        Err(msg) => eprintln!("{}", msg)
+#[derive(Subcommand)]
+enum AgentCommand {
+    /// Start the agent in the background
+    Start {
+        /// Seconds to cache each key for, defaults to the agent_ttl setting
+        #[arg(long)]
+        ttl: Option<u64>,
+    },
+
+    /// Stop the agent, forgetting every cached key
+    Stop,
+
+    /// Show the vaults whose keys are cached
+    Status,
+}
+
+#[derive(Subcommand)]
+enum BackupCommand {
+    /// Write a backup of the vault, protected by a backup password, to a new file
+    Create {
+        /// The backup file to create
+        file: String
+    },
+
+    /// Restore a backup into the store, which must not already hold a vault
+    Restore {
+        /// The backup file to restore
+        file: String
+    },
+
+    /// List the automatic backups, oldest first
+    List,
+
+    /// Put back a password from an automatic backup, keeping the current one in its history
+    Recover {
+        /// The id of the automatic backup, see backup list
+        id: String,
+
+        /// The name of the password to put back
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum SnapshotCommand {
+    /// Take a snapshot of every file in the vault, which only stores the files changed since the
+    /// last snapshot
+    Create {
+        /// A label to tell the snapshot apart
+        label: Option<String>,
+    },
+
+    /// List the snapshots, oldest first
+    List,
+
+    /// Roll the whole vault back to a snapshot, after taking a snapshot of it as it is now
+    Rollback {
+        /// The id of the snapshot, see snapshot list
+        id: String,
+
+        /// Roll back without asking for confirmation
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum TokenCommand {
+    /// Create a token, which is printed once and only its hash is kept
+    Create {
+        /// The name of the token, for revoking it later
+        name: String,
+
+        /// Only allow listing and getting entries
+        #[arg(long)]
+        read_only: bool,
+
+        /// Only allow entries in the folder given by the prefix, such as work/, or the entry and
+        /// folder with its name if it doesn't end in /. Can be given more than once
+        #[arg(long = "prefix")]
+        prefixes: Vec<String>,
+
+        /// Days until the token expires, or never if not given
+        #[arg(long)]
+        expires: Option<u32>,
+    },
+
+    /// Revoke a token, so it can no longer be used
+    Revoke {
+        /// The name of the token
+        name: String
+    },
+
+    /// List the tokens, with what they allow and when they expire
+    List,
+}
+
+#[derive(Subcommand)]
+enum TrashCommand {
+    /// List the passwords in the trash, with when they were deleted
+    List,
+
+    /// Restore the most recently deleted password identified by 'name', along with its history
+    /// and attachments
+    Restore {
+        /// The name of the password
+        name: String,
+
+        /// Replace an existing password with the same name, moving it into the trash
+        #[arg(short, long)]
+        force: bool,
+    },
+
+    /// Permanently remove the passwords in the trash
+    Empty {
+        /// Only remove passwords deleted more than this many days ago
+        #[arg(long)]
+        days: Option<u64>,
+
+        /// Empty the trash without asking for confirmation
+        #[arg(short, long)]
+        force: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum AttachCommand {
+    /// Attach a file to the entry identified by 'name', replacing any attachment with the same
+    /// name. Large files are encrypted as they are read, so they never have to fit in memory
+    Add {
+        /// The name of the entry
+        name: String,
+
+        /// The file to attach
+        file: PathBuf,
+
+        /// The name to attach the file under, defaults to the file's name
+        #[arg(long = "as", value_name = "NAME")]
+        attachment: Option<String>,
+    },
+
+    /// Decrypt an attachment of the entry identified by 'name' into a file only you can read
+    Get {
+        /// The name of the entry
+        name: String,
+
+        /// The name of the attachment
+        attachment: String,
+
+        /// The file to write, defaults to the attachment's name in the current directory
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Replace the file if it already exists
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// List the attachments of the entry identified by 'name'
+    List {
+        /// The name of the entry
+        name: String,
+    },
+
+    /// Remove an attachment from the entry identified by 'name'
+    Remove {
+        /// The name of the entry
+        name: String,
+
+        /// The name of the attachment
+        attachment: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum MemberCommand {
+    /// Give someone access to the vault with their age public key
+    Add {
+        /// The name of the member, for removing them later
+        name: String,
+
+        /// Their public key, as an age1... key or a file holding one, such as the output of
+        /// share-key
+        #[arg(long)]
+        key: String,
+    },
+
+    /// Take away a member's access. The vault is re-encrypted with a new master key, so they
+    /// can't read anything changed afterwards, and recovery shares split before no longer work.
+    /// Requires the master password
+    Remove {
+        /// The name of the member
+        name: String
+    },
+
+    /// List the members, with their public keys
+    List,
+}
+
+#[derive(Subcommand)]
+enum RecoveryCommand {
+    /// Split the master key into printable shares, so any threshold of them restore access to
+    /// the vault. Give each share to a different person, or keep them in different places
+    Split {
+        /// The number of shares to make
+        #[arg(long, default_value_t = 5, value_parser = clap::value_parser!(u8).range(2..))]
+        shares: u8,
+
+        /// The number of shares needed to restore access
+        #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u8).range(2..))]
+        threshold: u8,
+    },
+
+    /// Restore access to the vault from recovery shares, and set a new master password
+    Restore {
+        /// The shares, read from stdin one per line if none are given
+        shares: Vec<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum DockerSecretCommand {
+    /// Create a secret holding the secret of an entry, or one of its fields given as entry#field
+    Create {
+        /// The name of the secret to create
+        name: String,
+
+        /// The entry holding the value
+        entry: String,
+
+        /// The container engine to create the secret with
+        #[arg(long, value_enum, default_value = "docker")]
+        engine: docker::Engine,
+
+        /// Print the value exactly as it is, without a newline, instead of creating the secret, for
+        /// piping into other tools
+        #[arg(long)]
+        stdout_raw: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum NoteCommand {
+    /// Add a note, read from stdin if it is piped in, or written in $EDITOR otherwise. This will
+    /// overwrite the note if it exists
+    Add {
+        /// The name of the note
+        name: String
+    },
+
+    /// Show the text of a note
+    Show {
+        /// The name of the note
+        name: String
+    },
+}
+
+impl Cli {
+    /// Returns the level of diagnostic output asked for with --log-level, --verbose, or --quiet
+    fn log_level(&self) -> LevelFilter {
+        if self.quiet {
+            return LevelFilter::OFF;
+        }
+        match (self.log_level, self.verbose) {
+            (Some(level), _) => level.into(),
+            (None, 0) => LevelFilter::WARN,
+            (None, 1) => LevelFilter::INFO,
+            (None, 2) => LevelFilter::DEBUG,
+            (None, _) => LevelFilter::TRACE,
+        }
+    }
+
+    /// Fails with `Error::ReadOnly` if the command would change a store which is read-only,
+    /// through the read_only setting or --read-only. This is checked once before any command
+    /// runs, so commands which write the store directly rather than through the vault are
+    /// covered too.
+    fn check_writable(&self) -> yap::Result<()> {
+        if !self.command.writes_store() {
+            return Ok(());
+        }
+
+        let store = match &self.command {
+            Commands::Sync { store: Some(store), .. } => Some(store.clone()),
+            _ => self.store.clone(),
+        };
+        let vault_dir = vault::get_path_or_default(store)?;
+        match config::read_or_default()?.with_vault(vault_dir.as_path())?.read_only() {
+            true => Err(Error::ReadOnly),
+            false => Ok(()),
+        }
+    }
+}
+
+impl ExecutableCommand for Cli {
+    fn execute(self) -> yap::Result<String> {
+        let json = self.format == OutputFormat::Json;
+        self.check_writable()?;
+        match self.command {
+
+            // Initialize the yap directory and the vaults
+            Commands::Init { keyfile, force } => {
+                let question = "A vault already exists in this store, replace it? Its passwords will be lost";
+                if vault::exists(self.store.clone())? && !prompt::allow(question, force, self.non_interactive)? {
+                    return Ok("Cancelled, the existing vault was kept".to_string());
+                }
+
+                yap::init()?;
+                let config = config::read_or_default()?;
+                let vault_dir = vault::get_path_or_default(self.store.clone())?;
+                let keyfile = keyfile.map(std::fs::canonicalize).transpose()?;
+                let contents = keyfile.as_ref().map(std::fs::read).transpose()?;
+
+                let pass = prompt::master_password(self.non_interactive, true)?;
+                vault::create(self.store, pass.as_str(), config.kdf(), config.backend(), contents.as_deref())?;
+
+                if let Some(keyfile) = keyfile {
+                    config::set_local_key(vault_dir.as_path(), SettingKey::Keyfile, keyfile.display().to_string().as_str())?;
+                }
+                Ok("Succesfully initialized Yap!".to_string())
+            }
+
+            // Execute the config subcommands. Settings are shown as they apply to the selected
+            // store, including the overrides in its vault config file.
+            Commands::Config { command: ConfigCommand::Get { key } } => {
+                let config = config::read_or_default()?.with_vault(vault::get_path_or_default(self.store)?.as_path())?;
+                let setting_key = SettingKey::parse(key.as_str())
+                    .ok_or_else(|| Error::BadConfigKey { key: key.clone() })?;
+                let value = config.get_key(setting_key);
+                if json {
+                    return Ok(json!({ "key": key, "value": value }).to_string());
+                }
+                Ok(value)
+            }
+
+            Commands::Config { command: ConfigCommand::List } => {
+                let config = config::read_or_default()?.with_vault(vault::get_path_or_default(self.store)?.as_path())?;
+                if !json {
+                    return Ok(config.listing());
+                }
+                let settings: Vec<_> = SettingKey::ALL.into_iter()
+                    .map(|key| json!({
+                        "key": key.name(),
+                        "value": config.get_key(key),
+                        "default": Configuration::default_key(key),
+                        "source": config.source(key),
+                    }))
+                    .collect();
+                Ok(json!(settings).to_string())
+            }
+
+            Commands::Config { command } => command.execute(),
+
+            // Sync the given store with a remote repository
+            Commands::Sync { store, pull, push, resolve, status } => {
+                let store = store.or(self.store);
+                let vault_dir = vault::get_path_or_default(store.clone())?;
+                let config = config::read_or_default()?.with_vault(vault_dir.as_path())?;
+
+                if status {
+                    let status = sync::status(vault_dir, config.remote_url(), pull, push)?;
+                    if json {
+                        return Ok(json!(status).to_string());
+                    }
+                    return Ok(status.to_string());
+                }
+
+                // Keeping both sides needs to know which entries conflict, so the first attempt
+                // only resolves conflicts by keeping one side
+                let first = resolve.filter(|resolution| *resolution != Resolution::Both);
+                let summary = match sync::sync(vault_dir.clone(), config.remote_url(), pull, push, first) {
+                    Err(Error::SyncConflict { names }) if first.is_none() => {
+                        let resolution = match resolve {
+                            Some(resolution) => resolution,
+                            None => choose_resolution(names.clone(), self.non_interactive)?,
+                        };
+                        if resolution == Resolution::Both {
+                            let mut vault = session::load_vault(store, self.non_interactive)?;
+                            sync::keep_both(vault.as_mut(), names.as_slice())?;
+                        }
+                        let mut summary = sync::sync(vault_dir, config.remote_url(), pull, push, Some(resolution))?;
+                        summary.resolved = names;
+                        summary
+                    }
+                    result => result?,
+                };
+                if json {
+                    return Ok(json!(summary).to_string());
+                }
+                Ok(summary.to_string())
+            }
+
+            // Serve the vault to other tools
+            Commands::Serve { listen, cert, key, encrypted_only } => {
+                let dir = vault::get_path_or_default(self.store.clone())?;
+                let mut vault = match encrypted_only {
+                    true => None,
+                    false => Some(session::load_vault(self.store, self.non_interactive)?),
+                };
+                let token = match server::configured_token() {
+                    Some(token) => Some(token),
+                    None if !token::list(dir.as_path())?.is_empty() => None,
+                    None => {
+                        let token = server::new_token()?;
+                        eprintln!("Clients can authenticate with the bearer token {}", token);
+                        Some(token)
+                    }
+                };
+                let vault = vault.as_mut().map(|vault| vault.as_mut() as &mut dyn Vault);
+                server::Server::new(dir, vault, token).serve(listen.as_str(), cert.as_deref().zip(key.as_deref()))?;
+                Ok(String::new())
+            }
+
+            Commands::Token { command: TokenCommand::Create { name, read_only, prefixes, expires } } => {
+                let vault_dir = vault::get_path_or_default(self.store)?;
+                let secret = token::create(vault_dir.as_path(), name.as_str(), read_only, prefixes, expires)?;
+                if json {
+                    return Ok(json!({ "name": name, "token": secret }).to_string());
+                }
+                Ok(secret)
+            }
+
+            Commands::Token { command: TokenCommand::Revoke { name } } => {
+                token::revoke(vault::get_path_or_default(self.store)?.as_path(), name.as_str())?;
+                Ok(format!("Successfully revoked token {}", name))
+            }
+
+            Commands::Token { command: TokenCommand::List } => {
+                let tokens = token::list(vault::get_path_or_default(self.store)?.as_path())?;
+                if json {
+                    // The hashes are only for the server, so they are left out
+                    let tokens: Vec<_> = tokens.iter()
+                        .map(|token| json!({ "name": token.name, "read_only": token.read_only, "prefixes": token.prefixes,
+                            "expires": token.expires, "created": token.created }))
+                        .collect();
+                    return Ok(json!(tokens).to_string());
+                }
+                Ok(token::Listing(tokens).to_string())
+            }
+
+            // Answer git's request for a credential
+            Commands::GitCredential { operation } => {
+                let credential = credential::Credential::read(std::io::stdin().lock())?;
+                let mut vault = session::load_vault(self.store, self.non_interactive)?;
+                credential::run(vault.as_mut(), operation, &credential)
+            }
+
+            // Answer a prompt from ssh or sudo
+            Commands::Askpass { prompt } => {
+                let config = config::read_or_default()?;
+                let mut vault = session::load_vault(self.store, self.non_interactive)?;
+                askpass::secret(vault.as_mut(), config.askpass_rules(), prompt.join(" ").as_str())
+            }
+
+            // Run a command with secrets in its environment
+            Commands::Run { env, command } => {
+                let mut vault = session::load_vault(self.store, self.non_interactive)?;
+                let env = run::resolve(vault.as_mut(), env.as_slice())?;
+                drop(vault);
+                std::process::exit(run::run(command.as_slice(), env.as_slice())?);
+            }
+
+            // Render a template with secrets
+            Commands::Render { template, out, force } => {
+                let template = std::fs::read_to_string(template)?;
+                let mut vault = session::load_vault(self.store, self.non_interactive)?;
+                let rendered = render::render(vault.as_mut(), template.as_str())?;
+                match out {
+                    Some(out) => {
+                        render::write(out.as_path(), rendered.as_str(), force)?;
+                        Ok(format!("Successfully rendered {}", out.display()))
+                    }
+                    None => Ok(rendered.trim_end_matches('\n').to_string()),
+                }
+            }
+
+            // Print or write the entries in a folder as environment variables
+            Commands::Env { prefix, out, force } => {
+                let mut vault = session::load_vault(self.store, self.non_interactive)?;
+                let variables = dotenv::render(vault.as_mut(), prefix.as_str())?;
+                match out {
+                    Some(out) => {
+                        render::write(out.as_path(), variables.as_str(), force)?;
+                        Ok(format!("Successfully wrote {}", out.display()))
+                    }
+                    None => Ok(variables.trim_end_matches('\n').to_string()),
+                }
+            }
+
+            // Encrypt an entry for someone else
+            Commands::Share { name, to, out, force } => {
+                let recipient = share::recipient(to.as_str())?;
+                let mut vault = session::load_vault(self.store, self.non_interactive)?;
+                let shared = share::share(vault.as_mut(), name.as_str(), &recipient)?;
+                match out {
+                    Some(out) => {
+                        render::write(out.as_path(), shared.as_str(), force)?;
+                        Ok(format!("Successfully shared {} in {}", name, out.display()))
+                    }
+                    None => Ok(shared.trim_end().to_string()),
+                }
+            }
+
+            // Store an entry someone shared
+            Commands::Receive { file, name, force } => {
+                let shared = match file {
+                    Some(file) => std::fs::read(file)?,
+                    None => prompt::read_stdin()?.into_bytes(),
+                };
+                let (shared_name, entry) = share::receive(share::key_path()?.as_path(), shared.as_slice())?;
+                let name = name.unwrap_or(shared_name);
+
+                let mut vault = session::load_vault(self.store.clone(), self.non_interactive)?;
+                let question = format!("Overwrite password {}?", name);
+                if vault.has_key(name.as_str()) && !prompt::allow(question.as_str(), force, self.non_interactive)? {
+                    return Ok("Cancelled, password was not changed".to_string());
+                }
+                vault.set_entry(name.as_str(), &entry)?;
+                auto_sync(self.store);
+                Ok(format!("Successfully received {}", name))
+            }
+
+            // Print the public share key
+            Commands::ShareKey => share::public_key(share::key_path()?.as_path()),
+
+            // Print the public signing key
+            Commands::SignKey => sign::public_key(sign::key_path()?.as_path()),
+
+            // Attach a file to an entry
+            Commands::Attach { command: AttachCommand::Add { name, file, attachment } } => {
+                let mut vault = session::load_vault(self.store.clone(), self.non_interactive)?;
+                let (attachment, len) = yap::attachment::add(vault.as_mut(), name.as_str(), file.as_path(), attachment.as_deref())?;
+                auto_sync(self.store);
+                Ok(format!("Successfully attached {} to {}, {} bytes", attachment, name, len))
+            }
+
+            // Decrypt an attachment into a file
+            Commands::Attach { command: AttachCommand::Get { name, attachment, output, force } } => {
+                let vault = session::load_vault(self.store, self.non_interactive)?;
+                let output = output.unwrap_or_else(|| PathBuf::from(attachment.as_str()));
+                let len = yap::attachment::save(vault.as_ref(), name.as_str(), attachment.as_str(), output.as_path(), force)?;
+                Ok(format!("Successfully wrote {} to {}, {} bytes", attachment, output.display(), len))
+            }
+
+            // List the attachments of an entry
+            Commands::Attach { command: AttachCommand::List { name } } => {
+                let vault = session::load_vault(self.store, self.non_interactive)?;
+                if !vault.has_key(name.as_str()) {
+                    return Err(Error::PasswordNotFound { name });
+                }
+                let attachments = vault.list_attachments(name.as_str())?;
+                if json {
+                    return Ok(json!(attachments).to_string());
+                }
+                Ok(attachments.join("\n"))
+            }
+
+            // Remove an attachment from an entry
+            Commands::Attach { command: AttachCommand::Remove { name, attachment } } => {
+                let mut vault = session::load_vault(self.store.clone(), self.non_interactive)?;
+                vault.remove_attachment(name.as_str(), attachment.as_str())?;
+                auto_sync(self.store);
+                Ok(format!("Successfully removed {} from {}", attachment, name))
+            }
+
+            // Give someone access to a team vault
+            Commands::Member { command: MemberCommand::Add { name, key } } => {
+                let recipient = share::recipient(key.as_str())?;
+                let mut vault = session::load_vault(self.store.clone(), self.non_interactive)?;
+                vault.add_member(name.as_str(), &recipient)?;
+                auto_sync(self.store);
+                Ok(format!("Successfully added member {}", name))
+            }
+
+            // Take away a member's access, rotating the master key
+            Commands::Member { command: MemberCommand::Remove { name } } => {
+                // The master password is always asked for, since the new master key is encrypted with it
+                let pass = prompt::master_password(self.non_interactive, false)?;
+                let mut vault = vault::load(self.store.clone(), pass.as_str())?;
+                vault.remove_member(name.as_str(), pass.as_str())?;
+                auto_sync(self.store);
+                Ok(format!("Successfully removed member {} and re-encrypted the vault", name))
+            }
+
+            // List the members of a team vault
+            Commands::Member { command: MemberCommand::List } => {
+                let members = vault::members(self.store)?;
+                if json {
+                    let members: Vec<_> = members.iter()
+                        .map(|(name, key)| json!({ "name": name, "key": key }))
+                        .collect();
+                    return Ok(json!(members).to_string());
+                }
+                Ok(members.iter().map(|(name, key)| format!("{}  {}", name, key)).collect::<Vec<_>>().join("\n"))
+            }
+
+            // Split the master key into recovery shares
+            Commands::Recovery { command: RecoveryCommand::Split { shares, threshold } } => {
+                // The master password is always asked for, even if the agent has cached the key
+                let pass = prompt::master_password(self.non_interactive, false)?;
+                let master_key = vault::master_key(self.store, pass.as_str())?;
+                let shares = recovery::split(&master_key, shares, threshold)?;
+                Ok(format!("Any {} of these {} shares restore access to the vault:\n{}", threshold, shares.len(), shares.join("\n")))
+            }
+
+            // Restore access to the vault with recovery shares
+            Commands::Recovery { command: RecoveryCommand::Restore { shares } } => {
+                let shares = match shares.is_empty() {
+                    true => prompt::read_stdin()?.lines().map(String::from).collect(),
+                    false => shares,
+                };
+                let master_key = recovery::combine(shares.as_slice())?;
+                let new_pass = prompt::new_master_password(self.non_interactive)?;
+                vault::recover(self.store, master_key, new_pass.as_str())?;
+                Ok("Successfully restored access and changed master password".to_string())
+            }
+
+            // Create a container secret from an entry
+            Commands::DockerSecret { command: DockerSecretCommand::Create { name, entry, engine, stdout_raw } } => {
+                let mut vault = session::load_vault(self.store, self.non_interactive)?;
+                let value = run::Secret::parse(entry.as_str()).resolve(vault.as_mut())?;
+                if stdout_raw {
+                    docker::write_raw(value.as_str())?;
+                    std::process::exit(0);
+                }
+                docker::create(engine, name.as_str(), value.as_str())?;
+                Ok(format!("Successfully created secret {}", name))
+            }
+
+            // Choose an entry in a picker, then copy or type it
+            Commands::Menu { picker, field, type_value } => {
+                let vault_dir = vault::get_path_or_default(self.store.clone())?;
+                let mut vault = session::load_vault(self.store, self.non_interactive)?;
+                let names = vault.list_keys("")?;
+                let Some(name) = menu::pick(names.as_slice(), picker.as_deref())? else {
+                    return Ok("No entry was chosen".to_string());
+                };
+                let entry = vault.access_entry(name.as_str())?;
+                sign::verify(name.as_str(), &entry, &sign::trusted_authors(&config::read_or_default()?.with_vault(vault_dir.as_path())?)?)?;
+
+                let field = field.unwrap_or_else(|| entry.secret_field().to_string());
+                let value = entry.field(field.as_str())
+                    .ok_or_else(|| Error::FieldNotFound { name: name.clone(), field: field.clone() })?;
+
+                if type_value {
+                    menu::type_text(value)?;
+                    Ok(format!("Typed {} of {}", field, name))
+                } else {
+                    clipboard::copy_with_timeout(value, config::read_or_default()?.with_vault(vault_dir.as_path())?.clip_timeout())?;
+                    Ok(format!("Copied {} of {} to the clipboard", field, name))
+                }
+            }
+
+            // Get a password
+            Commands::Get { name, field, clip, first } => {
+                let vault_dir = vault::get_path_or_default(self.store.clone())?;
+                let mut vault = session::load_vault(self.store, self.non_interactive)?;
+                let name = closest_entry(vault.as_ref(), name, first, self.non_interactive)?;
+                let entry = vault.access_entry(name.as_str())?;
+                sign::verify(name.as_str(), &entry, &sign::trusted_authors(&config::read_or_default()?.with_vault(vault_dir.as_path())?)?)?;
+
+                let field = field.unwrap_or_else(|| entry.secret_field().to_string());
+                let value = entry.field(field.as_str())
+                    .ok_or_else(|| Error::FieldNotFound { name: name.clone(), field: field.clone() })?;
+
+                if clip {
+                    clipboard::copy_with_timeout(value, config::read_or_default()?.with_vault(vault_dir.as_path())?.clip_timeout())?;
+                    if json {
+                        return Ok(json!({ "name": name, "field": field, "copied": true }).to_string());
+                    }
+                    Ok(format!("Copied {} of {} to the clipboard", field, name))
+                } else if json {
+                    Ok(json!({ "name": name, "field": field, "value": value }).to_string())
+                } else {
+                    Ok(value.to_string())
+                }
+            }
+
+            // Set a batch of passwords, which are all written or not at all
+            Commands::Set { batch: Some(batch), force, .. } => {
+                let contents = match batch.as_str() {
+                    "-" => prompt::read_stdin()?,
+                    path => std::fs::read_to_string(path)?,
+                };
+                let updates = yap::batch::parse(contents.as_str())?;
+                let mut vault = session::load_vault(self.store.clone(), self.non_interactive)?;
+
+                let existing = updates.iter().filter(|(name, _)| vault.has_key(name.as_str())).count();
+                if existing > 0 {
+                    let question = format!("Overwrite {} existing passwords?", existing);
+                    if !prompt::allow(question.as_str(), force, self.non_interactive)? {
+                        return Ok("Cancelled, no passwords were changed".to_string());
+                    }
+                }
+
+                let vault_dir = vault::get_path_or_default(self.store.clone())?;
+                let min_strength = config::read_or_default()?.with_vault(vault_dir.as_path())?.min_strength();
+                let weak = updates.iter()
+                    .filter(|(_, update)| audit::score_guesses(audit::guesses_log10(update.password.as_str())) < min_strength)
+                    .count();
+                if weak > 0 && !self.non_interactive && batch != "-" {
+                    let question = format!("{} of the passwords are weak, save them anyway?", weak);
+                    if !prompt::allow(question.as_str(), force, self.non_interactive)? {
+                        return Ok("Cancelled, no passwords were changed".to_string());
+                    }
+                }
+
+                let mut entries = vec![];
+                for (name, update) in updates {
+                    let entry = if vault.has_key(name.as_str()) { vault.get_entry(name.as_str())? } else { Entry::default() };
+                    entries.push((name, update.apply(entry)));
+                }
+                vault.set_entries(entries.as_slice())?;
+                auto_sync(self.store);
+                Ok(format!("Successfully saved {} passwords", entries.len()))
+            }
+
+            // Set a password
+            Commands::Set { name, value, stdin, username, url, notes, field, tag, force, batch: None } => {
+                let name = name.unwrap_or_default();
+                let mut vault = session::load_vault(self.store.clone(), self.non_interactive)?;
+
+                let mut entry = if vault.has_key(name.as_str()) {
+                    let question = format!("Overwrite password {}?", name);
+                    if !prompt::allow(question.as_str(), force, self.non_interactive)? {
+                        return Ok("Cancelled, password was not changed".to_string());
+                    }
+                    vault.get_entry(name.as_str())?
+                } else {
+                    Entry::default()
+                };
+
+                let value = match value {
+                    Some(value) => value,
+                    None if stdin => prompt::read_stdin()?,
+                    None => prompt::secret(name.as_str(), self.non_interactive)?,
+                };
+
+                let vault_dir = vault::get_path_or_default(self.store.clone())?;
+                let min_strength = config::read_or_default()?.with_vault(vault_dir.as_path())?.min_strength();
+                let guesses = audit::guesses_log10(value.as_str());
+                if !allow_strength(guesses, min_strength, force, self.non_interactive || stdin)? {
+                    return Ok("Cancelled, password was not saved".to_string());
+                }
+
+                entry.password = value;
+                entry.username = username.or(entry.username);
+                entry.url = url.or(entry.url);
+                entry.notes = notes.or(entry.notes);
+                for field in field {
+                    let (field_name, field_value) = entry::parse_field(field.as_str())?;
+                    entry.set_field(field_name.as_str(), field_value);
+                }
+                for tag in tag {
+                    entry.tags.insert(entry::parse_tag(tag.as_str())?);
+                }
+
+                vault.set_entry(name.as_str(), &entry)?;
+                auto_sync(self.store);
+                Ok(format!("Successfully saved password, {}", strength(guesses)))
+            }
+
+            // Edit an entry
+            Commands::Edit { name } => {
+                let mut vault = session::load_vault(self.store, self.non_interactive)?;
+
+                let entry = if vault.has_key(name.as_str()) {
+                    vault.get_entry(name.as_str())?
+                } else {
+                    Entry::default()
+                };
+
+                let before = entry.to_editable()?;
+                let after = editor::edit(before.as_str())?;
+                if before == after {
+                    return Ok("No changes made".to_string());
+                }
+
+                vault.set_entry(name.as_str(), &entry.from_editable(after.as_str())?)?;
+                Ok("Successfully saved entry".to_string())
+            }
+
+            // Start or end a session
+            Commands::Unlock => {
+                session::unlock(self.store, self.non_interactive)?;
+                Ok("Successfully unlocked the vault".to_string())
+            }
+
+            Commands::Lock { all } => {
+                session::lock(self.store, all)?;
+                Ok("Successfully locked the vault".to_string())
+            }
+
+            Commands::Status => {
+                let status = status::status(self.store)?;
+                if json {
+                    return Ok(json!(status).to_string());
+                }
+                Ok(display::status_summary(&status))
+            }
+
+            // Manage the agent
+            Commands::Agent { command: AgentCommand::Start { ttl } } => {
+                let ttl = match ttl {
+                    Some(ttl) => ttl,
+                    None => config::read_or_default()?.agent_ttl(),
+                };
+                agent::start(ttl)?;
+                Ok("Successfully started the agent".to_string())
+            }
+
+            Commands::Agent { command: AgentCommand::Stop } => {
+                agent::stop()?;
+                Ok("Successfully stopped the agent".to_string())
+            }
+
+            Commands::Agent { command: AgentCommand::Status } => {
+                let vaults = agent::status()?;
+                if vaults.is_empty() {
+                    return Ok("The agent is running, no keys are cached".to_string());
+                }
+                Ok(vaults.iter()
+                    .map(|(vault, ttl)| format!("{} (expires in {}s)", vault, ttl))
+                    .collect::<Vec<_>>()
+                    .join("\n"))
+            }
+
+            // Back up the vault
+            Commands::Backup { command: BackupCommand::Create { file } } => {
+                let vault_dir = vault::get_path_or_default(self.store)?;
+                let config = config::read_or_default()?.with_vault(vault_dir.as_path())?;
+                let pass = prompt::backup_password(self.non_interactive, true)?;
+                let count = backup::create(vault_dir.as_path(), file.as_ref(), pass.as_str(), config.kdf())?;
+                Ok(format!("Successfully backed up {} files to {}", count, file))
+            }
+
+            // Restore a backup
+            Commands::Backup { command: BackupCommand::Restore { file } } => {
+                let vault_dir = vault::get_path_or_default(self.store)?;
+                let pass = prompt::backup_password(self.non_interactive, false)?;
+                let count = backup::restore(file.as_ref(), vault_dir.as_path(), pass.as_str())?;
+                Ok(format!("Successfully restored {} files into {}", count, vault_dir.display()))
+            }
+
+            // List the automatic backups
+            Commands::Backup { command: BackupCommand::List } => {
+                let vault_dir = vault::get_path_or_default(self.store)?;
+                let backups = autobackup::list(vault_dir.as_path())?;
+                if json {
+                    return Ok(json!(backups).to_string());
+                }
+                Ok(display::backup_listing(&backups))
+            }
+
+            // Put back a password from an automatic backup
+            Commands::Backup { command: BackupCommand::Recover { id, name } } => {
+                let vault_dir = vault::get_path_or_default(self.store.clone())?;
+                let backup = autobackup::path(vault_dir.as_path(), id.as_str())?;
+                let mut vault = session::load_vault(self.store.clone(), self.non_interactive)?;
+                vault.recover(backup.as_path(), name.as_str())?;
+                auto_sync(self.store);
+                Ok(format!("Successfully recovered {} from backup {}", name, id))
+            }
+
+            // Take a snapshot of the vault
+            Commands::Snapshot { command: SnapshotCommand::Create { label } } => {
+                let vault_dir = vault::get_path_or_default(self.store)?;
+                let snapshot = snapshot::create(vault_dir.as_path(), label.as_deref())?;
+                Ok(format!("Successfully took snapshot {} of {} files", snapshot.id, snapshot.files.len()))
+            }
+
+            // List the snapshots
+            Commands::Snapshot { command: SnapshotCommand::List } => {
+                let vault_dir = vault::get_path_or_default(self.store)?;
+                let snapshots = snapshot::list(vault_dir.as_path())?;
+                if json {
+                    let snapshots: Vec<_> = snapshots.iter()
+                        .map(|snapshot| json!({ "id": snapshot.id, "label": snapshot.label, "taken": snapshot.taken, "files": snapshot.files.len() }))
+                        .collect();
+                    return Ok(json!(snapshots).to_string());
+                }
+                Ok(display::snapshot_listing(&snapshots))
+            }
+
+            // Roll the vault back to a snapshot
+            Commands::Snapshot { command: SnapshotCommand::Rollback { id, force } } => {
+                let vault_dir = vault::get_path_or_default(self.store.clone())?;
+                let question = format!("Roll the vault in {} back to snapshot {}? Changes since then are kept in a new snapshot", vault_dir.display(), id);
+                if !prompt::allow(question.as_str(), force, self.non_interactive)? {
+                    return Ok("Cancelled, the vault was not rolled back".to_string());
+                }
+
+                let before = snapshot::rollback(vault_dir.as_path(), id.as_str())?;
+                // The master password may have changed along with the header, so cached keys are forgotten
+                session::lock(self.store.clone(), false)?;
+                auto_sync(self.store);
+                Ok(format!("Successfully rolled back to snapshot {}, undo with snapshot rollback {}", id, before.id))
+            }
+
+            // Export every entry in plaintext
+            Commands::Export { format, out, force } => {
+                let warning = format!(
+                    "This writes every password in the vault to {} without encryption, where anyone who can read it can read them.",
+                    out.as_deref().unwrap_or("the terminal"),
+                );
+                if !force && !prompt::confirm_phrase(warning.as_str(), "export", self.non_interactive)? {
+                    return Ok("Cancelled, nothing was exported".to_string());
+                }
+
+                let vault = session::load_vault(self.store, self.non_interactive)?;
+                let contents = export::export(vault.as_ref(), format)?;
+                match out {
+                    Some(out) => {
+                        export::write(out.as_ref(), contents.as_str())?;
+                        Ok(format!("Successfully exported entries to {}", out))
+                    }
+                    None => Ok(contents),
+                }
+            }
+
+            // Import entries from another password manager
+            Commands::Import { source, force } => {
+                let entries = source.read(self.non_interactive)?;
+                let mut vault = session::load_vault(self.store, self.non_interactive)?;
+                Ok(import::store(vault.as_mut(), entries, force)?.to_string())
+            }
+
+            // Add a secure note
+            Commands::Note { command: NoteCommand::Add { name } } => {
+                let mut vault = session::load_vault(self.store, self.non_interactive)?;
+
+                let text = if std::io::stdin().is_terminal() {
+                    editor::edit("")?
+                } else {
+                    let mut text = String::new();
+                    std::io::stdin().read_to_string(&mut text)?;
+                    text
+                };
+
+                vault.set_entry(name.as_str(), &Entry::note(text))?;
+                Ok("Successfully saved note".to_string())
+            }
+
+            // Show a secure note
+            Commands::Note { command: NoteCommand::Show { name } } => {
+                let mut vault = session::load_vault(self.store, self.non_interactive)?;
+                let entry = vault.access_entry(name.as_str())?;
+                Ok(entry.notes.unwrap_or_default())
+            }
+
+            // Delete a password
+            Commands::Rm { name, recursive: true, force } => {
+                let mut vault = session::load_vault(self.store.clone(), self.non_interactive)?;
+
+                let question = format!("Delete every password in {}?", name);
+                if !prompt::allow(question.as_str(), force, self.non_interactive)? {
+                    return Ok("Cancelled, no passwords were deleted".to_string());
+                }
+
+                let deleted = vault.delete_folder(name.as_str())?;
+                empty_old_trash(vault.as_mut(), self.store.clone())?;
+                auto_sync(self.store);
+                Ok(format!("Successfully moved {} passwords to the trash", deleted.len()))
+            }
+
+            Commands::Rm { name, recursive: false, force } => {
+                let mut vault = session::load_vault(self.store.clone(), self.non_interactive)?;
+                if !vault.has_key(name.as_str()) {
+                    return Err(Error::PasswordNotFound { name });
+                }
+
+                let question = format!("Delete password {}?", name);
+                if !prompt::allow(question.as_str(), force, self.non_interactive)? {
+                    return Ok("Cancelled, password was not deleted".to_string());
+                }
+
+                vault.delete_key(name.as_str())?;
+                empty_old_trash(vault.as_mut(), self.store.clone())?;
+                auto_sync(self.store);
+                Ok("Successfully moved password to the trash".to_string())
+            }
+
+            // Rename a password
+            Commands::Mv { old, new, force } => {
+                let mut vault = session::load_vault(self.store.clone(), self.non_interactive)?;
+                vault.rename_key(old.as_str(), new.as_str(), force)?;
+                auto_sync(self.store);
+                Ok("Successfully renamed password".to_string())
+            }
+
+            // Convert the vault to another backend
+            Commands::Migrate { to } => {
+                let backend = vault::Backend::parse(to.as_str()).ok_or_else(|| Error::BadConfigValue {
+                    key: "--to".to_string(), value: to.clone(), expected: SettingKey::Backend.expected(),
+                })?;
+                match vault::migrate(self.store.clone(), backend)? {
+                    true => {
+                        auto_sync(self.store);
+                        Ok(format!("Successfully migrated the vault to the {} backend", backend.name()))
+                    }
+                    false => Ok(format!("The vault already uses the {} backend, nothing was migrated", backend.name())),
+                }
+            }
+
+            // Destroy the vault
+            Commands::Nuke { force } => {
+                let vault_dir = vault::get_path_or_default(self.store.clone())?;
+                let question = format!("Permanently destroy the vault in {} and every password in it? This can't be undone", vault_dir.display());
+                if !prompt::allow(question.as_str(), force, self.non_interactive)? {
+                    return Ok("Cancelled, the vault was kept".to_string());
+                }
+
+                session::lock(self.store, false)?;
+                let wiped = wipe::nuke(vault_dir.as_path())?;
+                Ok(format!("Successfully destroyed the vault in {}, wiping {} files", vault_dir.display(), wiped))
+            }
+
+            // List deleted passwords
+            Commands::Trash { command: TrashCommand::List } => {
+                let vault = session::load_vault(self.store, self.non_interactive)?;
+                let trashed = vault.list_trash()?;
+                if json {
+                    let trashed: Vec<_> = trashed.iter()
+                        .map(|(name, deleted)| json!({ "name": name, "deleted": deleted }))
+                        .collect();
+                    return Ok(json!(trashed).to_string());
+                }
+                Ok(display::trash_listing(&trashed))
+            }
+
+            // Restore a deleted password
+            Commands::Trash { command: TrashCommand::Restore { name, force } } => {
+                let mut vault = session::load_vault(self.store.clone(), self.non_interactive)?;
+                vault.restore_trash(name.as_str(), force)?;
+                auto_sync(self.store);
+                Ok(format!("Successfully restored {} from the trash", name))
+            }
+
+            // Permanently remove deleted passwords
+            Commands::Trash { command: TrashCommand::Empty { days, force } } => {
+                let mut vault = session::load_vault(self.store.clone(), self.non_interactive)?;
+                if !prompt::allow("Permanently remove the passwords in the trash?", force, self.non_interactive)? {
+                    return Ok("Cancelled, the trash was not emptied".to_string());
+                }
+
+                let before = days.map(|days| entry::now() - (days * 24 * 60 * 60) as i64);
+                let removed = vault.empty_trash(before)?;
+                auto_sync(self.store);
+                Ok(format!("Successfully removed {} passwords from the trash", removed))
+            }
+
+            // Show the history of a password
+            Commands::History { name, audit: false } => {
+                let vault = session::load_vault(self.store, self.non_interactive)?;
+                Ok(display::history_listing(&vault.history(name.unwrap_or_default().as_str())?))
+            }
+
+            // Show the activity log
+            Commands::History { name, audit: true } => {
+                let vault = session::load_vault(self.store, self.non_interactive)?;
+                let log: Vec<_> = vault.activity()?.into_iter()
+                    .filter(|activity| name.as_deref().is_none_or(|name| activity.concerns(name)))
+                    .collect();
+                if json {
+                    return Ok(json!(log).to_string());
+                }
+                Ok(display::activity_listing(&log))
+            }
+
+            // Restore a previous version of a password
+            Commands::Restore { name, version } => {
+                let mut vault = session::load_vault(self.store, self.non_interactive)?;
+                vault.restore(name.as_str(), version)?;
+                Ok(format!("Successfully restored version {} of {}", version, name))
+            }
+
+            // Generate a new password for an entry
+            Commands::Rotate { name, length, clip, print } => {
+                let vault_dir = vault::get_path_or_default(self.store.clone())?;
+                let settings = config::read_or_default()?.with_vault(vault_dir.as_path())?;
+                let mut vault = session::load_vault(self.store.clone(), self.non_interactive)?;
+                let mut entry = vault.get_entry(name.as_str())?;
+                if entry.kind != EntryKind::Login {
+                    return Err(Error::NotALogin { name });
+                }
+
+                let mut policy = entry.policy.clone().unwrap_or_default().apply(settings.password_policy());
+                policy.length = length.unwrap_or(policy.length);
+                entry.password = generate::generate(&policy)?;
+                vault.set_entry(name.as_str(), &entry)?;
+                auto_sync(self.store);
+
+                if clip {
+                    clipboard::copy_with_timeout(entry.password.as_str(), settings.clip_timeout())?;
+                }
+                if print {
+                    return Ok(entry.password.clone());
+                }
+                let version = vault.history(name.as_str())?.last().map(|(version, _)| *version).unwrap_or_default();
+                Ok(format!("Successfully rotated {}, the old password is version {} in its history", name, version))
+            }
+
+            // Set or show the password policy of an entry
+            Commands::Policy { name, length, symbols, no_symbols, require, clear } => {
+                let mut vault = session::load_vault(self.store.clone(), self.non_interactive)?;
+                let mut entry = vault.get_entry(name.as_str())?;
+                let mut policy = entry.policy.clone().unwrap_or_default();
+
+                let changed = clear || length.is_some() || symbols.is_some() || no_symbols || !require.is_empty();
+                if !changed {
+                    return match json {
+                        true => Ok(json!({ "name": name, "policy": policy }).to_string()),
+                        false => Ok(display::policy(&name, &policy)),
+                    };
+                }
+
+                if clear {
+                    policy = EntryPolicy::default();
+                }
+                policy.length = length.or(policy.length);
+                if no_symbols {
+                    policy.symbols = Some(String::new());
+                }
+                policy.symbols = symbols.or(policy.symbols);
+                if !require.is_empty() {
+                    policy.require = require;
+                }
+
+                // Check that passwords can follow the policy before storing it
+                generate::generate(&policy.apply(PasswordPolicy::default()))?;
+                entry.policy = Some(policy).filter(|policy| !policy.is_empty());
+                vault.set_entry(name.as_str(), &entry)?;
+                auto_sync(self.store);
+                match json {
+                    true => Ok(json!({ "name": name, "policy": entry.policy.unwrap_or_default() }).to_string()),
+                    false => Ok(format!("Successfully updated the password policy of {}", name)),
+                }
+            }
+
+            // Measure the key derivation functions, and apply the suggested parameters
+            Commands::BenchKdf { target_ms, apply, kdf } => {
+                let vault = match apply {
+                    true => {
+                        let pass = prompt::master_password(self.non_interactive, false)?;
+                        let kdf = match kdf {
+                            Some(name) => Kdf::parse(name.as_str()).unwrap_or_default(),
+                            None => vault::kdf(self.store.clone())?,
+                        };
+                        Some((vault::load(self.store.clone(), pass.as_str())?, pass, kdf))
+                    }
+                    false => None,
+                };
+
+                let target = Duration::from_millis(target_ms);
+                let mut results = vec![];
+                for kdf in ["pbkdf2", "argon2id"].into_iter().filter_map(Kdf::parse) {
+                    results.push((kdf, kdf.time()?, kdf.calibrate(target)?));
+                }
+
+                if json && vault.is_none() {
+                    let results: Vec<_> = results.iter()
+                        .map(|(kdf, took, suggested)| json!({ "algorithm": kdf.name(), "default_ms": took.as_millis(), "suggested": suggested }))
+                        .collect();
+                    return Ok(json!(results).to_string());
+                }
+
+                let mut lines = vec![];
+                for (kdf, took, suggested) in results.iter() {
+                    let seconds = took.as_secs_f64().max(1e-6);
+                    let throughput = match kdf {
+                        Kdf::Pbkdf2 { iterations } => format!("{:.0} iterations per second", *iterations as f64 / seconds),
+                        Kdf::Argon2id { memory, iterations, .. } => format!("{:.0} MiB per second", (*memory * *iterations) as f64 / 1024.0 / seconds),
+                    };
+                    lines.push(format!("{}: {}, {} ms with the defaults", kdf.name(), throughput, took.as_millis()));
+                    lines.push(format!("  suggested for {} ms: {}", target_ms, suggested));
+                }
+
+                if let Some((mut vault, pass, kdf)) = vault {
+                    let suggested = results.iter()
+                        .find(|(default, _, _)| default.name() == kdf.name())
+                        .map(|(_, _, suggested)| *suggested)
+                        .unwrap_or(kdf);
+                    vault.set_kdf(suggested, pass.as_str())?;
+                    auto_sync(self.store);
+                    lines.push(format!("Successfully changed the vault to {}", suggested));
+                }
+                Ok(lines.join("\n"))
+            }
+
+            // Change the master password
+            Commands::Rekey => {
+                // The current password is always asked for, even if the agent has cached the key
+                let pass = prompt::master_password(self.non_interactive, false)?;
+                let mut vault = vault::load(self.store, pass.as_str())?;
+                let new_pass = prompt::new_master_password(self.non_interactive)?;
+                vault.rekey(new_pass.as_str())?;
+                Ok("Successfully changed master password".to_string())
+            }
+
+            // Rebuild the index
+            Commands::Reindex => {
+                let mut vault = session::load_vault(self.store, self.non_interactive)?;
+                let count = vault.rebuild_index()?;
+                Ok(format!("Successfully indexed {} entries", count))
+            }
+
+            // List passwords
+            Commands::List { prefix, tree, long, tag } => {
+                let vault_dir = vault::get_path_or_default(self.store.clone())?;
+                let vault = session::load_vault(self.store, self.non_interactive)?;
+                let keys = search::tagged(vault.as_ref(), vault.list_keys(prefix.unwrap_or_default().as_str())?, tag.as_slice())?;
+
+                if long {
+                    let trusted = sign::trusted_authors(&config::read_or_default()?.with_vault(vault_dir.as_path())?)?;
+                    let entries = keys.into_iter()
+                        .map(|key| {
+                            let metadata = vault.metadata(key.as_str())?;
+                            // Entries with bad signatures are listed as such rather than failing the listing
+                            let author = match vault.get_entry(key.as_str()) {
+                                Ok(entry) => sign::author(&entry, &trusted),
+                                Err(Error::BadSignature { author, .. }) => Author::Invalid(author),
+                                Err(e) => return Err(e),
+                            };
+                            Ok((key, metadata, author))
+                        })
+                        .collect::<yap::Result<Vec<_>>>()?;
+                    if json {
+                        let entries: Vec<_> = entries.iter()
+                            .map(|(name, metadata, author)| {
+                                let (author, verified) = match author {
+                                    Author::Unsigned => (None, false),
+                                    Author::Verified(author) => (Some(author), true),
+                                    Author::Untrusted(author) | Author::Invalid(author) => (Some(author), false),
+                                };
+                                json!({ "name": name, "metadata": metadata, "author": author, "verified": verified })
+                            })
+                            .collect();
+                        return Ok(json!(entries).to_string());
+                    }
+                    Ok(display::long_listing(&entries))
+                } else if json {
+                    Ok(json!(keys).to_string())
+                } else if tree {
+                    Ok(display::tree(&keys))
+                } else {
+                    Ok(keys.join("\n"))
+                }
+            }
+
+            // Find passwords
+            Commands::Find { pattern, regex, ignore_case, deep, tag } => {
+                let matcher = search::Matcher::new(pattern.unwrap_or_default().as_str(), regex, ignore_case)?;
+                let vault = session::load_vault(self.store, self.non_interactive)?;
+                Ok(search::find(vault.as_ref(), &matcher, deep, tag.as_slice())?.join("\n"))
+            }
+
+            // Summarize the vault's entries
+            Commands::Stats => {
+                let stats = session::load_vault(self.store, self.non_interactive)?.stats()?;
+                if json {
+                    return Ok(json!(stats).to_string());
+                }
+                Ok(display::stats_report(&stats))
+            }
+
+            // Audit the strength of stored passwords
+            Commands::Audit { max_age, breach } => {
+                let vault = session::load_vault(self.store, self.non_interactive)?;
+                let report = audit::audit(vault.as_ref(), max_age, breach)?;
+
+                let count = report.problems().count();
+                if json && count == 0 {
+                    return Ok(json!(report).to_string());
+                } else if json {
+                    println!("{}", json!(report));
+                    return Err(Error::AuditFailed { count });
+                }
+                if count == 0 {
+                    return Ok(format!("No problems found with {} passwords", report.findings.len()));
+                }
+                println!("{}", display::audit_table(&report));
+                Err(Error::AuditFailed { count })
+            }
+
+            // Verify the vault
+            Commands::Verify { quarantine } => {
+                let mut vault = session::load_vault(self.store, self.non_interactive)?;
+                let report = vault.verify(quarantine)?;
+
+                let count = report.findings.len();
+                if json && count == 0 {
+                    return Ok(json!(report).to_string());
+                } else if json {
+                    println!("{}", json!(report));
+                    return Err(Error::VerifyFailed { count });
+                }
+                if count == 0 {
+                    return Ok(format!("No problems found with {} files", report.checked));
+                }
+                println!("{}", display::verify_table(&report));
+                Err(Error::VerifyFailed { count })
+            }
+
+            // Diagnose problems with yap's setup
+            Commands::Doctor => {
+                let checks = doctor::run(self.store);
+                let count = checks.iter().filter(|check| check.status == doctor::Status::Failed).count();
+                if json && count == 0 {
+                    return Ok(json!(checks).to_string());
+                } else if json {
+                    println!("{}", json!(checks));
+                    return Err(Error::DoctorFailed { count });
+                }
+                if count == 0 {
+                    return Ok(display::doctor_report(checks.as_slice()));
+                }
+                println!("{}", display::doctor_report(checks.as_slice()));
+                Err(Error::DoctorFailed { count })
+            }
+
+            // Repair the vault
+            Commands::Repair { backup } => {
+                let mut vault = session::load_vault(self.store, self.non_interactive)?;
+                let report = match backup {
+                    Some(backup) => {
+                        // The backup is restored into a scratch directory, and only its readable files are used
+                        let pass = prompt::backup_password(self.non_interactive, false)?;
+                        let dir = std::env::temp_dir().join(format!("yap-repair-{}", std::process::id()));
+                        let report = backup::restore(backup.as_ref(), dir.as_path(), pass.as_str())
+                            .and_then(|_| vault.repair(Some(dir.as_path())));
+                        if dir.exists() {
+                            wipe::wipe_dir(dir.as_path())?;
+                        }
+                        report?
+                    }
+                    None => vault.repair(None)?,
+                };
+
+                if json {
+                    return Ok(json!(report).to_string());
+                }
+                Ok(display::repair_table(&report))
+            }
+
+            // Generate and store a password
+            Commands::Generate { name, length, symbols, no_symbols, no_digits, charset, pattern, pin, pronounceable, words, separator, capitalize, print, clip, no_save, force } => {
+                let vault_dir = vault::get_path_or_default(self.store.clone())?;
+                let settings = config::read_or_default()?.with_vault(vault_dir.as_path())?;
+
+                // The vault is only needed to store the password, and for the policy of the entry
+                // it replaces
+                let name = name.filter(|_| !no_save);
+                let vault = match name {
+                    Some(_) => Some(session::load_vault(self.store.clone(), self.non_interactive)?),
+                    None => None,
+                };
+                let entry_policy = match (vault.as_ref(), name.as_deref()) {
+                    (Some(vault), Some(name)) if vault.has_key(name) => vault.get_entry(name)?.policy.unwrap_or_default(),
+                    _ => EntryPolicy::default(),
+                };
+
+                let (pw, entropy) = match (words, pattern) {
+                    (_, Some(pattern)) => {
+                        (generate::pattern(pattern.as_str())?, generate::pattern_entropy(pattern.as_str())?)
+                    }
+                    (None, None) if pin => {
+                        let policy = PasswordPolicy::pin(length.unwrap_or(PIN_LENGTH));
+                        (generate::generate(&policy)?, policy.entropy())
+                    }
+                    (Some(words), None) => {
+                        let policy = PassphrasePolicy { words: words as usize, separator, capitalize };
+                        (generate::passphrase(&policy)?, policy.entropy())
+                    }
+                    (None, None) => {
+                        // The policy in the config is the default, which the entry's policy
+                        // replaces, and flags take precedence over both
+                        let default = entry_policy.apply(settings.password_policy());
+                        let policy = PasswordPolicy {
+                            length: length.unwrap_or(default.length),
+                            symbols: (default.symbols || symbols) && !no_symbols,
+                            digits: !no_digits,
+                            charset: charset.or(default.charset),
+                            ..default
+                        };
+                        match pronounceable {
+                            true => (generate::pronounceable(&policy)?, policy.pronounceable_entropy()),
+                            false => (generate::generate(&policy)?, policy.entropy()),
+                        }
+                    }
+                };
+
+                // Without a name there is nothing to store, only a password to print or copy
+                let (Some(name), Some(mut vault)) = (name, vault) else {
+                    if clip {
+                        clipboard::copy_with_timeout(pw.as_str(), settings.clip_timeout())?;
+                        return Ok(format!("Copied a password with {:.0} bits of entropy to the clipboard", entropy));
+                    }
+                    return Ok(pw);
+                };
+
+                let question = format!("Overwrite password {}?", name);
+                if vault.has_key(name.as_str()) && !prompt::allow(question.as_str(), force, self.non_interactive)? {
+                    return Ok("Cancelled, password was not changed".to_string());
+                }
+                let guesses = entropy * std::f64::consts::LOG10_2;
+                if !allow_strength(guesses, settings.min_strength(), force, self.non_interactive)? {
+                    return Ok("Cancelled, password was not saved".to_string());
+                }
+                vault.set_key(name.as_str(), pw.clone())?;
+                auto_sync(self.store);
+
+                if clip {
+                    clipboard::copy_with_timeout(pw.as_str(), settings.clip_timeout())?;
+                }
+                if print {
+                    Ok(pw)
+                } else {
+                    Ok(format!("Successfully generated password, {}", strength(guesses)))
+                }
+            }
+
+            // Browse entries in the terminal interface
+            #[cfg(feature = "tui")]
+            Commands::Tui => {
+                let vault_dir = vault::get_path_or_default(self.store.clone())?;
+                let mut vault = session::load_vault(self.store, self.non_interactive)?;
+                yap::tui::run(vault.as_mut(), config::read_or_default()?.with_vault(vault_dir.as_path())?.clip_timeout())?;
+                Ok(String::new())
+            }
+
+            #[cfg(not(feature = "tui"))]
+            Commands::Tui => Err(Error::TuiUnsupported),
+
+            // Serve the vault to applications over D-Bus
+            #[cfg(feature = "secret-service")]
+            Commands::SecretService => {
+                let vault = session::load_vault(self.store, self.non_interactive)?;
+                yap::secret_service::serve(vault)?;
+                Ok(String::new())
+            }
+
+            #[cfg(not(feature = "secret-service"))]
+            Commands::SecretService => Err(Error::SecretServiceUnsupported),
+
+            // Generate shell completions
+            Commands::Completions { shell } => {
+                let script = completions::script(shell, &mut Cli::command())?;
+                Ok(script.trim_end().to_string())
+            }
+
+            // Complete entry names, printing nothing if the vault can't be read
+            Commands::Complete { prefix } => {
+                Ok(vault::list_names(self.store, prefix.as_str()).unwrap_or_default().join("\n"))
+            }
+
+            // Run the agent started by agent start
+            Commands::RunAgent { ttl } => {
+                agent::serve(ttl)?;
+                Ok(String::new())
+            }
+
+            // Restore the clipboard in the background after copying a secret
+            Commands::ClearClipboard { timeout } => {
+                clipboard::clear_after(timeout)?;
+                Ok(String::new())
+            }
+        }
+    }
+}
+
+/// Describes how strong a password which takes 10^`guesses_log10` guesses to find is, such as
+/// "strength 2/4, about 33 bits"
+fn strength(guesses_log10: f64) -> String {
+    format!("strength {}/4, about {:.0} bits", audit::score_guesses(guesses_log10), guesses_log10 * std::f64::consts::LOG2_10)
+}
+
+/// Asks before saving a password weaker than the min_strength setting. Only users at a terminal
+/// are asked, so scripts which save passwords aren't interrupted.
+fn allow_strength(guesses_log10: f64, min_strength: u8, force: bool, non_interactive: bool) -> yap::Result<bool> {
+    if non_interactive || audit::score_guesses(guesses_log10) >= min_strength {
+        return Ok(true);
+    }
+    let question = format!("This password is weak ({}), save it anyway?", strength(guesses_log10));
+    prompt::allow(question.as_str(), force, non_interactive)
+}
+
+/// Returns the name of the entry to use for a name given on the command line. If no entry has
+/// exactly that name, entries whose names fuzzily match it are offered instead. The closest match
+/// is used with `first`, otherwise the user is asked to choose one if they are at a terminal.
+fn closest_entry(vault: &dyn Vault, name: String, first: bool, non_interactive: bool) -> yap::Result<String> {
+    if vault.has_key(name.as_str()) {
+        return Ok(name);
+    }
+
+    let mut candidates = search::fuzzy(&vault.list_keys("")?, name.as_str());
+    candidates.truncate(MAX_CANDIDATES);
+    if candidates.is_empty() {
+        return Err(Error::PasswordNotFound { name });
+    }
+    if first {
+        return Ok(candidates.swap_remove(0));
+    }
+
+    if !non_interactive && std::io::stdin().is_terminal() {
+        let question = format!("No entry named {}, did you mean:", name);
+        return match prompt::choose(question.as_str(), &candidates)? {
+            Some(choice) => Ok(candidates.swap_remove(choice)),
+            None => Err(Error::PasswordNotFound { name }),
+        };
+    }
+    Err(Error::NoExactMatch { name, candidates: candidates.join(", ") })
+}
+
+/// Asks the user which side of a sync conflict to keep if they are at a terminal. Otherwise, or
+/// if they don't choose, the conflict is returned as an error so --resolve can be given.
+fn choose_resolution(names: Vec<String>, non_interactive: bool) -> yap::Result<Resolution> {
+    if non_interactive || !std::io::stdin().is_terminal() {
+        return Err(Error::SyncConflict { names });
+    }
+
+    let resolutions = [Resolution::Local, Resolution::Remote, Resolution::Both];
+    let options = [
+        "Keep the local entries".to_string(),
+        "Keep the remote entries".to_string(),
+        "Keep both, with the local entries renamed to <name>.conflict".to_string(),
+    ];
+    let question = format!("Local and remote changes conflict for {}:", names.join(", "));
+    match prompt::choose(question.as_str(), &options)? {
+        Some(choice) => Ok(resolutions[choice]),
+        None => Err(Error::SyncConflict { names }),
+    }
+}
+
+/// Permanently removes the passwords which have been in the trash for longer than the trash_days
+/// setting, unless it is zero
+fn empty_old_trash(vault: &mut dyn vault::Vault, store: Option<String>) -> yap::Result<usize> {
+    let vault_dir = vault::get_path_or_default(store)?;
+    match config::read_or_default()?.with_vault(vault_dir.as_path())?.trash_days() {
+        0 => Ok(0),
+        days => vault.empty_trash(Some(entry::now() - (days * 24 * 60 * 60) as i64)),
+    }
+}
+
+/// Pushes the vault to the remote after a command changes it, if the auto_sync setting is on.
+/// The change is already saved locally, so a failed sync is reported without failing the
+/// command, and can be retried with `yap sync`.
+fn auto_sync(store: Option<String>) {
+    let sync = || -> yap::Result<()> {
+        let vault_dir = vault::get_path_or_default(store)?;
+        let config = config::read_or_default()?.with_vault(vault_dir.as_path())?;
+        if config.auto_sync() && !config.remote_url().is_empty() {
+            sync::sync(vault_dir, config.remote_url(), false, true, None)?;
+        }
+        Ok(())
+    };
+
+    if let Err(e) = sync() {
+        eprintln!("Saved locally, but syncing with the remote failed: {}", e);
+    }
+}
+
+/// Prints diagnostic output at `level` and below to stderr. Only yap's own output is printed, not
+/// that of the libraries it uses.
+fn init_logging(level: LevelFilter) {
+    let subscriber = tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_writer(std::io::stderr)
+        .finish()
+        .with(Targets::new().with_target("yap", level));
+    // Logging is only set up once, before anything is logged, so this can't fail
+    let _ = tracing::subscriber::set_global_default(subscriber);
+}
+
+fn main() {
+    let cli = Cli::parse();
+    init_logging(cli.log_level());
+    if cli.read_only {
+        config::override_with_flags(vec![(SettingKey::ReadOnly, "true".to_string())]);
+    }
+    let (format, prints_json) = (cli.format, cli.command.prints_json());
+
+    match (cli.execute(), format) {
+        (Ok(msg), OutputFormat::Json) if prints_json => println!("{}", msg),
+        (Ok(msg), OutputFormat::Json) => println!("{}", json!({ "message": msg })),
+        (Ok(msg), OutputFormat::Text) => println!("{}", msg),
+        (Err(e), OutputFormat::Json) => {
+            eprintln!("{}", json!({ "error": e.to_string(), "code": e.code() }));
+            std::process::exit(e.code());
+        }
+        (Err(e), OutputFormat::Text) => {
+            eprintln!("{}", e);
+            std::process::exit(e.code());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use clap::Parser;
+
+    use yap::{Error, ExecutableCommand, Kdf};
+    use yap::vault::{self, Backend};
+
+    use crate::Cli;
+
+    #[test]
+    fn read_only_stores_refuse_every_write() {
+        let yap_test = String::from(".yap_test_main_read_only");
+        let dir = Path::new(yap_test.as_str());
+        let mut vault = vault::create(Some(yap_test.clone()), "asdf", Kdf::default(), Backend::default(), None).unwrap();
+        vault.set_key("a", "password".to_string()).unwrap();
+        std::fs::write(dir.join(".config.yaml"), "read_only: true\n").unwrap();
+        let header = std::fs::read(dir.join(".header.yaml")).unwrap();
+
+        let cli = |args: &[&str]| Cli::try_parse_from(["yap", "--non-interactive", "--store", yap_test.as_str()].iter().chain(args)).unwrap();
+        for args in [&["init", "--force"][..], &["backup", "restore", "backup.yap"], &["token", "create", "ci"], &["sync", "--pull"],
+                     &["set", "a", "new"], &["rm", "a", "--force"], &["nuke", "--force"]] {
+            assert!(matches!(cli(args).execute(), Err(Error::ReadOnly)), "{:?} changed a read-only store", args);
+        }
+        for args in [&["get", "a"][..], &["list"], &["sync", "--status"], &["backup", "create", "backup.yap"]] {
+            assert!(cli(args).check_writable().is_ok(), "{:?} doesn't change the store", args);
+        }
+        assert_eq!(std::fs::read(dir.join(".header.yaml")).unwrap(), header);
+
+        std::fs::remove_dir_all(dir).unwrap();
     }
 }
@@ -0,0 +1,96 @@
+use std::io::{ErrorKind, Read, Write};
+use std::process::{Command, Stdio};
+
+use crate::error::{Error, Result};
+
+/// Returns the pickers to try, in order of preference. Each reads choices from stdin, one per
+/// line, and prints the chosen one.
+fn pickers() -> Vec<Vec<String>> {
+    let mut pickers = vec![vec!["rofi", "-dmenu", "-i", "-p", "yap"], vec!["dmenu", "-i", "-p", "yap"]];
+    if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        pickers.insert(0, vec!["wofi", "--dmenu", "-i", "-p", "yap"]);
+    }
+    pickers.into_iter().map(|picker| picker.into_iter().map(String::from).collect()).collect()
+}
+
+/// Returns the tools which can type text into the focused window, in order of preference
+fn typers() -> Vec<Vec<String>> {
+    let mut typers = vec![vec!["xdotool", "type", "--clearmodifiers", "--file", "-"]];
+    if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        typers.insert(0, vec!["wtype", "-"]);
+    }
+    typers.into_iter().map(|typer| typer.into_iter().map(String::from).collect()).collect()
+}
+
+/// Runs a program with the input on its stdin, returning its stdout, or None if it isn't
+/// installed
+fn pipe(program: &[String], input: &str) -> Result<Option<(bool, String)>> {
+    let child = Command::new(program[0].as_str())
+        .args(&program[1..])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    // A program may exit without reading all of its input, such as a picker which was closed
+    if let Some(mut stdin) = child.stdin.take() {
+        match stdin.write_all(input.as_bytes()) {
+            Err(e) if e.kind() != ErrorKind::BrokenPipe => return Err(e.into()),
+            _ => {}
+        }
+    }
+    let mut output = String::new();
+    if let Some(mut stdout) = child.stdout.take() {
+        stdout.read_to_string(&mut output)?;
+    }
+    Ok(Some((child.wait()?.success(), output)))
+}
+
+/// Shows the names in a picker such as rofi or dmenu, returning the chosen name, or None if the
+/// picker was closed without choosing. `picker` is the command to run instead of the first
+/// installed picker, such as `fzf`, split on whitespace.
+pub fn pick(names: &[String], picker: Option<&str>) -> Result<Option<String>> {
+    let pickers = match picker {
+        Some(picker) => vec![picker.split_whitespace().map(String::from).collect::<Vec<_>>()],
+        None => pickers(),
+    };
+
+    let input = names.join("\n");
+    for picker in pickers.iter().filter(|picker| !picker.is_empty()) {
+        let Some((success, output)) = pipe(picker.as_slice(), input.as_str())? else { continue };
+        let chosen = output.lines().next().unwrap_or_default().trim();
+        return Ok(Some(chosen.to_string()).filter(|chosen| success && !chosen.is_empty()));
+    }
+    Err(Error::NoPicker)
+}
+
+/// Types the text into the focused window with xdotool, or wtype on Wayland. The text is sent
+/// over stdin, so it doesn't show up in the process list.
+pub fn type_text(text: &str) -> Result<()> {
+    for typer in typers() {
+        match pipe(typer.as_slice(), text)? {
+            Some((true, _)) => return Ok(()),
+            Some((false, _)) => return Err(Error::NoTyper),
+            None => continue,
+        }
+    }
+    Err(Error::NoTyper)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::menu;
+
+    #[test]
+    fn pickers_choose_from_names() {
+        let names = vec!["email".to_string(), "work/github".to_string()];
+        assert_eq!(menu::pick(names.as_slice(), Some("tail -n 1")).unwrap().as_deref(), Some("work/github"));
+        assert_eq!(menu::pick(names.as_slice(), Some("false")).unwrap(), None);
+        assert!(menu::pick(names.as_slice(), Some("yap-test-missing-picker")).is_err());
+    }
+}
@@ -0,0 +1,166 @@
+use std::io::{Read, Write};
+
+use zeroize::Zeroizing;
+
+use crate::error::{Error, Result};
+use crate::global::{BACKUP_PASSWORD_VAR, IMPORT_PASSWORD_VAR, NEW_PASSWORD_VAR, PASSWORD_VAR};
+
+/// Reads the master password for a vault. By default the user is prompted on the terminal
+/// without echoing their input. When `non_interactive` is set, the password is read from the
+/// PASS environment variable instead, which is useful for scripts but should be avoided
+/// otherwise. When `confirm` is set the password must be entered twice, which should be used
+/// whenever a new password is being chosen.
+pub fn master_password(non_interactive: bool, confirm: bool) -> Result<Zeroizing<String>> {
+    password("Master password", PASSWORD_VAR, non_interactive, confirm)
+}
+
+/// Reads a new master password when changing the password of a vault, which is always
+/// confirmed. The NEW_PASS environment variable is used when `non_interactive` is set.
+pub fn new_master_password(non_interactive: bool) -> Result<Zeroizing<String>> {
+    password("New master password", NEW_PASSWORD_VAR, non_interactive, true)
+}
+
+/// Reads the password protecting data being imported from another password manager, prompting
+/// with `label`. The IMPORT_PASS environment variable is used when `non_interactive` is set.
+pub fn import_password(label: &str, non_interactive: bool) -> Result<Zeroizing<String>> {
+    password(label, IMPORT_PASSWORD_VAR, non_interactive, false)
+}
+
+/// Reads the password protecting a backup, which is confirmed when creating a backup. The
+/// BACKUP_PASS environment variable is used when `non_interactive` is set.
+pub fn backup_password(non_interactive: bool, confirm: bool) -> Result<Zeroizing<String>> {
+    password("Backup password", BACKUP_PASSWORD_VAR, non_interactive, confirm)
+}
+
+/// Reads a password from the terminal, prompting with `label`, or from the environment variable
+/// `var` in non-interactive mode. The password is wiped from memory when it is dropped.
+fn password(label: &str, var: &str, non_interactive: bool, confirm: bool) -> Result<Zeroizing<String>> {
+    if non_interactive {
+        return given_password(var, std::env::var(var).ok());
+    }
+
+    let pass = Zeroizing::new(rpassword::prompt_password(format!("{}: ", label))?);
+    if confirm {
+        let confirmation = Zeroizing::new(rpassword::prompt_password(format!("Confirm {}: ", label.to_lowercase()))?);
+        if pass != confirmation {
+            return Err(Error::PasswordMismatch);
+        }
+    }
+
+    Ok(pass)
+}
+
+/// Returns the password given in the environment variable `var` in non-interactive mode, or an
+/// error naming the variable if it wasn't set
+fn given_password(var: &str, value: Option<String>) -> Result<Zeroizing<String>> {
+    value.map(Zeroizing::new).ok_or_else(|| Error::MissingPassword { var: var.to_string() })
+}
+
+/// Prompts for the new secret of the named entry without echoing it. The secret must be entered
+/// twice to catch typos. Nobody can answer the prompt in non-interactive mode, so an error is
+/// returned asking for the secret to be piped in instead.
+pub fn secret(name: &str, non_interactive: bool) -> Result<String> {
+    if non_interactive {
+        return Err(Error::MissingValue);
+    }
+
+    let value = rpassword::prompt_password(format!("Password for {}: ", name))?;
+    let confirmation = rpassword::prompt_password(format!("Confirm password for {}: ", name))?;
+    if value != confirmation {
+        return Err(Error::PasswordMismatch);
+    }
+
+    Ok(value)
+}
+
+/// Reads a secret piped in on stdin. A single trailing newline is removed, since most tools
+/// print one after their output.
+pub fn read_stdin() -> Result<String> {
+    let mut value = String::new();
+    std::io::stdin().read_to_string(&mut value)?;
+
+    if value.ends_with('\n') {
+        value.pop();
+        if value.ends_with('\r') {
+            value.pop();
+        }
+    }
+    Ok(value)
+}
+
+/// Asks the user a yes or no question on the terminal, returning true only if they answer yes.
+/// In non-interactive mode nobody can answer, so an error is returned asking for `--force`
+/// instead of assuming either answer.
+pub fn confirm(question: &str, non_interactive: bool) -> Result<bool> {
+    if non_interactive {
+        return Err(Error::ConfirmationRequired);
+    }
+
+    print!("{} [y/N] ", question);
+    std::io::stdout().flush()?;
+
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Asks the user to confirm an action which destroys data, such as deleting or overwriting an
+/// entry, returning true if it should go ahead. Nothing is asked when `force` is set, and in
+/// non-interactive mode an error is returned asking for `--force`, see `confirm`.
+pub fn allow(question: &str, force: bool, non_interactive: bool) -> Result<bool> {
+    Ok(force || confirm(question, non_interactive)?)
+}
+
+/// Asks the user to pick one of several options on the terminal, returning the index of the
+/// chosen option, or None if they didn't choose one. The options are listed on stderr, so they
+/// don't end up in the output of commands such as get.
+pub fn choose(question: &str, options: &[String]) -> Result<Option<usize>> {
+    eprintln!("{}", question);
+    for (i, option) in options.iter().enumerate() {
+        eprintln!("{:>3}) {}", i + 1, option);
+    }
+    eprint!("Choose a number, or press enter to cancel: ");
+    std::io::stderr().flush()?;
+
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+
+    Ok(answer.trim().parse::<usize>().ok()
+        .filter(|choice| (1..=options.len()).contains(choice))
+        .map(|choice| choice - 1))
+}
+
+/// Asks the user to confirm a dangerous action by typing `phrase`, which is harder to do by
+/// accident than answering yes. In non-interactive mode an error is returned asking for
+/// `--force` instead.
+pub fn confirm_phrase(warning: &str, phrase: &str, non_interactive: bool) -> Result<bool> {
+    if non_interactive {
+        return Err(Error::ConfirmationRequired);
+    }
+
+    println!("{}", warning);
+    print!("Type '{}' to continue: ", phrase);
+    std::io::stdout().flush()?;
+
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+
+    Ok(answer.trim() == phrase)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::error::Error;
+    use crate::global::PASSWORD_VAR;
+    use crate::prompt::{allow, given_password};
+
+    #[test]
+    fn non_interactive_reads_environment() {
+        assert_eq!(given_password(PASSWORD_VAR, Some("asdf".to_string())).unwrap().as_str(), "asdf");
+        assert!(matches!(given_password(PASSWORD_VAR, None), Err(Error::MissingPassword { var }) if var == PASSWORD_VAR));
+
+        assert!(allow("Delete password github?", true, true).unwrap());
+        assert!(allow("Delete password github?", false, true).is_err());
+    }
+}
@@ -0,0 +1,180 @@
+use std::collections::BTreeSet;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use age::x25519::Recipient;
+
+use crate::activity::Activity;
+use crate::crypto::Kdf;
+use crate::entry::{Entry, Metadata};
+use crate::error::{Error, Result};
+use crate::stats::Stats;
+use crate::vault::Vault;
+use crate::verify::{RepairReport, Report};
+
+/// ReadOnlyVault wraps a vault opened with the read_only setting or --read-only, so shared or
+/// mounted stores can be queried without being changed by accident. Entries can be read, but
+/// anything which would write to the vault fails with `Error::ReadOnly`. Reading an entry doesn't
+/// record when it was accessed, since that is a write too.
+pub(crate) struct ReadOnlyVault {
+    vault: Box<dyn Vault>,
+}
+
+impl ReadOnlyVault {
+    pub(crate) fn new(vault: Box<dyn Vault>) -> ReadOnlyVault {
+        ReadOnlyVault { vault }
+    }
+}
+
+impl Vault for ReadOnlyVault {
+    fn has_key(&self, key: &str) -> bool {
+        self.vault.has_key(key)
+    }
+
+    fn get_entry(&self, key: &str) -> Result<Entry> {
+        self.vault.get_entry(key)
+    }
+
+    fn access_entry(&mut self, key: &str) -> Result<Entry> {
+        self.vault.get_entry(key)
+    }
+
+    fn set_entry(&mut self, _key: &str, _entry: &Entry) -> Result<()> {
+        Err(Error::ReadOnly)
+    }
+
+    fn set_entries(&mut self, _entries: &[(String, Entry)]) -> Result<()> {
+        Err(Error::ReadOnly)
+    }
+
+    fn delete_key(&mut self, _key: &str) -> Result<()> {
+        Err(Error::ReadOnly)
+    }
+
+    fn rename_key(&mut self, _from: &str, _to: &str, _force: bool) -> Result<()> {
+        Err(Error::ReadOnly)
+    }
+
+    fn list_keys(&self, prefix: &str) -> Result<Vec<String>> {
+        self.vault.list_keys(prefix)
+    }
+
+    fn attach(&mut self, _key: &str, _attachment: &str, _reader: &mut dyn Read) -> Result<u64> {
+        Err(Error::ReadOnly)
+    }
+
+    fn read_attachment(&self, key: &str, attachment: &str, writer: &mut dyn Write) -> Result<u64> {
+        self.vault.read_attachment(key, attachment, writer)
+    }
+
+    fn list_attachments(&self, key: &str) -> Result<Vec<String>> {
+        self.vault.list_attachments(key)
+    }
+
+    fn remove_attachment(&mut self, _key: &str, _attachment: &str) -> Result<()> {
+        Err(Error::ReadOnly)
+    }
+
+    fn history(&self, key: &str) -> Result<Vec<(u32, Metadata)>> {
+        self.vault.history(key)
+    }
+
+    fn restore(&mut self, _key: &str, _version: u32) -> Result<()> {
+        Err(Error::ReadOnly)
+    }
+
+    fn list_trash(&self) -> Result<Vec<(String, i64)>> {
+        self.vault.list_trash()
+    }
+
+    fn restore_trash(&mut self, _key: &str, _force: bool) -> Result<()> {
+        Err(Error::ReadOnly)
+    }
+
+    fn empty_trash(&mut self, _before: Option<i64>) -> Result<usize> {
+        Err(Error::ReadOnly)
+    }
+
+    fn rebuild_index(&mut self) -> Result<usize> {
+        self.vault.rebuild_index()
+    }
+
+    fn verify(&mut self, quarantine: bool) -> Result<Report> {
+        match quarantine {
+            true => Err(Error::ReadOnly),
+            false => self.vault.verify(false),
+        }
+    }
+
+    fn repair(&mut self, _backup: Option<&Path>) -> Result<RepairReport> {
+        Err(Error::ReadOnly)
+    }
+
+    fn recover(&mut self, _backup: &Path, _key: &str) -> Result<()> {
+        Err(Error::ReadOnly)
+    }
+
+    fn activity(&self) -> Result<Vec<Activity>> {
+        self.vault.activity()
+    }
+
+    fn rekey(&mut self, _new_pass: &str) -> Result<()> {
+        Err(Error::ReadOnly)
+    }
+
+    fn set_kdf(&mut self, _kdf: Kdf, _pass: &str) -> Result<()> {
+        Err(Error::ReadOnly)
+    }
+
+    fn add_member(&mut self, _name: &str, _recipient: &Recipient) -> Result<()> {
+        Err(Error::ReadOnly)
+    }
+
+    fn remove_member(&mut self, _name: &str, _pass: &str) -> Result<()> {
+        Err(Error::ReadOnly)
+    }
+
+    fn delete_folder(&mut self, _folder: &str) -> Result<Vec<String>> {
+        Err(Error::ReadOnly)
+    }
+
+    fn metadata(&self, key: &str) -> Result<Metadata> {
+        self.vault.metadata(key)
+    }
+
+    fn tags(&self, key: &str) -> Result<BTreeSet<String>> {
+        self.vault.tags(key)
+    }
+
+    fn stats(&self) -> Result<Stats> {
+        self.vault.stats()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::crypto::Kdf;
+    use crate::error::Error;
+    use crate::readonly::ReadOnlyVault;
+    use crate::vault::{self, Backend, Vault};
+
+    #[test]
+    fn read_only_vaults_can_be_read_but_not_changed() {
+        let yap_test = String::from(".yap_test_read_only");
+        let mut vault = vault::create(Some(yap_test.clone()), "asdf", Kdf::default(), Backend::default(), None).unwrap();
+        vault.set_key("github", "password".to_string()).unwrap();
+
+        let mut read_only = ReadOnlyVault::new(vault);
+        assert_eq!(read_only.access_entry("github").unwrap().password, "password");
+        assert_eq!(read_only.metadata("github").unwrap().accessed, 0);
+        assert_eq!(read_only.list_keys("").unwrap(), vec!["github".to_string()]);
+        assert!(matches!(read_only.set_key("github", "new".to_string()), Err(Error::ReadOnly)));
+        assert!(matches!(read_only.set_key("gitlab", "new".to_string()), Err(Error::ReadOnly)));
+        assert!(matches!(read_only.delete_key("github"), Err(Error::ReadOnly)));
+        assert!(matches!(read_only.rename_key("github", "gitlab", false), Err(Error::ReadOnly)));
+        assert!(matches!(read_only.rekey("new"), Err(Error::ReadOnly)));
+        assert_eq!(read_only.get_key("github").unwrap(), "password");
+
+        std::fs::remove_dir_all(yap_test).unwrap();
+    }
+}
@@ -0,0 +1,176 @@
+use ring::digest::{self, SHA256};
+use zeroize::Zeroizing;
+
+use crate::crypto::{self, KEY_LEN};
+use crate::error::{Error, Result};
+
+/// The prefix of every recovery share, so they can be told apart from other secrets
+const PREFIX: &str = "yap";
+
+/// The number of bytes of the master key's hash kept in each share, which lets a wrong
+/// combination of shares be caught before it is used
+const CHECK_LEN: usize = 4;
+
+/// Multiplies two elements of GF(256), using the same reducing polynomial as AES
+fn mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+    while b != 0 {
+        if b & 1 != 0 {
+            product ^= a;
+        }
+        let carry = a & 0x80 != 0;
+        a <<= 1;
+        if carry {
+            a ^= 0x1b;
+        }
+        b >>= 1;
+    }
+    product
+}
+
+/// Divides two elements of GF(256). Every element other than 0 has an inverse, a^254.
+fn div(a: u8, b: u8) -> u8 {
+    let mut inverse = 1;
+    for _ in 0..254 {
+        inverse = mul(inverse, b);
+    }
+    mul(a, inverse)
+}
+
+/// Returns the first bytes of the master key's hash, as hex
+fn check(master_key: &[u8]) -> String {
+    digest::digest(&SHA256, master_key).as_ref()[..CHECK_LEN].iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Splits the vault's master key into `shares` recovery shares with Shamir's secret sharing, any
+/// `threshold` of which restore it, while fewer reveal nothing about it. Each byte of the key is
+/// the constant term of its own random polynomial of degree `threshold - 1`, and share `x` holds
+/// the value of every polynomial at `x`.
+///
+/// Shares are printable, in the form `yap-<threshold>-<x>-<values>-<check>`, with the values and
+/// a short hash of the master key in hex.
+pub fn split(master_key: &[u8; KEY_LEN], shares: u8, threshold: u8) -> Result<Vec<String>> {
+    if threshold < 2 || threshold > shares {
+        return Err(Error::BadThreshold);
+    }
+
+    let mut coefficients = vec![Zeroizing::new(*master_key)];
+    for _ in 1..threshold {
+        coefficients.push(crypto::new_key()?);
+    }
+
+    let check = check(master_key);
+    let shares = (1..=shares)
+        .map(|x| {
+            let values: String = (0..KEY_LEN)
+                .map(|i| coefficients.iter().rev().fold(0, |y, coefficient| mul(y, x) ^ coefficient[i]))
+                .map(|y| format!("{:02x}", y))
+                .collect();
+            format!("{}-{}-{}-{}-{}", PREFIX, threshold, x, values, check)
+        })
+        .collect();
+    Ok(shares)
+}
+
+/// Share is a parsed recovery share
+struct Share {
+    threshold: usize,
+    x: u8,
+    values: Zeroizing<Vec<u8>>,
+    check: String,
+}
+
+impl Share {
+    /// Parses a share written by `split`, ignoring any whitespace in it
+    fn parse(share: &str) -> Result<Share> {
+        let bad = || Error::BadRecoveryShare { share: share.trim().to_string() };
+        let compact: Zeroizing<String> = Zeroizing::new(share.chars().filter(|c| !c.is_whitespace()).collect());
+        let parts: Vec<&str> = compact.split('-').collect();
+        let [PREFIX, threshold, x, values, check] = parts.as_slice() else { return Err(bad()) };
+
+        let values = Zeroizing::new((0..values.len())
+            .step_by(2)
+            .map(|i| values.get(i..i + 2).and_then(|hex| u8::from_str_radix(hex, 16).ok()))
+            .collect::<Option<Vec<u8>>>()
+            .ok_or_else(bad)?);
+        let threshold = threshold.parse().map_err(|_| bad())?;
+        let x = x.parse().map_err(|_| bad())?;
+        if values.len() != KEY_LEN || x == 0 || check.len() != CHECK_LEN * 2 {
+            return Err(bad());
+        }
+        Ok(Share { threshold, x, values, check: check.to_string() })
+    }
+}
+
+/// Restores the master key from recovery shares written by `split`, by interpolating each
+/// byte's polynomial at 0. The shares must come from the same split, and at least its threshold
+/// of them are needed.
+pub fn combine(shares: &[String]) -> Result<Zeroizing<[u8; KEY_LEN]>> {
+    let mut parsed: Vec<Share> = shares.iter()
+        .filter(|share| !share.trim().is_empty())
+        .map(|share| Share::parse(share))
+        .collect::<Result<_>>()?;
+    parsed.sort_by_key(|share| share.x);
+    parsed.dedup_by_key(|share| share.x);
+
+    let Some(first) = parsed.first() else { return Err(Error::NotEnoughShares { needed: 2, given: 0 }) };
+    let (threshold, expected) = (first.threshold, first.check.clone());
+    if parsed.iter().any(|share| share.threshold != threshold || share.check != expected) {
+        return Err(Error::WrongRecoveryShares);
+    }
+    if parsed.len() < threshold {
+        return Err(Error::NotEnoughShares { needed: threshold, given: parsed.len() });
+    }
+    parsed.truncate(threshold);
+
+    let mut master_key = Zeroizing::new([0u8; KEY_LEN]);
+    for (j, share) in parsed.iter().enumerate() {
+        // The Lagrange basis polynomial for this share, at 0. Subtraction is xor in GF(256).
+        let basis = parsed.iter()
+            .enumerate()
+            .filter(|(m, _)| *m != j)
+            .fold(1, |basis, (_, other)| mul(basis, div(other.x, other.x ^ share.x)));
+        for (byte, value) in master_key.iter_mut().zip(share.values.iter()) {
+            *byte ^= mul(*value, basis);
+        }
+    }
+
+    if check(master_key.as_slice()) != expected {
+        return Err(Error::WrongRecoveryShares);
+    }
+    Ok(master_key)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::crypto;
+    use crate::error::Error;
+    use crate::recovery;
+
+    #[test]
+    fn any_threshold_of_the_shares_restore_the_master_key() {
+        let master_key = crypto::new_key().unwrap();
+        let shares = recovery::split(&master_key, 5, 3).unwrap();
+        assert_eq!(shares.len(), 5);
+        assert!(shares.iter().all(|share| share.starts_with("yap-3-")));
+
+        let some = vec![shares[4].clone(), format!(" {} ", shares[0]), shares[2].replace('-', " - ")];
+        assert_eq!(*recovery::combine(some.as_slice()).unwrap(), *master_key);
+        assert_eq!(*recovery::combine(shares.as_slice()).unwrap(), *master_key);
+
+        let too_few = vec![shares[1].clone(), shares[3].clone(), shares[1].clone()];
+        assert!(matches!(recovery::combine(too_few.as_slice()), Err(Error::NotEnoughShares { needed: 3, given: 2 })));
+
+        let other = recovery::split(&crypto::new_key().unwrap(), 5, 3).unwrap();
+        let mixed = vec![shares[0].clone(), shares[1].clone(), other[2].clone()];
+        assert!(matches!(recovery::combine(mixed.as_slice()), Err(Error::WrongRecoveryShares)));
+
+        let mut corrupted = shares[..3].to_vec();
+        let byte = if &corrupted[0][8..10] == "00" { "01" } else { "00" };
+        corrupted[0].replace_range(8..10, byte);
+        assert!(matches!(recovery::combine(corrupted.as_slice()), Err(Error::WrongRecoveryShares)));
+
+        assert!(recovery::combine(&["yap-3-1-zz".to_string()]).is_err());
+        assert!(recovery::split(&master_key, 2, 3).is_err());
+    }
+}
@@ -0,0 +1,373 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use age::x25519::Recipient;
+
+use crate::activity::Activity;
+use crate::crypto::Kdf;
+use crate::entry::{Entry, Metadata};
+use crate::error::{Error, Result};
+use crate::global::{REMOTE_CACHE_DIR, REMOTE_TOKEN_VAR};
+use crate::stats::Stats;
+use crate::vault::{self, Vault};
+use crate::verify::{RepairReport, Report};
+use crate::webdav;
+
+/// The schemes of stores which are vaults served by `yap serve` on another machine
+const SCHEMES: &[&str] = &["https://", "http://"];
+
+/// Checks whether a store is a vault served by `yap serve`, such as `https://vault.example.com`
+pub fn is_remote(store: &str) -> bool {
+    SCHEMES.iter().any(|scheme| store.starts_with(scheme))
+}
+
+/// Returns the directory holding the local copy of a remote vault, named after its URL so each
+/// remote vault has its own
+pub(crate) fn cache_dir(url: &str) -> Result<PathBuf> {
+    let name: String = url.split_once("://").map_or(url, |(_, rest)| rest)
+        .trim_end_matches('/')
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '_' })
+        .collect();
+    Ok(home::home_dir().ok_or(Error::NoHomeDir)?.join(REMOTE_CACHE_DIR).join(name))
+}
+
+/// Returns the token the server of a remote vault is authenticated with, from the
+/// YAP_REMOTE_TOKEN variable
+pub(crate) fn token() -> Result<String> {
+    std::env::var(REMOTE_TOKEN_VAR).ok()
+        .filter(|token| !token.is_empty())
+        .ok_or_else(|| Error::MissingToken { var: REMOTE_TOKEN_VAR.to_string() })
+}
+
+/// RemoteStore keeps a local copy of the encrypted files of a vault served by `yap serve`, see
+/// `server::Server`. The hash of each file as the server last had it is kept, so only files which
+/// changed are sent, and the server refuses to overwrite a file another client changed since.
+pub(crate) struct RemoteStore {
+    url: String,
+    token: String,
+    dir: PathBuf,
+    known: BTreeMap<String, String>,
+}
+
+impl RemoteStore {
+    /// Opens the remote vault at a URL, keeping its local copy in `dir`. The server is
+    /// authenticated with using `token`, see `token`.
+    pub fn open(url: &str, dir: PathBuf, token: String) -> RemoteStore {
+        RemoteStore { url: url.trim_end_matches('/').to_string(), token, dir, known: BTreeMap::new() }
+    }
+
+    /// Brings the local copy up to date with the server, downloading the files which differ from
+    /// the server's and deleting the files the server doesn't have
+    pub fn fetch(&mut self) -> Result<()> {
+        let response = self.request("GET", "").call().map_err(|e| remote_error(e, "the vault"))?;
+        let remote: BTreeMap<String, String> = serde_json::from_reader(response.into_reader())
+            .map_err(|e| Error::Remote { message: e.to_string() })?;
+        let local = webdav::local_files(self.dir.as_path())?;
+
+        for (name, hash) in remote.iter().filter(|(name, hash)| local.get(*name) != Some(*hash)) {
+            let response = self.request("GET", name).call().map_err(|e| remote_error(e, name))?;
+            let mut contents = vec![];
+            response.into_reader().read_to_end(&mut contents)?;
+            if webdav::hash(contents.as_slice()) != *hash {
+                return Err(Error::RemoteChanged { name: name.clone() });
+            }
+
+            let p = self.dir.join(name.as_str());
+            if let Some(parent) = p.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            vault::atomic_write(p.as_path(), contents.as_slice())?;
+        }
+
+        for name in local.keys().filter(|name| !remote.contains_key(*name)) {
+            let p = self.dir.join(name.as_str());
+            std::fs::remove_file(p.as_path())?;
+            vault::remove_empty_parents(self.dir.as_path(), p.as_path())?;
+        }
+        if remote != local {
+            vault::remove_index(self.dir.as_path())?;
+        }
+
+        self.known = remote;
+        Ok(())
+    }
+
+    /// Sends the files which changed locally since the last fetch or push to the server
+    pub fn push(&mut self) -> Result<()> {
+        let local = webdav::local_files(self.dir.as_path())?;
+        let changed: Vec<(&String, &String)> = local.iter()
+            .filter(|(name, hash)| self.known.get(*name) != Some(*hash))
+            .collect();
+        for (name, hash) in changed {
+            let contents = std::fs::read(self.dir.join(name.as_str()))?;
+            let request = match self.known.get(name) {
+                Some(known) => self.request("PUT", name).set("If-Match", format!("\"{}\"", known).as_str()),
+                None => self.request("PUT", name).set("If-None-Match", "*"),
+            };
+            request.send_bytes(contents.as_slice()).map_err(|e| remote_error(e, name))?;
+            self.known.insert(name.clone(), hash.clone());
+        }
+
+        let deleted: Vec<(String, String)> = self.known.iter()
+            .filter(|(name, _)| !local.contains_key(*name))
+            .map(|(name, hash)| (name.clone(), hash.clone()))
+            .collect();
+        for (name, hash) in deleted {
+            match self.request("DELETE", name.as_str()).set("If-Match", format!("\"{}\"", hash).as_str()).call() {
+                Ok(_) | Err(ureq::Error::Status(404, _)) => {}
+                Err(e) => return Err(remote_error(e, name.as_str())),
+            }
+            self.known.remove(name.as_str());
+        }
+        Ok(())
+    }
+
+    /// Checks whether the server had the file when it was last fetched
+    pub fn has(&self, name: &str) -> bool {
+        self.known.contains_key(name)
+    }
+
+    /// Creates a request for a file of the vault, or for the list of files if `name` is empty
+    fn request(&self, method: &str, name: &str) -> ureq::Request {
+        let url = match name {
+            "" => format!("{}/files", self.url),
+            name => format!("{}/files/{}", self.url, webdav::encode(name)),
+        };
+        ureq::request(method, url.as_str())
+            .set("User-Agent", "yap")
+            .set("Authorization", format!("Bearer {}", self.token).as_str())
+    }
+}
+
+/// RemoteVault is a vault served by `yap serve` on another machine, opened by giving its URL as
+/// the store. Entries are encrypted and decrypted locally by the vault the files belong to, which
+/// works on a local copy of the files brought up to date when the vault is opened. Each change is
+/// sent to the server as soon as it is made, as the encrypted files it touched, so the server
+/// only ever sees ciphertext.
+pub(crate) struct RemoteVault {
+    store: RemoteStore,
+    vault: Box<dyn Vault>,
+}
+
+impl RemoteVault {
+    /// Wraps the vault in the local copy of a remote vault, sending any local changes which
+    /// haven't reached the server yet, such as the header of a newly created vault
+    pub fn new(store: RemoteStore, vault: Box<dyn Vault>) -> Result<RemoteVault> {
+        let mut remote = RemoteVault { store, vault };
+        remote.store.push()?;
+        Ok(remote)
+    }
+}
+
+impl Vault for RemoteVault {
+    fn has_key(&self, key: &str) -> bool {
+        self.vault.has_key(key)
+    }
+
+    fn get_entry(&self, key: &str) -> Result<Entry> {
+        self.vault.get_entry(key)
+    }
+
+    fn access_entry(&mut self, key: &str) -> Result<Entry> {
+        let entry = self.vault.access_entry(key)?;
+        self.store.push()?;
+        Ok(entry)
+    }
+
+    fn set_entry(&mut self, key: &str, entry: &Entry) -> Result<()> {
+        self.vault.set_entry(key, entry)?;
+        self.store.push()
+    }
+
+    fn set_entries(&mut self, entries: &[(String, Entry)]) -> Result<()> {
+        self.vault.set_entries(entries)?;
+        self.store.push()
+    }
+
+    fn delete_key(&mut self, key: &str) -> Result<()> {
+        self.vault.delete_key(key)?;
+        self.store.push()
+    }
+
+    fn rename_key(&mut self, from: &str, to: &str, force: bool) -> Result<()> {
+        self.vault.rename_key(from, to, force)?;
+        self.store.push()
+    }
+
+    fn list_keys(&self, prefix: &str) -> Result<Vec<String>> {
+        self.vault.list_keys(prefix)
+    }
+
+    fn attach(&mut self, key: &str, attachment: &str, reader: &mut dyn Read) -> Result<u64> {
+        let len = self.vault.attach(key, attachment, reader)?;
+        self.store.push()?;
+        Ok(len)
+    }
+
+    fn read_attachment(&self, key: &str, attachment: &str, writer: &mut dyn Write) -> Result<u64> {
+        self.vault.read_attachment(key, attachment, writer)
+    }
+
+    fn list_attachments(&self, key: &str) -> Result<Vec<String>> {
+        self.vault.list_attachments(key)
+    }
+
+    fn remove_attachment(&mut self, key: &str, attachment: &str) -> Result<()> {
+        self.vault.remove_attachment(key, attachment)?;
+        self.store.push()
+    }
+
+    fn history(&self, key: &str) -> Result<Vec<(u32, Metadata)>> {
+        self.vault.history(key)
+    }
+
+    fn restore(&mut self, key: &str, version: u32) -> Result<()> {
+        self.vault.restore(key, version)?;
+        self.store.push()
+    }
+
+    fn list_trash(&self) -> Result<Vec<(String, i64)>> {
+        self.vault.list_trash()
+    }
+
+    fn restore_trash(&mut self, key: &str, force: bool) -> Result<()> {
+        self.vault.restore_trash(key, force)?;
+        self.store.push()
+    }
+
+    fn empty_trash(&mut self, before: Option<i64>) -> Result<usize> {
+        let removed = self.vault.empty_trash(before)?;
+        if removed > 0 {
+            self.store.push()?;
+        }
+        Ok(removed)
+    }
+
+    fn rebuild_index(&mut self) -> Result<usize> {
+        self.vault.rebuild_index()
+    }
+
+    fn verify(&mut self, quarantine: bool) -> Result<Report> {
+        let report = self.vault.verify(quarantine)?;
+        if quarantine {
+            self.store.push()?;
+        }
+        Ok(report)
+    }
+
+    fn repair(&mut self, backup: Option<&Path>) -> Result<RepairReport> {
+        let report = self.vault.repair(backup)?;
+        self.store.push()?;
+        Ok(report)
+    }
+
+    fn recover(&mut self, backup: &Path, key: &str) -> Result<()> {
+        self.vault.recover(backup, key)?;
+        self.store.push()
+    }
+
+    fn activity(&self) -> Result<Vec<Activity>> {
+        self.vault.activity()
+    }
+
+    fn rekey(&mut self, new_pass: &str) -> Result<()> {
+        self.vault.rekey(new_pass)?;
+        self.store.push()
+    }
+
+    fn set_kdf(&mut self, kdf: Kdf, pass: &str) -> Result<()> {
+        self.vault.set_kdf(kdf, pass)?;
+        self.store.push()
+    }
+
+    fn add_member(&mut self, name: &str, recipient: &Recipient) -> Result<()> {
+        self.vault.add_member(name, recipient)?;
+        self.store.push()
+    }
+
+    fn remove_member(&mut self, name: &str, pass: &str) -> Result<()> {
+        self.vault.remove_member(name, pass)?;
+        self.store.push()
+    }
+
+    fn delete_folder(&mut self, folder: &str) -> Result<Vec<String>> {
+        let deleted = self.vault.delete_folder(folder)?;
+        self.store.push()?;
+        Ok(deleted)
+    }
+
+    fn metadata(&self, key: &str) -> Result<Metadata> {
+        self.vault.metadata(key)
+    }
+
+    fn tags(&self, key: &str) -> Result<BTreeSet<String>> {
+        self.vault.tags(key)
+    }
+
+    fn stats(&self) -> Result<Stats> {
+        self.vault.stats()
+    }
+}
+
+/// Describes a failed request for a file, using the error the server gave if there is one
+fn remote_error(e: ureq::Error, name: &str) -> Error {
+    match e {
+        ureq::Error::Status(412, _) => Error::RemoteChanged { name: name.to_string() },
+        ureq::Error::Status(status, response) => {
+            let message = serde_json::from_reader::<_, serde_json::Value>(response.into_reader()).ok()
+                .and_then(|body| body["error"].as_str().map(String::from))
+                .unwrap_or_else(|| format!("the server responded with {}", status));
+            Error::Remote { message: format!("{}: {}", name, message) }
+        }
+        e => Error::Remote { message: e.to_string() },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use crate::crypto::Kdf;
+    use crate::global::HEADER_FILE;
+    use crate::remote::{cache_dir, RemoteStore, RemoteVault};
+    use crate::server::Server;
+    use crate::vault::{self, Backend, Vault};
+
+    #[test]
+    fn remote_vaults_only_send_ciphertext() {
+        let served = Path::new(".yap_test_remote");
+        std::fs::create_dir_all(served).unwrap();
+        std::thread::spawn(move || Server::new(served.to_path_buf(), None, Some("token".to_string())).serve("127.0.0.1:17879", None).unwrap());
+
+        let url = "http://127.0.0.1:17879";
+        let local = cache_dir(url).unwrap();
+        let local_store = Some(local.display().to_string());
+        let mut store = RemoteStore::open(url, local.clone(), "token".to_string());
+        while store.fetch().is_err() {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        let vault = vault::create(local_store.clone(), "asdf", Kdf::default(), Backend::default(), None).unwrap();
+        let mut vault = RemoteVault::new(store, vault).unwrap();
+        assert!(served.join(HEADER_FILE).exists());
+
+        vault.set_key("work/github", "s3cret".to_string()).unwrap();
+        let served_entry = std::fs::read(served.join("work/github")).unwrap();
+        assert!(!String::from_utf8_lossy(served_entry.as_slice()).contains("s3cret"));
+
+        // Another machine starts without a local copy, and fetches the vault from the server
+        std::fs::remove_dir_all(local.as_path()).unwrap();
+        let mut store = RemoteStore::open(url, local.clone(), "token".to_string());
+        store.fetch().unwrap();
+        assert!(store.has(HEADER_FILE));
+        let mut vault = RemoteVault::new(store, vault::load(local_store, "asdf").unwrap()).unwrap();
+        assert_eq!(vault.get_key("work/github").unwrap(), "s3cret");
+
+        vault.delete_key("work/github").unwrap();
+        assert!(!served.join("work").exists());
+
+        std::fs::remove_dir_all(local).unwrap();
+        std::fs::remove_dir_all(served).unwrap();
+    }
+}
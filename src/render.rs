@@ -0,0 +1,80 @@
+use std::path::Path;
+
+use regex::{Captures, Regex};
+use zeroize::Zeroizing;
+
+use crate::editor;
+use crate::error::{Error, Result};
+use crate::run::Secret;
+use crate::vault::Vault;
+
+/// Matches a reference to a secret in a template, `{{ yap "name" }}` for the secret of an entry,
+/// or `{{ yap "name" "field" }}` or `{{ yap "name#field" }}` for one of its fields
+const REFERENCE: &str = r#"\{\{\s*yap\s+"([^"]+)"(?:\s+"([^"]+)")?\s*\}\}"#;
+
+/// Renders a template, replacing each reference to a secret with its value. Anything else in
+/// the template, including other `{{ }}` expressions, is left as it is. Every reference must
+/// resolve, so a half rendered file is never written.
+pub fn render(vault: &mut dyn Vault, template: &str) -> Result<Zeroizing<String>> {
+    let reference = Regex::new(REFERENCE)?;
+    let mut error = None;
+    let rendered = reference.replace_all(template, |captures: &Captures| {
+        let secret = match captures.get(2) {
+            Some(field) => Secret { entry: captures[1].to_string(), field: Some(field.as_str().to_string()) },
+            None => Secret::parse(&captures[1]),
+        };
+        match secret.resolve(vault) {
+            Ok(value) => value.to_string(),
+            Err(e) => {
+                error.get_or_insert(e);
+                String::new()
+            }
+        }
+    });
+
+    match error {
+        Some(e) => Err(e),
+        None => Ok(Zeroizing::new(rendered.into_owned())),
+    }
+}
+
+/// Writes a rendered template to a file which only the user can read. An existing file is only
+/// replaced when `force` is set.
+pub fn write(p: &Path, contents: &str, force: bool) -> Result<()> {
+    if p.exists() {
+        if !force {
+            return Err(Error::FileExists { path: p.display().to_string() });
+        }
+        std::fs::remove_file(p)?;
+    }
+    editor::write_private(p, contents.as_bytes())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::crypto::Kdf;
+    use crate::entry::Entry;
+    use crate::render;
+    use crate::vault::{self, Backend};
+
+    #[test]
+    fn templates_render_secrets_and_fields() {
+        let yap_test = String::from(".yap_test_render");
+        let mut vault = vault::create(Some(yap_test.clone()), "asdf", Kdf::default(), Backend::default(), None).unwrap();
+        let entry = Entry { username: Some("app".to_string()), ..Entry::new("s3cret".to_string()) };
+        vault.set_entry("db/prod", &entry).unwrap();
+
+        let template = "db:5432:*:{{ yap \"db/prod\" \"username\" }}:{{yap \"db/prod\"}}\n# {{ yap \"db/prod#username\" }} {{ other }}\n";
+        let rendered = render::render(vault.as_mut(), template).unwrap();
+        assert_eq!(rendered.as_str(), "db:5432:*:app:s3cret\n# app {{ other }}\n");
+        assert!(render::render(vault.as_mut(), "{{ yap \"missing\" }}").is_err());
+
+        let out = std::path::Path::new(yap_test.as_str()).join("pgpass");
+        render::write(out.as_path(), rendered.as_str(), false).unwrap();
+        assert!(render::write(out.as_path(), rendered.as_str(), false).is_err());
+        render::write(out.as_path(), "replaced", true).unwrap();
+        assert_eq!(std::fs::read_to_string(out).unwrap(), "replaced");
+
+        std::fs::remove_dir_all(yap_test).unwrap();
+    }
+}
@@ -0,0 +1,113 @@
+use std::process::Command;
+use std::str::FromStr;
+
+use zeroize::Zeroizing;
+
+use crate::error::{Error, Result};
+use crate::vault::Vault;
+
+/// Separates the name of an entry from one of its fields in a reference to a secret
+const FIELD_SEPARATOR: char = '#';
+
+/// Secret is a reference to a value in the vault, either the secret of an entry, given as its
+/// name, or one of its fields, given as `name#field`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Secret {
+    pub entry: String,
+    pub field: Option<String>,
+}
+
+impl Secret {
+    /// Parses a reference to a secret, such as `db/prod` or `db/prod#username`
+    pub fn parse(reference: &str) -> Secret {
+        match reference.rsplit_once(FIELD_SEPARATOR) {
+            Some((entry, field)) if !field.is_empty() => Secret { entry: entry.to_string(), field: Some(field.to_string()) },
+            _ => Secret { entry: reference.to_string(), field: None },
+        }
+    }
+
+    /// Decrypts the value, recording that the entry was accessed
+    pub fn resolve(&self, vault: &mut dyn Vault) -> Result<Zeroizing<String>> {
+        let entry = vault.access_entry(self.entry.as_str())?;
+        let field = self.field.as_deref().unwrap_or(entry.secret_field());
+        let value = entry.field(field)
+            .ok_or_else(|| Error::FieldNotFound { name: self.entry.clone(), field: field.to_string() })?;
+        Ok(Zeroizing::new(value.to_string()))
+    }
+}
+
+/// EnvVar is an environment variable set from a secret, given as `NAME=entry` or
+/// `NAME=entry#field`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EnvVar {
+    pub name: String,
+    pub secret: Secret,
+}
+
+impl FromStr for EnvVar {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<EnvVar> {
+        match s.split_once('=') {
+            Some((name, reference)) if !name.is_empty() && !reference.is_empty() => {
+                Ok(EnvVar { name: name.to_string(), secret: Secret::parse(reference) })
+            }
+            _ => Err(Error::BadEnvVar { var: s.to_string() }),
+        }
+    }
+}
+
+/// Decrypts the secrets for each variable, so every entry is checked before the command starts
+pub fn resolve(vault: &mut dyn Vault, vars: &[EnvVar]) -> Result<Vec<(String, Zeroizing<String>)>> {
+    vars.iter()
+        .map(|var| Ok((var.name.clone(), var.secret.resolve(vault)?)))
+        .collect()
+}
+
+/// Runs a command with the variables set in its environment, returning its exit code. On Unix
+/// yap is replaced by the command, so this only returns if the command couldn't be started.
+pub fn run(command: &[String], env: &[(String, Zeroizing<String>)]) -> Result<i32> {
+    let Some((program, args)) = command.split_first() else {
+        return Err(Error::MissingCommand);
+    };
+    let mut command = Command::new(program);
+    command.args(args).envs(env.iter().map(|(name, value)| (name.as_str(), value.as_str())));
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        Err(command.exec().into())
+    }
+    #[cfg(not(unix))]
+    {
+        Ok(command.status()?.code().unwrap_or(crate::error::EXIT_FAILURE))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::crypto::Kdf;
+    use crate::entry::Entry;
+    use crate::run::{self, EnvVar, Secret};
+    use crate::vault::{self, Backend};
+
+    #[test]
+    fn env_vars_resolve_entries_and_fields() {
+        let yap_test = String::from(".yap_test_run");
+        let mut vault = vault::create(Some(yap_test.clone()), "asdf", Kdf::default(), Backend::default(), None).unwrap();
+        let entry = Entry { username: Some("app".to_string()), ..Entry::new("s3cret".to_string()) };
+        vault.set_entry("db/prod", &entry).unwrap();
+
+        assert_eq!(Secret::parse("db/prod#username"), Secret { entry: "db/prod".to_string(), field: Some("username".to_string()) });
+        assert!("DB_PASS".parse::<EnvVar>().is_err());
+        assert!("=db/prod".parse::<EnvVar>().is_err());
+
+        let vars: Vec<EnvVar> = ["DB_PASS=db/prod", "DB_USER=db/prod#username"].iter().map(|var| var.parse().unwrap()).collect();
+        let env = run::resolve(vault.as_mut(), vars.as_slice()).unwrap();
+        assert_eq!((env[0].0.as_str(), env[0].1.as_str()), ("DB_PASS", "s3cret"));
+        assert_eq!(env[1].1.as_str(), "app");
+        assert!(run::resolve(vault.as_mut(), &["X=db/prod#url".parse().unwrap()]).is_err());
+
+        std::fs::remove_dir_all(yap_test).unwrap();
+    }
+}
@@ -0,0 +1,160 @@
+use regex::{Regex, RegexBuilder};
+
+use crate::entry::Entry;
+use crate::error::Result;
+use crate::vault::Vault;
+
+/// Matcher matches text against a search pattern, either as a plain substring or as a regular
+/// expression.
+pub enum Matcher {
+    Substring { pattern: String, ignore_case: bool },
+    Regex(Regex),
+}
+
+impl Matcher {
+    /// Creates a matcher for the pattern. The pattern is compiled as a regular expression if
+    /// `regex` is set, which fails if the pattern is not a valid regular expression.
+    pub fn new(pattern: &str, regex: bool, ignore_case: bool) -> Result<Matcher> {
+        if regex {
+            Ok(Matcher::Regex(RegexBuilder::new(pattern).case_insensitive(ignore_case).build()?))
+        } else if ignore_case {
+            Ok(Matcher::Substring { pattern: pattern.to_lowercase(), ignore_case })
+        } else {
+            Ok(Matcher::Substring { pattern: pattern.to_string(), ignore_case })
+        }
+    }
+
+    /// Checks whether the text matches the pattern
+    pub fn is_match(&self, text: &str) -> bool {
+        match self {
+            Matcher::Substring { pattern, ignore_case: true } => text.to_lowercase().contains(pattern.as_str()),
+            Matcher::Substring { pattern, ignore_case: false } => text.contains(pattern.as_str()),
+            Matcher::Regex(regex) => regex.is_match(text),
+        }
+    }
+}
+
+/// Finds the entries whose names match. When `deep` is set, entries are also decrypted and their
+/// username, URL, notes, and custom fields are searched too, but never their password. Each
+/// match is a line in the output, either the entry name, or the entry name and the name of the
+/// matching field separated by a tab, so the output can easily be used in scripts. Only entries
+/// with every one of `tags` are searched.
+pub fn find(vault: &dyn Vault, matcher: &Matcher, deep: bool, tags: &[String]) -> Result<Vec<String>> {
+    let mut matches = vec![];
+    for key in tagged(vault, vault.list_keys("")?, tags)? {
+        if matcher.is_match(key.as_str()) {
+            matches.push(key);
+        } else if deep {
+            let entry = vault.get_entry(key.as_str())?;
+            matches.extend(matching_fields(&entry, matcher).into_iter().map(|field| format!("{}\t{}", key, field)));
+        }
+    }
+    Ok(matches)
+}
+
+/// Keeps the names of the entries which have every one of `tags`. The tags come from the index,
+/// so no entry is decrypted.
+pub fn tagged(vault: &dyn Vault, keys: Vec<String>, tags: &[String]) -> Result<Vec<String>> {
+    if tags.is_empty() {
+        return Ok(keys);
+    }
+
+    let mut matches = vec![];
+    for key in keys {
+        let entry_tags = vault.tags(key.as_str())?;
+        if tags.iter().all(|tag| entry_tags.contains(tag)) {
+            matches.push(key);
+        }
+    }
+    Ok(matches)
+}
+
+/// Returns the names which fuzzily match `query`, best match first, for when no entry has exactly
+/// that name. See `fuzzy_score` for how names are matched and ranked.
+pub fn fuzzy(names: &[String], query: &str) -> Vec<String> {
+    let mut scored: Vec<(i64, &String)> = names.iter()
+        .filter_map(|name| fuzzy_score(name.as_str(), query).map(|score| (score, name)))
+        .collect();
+
+    scored.sort_by(|(a_score, a), (b_score, b)| b_score.cmp(a_score).then_with(|| a.cmp(b)));
+    scored.into_iter().map(|(_, name)| name.clone()).collect()
+}
+
+/// Scores how well a name matches a query, ignoring case, or returns None if the characters of
+/// the query don't all appear in the name in the same order. Characters matching one after
+/// another, or at the start of a part of the name such as after a `/`, score more, and
+/// characters of the name which aren't matched score less, so `gh` matches `github` better than
+/// `work/graphql-hub`.
+fn fuzzy_score(name: &str, query: &str) -> Option<i64> {
+    let name: Vec<char> = name.to_lowercase().chars().collect();
+    let mut score = 0;
+    let mut last: Option<usize> = None;
+
+    for c in query.to_lowercase().chars() {
+        let start = last.map(|i| i + 1).unwrap_or_default();
+        let i = start + name.get(start..)?.iter().position(|n| *n == c)?;
+
+        score += 1;
+        if last.is_some_and(|last| last + 1 == i) {
+            score += 5;
+        }
+        if i == 0 || matches!(name[i - 1], '/' | '-' | '_' | '.' | ' ') {
+            score += 8;
+        }
+        last = Some(i);
+    }
+
+    Some(score * 2 - (name.len() as i64 - query.chars().count() as i64).max(0))
+}
+
+/// Returns the names of the entry's searchable fields whose values match
+fn matching_fields(entry: &Entry, matcher: &Matcher) -> Vec<String> {
+    let builtin = ["username", "url", "notes"].into_iter().map(|name| name.to_string());
+    builtin.chain(entry.fields.keys().cloned())
+        .filter(|name| entry.field(name.as_str()).map(|value| matcher.is_match(value)).unwrap_or_default())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::crypto::Kdf;
+    use crate::entry::Entry;
+    use crate::search::{find, fuzzy, tagged, Matcher};
+    use crate::vault::{self, Backend};
+
+    #[test]
+    fn find_entries_by_name_and_fields() {
+        let yap_test = String::from(".yap_test_search");
+        let mut vault = vault::create(Some(yap_test.clone()), "asdf", Kdf::default(), Backend::default(), None).unwrap();
+
+        let mut entry = Entry::new("hunter2".to_string());
+        entry.set_field("url", "https://github.com".to_string());
+        entry.tags.insert("work".to_string());
+        vault.set_entry("code", &entry).unwrap();
+        vault.set_entry("github", &Entry::new("hunter2".to_string())).unwrap();
+        vault.set_entry("email", &Entry::new("hunter2".to_string())).unwrap();
+
+        let matcher = Matcher::new("GitHub", false, true).unwrap();
+        assert_eq!(find(vault.as_ref(), &matcher, false, &[]).unwrap(), vec!["github"]);
+        assert_eq!(find(vault.as_ref(), &matcher, true, &[]).unwrap(), vec!["code\turl", "github"]);
+
+        let matcher = Matcher::new("^e.*l$", true, false).unwrap();
+        assert_eq!(find(vault.as_ref(), &matcher, false, &[]).unwrap(), vec!["email"]);
+
+        let work = vec!["work".to_string()];
+        assert_eq!(find(vault.as_ref(), &Matcher::new("", false, false).unwrap(), false, &work).unwrap(), vec!["code"]);
+        assert_eq!(find(vault.as_ref(), &matcher, false, &work).unwrap(), Vec::<String>::new());
+        assert_eq!(tagged(vault.as_ref(), vault.list_keys("").unwrap(), &["work".to_string(), "aws".to_string()]).unwrap(), Vec::<String>::new());
+
+        let matcher = Matcher::new("hunter", false, false).unwrap();
+        assert!(find(vault.as_ref(), &matcher, true, &[]).unwrap().is_empty());
+        assert!(Matcher::new("(", true, false).is_err());
+
+        let names: Vec<String> = ["work/github", "work/graphql-hub", "email", "gitlab"].iter().map(|n| n.to_string()).collect();
+        assert_eq!(fuzzy(&names, "ghub"), vec!["work/github", "work/graphql-hub"]);
+        assert_eq!(fuzzy(&names, "GIT"), vec!["gitlab", "work/github"]);
+        assert!(fuzzy(&names, "xyz").is_empty());
+
+        std::fs::remove_dir_all(yap_test).unwrap();
+    }
+}
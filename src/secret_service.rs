@@ -0,0 +1,398 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use ring::rand::{SecureRandom, SystemRandom};
+use zbus::blocking::connection;
+use zbus::zvariant::{ObjectPath, OwnedObjectPath, OwnedValue, Value};
+use zbus::{fdo, interface, ObjectServer};
+
+use crate::entry::Entry;
+use crate::error::{Error, Result};
+use crate::vault::Vault;
+
+/// The well known name applications look for the Secret Service under
+const SERVICE_NAME: &str = "org.freedesktop.secrets";
+const SERVICE_PATH: &str = "/org/freedesktop/secrets";
+/// The only collection, which holds every item
+const COLLECTION_PATH: &str = "/org/freedesktop/secrets/collection/yap";
+/// The default collection, which most applications store their items in
+const DEFAULT_ALIAS_PATH: &str = "/org/freedesktop/secrets/aliases/default";
+const LABEL_PROPERTY: &str = "org.freedesktop.Secret.Item.Label";
+const ATTRIBUTES_PROPERTY: &str = "org.freedesktop.Secret.Item.Attributes";
+
+/// The folder items are kept in, as `secret-service/<id>`
+const FOLDER: &str = "secret-service";
+/// The field of an entry holding the label of its item
+const LABEL_FIELD: &str = "label";
+/// The prefix of the fields of an entry holding the attributes of its item
+const ATTRIBUTE_PREFIX: &str = "attribute.";
+
+/// A secret as it is sent over D-Bus: the session, the parameters of its encryption, the value,
+/// and its content type
+type Secret = (OwnedObjectPath, Vec<u8>, Vec<u8>, String);
+
+/// The vault is shared by every object on the bus, which are called from zbus's own threads
+type Shared = Arc<Mutex<Box<dyn Vault>>>;
+
+/// Runs the Secret Service on the session bus until yap is stopped, so applications which use
+/// GNOME Keyring or KWallet through `org.freedesktop.secrets` keep their secrets in the vault
+/// instead. Items are entries in the `secret-service` folder, with their label and attributes in
+/// fields. Only the `plain` session algorithm is supported, since the session bus is already
+/// private to the user.
+pub fn serve(vault: Box<dyn Vault>) -> Result<()> {
+    let ids = vault.list_keys(format!("{}/", FOLDER).as_str())?;
+    let vault: Shared = Arc::new(Mutex::new(vault));
+
+    let mut builder = connection::Builder::session().map_err(bus_error)?
+        .serve_at(SERVICE_PATH, Service { vault: vault.clone(), sessions: AtomicU32::new(0) }).map_err(bus_error)?
+        .serve_at(COLLECTION_PATH, Collection { vault: vault.clone() }).map_err(bus_error)?
+        .serve_at(DEFAULT_ALIAS_PATH, Collection { vault: vault.clone() }).map_err(bus_error)?;
+    for name in ids {
+        let id = name[FOLDER.len() + 1..].to_string();
+        builder = builder.serve_at(item_path(id.as_str()), Item { vault: vault.clone(), id }).map_err(bus_error)?;
+    }
+    let _connection = builder.name(SERVICE_NAME).map_err(bus_error)?.build().map_err(bus_error)?;
+
+    loop {
+        std::thread::park();
+    }
+}
+
+/// Service is the entry point of the Secret Service, for opening sessions and finding items
+struct Service {
+    vault: Shared,
+    sessions: AtomicU32,
+}
+
+#[interface(name = "org.freedesktop.Secret.Service")]
+impl Service {
+    async fn open_session(
+        &self,
+        algorithm: &str,
+        _input: OwnedValue,
+        #[zbus(object_server)] server: &ObjectServer,
+    ) -> fdo::Result<(Value<'static>, OwnedObjectPath)> {
+        if algorithm != "plain" {
+            return Err(fdo::Error::NotSupported(format!("The {} algorithm is not supported", algorithm)));
+        }
+        let path = format!("{}/session/{}", SERVICE_PATH, self.sessions.fetch_add(1, Ordering::Relaxed));
+        server.at(path.as_str(), Session).await?;
+        Ok((Value::from(""), object_path(path.as_str())?))
+    }
+
+    async fn create_collection(
+        &self,
+        _properties: HashMap<String, OwnedValue>,
+        _alias: &str,
+    ) -> fdo::Result<(OwnedObjectPath, OwnedObjectPath)> {
+        Ok((object_path(COLLECTION_PATH)?, object_path("/")?))
+    }
+
+    fn search_items(&self, attributes: HashMap<String, String>) -> fdo::Result<(Vec<OwnedObjectPath>, Vec<OwnedObjectPath>)> {
+        let ids = search(lock(&self.vault)?.as_ref(), &attributes).map_err(failed)?;
+        Ok((ids.iter().map(|id| object_path(item_path(id).as_str())).collect::<fdo::Result<_>>()?, vec![]))
+    }
+
+    /// The vault was unlocked when the service started, so there is nothing to unlock
+    fn unlock(&self, objects: Vec<OwnedObjectPath>) -> fdo::Result<(Vec<OwnedObjectPath>, OwnedObjectPath)> {
+        Ok((objects, object_path("/")?))
+    }
+
+    fn lock(&self, _objects: Vec<OwnedObjectPath>) -> fdo::Result<(Vec<OwnedObjectPath>, OwnedObjectPath)> {
+        Ok((vec![], object_path("/")?))
+    }
+
+    fn get_secrets(&self, items: Vec<OwnedObjectPath>, session: OwnedObjectPath) -> fdo::Result<HashMap<OwnedObjectPath, Secret>> {
+        let mut vault = lock(&self.vault)?;
+        let mut secrets = HashMap::new();
+        for item in items {
+            let Some(id) = item.as_str().strip_prefix(format!("{}/", COLLECTION_PATH).as_str()) else { continue };
+            let entry = vault.access_entry(entry_name(id).as_str()).map_err(failed)?;
+            secrets.insert(item.clone(), (session.clone(), vec![], entry.password.into_bytes(), "text/plain".to_string()));
+        }
+        Ok(secrets)
+    }
+
+    fn read_alias(&self, name: &str) -> fdo::Result<OwnedObjectPath> {
+        match name {
+            "default" | "login" => object_path(COLLECTION_PATH),
+            _ => object_path("/"),
+        }
+    }
+
+    fn set_alias(&self, _name: &str, _collection: OwnedObjectPath) -> fdo::Result<()> {
+        Err(fdo::Error::NotSupported("Aliases can't be changed".to_string()))
+    }
+
+    #[zbus(property)]
+    fn collections(&self) -> fdo::Result<Vec<OwnedObjectPath>> {
+        Ok(vec![object_path(COLLECTION_PATH)?])
+    }
+}
+
+/// Session is a session opened by a client. Secrets are sent as they are in plain sessions, so
+/// there is nothing to keep for them.
+struct Session;
+
+#[interface(name = "org.freedesktop.Secret.Session")]
+impl Session {
+    async fn close(&self, #[zbus(header)] header: zbus::message::Header<'_>, #[zbus(object_server)] server: &ObjectServer) -> fdo::Result<()> {
+        if let Some(path) = header.path() {
+            server.remove::<Session, _>(path).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Collection is the collection holding every item, which is also the default collection
+struct Collection {
+    vault: Shared,
+}
+
+#[interface(name = "org.freedesktop.Secret.Collection")]
+impl Collection {
+    fn search_items(&self, attributes: HashMap<String, String>) -> fdo::Result<Vec<OwnedObjectPath>> {
+        let ids = search(lock(&self.vault)?.as_ref(), &attributes).map_err(failed)?;
+        ids.iter().map(|id| object_path(item_path(id).as_str())).collect()
+    }
+
+    async fn create_item(
+        &self,
+        properties: HashMap<String, OwnedValue>,
+        secret: Secret,
+        replace: bool,
+        #[zbus(object_server)] server: &ObjectServer,
+    ) -> fdo::Result<(OwnedObjectPath, OwnedObjectPath)> {
+        let label = properties.get(LABEL_PROPERTY)
+            .and_then(|label| String::try_from(label.try_clone().ok()?).ok())
+            .unwrap_or_default();
+        let attributes = properties.get(ATTRIBUTES_PROPERTY)
+            .and_then(|attributes| HashMap::<String, String>::try_from(attributes.try_clone().ok()?).ok())
+            .unwrap_or_default();
+        let value = String::from_utf8(secret.2).map_err(|_| fdo::Error::InvalidArgs("Secrets must be UTF-8".to_string()))?;
+
+        let (id, created) = store(lock(&self.vault)?.as_mut(), label.as_str(), &attributes, value, replace).map_err(failed)?;
+        if created {
+            server.at(item_path(id.as_str()).as_str(), Item { vault: self.vault.clone(), id: id.clone() }).await?;
+        }
+        Ok((object_path(item_path(id.as_str()).as_str())?, object_path("/")?))
+    }
+
+    fn delete(&self) -> fdo::Result<OwnedObjectPath> {
+        Err(fdo::Error::NotSupported("The collection can't be deleted".to_string()))
+    }
+
+    #[zbus(property)]
+    fn items(&self) -> fdo::Result<Vec<OwnedObjectPath>> {
+        let ids = search(lock(&self.vault)?.as_ref(), &HashMap::new()).map_err(failed)?;
+        ids.iter().map(|id| object_path(item_path(id).as_str())).collect()
+    }
+
+    #[zbus(property)]
+    fn label(&self) -> String {
+        "yap".to_string()
+    }
+
+    #[zbus(property)]
+    fn locked(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn created(&self) -> u64 {
+        0
+    }
+
+    #[zbus(property)]
+    fn modified(&self) -> u64 {
+        0
+    }
+}
+
+/// Item is a secret stored by an application, kept in the entry `secret-service/<id>`
+struct Item {
+    vault: Shared,
+    id: String,
+}
+
+impl Item {
+    fn entry(&self) -> fdo::Result<Entry> {
+        lock(&self.vault)?.get_entry(entry_name(self.id.as_str()).as_str()).map_err(failed)
+    }
+
+    fn update(&self, update: impl FnOnce(&mut Entry)) -> fdo::Result<()> {
+        let mut vault = lock(&self.vault)?;
+        let name = entry_name(self.id.as_str());
+        let mut entry = vault.get_entry(name.as_str()).map_err(failed)?;
+        update(&mut entry);
+        vault.set_entry(name.as_str(), &entry).map_err(failed)
+    }
+}
+
+#[interface(name = "org.freedesktop.Secret.Item")]
+impl Item {
+    fn get_secret(&self, session: OwnedObjectPath) -> fdo::Result<Secret> {
+        let entry = lock(&self.vault)?.access_entry(entry_name(self.id.as_str()).as_str()).map_err(failed)?;
+        Ok((session, vec![], entry.password.into_bytes(), "text/plain".to_string()))
+    }
+
+    fn set_secret(&self, secret: Secret) -> fdo::Result<()> {
+        let value = String::from_utf8(secret.2).map_err(|_| fdo::Error::InvalidArgs("Secrets must be UTF-8".to_string()))?;
+        self.update(|entry| entry.password = value)
+    }
+
+    async fn delete(&self, #[zbus(object_server)] server: &ObjectServer) -> fdo::Result<OwnedObjectPath> {
+        lock(&self.vault)?.delete_key(entry_name(self.id.as_str()).as_str()).map_err(failed)?;
+        server.remove::<Item, _>(item_path(self.id.as_str()).as_str()).await?;
+        object_path("/")
+    }
+
+    #[zbus(property)]
+    fn locked(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn attributes(&self) -> fdo::Result<HashMap<String, String>> {
+        Ok(attributes(&self.entry()?))
+    }
+
+    #[zbus(property)]
+    fn set_attributes(&self, attributes: HashMap<String, String>) -> fdo::Result<()> {
+        self.update(|entry| set_attributes(entry, &attributes))
+    }
+
+    #[zbus(property)]
+    fn label(&self) -> fdo::Result<String> {
+        Ok(self.entry()?.fields.get(LABEL_FIELD).cloned().unwrap_or_default())
+    }
+
+    #[zbus(property)]
+    fn set_label(&self, label: String) -> fdo::Result<()> {
+        self.update(|entry| entry.set_field(LABEL_FIELD, label))
+    }
+
+    #[zbus(property)]
+    fn created(&self) -> fdo::Result<u64> {
+        Ok(self.entry()?.metadata.created.max(0) as u64)
+    }
+
+    #[zbus(property)]
+    fn modified(&self) -> fdo::Result<u64> {
+        Ok(self.entry()?.metadata.modified.max(0) as u64)
+    }
+}
+
+/// Finds the ids of the items which have every one of the attributes
+fn search(vault: &dyn Vault, query: &HashMap<String, String>) -> Result<Vec<String>> {
+    let mut ids = vec![];
+    for name in vault.list_keys(format!("{}/", FOLDER).as_str())? {
+        let item = attributes(&vault.get_entry(name.as_str())?);
+        if query.iter().all(|(key, value)| item.get(key) == Some(value)) {
+            ids.push(name[FOLDER.len() + 1..].to_string());
+        }
+    }
+    Ok(ids)
+}
+
+/// Stores a secret as a new item, or replaces the secret of the item with the same attributes if
+/// `replace` is set. Returns the id of the item and whether it was created.
+fn store(vault: &mut dyn Vault, label: &str, item: &HashMap<String, String>, secret: String, replace: bool) -> Result<(String, bool)> {
+    let existing = match replace {
+        true => search(vault, item)?.into_iter().find(|id| {
+            vault.get_entry(entry_name(id).as_str()).is_ok_and(|entry| attributes(&entry).len() == item.len())
+        }),
+        false => None,
+    };
+
+    let (id, mut entry) = match &existing {
+        Some(id) => (id.clone(), vault.get_entry(entry_name(id).as_str())?),
+        None => (new_id()?, Entry::default()),
+    };
+    entry.password = secret;
+    entry.set_field(LABEL_FIELD, label.to_string());
+    set_attributes(&mut entry, item);
+    vault.set_entry(entry_name(id.as_str()).as_str(), &entry)?;
+    Ok((id, existing.is_none()))
+}
+
+/// Returns the attributes of an item, which are kept in the fields of its entry
+fn attributes(entry: &Entry) -> HashMap<String, String> {
+    entry.fields.iter()
+        .filter_map(|(name, value)| name.strip_prefix(ATTRIBUTE_PREFIX).map(|name| (name.to_string(), value.clone())))
+        .collect()
+}
+
+fn set_attributes(entry: &mut Entry, attributes: &HashMap<String, String>) {
+    entry.fields.retain(|name, _| !name.starts_with(ATTRIBUTE_PREFIX));
+    for (name, value) in attributes {
+        entry.fields.insert(format!("{}{}", ATTRIBUTE_PREFIX, name), value.clone());
+    }
+}
+
+/// Creates a random id for an item, which is also the last part of its object path
+fn new_id() -> Result<String> {
+    let mut random = [0u8; 8];
+    SystemRandom::new().fill(&mut random)?;
+    Ok(random.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+fn entry_name(id: &str) -> String {
+    format!("{}/{}", FOLDER, id)
+}
+
+fn item_path(id: &str) -> String {
+    format!("{}/{}", COLLECTION_PATH, id)
+}
+
+fn object_path(path: &str) -> fdo::Result<OwnedObjectPath> {
+    ObjectPath::try_from(path)
+        .map(OwnedObjectPath::from)
+        .map_err(|e| fdo::Error::Failed(e.to_string()))
+}
+
+fn lock(vault: &Shared) -> fdo::Result<MutexGuard<'_, Box<dyn Vault>>> {
+    vault.lock().map_err(|_| fdo::Error::Failed("The vault is unavailable after an earlier failure".to_string()))
+}
+
+fn failed(e: Error) -> fdo::Error {
+    fdo::Error::Failed(e.to_string())
+}
+
+fn bus_error(e: zbus::Error) -> Error {
+    Error::SecretService { message: e.to_string() }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use crate::crypto::Kdf;
+    use crate::secret_service::{attributes, search, store};
+    use crate::vault::{self, Backend};
+
+    #[test]
+    fn items_are_stored_and_found_by_attributes() {
+        let yap_test = String::from(".yap_test_secret_service");
+        let mut vault = vault::create(Some(yap_test.clone()), "asdf", Kdf::default(), Backend::default(), None).unwrap();
+
+        let item = HashMap::from([("service".to_string(), "mail".to_string()), ("user".to_string(), "alice".to_string())]);
+        let (id, created) = store(vault.as_mut(), "Mail", &item, "s3cret".to_string(), false).unwrap();
+        assert!(created);
+        let entry = vault.get_entry(format!("secret-service/{}", id).as_str()).unwrap();
+        assert_eq!(entry.password, "s3cret");
+        assert_eq!(attributes(&entry), item);
+
+        let query = HashMap::from([("service".to_string(), "mail".to_string())]);
+        assert_eq!(search(vault.as_ref(), &query).unwrap(), vec![id.clone()]);
+        assert!(search(vault.as_ref(), &HashMap::from([("service".to_string(), "chat".to_string())])).unwrap().is_empty());
+
+        // Replacing only replaces an item with exactly the same attributes
+        assert_eq!(store(vault.as_mut(), "Mail", &item, "changed".to_string(), true).unwrap(), (id.clone(), false));
+        assert!(store(vault.as_mut(), "Mail", &query, "other".to_string(), true).unwrap().1);
+        assert_eq!(search(vault.as_ref(), &query).unwrap().len(), 2);
+
+        std::fs::remove_dir_all(yap_test).unwrap();
+    }
+}
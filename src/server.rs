@@ -0,0 +1,526 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use base64::Engine;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+use serde_json::{json, Value};
+
+use crate::crypto;
+use crate::entry::Entry;
+use crate::error::{Error, Result, EXIT_AUTH, EXIT_FAILURE, EXIT_NOT_FOUND};
+use crate::global::SERVE_TOKEN_VAR;
+use crate::token::{Token, Tokens};
+use crate::vault::{self, Vault};
+use crate::webdav::{self, decode};
+
+/// The largest request body the server accepts, which is plenty for any entry
+const MAX_BODY: usize = 1024 * 1024;
+
+/// The most headers the server reads from a request
+const MAX_HEADERS: usize = 100;
+
+/// The path entries are served under, as `/entries/<name>`
+const ENTRIES_PATH: &str = "/entries";
+
+/// The path the vault's encrypted files are served under, as `/files/<path>`
+const FILES_PATH: &str = "/files";
+
+/// Request is an HTTP request, with only the parts the server uses
+#[derive(Debug, Default)]
+struct Request {
+    method: String,
+    path: String,
+    query: String,
+    authorization: Option<String>,
+    if_match: Option<String>,
+    if_none_match: Option<String>,
+    body: Vec<u8>,
+}
+
+/// Response is an HTTP response, which is JSON unless it holds one of the vault's files
+#[derive(Debug)]
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    etag: Option<String>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn ok(body: Value) -> Response {
+        Response { status: 200, content_type: "application/json", etag: None, body: body.to_string().into_bytes() }
+    }
+
+    /// Responds with an error in the same form as `--format json` prints errors
+    fn error(status: u16, message: String, code: i32) -> Response {
+        Response { status, ..Response::ok(json!({ "error": message, "code": code })) }
+    }
+}
+
+/// Access is what an authenticated request is allowed to do
+enum Access {
+    /// The server's own token, which can do anything
+    Full,
+    /// A token created with `yap token create`, limited to what it was created for
+    Scoped(Token),
+}
+
+impl Access {
+    fn can_write(&self) -> bool {
+        !matches!(self, Access::Scoped(token) if token.read_only)
+    }
+
+    fn allows(&self, name: &str) -> bool {
+        match self {
+            Access::Full => true,
+            Access::Scoped(token) => token.allows(name),
+        }
+    }
+
+    /// Checks whether the encrypted files can be used, which hold every entry
+    fn allows_files(&self) -> bool {
+        !matches!(self, Access::Scoped(token) if !token.prefixes.is_empty())
+    }
+}
+
+/// Server serves a vault over HTTP, so other tools can use it without running yap. Every request
+/// must carry `Authorization: Bearer <token>`, with either the server's own token or one created
+/// for the vault with `yap token create`, see `token::Tokens`. Tokens which are read-only can only
+/// get entries, and tokens limited to prefixes can only use the entries under them. When the vault
+/// is unlocked, its entries are served as JSON:
+///
+/// - `GET /entries?prefix=<prefix>` lists the names of the entries
+/// - `GET /entries/<name>` gets an entry, recording that it was accessed
+/// - `PUT /entries/<name>` sets an entry from the entry given as JSON
+/// - `DELETE /entries/<name>` deletes an entry
+///
+/// The encrypted files of the vault are served as they are, for remote stores which decrypt them
+/// themselves, see `remote::RemoteVault`. These don't need the vault to be unlocked, so the server
+/// never sees their contents:
+///
+/// - `GET /files` lists every file with the SHA-256 hash of its contents
+/// - `GET /files/<path>` gets a file
+/// - `PUT /files/<path>` writes a file, only replacing a file with the hash given by `If-Match`,
+///   or only creating one when given `If-None-Match: *`
+/// - `DELETE /files/<path>` deletes a file, only if it has the hash given by `If-Match`
+pub struct Server<'a> {
+    dir: PathBuf,
+    vault: Option<&'a mut dyn Vault>,
+    token: Option<String>,
+}
+
+impl<'a> Server<'a> {
+    /// Creates a server for the vault in a directory, which serves its entries if it was unlocked.
+    /// Clients can authenticate with `token` as well as the tokens created for the vault.
+    pub fn new(dir: PathBuf, vault: Option<&'a mut dyn Vault>, token: Option<String>) -> Server<'a> {
+        Server { dir, vault, token }
+    }
+
+    /// Answers requests on the address until yap is stopped, one at a time. Connections are
+    /// served over TLS with the certificate chain and private key in the PEM files `tls`.
+    /// Without them, secrets would cross the network in plaintext, so plain HTTP is only served
+    /// on loopback addresses.
+    pub fn serve(&mut self, listen: &str, tls: Option<(&Path, &Path)>) -> Result<()> {
+        let listener = TcpListener::bind(listen)?;
+        let config = match tls {
+            Some((cert, key)) => Some(tls_config(cert, key)?),
+            None if listener.local_addr()?.ip().is_loopback() => None,
+            None => return Err(Error::InsecureServer { address: listen.to_string() }),
+        };
+
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else { continue };
+            // A client which stops sending shouldn't hold up everyone else
+            if stream.set_read_timeout(Some(Duration::from_secs(10))).is_err() {
+                continue;
+            }
+
+            // Errors only affect the connection they happened on, so they are dropped along with it
+            let _ = match &config {
+                Some(config) => ServerConnection::new(config.clone()).map_err(server_error).and_then(|connection| {
+                    let mut stream = StreamOwned::new(connection, stream);
+                    self.respond(&mut stream)?;
+                    stream.conn.send_close_notify();
+                    Ok(stream.flush()?)
+                }),
+                None => self.respond(&mut &stream),
+            };
+        }
+        Ok(())
+    }
+
+    /// Reads a request from a connection and writes the response. Each connection carries one
+    /// request.
+    fn respond<S: Read + Write>(&mut self, stream: &mut S) -> Result<()> {
+        let response = match read_request(&mut BufReader::new(&mut *stream)) {
+            Ok(request) => self.handle(&request),
+            Err(e) => Response::error(400, e.to_string(), e.code()),
+        };
+
+        let mut head = format!("HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+            response.status, reason(response.status), response.content_type, response.body.len());
+        if let Some(etag) = response.etag {
+            head.push_str(format!("ETag: \"{}\"\r\n", etag).as_str());
+        }
+        head.push_str("\r\n");
+        stream.write_all([head.as_bytes(), response.body.as_slice()].concat().as_slice())?;
+        Ok(stream.flush()?)
+    }
+
+    /// Answers an authenticated request
+    fn handle(&mut self, request: &Request) -> Response {
+        let access = match self.authenticate(request) {
+            Ok(Some(access)) => access,
+            Ok(None) => return Response::error(401, "Missing, invalid, or expired bearer token".to_string(), EXIT_AUTH),
+            Err(e) => return Response::error(500, e.to_string(), e.code()),
+        };
+
+        let result = if let Some(name) = subpath(request.path.as_str(), ENTRIES_PATH) {
+            self.handle_entries(request, name, &access)
+        } else if let Some(name) = subpath(request.path.as_str(), FILES_PATH) {
+            self.handle_files(request, name, &access)
+        } else {
+            return Response::error(404, format!("No such endpoint {}", request.path), EXIT_NOT_FOUND);
+        };
+
+        result.unwrap_or_else(|e| {
+            let status = match &e {
+                e if e.code() == EXIT_NOT_FOUND => 404,
+                Error::BadKeyName { .. } => 400,
+                Error::RemoteChanged { .. } => 412,
+                _ => 500,
+            };
+            Response::error(status, e.to_string(), e.code())
+        })
+    }
+
+    /// Finds what the bearer token of a request allows, or None if it isn't a valid token. Tokens
+    /// are read for every request, so revoked tokens stop working straight away.
+    fn authenticate(&self, request: &Request) -> Result<Option<Access>> {
+        let given = request.authorization.as_deref().and_then(|auth| auth.strip_prefix("Bearer ")).unwrap_or_default();
+        if let Some(token) = &self.token {
+            if ring::constant_time::verify_slices_are_equal(given.as_bytes(), token.as_bytes()).is_ok() {
+                return Ok(Some(Access::Full));
+            }
+        }
+
+        let tokens = Tokens::read(self.dir.as_path())?;
+        Ok(tokens.find(given).filter(|token| !token.is_expired()).cloned().map(Access::Scoped))
+    }
+
+    /// Answers a request for the entries of the vault, or for the entry with the given name
+    fn handle_entries(&mut self, request: &Request, name: Option<String>, access: &Access) -> Result<Response> {
+        let Some(vault) = self.vault.as_mut() else {
+            return Ok(Response::error(403, "The vault is locked, only its encrypted files are served".to_string(), EXIT_AUTH));
+        };
+        // Names are checked before the token's prefixes, so `..` can't escape the folders a token
+        // is limited to
+        if let Some(name) = name.as_deref().filter(|name| vault::validate_key(name).is_err()) {
+            return Ok(Response::error(400, format!("Invalid entry name {}", name), EXIT_FAILURE));
+        }
+        if request.method != "GET" && !access.can_write() {
+            return Ok(forbidden("The token is read-only"));
+        }
+        if let Some(name) = name.as_deref().filter(|name| !access.allows(name)) {
+            return Ok(forbidden(format!("The token can't use {}", name).as_str()));
+        }
+
+        let body = match (request.method.as_str(), name) {
+            ("GET", None) => {
+                let prefix = request.query.split('&')
+                    .find_map(|param| param.strip_prefix("prefix="))
+                    .map(decode)
+                    .unwrap_or_default();
+                let names: Vec<String> = vault.list_keys(prefix.as_str())?.into_iter()
+                    .filter(|name| access.allows(name))
+                    .collect();
+                json!(names)
+            }
+            ("GET", Some(name)) => json!(vault.access_entry(name.as_str())?),
+            ("PUT", Some(name)) => match serde_json::from_slice::<Entry>(request.body.as_slice()) {
+                Ok(entry) => {
+                    vault.set_entry(name.as_str(), &entry)?;
+                    json!({ "message": "Successfully saved password" })
+                }
+                Err(e) => return Ok(Response::error(400, format!("Invalid entry: {}", e), EXIT_FAILURE)),
+            },
+            ("DELETE", Some(name)) => {
+                vault.delete_key(name.as_str())?;
+                json!({ "message": "Successfully deleted password" })
+            }
+            (method, _) => return Ok(not_allowed(method, request)),
+        };
+        Ok(Response::ok(body))
+    }
+
+    /// Answers a request for the list of the vault's files, or for the file at the given path.
+    /// Only the files which would be synced are served, see `webdav::is_synced`.
+    fn handle_files(&mut self, request: &Request, name: Option<String>, access: &Access) -> Result<Response> {
+        if !access.allows_files() {
+            return Ok(forbidden("The token is limited to prefixes, so it can't use the vault's files"));
+        }
+        if request.method != "GET" && !access.can_write() {
+            return Ok(forbidden("The token is read-only"));
+        }
+        let Some(name) = name else {
+            return match request.method.as_str() {
+                "GET" => Ok(Response::ok(json!(webdav::local_files(self.dir.as_path())?))),
+                method => Ok(not_allowed(method, request)),
+            };
+        };
+
+        let valid = name.split('/').all(|part| !part.is_empty() && part != "." && part != "..") && !name.contains('\\');
+        if !valid || !webdav::is_synced(name.as_str()) {
+            return Ok(Response::error(400, format!("Invalid file {}", name), EXIT_FAILURE));
+        }
+
+        let p = self.dir.join(name.as_str());
+        let current = match std::fs::read(p.as_path()) {
+            Ok(contents) => Some(contents),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+        let hash = current.as_deref().map(webdav::hash);
+
+        // The file must be as the client last saw it, so changes from other clients aren't lost
+        let if_match = request.if_match.as_deref().map(|etag| etag.trim_matches('"'));
+        let unchanged = match (if_match, request.if_none_match.as_deref()) {
+            (Some(expected), _) => hash.as_deref() == Some(expected),
+            (None, Some("*")) => hash.is_none(),
+            _ => true,
+        };
+
+        match request.method.as_str() {
+            "GET" => match current {
+                Some(contents) => Ok(Response { status: 200, content_type: "application/octet-stream", etag: hash, body: contents }),
+                None => Err(Error::PasswordNotFound { name }),
+            },
+            "PUT" if !unchanged => Err(Error::RemoteChanged { name }),
+            "PUT" => {
+                if let Some(parent) = p.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                vault::atomic_write(p.as_path(), request.body.as_slice())?;
+                Ok(Response { etag: Some(webdav::hash(request.body.as_slice())), ..Response::ok(json!({ "message": "Saved file" })) })
+            }
+            "DELETE" if current.is_none() => Err(Error::PasswordNotFound { name }),
+            "DELETE" if !unchanged => Err(Error::RemoteChanged { name }),
+            "DELETE" => {
+                std::fs::remove_file(p.as_path())?;
+                vault::remove_empty_parents(self.dir.as_path(), p.as_path())?;
+                Ok(Response::ok(json!({ "message": "Deleted file" })))
+            }
+            method => Ok(not_allowed(method, request)),
+        }
+    }
+}
+
+/// Returns the token clients authenticate with from the YAP_SERVE_TOKEN variable, if it is set
+pub fn configured_token() -> Option<String> {
+    std::env::var(SERVE_TOKEN_VAR).ok().filter(|token| !token.is_empty())
+}
+
+/// Creates a random token for authenticating with the server
+pub fn new_token() -> Result<String> {
+    let token = crypto::new_key()?;
+    Ok(base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(token.as_slice()))
+}
+
+/// Loads the TLS certificate chain and private key from PEM files
+fn tls_config(cert: &Path, key: &Path) -> Result<Arc<ServerConfig>> {
+    let certs = CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<std::result::Result<Vec<_>, _>>())
+        .map_err(|e| Error::Server { message: format!("unable to read {}: {}", cert.display(), e) })?;
+    let key = PrivateKeyDer::from_pem_file(key)
+        .map_err(|e| Error::Server { message: format!("unable to read {}: {}", key.display(), e) })?;
+
+    let config = ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(server_error)?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(server_error)?;
+    Ok(Arc::new(config))
+}
+
+/// Reads the request line, headers, and body of a request
+fn read_request(reader: &mut impl BufRead) -> Result<Request> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target), Some(_)) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(Error::Server { message: "malformed request line".to_string() });
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let mut request = Request { method: method.to_string(), path: path.to_string(), query: query.to_string(), ..Request::default() };
+
+    let mut length = 0;
+    for _ in 0..MAX_HEADERS {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            if length > MAX_BODY {
+                return Err(Error::Server { message: "request body is too large".to_string() });
+            }
+            request.body = vec![0; length];
+            reader.read_exact(request.body.as_mut_slice())?;
+            return Ok(request);
+        }
+
+        let Some((name, value)) = line.split_once(':') else { continue };
+        let value = value.trim().to_string();
+        match name.trim().to_lowercase().as_str() {
+            "content-length" => {
+                length = value.parse().map_err(|_| Error::Server { message: "malformed Content-Length".to_string() })?
+            }
+            "authorization" => request.authorization = Some(value),
+            "if-match" => request.if_match = Some(value),
+            "if-none-match" => request.if_none_match = Some(value),
+            _ => {}
+        }
+    }
+    Err(Error::Server { message: "too many headers".to_string() })
+}
+
+/// Matches a request path against an endpoint, returning Some(None) for the endpoint itself and
+/// Some(Some(name)) for the decoded name of something under it
+fn subpath(path: &str, endpoint: &str) -> Option<Option<String>> {
+    match path.strip_prefix(endpoint)? {
+        "" | "/" => Some(None),
+        name => name.strip_prefix('/').map(|name| Some(decode(name))),
+    }
+}
+
+fn forbidden(message: &str) -> Response {
+    Response::error(403, message.to_string(), EXIT_AUTH)
+}
+
+fn not_allowed(method: &str, request: &Request) -> Response {
+    Response::error(405, format!("{} is not allowed on {}", method, request.path), EXIT_FAILURE)
+}
+
+/// Returns the reason phrase for the status codes the server uses
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        412 => "Precondition Failed",
+        _ => "Internal Server Error",
+    }
+}
+
+fn server_error(e: rustls::Error) -> Error {
+    Error::Server { message: e.to_string() }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use serde_json::{json, Value};
+
+    use crate::crypto::Kdf;
+    use crate::server::{read_request, Request, Server};
+    use crate::token;
+    use crate::vault::{self, Backend};
+
+    #[test]
+    fn serves_entries_and_files_to_authenticated_requests() {
+        let yap_test = String::from(".yap_test_server");
+        let mut vault = vault::create(Some(yap_test.clone()), "asdf", Kdf::default(), Backend::default(), None).unwrap();
+        vault.set_key("email", "hunter2".to_string()).unwrap();
+        let mut server = Server::new(yap_test.clone().into(), Some(vault.as_mut()), Some("token".to_string()));
+
+        let body = r#"{"kind":"login","password":"s3cret"}"#;
+        let raw = format!("PUT /entries/work%2Fgithub HTTP/1.1\r\nAuthorization: Bearer token\r\nContent-Length: {}\r\n\r\n{}", body.len(), body);
+        let request = read_request(&mut raw.as_bytes()).unwrap();
+        assert_eq!(request.path, "/entries/work%2Fgithub");
+        assert_eq!(server.handle(&request).status, 200);
+
+        let request = |method: &str, path: &str, token: &str| Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            query: "prefix=work".to_string(),
+            authorization: Some(format!("Bearer {}", token)),
+            ..Request::default()
+        };
+        let json = |body: Vec<u8>| serde_json::from_slice::<Value>(body.as_slice()).unwrap();
+        assert_eq!(server.handle(&request("GET", "/entries", "wrong")).status, 401);
+        assert_eq!(json(server.handle(&request("GET", "/entries", "token")).body), json!(["work/github"]));
+        assert_eq!(json(server.handle(&request("GET", "/entries/work/github", "token")).body)["password"], "s3cret");
+
+        assert_eq!(server.handle(&request("DELETE", "/entries/email", "token")).status, 200);
+        assert_eq!(server.handle(&request("GET", "/entries/email", "token")).status, 404);
+        assert_eq!(server.handle(&request("PUT", "/entries/email", "token")).status, 400);
+        assert_eq!(server.handle(&request("POST", "/entries", "token")).status, 405);
+
+        // Files are served as they are, and are only replaced if the client saw the latest version
+        let files = json(server.handle(&request("GET", "/files", "token")).body);
+        let hash = files["work/github"].as_str().unwrap().to_string();
+        assert!(files.get(".header.yaml").is_some());
+        let contents = server.handle(&request("GET", "/files/work/github", "token")).body;
+        assert!(!String::from_utf8_lossy(contents.as_slice()).contains("s3cret"));
+
+        let put = |if_match: Option<&str>| Request { if_match: if_match.map(String::from), body: contents.clone(), ..request("PUT", "/files/work/github", "token") };
+        assert_eq!(server.handle(&put(Some("stale"))).status, 412);
+        assert_eq!(server.handle(&put(Some(hash.as_str()))).status, 200);
+        assert_eq!(server.handle(&request("GET", "/files/config.yaml", "token")).status, 400);
+        assert_eq!(server.handle(&request("GET", "/files/../secrets", "token")).status, 400);
+
+        // Created tokens are limited to what they were created for
+        let read_only = token::create(yap_test.as_ref(), "read-only", true, vec!["work/".to_string()], None).unwrap();
+        assert_eq!(json(server.handle(&request("GET", "/entries", read_only.as_str())).body), json!(["work/github"]));
+        assert_eq!(server.handle(&request("GET", "/entries/work/github", read_only.as_str())).status, 200);
+        assert_eq!(server.handle(&request("DELETE", "/entries/work/github", read_only.as_str())).status, 403);
+        assert_eq!(server.handle(&request("GET", "/files", read_only.as_str())).status, 403);
+        token::revoke(yap_test.as_ref(), "read-only").unwrap();
+        assert_eq!(server.handle(&request("GET", "/entries/work/github", read_only.as_str())).status, 401);
+
+        let mut locked = Server::new(yap_test.clone().into(), None, Some("token".to_string()));
+        assert_eq!(locked.handle(&request("GET", "/entries", "token")).status, 403);
+        assert_eq!(locked.handle(&request("GET", "/files/work/github", "token")).status, 200);
+
+        std::fs::remove_dir_all(yap_test).unwrap();
+    }
+
+    #[test]
+    fn names_cant_escape_scoped_tokens() {
+        let yap_test = String::from(".yap_test_server_escape");
+        let mut vault = vault::create(Some(yap_test.clone()), "asdf", Kdf::default(), Backend::default(), None).unwrap();
+        vault.set_key("bank", "hunter2".to_string()).unwrap();
+        vault.set_key("work/email", "password".to_string()).unwrap();
+        let mut server = Server::new(yap_test.clone().into(), Some(vault.as_mut()), None);
+        let work = token::create(yap_test.as_ref(), "work", false, vec!["work/".to_string()], None).unwrap();
+
+        let request = |method: &str, path: &str| Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            authorization: Some(format!("Bearer {}", work)),
+            body: br#"{"kind":"login","password":"stolen"}"#.to_vec(),
+            ..Request::default()
+        };
+        for path in ["/entries/work%2F..%2Fbank", "/entries/work%2F..%2F.header.yaml", "/entries/work/../bank"] {
+            for method in ["GET", "PUT", "DELETE"] {
+                assert_eq!(server.handle(&request(method, path)).status, 400, "{} {}", method, path);
+            }
+        }
+        assert_eq!(server.handle(&request("GET", "/entries/workshop")).status, 403);
+        assert_eq!(server.handle(&request("GET", "/entries/work%2Femail")).status, 200);
+
+        drop(server);
+        assert_eq!(vault.get_key("bank").unwrap(), "hunter2");
+        assert!(Path::new(yap_test.as_str()).join(".header.yaml").exists());
+
+        std::fs::remove_dir_all(yap_test).unwrap();
+    }
+}
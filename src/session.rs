@@ -0,0 +1,221 @@
+use std::fmt::{Display, Formatter};
+use std::time::{Duration, SystemTime};
+
+use zeroize::Zeroizing;
+
+use crate::agent;
+use crate::config;
+use crate::crypto::KEY_LEN;
+use crate::error::{Error, Result};
+use crate::header::VaultHeader;
+use crate::keychain;
+use crate::prompt;
+use crate::share;
+use crate::vault::{self, Vault};
+
+/// SessionStore is where the keys of unlocked vaults are cached between commands, chosen by the
+/// `session` setting.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SessionStore {
+    /// Keys are held in memory by the agent, see `yap agent`
+    Agent,
+    /// Keys are stored in the platform keychain until the vault is locked, so a session lasts as
+    /// long as the OS login session. This needs yap to be built with the keyring feature.
+    Keychain,
+    /// Keys are never cached, so every command asks for the master password
+    None,
+}
+
+impl SessionStore {
+    /// parse creates a SessionStore from its name, as used in the `session` config setting. If
+    /// the name is not known, then a None option is returned.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "agent" => Some(SessionStore::Agent),
+            "keychain" => Some(SessionStore::Keychain),
+            "none" => Some(SessionStore::None),
+            _ => None,
+        }
+    }
+
+    /// Returns the name of the SessionStore, as used in the `session` config setting
+    pub fn name(&self) -> &'static str {
+        match self {
+            SessionStore::Agent => "agent",
+            SessionStore::Keychain => "keychain",
+            SessionStore::None => "none",
+        }
+    }
+}
+
+/// SessionState is whether the vault in a store is unlocked, and for how much longer
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SessionState {
+    Locked,
+    /// The vault is unlocked, and is locked again in the given number of seconds unless it is
+    /// used. None means that it stays unlocked until it is locked.
+    Unlocked(Option<u64>),
+}
+
+impl Display for SessionState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SessionState::Locked => write!(f, "Locked"),
+            SessionState::Unlocked(Some(secs)) => write!(f, "Unlocked, locks in {}m {}s", secs / 60, secs % 60),
+            SessionState::Unlocked(None) => write!(f, "Unlocked until locked"),
+        }
+    }
+}
+
+/// Opens the vault in the given store. If the vault has a session, its cached key is used, and
+/// otherwise the master password is read. When the agent is running, the key derived from the
+/// password starts a session so the next command doesn't ask again. Members of a team vault open
+/// it with their share key instead, see `vault::load_as_member`.
+pub fn load_vault(store: Option<String>, non_interactive: bool) -> Result<Box<dyn Vault>> {
+    let (session, timeout) = settings()?;
+    let id = session_id(store.clone());
+
+    if let Some((vault, salt)) = id.as_ref() {
+        if let Some(key) = cached_key(session, timeout, vault, salt) {
+            return vault::load_with_key(store, key);
+        }
+    }
+
+    if let Some(vault) = vault::load_as_member(store.clone(), share::key_path()?.as_path())? {
+        return Ok(vault);
+    }
+
+    let pass = prompt::master_password(non_interactive, false)?;
+    let key = vault::derive_key(store.clone(), pass.as_str())?;
+    let vault = vault::load_with_key(store, key.clone())?;
+
+    if let (SessionStore::Agent, Some((id, salt))) = (session, id) {
+        if check_key(vault.as_ref()).is_ok() {
+            // The agent not running is not an error, the session just isn't started
+            let _ = agent::put(id, salt, key.to_vec(), timeout.as_secs());
+        }
+    }
+    Ok(vault)
+}
+
+/// Unlocks the vault in the given store, starting a session which ends once the vault goes
+/// unused for the lock_timeout setting. With the agent, the session also ends after the
+/// agent_ttl setting, and the agent is started if it isn't already running. The master password
+/// is checked against the vault before the session is started.
+pub fn unlock(store: Option<String>, non_interactive: bool) -> Result<()> {
+    let (session, timeout) = settings()?;
+    if session == SessionStore::None {
+        return Err(Error::SessionsDisabled);
+    }
+
+    let (id, salt) = session_id(store.clone())
+        .ok_or_else(|| Error::MissingHeader { path: vault::get_path_or_default(store.clone()).unwrap_or_default().display().to_string() })?;
+
+    let pass = prompt::master_password(non_interactive, false)?;
+    let key = vault::derive_key(store.clone(), pass.as_str())?;
+    check_key(vault::load_with_key(store, key.clone())?.as_ref())?;
+
+    if session == SessionStore::Keychain {
+        return keychain::put(id.as_str(), salt.as_slice(), &key);
+    }
+    if let Err(Error::AgentNotRunning) = agent::status() {
+        agent::start(config::read_or_default()?.agent_ttl())?;
+    }
+    agent::put(id, salt, key.to_vec(), timeout.as_secs())
+}
+
+/// Locks the vault in the given store, ending its session, or every vault if `all` is set.
+/// Nothing needs to be done if there is no agent running. The keychain can't list the vaults it
+/// holds keys for, so only the vault in the given store is removed from it.
+pub fn lock(store: Option<String>, all: bool) -> Result<()> {
+    let id = session_id(store).map(|(id, _)| id);
+    if settings()?.0 == SessionStore::Keychain {
+        if let Some(id) = id.as_ref() {
+            keychain::delete(id.as_str())?;
+        }
+    }
+
+    let vault = if all { None } else { id };
+    if !all && vault.is_none() {
+        return Ok(());
+    }
+    match agent::lock(vault) {
+        Err(Error::AgentNotRunning) => Ok(()),
+        result => result,
+    }
+}
+
+/// Returns whether the vault in the given store is unlocked, without using its session
+pub fn status(store: Option<String>) -> Result<SessionState> {
+    let (session, timeout) = settings()?;
+    let Some((id, salt)) = session_id(store) else { return Ok(SessionState::Locked) };
+
+    let remaining = match session {
+        SessionStore::Agent => match agent::status() {
+            Ok(vaults) => vaults.into_iter().find(|(vault, _)| *vault == id).map(|(_, secs)| Some(secs)),
+            Err(Error::AgentNotRunning) => None,
+            Err(e) => return Err(e),
+        },
+        SessionStore::Keychain => keychain::get(id.as_str(), salt.as_slice())?
+            .and_then(|(_, used)| remaining(timeout, used)),
+        SessionStore::None => None,
+    };
+    Ok(remaining.map_or(SessionState::Locked, SessionState::Unlocked))
+}
+
+/// Returns the key cached by the session store for a vault, if there is one. A store which
+/// can't be reached, such as the agent not running, has no keys cached. The agent forgets keys
+/// which go unused itself, while keys in the keychain are removed here.
+fn cached_key(session: SessionStore, timeout: Duration, vault: &str, salt: &[u8]) -> Option<Zeroizing<[u8; KEY_LEN]>> {
+    match session {
+        SessionStore::Agent => {
+            let key = agent::get(vault.to_string(), salt.to_vec()).ok()??;
+            Some(Zeroizing::new(key.as_slice().try_into().ok()?))
+        }
+        SessionStore::Keychain => {
+            let (key, used) = keychain::get(vault, salt).ok()??;
+            if remaining(timeout, used).is_none() {
+                let _ = keychain::delete(vault);
+                return None;
+            }
+            // Storing the key again records that it was used
+            let _ = keychain::put(vault, salt, &key);
+            Some(key)
+        }
+        SessionStore::None => None,
+    }
+}
+
+/// Returns the seconds left before a key last used at `used` times out, which is Some(None) if
+/// keys never time out, and None if it has already timed out
+fn remaining(timeout: Duration, used: SystemTime) -> Option<Option<u64>> {
+    if timeout.is_zero() {
+        return Some(None);
+    }
+    let elapsed = used.elapsed().unwrap_or_default();
+    timeout.checked_sub(elapsed).filter(|left| !left.is_zero()).map(|left| Some(left.as_secs()))
+}
+
+/// Returns the session store chosen in the config, and how long a vault can go unused before its
+/// session is locked. Zero means that sessions are never locked for inactivity.
+fn settings() -> Result<(SessionStore, Duration)> {
+    let config = config::read_or_default()?;
+    Ok((config.session(), Duration::from_secs(config.lock_timeout() * 60)))
+}
+
+/// Identifies the vault in the given store for its session, by its full path and salt. A vault
+/// whose salt changed, such as after a rekey, needs a new session.
+fn session_id(store: Option<String>) -> Option<(String, Vec<u8>)> {
+    let vault_dir = vault::get_path_or_default(store).ok()?.canonicalize().ok()?;
+    let header = VaultHeader::read(vault_dir.as_path()).ok()?;
+    Some((vault_dir.display().to_string(), header.salt))
+}
+
+/// Checks that the vault's key decrypts its entries, by reading one of them. An empty vault has
+/// nothing to check.
+fn check_key(vault: &dyn Vault) -> Result<()> {
+    if let Some(key) = vault.list_keys("")?.first() {
+        vault.get_entry(key.as_str())?;
+    }
+    Ok(())
+}
@@ -0,0 +1,115 @@
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use age::secrecy::ExposeSecret;
+use age::x25519::{Identity, Recipient};
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+use crate::editor;
+use crate::entry::{Entry, Metadata};
+use crate::error::{Error, Result};
+use crate::global::SHARE_KEY_FILE;
+use crate::vault::Vault;
+
+/// Bundle is an entry shared with someone else, along with the name it was shared under. The
+/// bundle is encrypted as a whole, so the name is as protected as the entry.
+#[derive(Serialize, Deserialize)]
+struct Bundle {
+    name: String,
+    entry: Entry,
+}
+
+/// Returns the path of the key which shared entries are received with. It is kept in the home
+/// directory rather than the vault, so it is never synced anywhere.
+pub fn key_path() -> Result<PathBuf> {
+    Ok(home::home_dir().ok_or(Error::NoHomeDir)?.join(SHARE_KEY_FILE))
+}
+
+/// Returns the public key which others share entries with, in age's `age1...` format. The key
+/// pair is created the first time, with the private key only readable by the user.
+pub fn public_key(p: &Path) -> Result<String> {
+    if !p.exists() {
+        let identity = Identity::generate();
+        editor::write_private(p, identity.to_string().expose_secret().as_bytes())?;
+    }
+    Ok(read_identity(p)?.to_public().to_string())
+}
+
+/// Reads a private key, which may also be a key file written by age-keygen
+pub(crate) fn read_identity(p: &Path) -> Result<Identity> {
+    if !p.exists() {
+        return Err(Error::BadShareKey { path: p.display().to_string() });
+    }
+    let contents = Zeroizing::new(std::fs::read_to_string(p)?);
+    contents.lines()
+        .map(str::trim)
+        .find(|line| line.starts_with("AGE-SECRET-KEY-"))
+        .and_then(|line| Identity::from_str(line).ok())
+        .ok_or_else(|| Error::BadShareKey { path: p.display().to_string() })
+}
+
+/// Parses the public key to share with, given either as an `age1...` key or the path of a file
+/// holding one, such as a .pub file or a key file written by age-keygen.
+pub fn recipient(to: &str) -> Result<Recipient> {
+    let contents = match to.starts_with("age1") {
+        true => to.to_string(),
+        false => std::fs::read_to_string(to)?,
+    };
+    contents.split_whitespace()
+        .find(|word| word.starts_with("age1"))
+        .and_then(|word| Recipient::from_str(word).ok())
+        .ok_or_else(|| Error::BadShareKey { path: to.to_string() })
+}
+
+/// Encrypts an entry so only the holder of the recipient's private key can receive it, returning
+/// an armored age file. The entry's timestamps are left out, since they belong to this vault.
+pub fn share(vault: &mut dyn Vault, name: &str, recipient: &Recipient) -> Result<String> {
+    let entry = Entry { metadata: Metadata::default(), ..vault.access_entry(name)? };
+    let bundle = Zeroizing::new(serde_yaml::to_string(&Bundle { name: name.to_string(), entry })?);
+    age::encrypt_and_armor(recipient, bundle.as_bytes()).map_err(|e| Error::Share { message: e.to_string() })
+}
+
+/// Decrypts a shared entry with the private key, returning the name it was shared under and the
+/// entry itself
+pub fn receive(key: &Path, shared: &[u8]) -> Result<(String, Entry)> {
+    let identity = read_identity(key)?;
+    let bundle = Zeroizing::new(age::decrypt(&identity, shared).map_err(|e| Error::Share { message: e.to_string() })?);
+    let bundle: Bundle = serde_yaml::from_slice(bundle.as_slice())?;
+    Ok((bundle.name, bundle.entry))
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use crate::crypto::Kdf;
+    use crate::entry::Entry;
+    use crate::share;
+    use crate::vault::{self, Backend};
+
+    #[test]
+    fn shared_entries_are_received_with_the_private_key() {
+        let yap_test = String::from(".yap_test_share");
+        let mut vault = vault::create(Some(yap_test.clone()), "asdf", Kdf::default(), Backend::default(), None).unwrap();
+        let entry = Entry { username: Some("alice".to_string()), ..Entry::new("s3cret".to_string()) };
+        vault.set_entry("work/vpn", &entry).unwrap();
+
+        let key = Path::new(yap_test.as_str()).join("share.key");
+        let public = share::public_key(key.as_path()).unwrap();
+        assert_eq!(share::public_key(key.as_path()).unwrap(), public);
+
+        let shared = share::share(vault.as_mut(), "work/vpn", &share::recipient(public.as_str()).unwrap()).unwrap();
+        assert!(shared.starts_with("-----BEGIN AGE ENCRYPTED FILE-----") && !shared.contains("s3cret"));
+        let (name, received) = share::receive(key.as_path(), shared.as_bytes()).unwrap();
+        assert_eq!(name, "work/vpn");
+        assert_eq!((received.password.as_str(), received.username.as_deref()), ("s3cret", Some("alice")));
+
+        let other = Path::new(yap_test.as_str()).join("other.key");
+        share::public_key(other.as_path()).unwrap();
+        assert!(share::receive(other.as_path(), shared.as_bytes()).is_err());
+        assert!(share::recipient("age1notakey").is_err());
+
+        std::fs::remove_dir_all(yap_test).unwrap();
+    }
+}
@@ -0,0 +1,372 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Display, Formatter};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use age::x25519::Recipient;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use ring::rand::SystemRandom;
+use ring::signature::{self, Ed25519KeyPair, KeyPair, UnparsedPublicKey};
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+use crate::activity::Activity;
+use crate::config::Configuration;
+use crate::crypto::Kdf;
+use crate::editor;
+use crate::entry::{Entry, Metadata};
+use crate::error::{Error, Result};
+use crate::global::SIGN_KEY_FILE;
+use crate::stats::Stats;
+use crate::vault::Vault;
+use crate::verify::{RepairReport, Report};
+
+/// Signature records who last wrote an entry, with an Ed25519 signature over the author's name
+/// and the entry. The entry's timestamps aren't signed, since reading an entry updates them, and
+/// neither is its name, so renaming an entry keeps its signature. Anyone who can open the vault
+/// can still write an entry, so a signature shows who wrote it rather than preventing changes.
+///
+/// The key is stored with the signature so an unknown author can be added to the trusted
+/// authors, but it is never trusted by itself: anyone can sign with their own key under any
+/// name, so signatures are checked against the authors setting instead, see `author`.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct Signature {
+    pub author: String,
+    /// The author's public key, in base64
+    pub key: String,
+    /// The signature, in base64
+    pub value: String,
+}
+
+/// Author describes who last wrote an entry, as far as its signature tells
+#[derive(PartialEq, Eq, Debug)]
+pub enum Author {
+    Unsigned,
+    /// The entry was signed with the key of a trusted author
+    Verified(String),
+    /// The entry was signed by an author who isn't trusted, so the name can't be checked
+    Untrusted(String),
+    /// The entry was changed after it was signed, or the signature was forged
+    Invalid(String),
+}
+
+impl Display for Author {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Author::Unsigned => write!(f, "-"),
+            Author::Verified(author) => write!(f, "{}", author),
+            Author::Untrusted(author) => write!(f, "{} (untrusted)", author),
+            Author::Invalid(author) => write!(f, "{} (bad signature)", author),
+        }
+    }
+}
+
+/// Returns the path of the key which entries are signed with. Like the share key, it is kept in
+/// the home directory rather than the vault.
+pub fn key_path() -> Result<PathBuf> {
+    Ok(home::home_dir().ok_or(Error::NoHomeDir)?.join(SIGN_KEY_FILE))
+}
+
+/// Returns the public key entries are signed with, in base64. The key pair is created the first
+/// time, with the private key only readable by the user.
+pub fn public_key(p: &Path) -> Result<String> {
+    if !p.exists() {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())?;
+        editor::write_private(p, STANDARD.encode(pkcs8.as_ref()).as_bytes())?;
+    }
+    Ok(STANDARD.encode(read_key_pair(p)?.public_key().as_ref()))
+}
+
+/// Reads the key pair written by `public_key`
+fn read_key_pair(p: &Path) -> Result<Ed25519KeyPair> {
+    let bad_key = || Error::BadSignKey { path: p.display().to_string() };
+    let contents = Zeroizing::new(std::fs::read_to_string(p).map_err(|_| bad_key())?);
+    let pkcs8 = Zeroizing::new(STANDARD.decode(contents.trim()).map_err(|_| bad_key())?);
+    Ed25519KeyPair::from_pkcs8(pkcs8.as_slice()).map_err(|_| bad_key())
+}
+
+/// Returns the bytes which are signed for an entry written by the author
+fn signed_bytes(author: &str, entry: &Entry) -> Result<Zeroizing<Vec<u8>>> {
+    let unsigned = Entry { metadata: Metadata::default(), signature: None, ..entry.clone() };
+    let mut bytes = Zeroizing::new(author.as_bytes().to_vec());
+    bytes.push(0);
+    bytes.extend_from_slice(unsigned.to_bytes()?.as_slice());
+    Ok(bytes)
+}
+
+/// Signs the entry as the author, replacing any previous signature
+fn sign(key_pair: &Ed25519KeyPair, author: &str, entry: &Entry) -> Result<Signature> {
+    let value = key_pair.sign(signed_bytes(author, entry)?.as_slice());
+    Ok(Signature {
+        author: author.to_string(),
+        key: STANDARD.encode(key_pair.public_key().as_ref()),
+        value: STANDARD.encode(value.as_ref()),
+    })
+}
+
+/// Returns the public keys of the authors whose signatures are trusted, by name: the authors
+/// setting, along with the user's own key when the sign_as setting is set.
+pub fn trusted_authors(config: &Configuration) -> Result<BTreeMap<String, String>> {
+    let mut trusted = config.authors().clone();
+    if let Some(author) = config.sign_as() {
+        if !trusted.contains_key(author) {
+            trusted.insert(author.to_string(), public_key(key_path()?.as_path())?);
+        }
+    }
+    Ok(trusted)
+}
+
+/// Checks that the signature was made over the entry with the public key, given in base64
+fn signed_with(entry: &Entry, signature: &Signature, key: &str) -> bool {
+    match (STANDARD.decode(key), STANDARD.decode(signature.value.as_str()), signed_bytes(signature.author.as_str(), entry)) {
+        (Ok(key), Ok(value), Ok(bytes)) => UnparsedPublicKey::new(&signature::ED25519, key).verify(bytes.as_slice(), value.as_slice()).is_ok(),
+        _ => false,
+    }
+}
+
+/// Checks the entry's signature against the trusted authors' keys, returning who last wrote it.
+/// An entry signed under a trusted author's name with any other key is invalid.
+pub fn author(entry: &Entry, trusted: &BTreeMap<String, String>) -> Author {
+    let Some(signature) = entry.signature.as_ref() else { return Author::Unsigned };
+
+    let author = signature.author.clone();
+    match trusted.get(author.as_str()) {
+        Some(key) if signed_with(entry, signature, key) => Author::Verified(author),
+        None if signed_with(entry, signature, signature.key.as_str()) => Author::Untrusted(author),
+        _ => Author::Invalid(author),
+    }
+}
+
+/// Checks the signature of the entry with the given name, returning its author, or None if it
+/// isn't signed by a trusted author. An entry whose signature doesn't match is an error.
+pub fn verify(name: &str, entry: &Entry, trusted: &BTreeMap<String, String>) -> Result<Option<String>> {
+    match author(entry, trusted) {
+        Author::Unsigned | Author::Untrusted(_) => Ok(None),
+        Author::Verified(author) => Ok(Some(author)),
+        Author::Invalid(author) => Err(Error::BadSignature { name: name.to_string(), author }),
+    }
+}
+
+/// Checks whether the entry is unchanged since it was signed, by the key stored with the
+/// signature. This doesn't tell who signed it, see `author`.
+pub(crate) fn intact(entry: &Entry) -> bool {
+    entry.signature.as_ref().is_none_or(|signature| signed_with(entry, signature, signature.key.as_str()))
+}
+
+/// SigningVault wraps a vault opened with the sign_as setting, signing every entry written to it
+/// with the user's key. Entries read from it are checked against the trusted authors, so a
+/// forged or tampered signature is an error, see `verify`.
+pub(crate) struct SigningVault {
+    vault: Box<dyn Vault>,
+    key_pair: Ed25519KeyPair,
+    author: String,
+    trusted: BTreeMap<String, String>,
+}
+
+impl SigningVault {
+    /// Wraps the vault, signing entries as the author with the key at `p`, which is created if
+    /// it doesn't exist yet, and trusting the signatures of the given authors
+    pub(crate) fn new(vault: Box<dyn Vault>, p: &Path, author: &str, trusted: BTreeMap<String, String>) -> Result<SigningVault> {
+        public_key(p)?;
+        Ok(SigningVault { vault, key_pair: read_key_pair(p)?, author: author.to_string(), trusted })
+    }
+}
+
+impl Vault for SigningVault {
+    fn has_key(&self, key: &str) -> bool {
+        self.vault.has_key(key)
+    }
+
+    fn get_entry(&self, key: &str) -> Result<Entry> {
+        let entry = self.vault.get_entry(key)?;
+        verify(key, &entry, &self.trusted)?;
+        Ok(entry)
+    }
+
+    fn access_entry(&mut self, key: &str) -> Result<Entry> {
+        let entry = self.vault.access_entry(key)?;
+        verify(key, &entry, &self.trusted)?;
+        Ok(entry)
+    }
+
+    fn set_entry(&mut self, key: &str, entry: &Entry) -> Result<()> {
+        let signature = sign(&self.key_pair, self.author.as_str(), entry)?;
+        self.vault.set_entry(key, &Entry { signature: Some(signature), ..entry.clone() })
+    }
+
+    fn set_entries(&mut self, entries: &[(String, Entry)]) -> Result<()> {
+        let signed = entries.iter()
+            .map(|(key, entry)| Ok((key.clone(), Entry { signature: Some(sign(&self.key_pair, self.author.as_str(), entry)?), ..entry.clone() })))
+            .collect::<Result<Vec<_>>>()?;
+        self.vault.set_entries(signed.as_slice())
+    }
+
+    fn delete_key(&mut self, key: &str) -> Result<()> {
+        self.vault.delete_key(key)
+    }
+
+    fn rename_key(&mut self, from: &str, to: &str, force: bool) -> Result<()> {
+        self.vault.rename_key(from, to, force)
+    }
+
+    fn list_keys(&self, prefix: &str) -> Result<Vec<String>> {
+        self.vault.list_keys(prefix)
+    }
+
+    fn attach(&mut self, key: &str, attachment: &str, reader: &mut dyn Read) -> Result<u64> {
+        self.vault.attach(key, attachment, reader)
+    }
+
+    fn read_attachment(&self, key: &str, attachment: &str, writer: &mut dyn Write) -> Result<u64> {
+        self.vault.read_attachment(key, attachment, writer)
+    }
+
+    fn list_attachments(&self, key: &str) -> Result<Vec<String>> {
+        self.vault.list_attachments(key)
+    }
+
+    fn remove_attachment(&mut self, key: &str, attachment: &str) -> Result<()> {
+        self.vault.remove_attachment(key, attachment)
+    }
+
+    fn history(&self, key: &str) -> Result<Vec<(u32, Metadata)>> {
+        self.vault.history(key)
+    }
+
+    fn restore(&mut self, key: &str, version: u32) -> Result<()> {
+        self.vault.restore(key, version)
+    }
+
+    fn list_trash(&self) -> Result<Vec<(String, i64)>> {
+        self.vault.list_trash()
+    }
+
+    fn restore_trash(&mut self, key: &str, force: bool) -> Result<()> {
+        self.vault.restore_trash(key, force)
+    }
+
+    fn empty_trash(&mut self, before: Option<i64>) -> Result<usize> {
+        self.vault.empty_trash(before)
+    }
+
+    fn rebuild_index(&mut self) -> Result<usize> {
+        self.vault.rebuild_index()
+    }
+
+    fn verify(&mut self, quarantine: bool) -> Result<Report> {
+        self.vault.verify(quarantine)
+    }
+
+    fn repair(&mut self, backup: Option<&Path>) -> Result<RepairReport> {
+        self.vault.repair(backup)
+    }
+
+    fn recover(&mut self, backup: &Path, key: &str) -> Result<()> {
+        self.vault.recover(backup, key)
+    }
+
+    fn activity(&self) -> Result<Vec<Activity>> {
+        self.vault.activity()
+    }
+
+    fn rekey(&mut self, new_pass: &str) -> Result<()> {
+        self.vault.rekey(new_pass)
+    }
+
+    fn set_kdf(&mut self, kdf: Kdf, pass: &str) -> Result<()> {
+        self.vault.set_kdf(kdf, pass)
+    }
+
+    fn add_member(&mut self, name: &str, recipient: &Recipient) -> Result<()> {
+        self.vault.add_member(name, recipient)
+    }
+
+    fn remove_member(&mut self, name: &str, pass: &str) -> Result<()> {
+        self.vault.remove_member(name, pass)
+    }
+
+    fn delete_folder(&mut self, folder: &str) -> Result<Vec<String>> {
+        self.vault.delete_folder(folder)
+    }
+
+    fn metadata(&self, key: &str) -> Result<Metadata> {
+        self.vault.metadata(key)
+    }
+
+    fn tags(&self, key: &str) -> Result<BTreeSet<String>> {
+        self.vault.tags(key)
+    }
+
+    fn stats(&self) -> Result<Stats> {
+        self.vault.stats()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+    use std::path::Path;
+
+    use crate::crypto::Kdf;
+    use crate::entry::Entry;
+    use crate::error::Error;
+    use crate::sign::{self, Author, Signature, SigningVault};
+    use crate::vault::{self, Backend, Vault};
+
+    #[test]
+    fn signed_entries_name_their_author() {
+        let yap_test = String::from(".yap_test_sign");
+        let mut vault = vault::create(Some(yap_test.clone()), "asdf", Kdf::default(), Backend::default(), None).unwrap();
+        vault.set_key("unsigned", "password".to_string()).unwrap();
+
+        let key = Path::new(yap_test.as_str()).join(".sign.key");
+        let trusted = BTreeMap::from([("alice".to_string(), sign::public_key(key.as_path()).unwrap())]);
+        let mut signing = SigningVault::new(vault, key.as_path(), "alice", trusted.clone()).unwrap();
+        assert_eq!(trusted["alice"].len(), 44);
+        signing.set_key("github", "password".to_string()).unwrap();
+        assert_eq!(sign::author(&signing.get_entry("unsigned").unwrap(), &trusted), Author::Unsigned);
+
+        // Reading an entry changes its timestamps, which aren't signed
+        let entry = signing.access_entry("github").unwrap();
+        assert_eq!(sign::verify("github", &signing.get_entry("github").unwrap(), &trusted).unwrap().as_deref(), Some("alice"));
+
+        let tampered = Entry { password: "stolen".to_string(), ..entry.clone() };
+        assert_eq!(sign::author(&tampered, &trusted), Author::Invalid("alice".to_string()));
+        assert!(matches!(sign::verify("github", &tampered, &trusted), Err(Error::BadSignature { .. })));
+
+        let signature = entry.signature.clone().map(|signature| Signature { author: "bob".to_string(), ..signature });
+        assert!(sign::verify("github", &Entry { signature, ..entry }, &trusted).is_err());
+
+        // Writing without signing drops the signature, which no longer matches
+        drop(signing);
+        let mut unsigned = vault::load(Some(yap_test.clone()), "asdf").unwrap();
+        unsigned.set_key("github", "changed".to_string()).unwrap();
+        assert_eq!(sign::author(&unsigned.get_entry("github").unwrap(), &trusted), Author::Unsigned);
+
+        std::fs::remove_dir_all(yap_test).unwrap();
+    }
+
+    #[test]
+    fn forged_signatures_are_rejected() {
+        let yap_test = String::from(".yap_test_sign_forged");
+        let vault = vault::create(Some(yap_test.clone()), "asdf", Kdf::default(), Backend::default(), None).unwrap();
+        let alice = Path::new(yap_test.as_str()).join(".alice.key");
+        let mallory = Path::new(yap_test.as_str()).join(".mallory.key");
+        let trusted = BTreeMap::from([("alice".to_string(), sign::public_key(alice.as_path()).unwrap())]);
+
+        // Mallory signs an entry with their own key, claiming to be alice
+        let mut forger = SigningVault::new(vault, mallory.as_path(), "alice", BTreeMap::new()).unwrap();
+        forger.set_key("github", "stolen".to_string()).unwrap();
+        let forged = forger.get_entry("github").unwrap();
+        assert_eq!(sign::author(&forged, &BTreeMap::new()), Author::Untrusted("alice".to_string()));
+        drop(forger);
+
+        assert_eq!(sign::author(&forged, &trusted), Author::Invalid("alice".to_string()));
+        let mut reader = SigningVault::new(vault::load(Some(yap_test.clone()), "asdf").unwrap(), alice.as_path(), "alice", trusted).unwrap();
+        assert!(matches!(reader.get_entry("github"), Err(Error::BadSignature { .. })));
+        assert!(matches!(reader.access_entry("github"), Err(Error::BadSignature { .. })));
+
+        std::fs::remove_dir_all(yap_test).unwrap();
+    }
+}
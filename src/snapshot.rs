@@ -0,0 +1,186 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::{Serialize, Deserialize};
+
+use crate::config;
+use crate::entry;
+use crate::error::{Error, Result};
+use crate::global::{HEADER_FILE, SNAPSHOTS_DIR};
+use crate::lock;
+use crate::vault;
+use crate::webdav;
+
+/// The directory in `.snapshots` holding the contents of snapshotted files, each named by the
+/// hash of its contents
+const OBJECTS_DIR: &str = "objects";
+
+/// Snapshot is the state of every file of a vault at one point in time, which the vault can be
+/// rolled back to. Files are stored once in the vault's `.snapshots/objects`, named by the hash of
+/// their contents, and a snapshot only names the contents of each file, so a snapshot costs no
+/// more than the files which changed since the last one. Files are stored as they are in the
+/// vault, still encrypted.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub struct Snapshot {
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// When the snapshot was taken, in seconds since the Unix epoch
+    pub taken: i64,
+    /// The hash of the contents of each file, by its path relative to the vault
+    pub files: BTreeMap<String, String>,
+}
+
+impl Snapshot {
+    /// Reads the snapshot with the given id of the vault in `vault_dir`
+    pub fn read(vault_dir: &Path, id: &str) -> Result<Snapshot> {
+        let p = vault_dir.join(SNAPSHOTS_DIR).join(format!("{}.yaml", id));
+        if id.is_empty() || id.contains(['/', '\\']) || !p.is_file() {
+            return Err(Error::SnapshotNotFound { id: id.to_string() });
+        }
+        Ok(serde_yaml::from_slice(std::fs::read(p)?.as_slice())?)
+    }
+}
+
+/// Lists the snapshots of the vault in `vault_dir`, oldest first
+pub fn list(vault_dir: &Path) -> Result<Vec<Snapshot>> {
+    let dir = vault_dir.join(SNAPSHOTS_DIR);
+    if !dir.is_dir() {
+        return Ok(vec![]);
+    }
+
+    let mut snapshots = vec![];
+    for file in std::fs::read_dir(dir.as_path())? {
+        let name = file?.file_name().to_string_lossy().to_string();
+        if let Some(id) = name.strip_suffix(".yaml").filter(|id| !id.starts_with('.')) {
+            snapshots.push(Snapshot::read(vault_dir, id)?);
+        }
+    }
+    snapshots.sort_by_key(|snapshot| snapshot.taken);
+    Ok(snapshots)
+}
+
+/// Takes a snapshot of the vault in `vault_dir`, with an optional label to tell it apart. Every
+/// file which is synced is included, see `webdav::is_synced`, so the header, entries, history,
+/// attachments, and trash are, and the index, lock file, and backups aren't.
+pub fn create(vault_dir: &Path, label: Option<&str>) -> Result<Snapshot> {
+    vault::recover_staging(vault_dir)?;
+    let _lock = lock::lock(vault_dir)?;
+    take(vault_dir, label)
+}
+
+fn take(vault_dir: &Path, label: Option<&str>) -> Result<Snapshot> {
+    if !vault_dir.join(HEADER_FILE).exists() {
+        return Err(Error::MissingHeader { path: vault_dir.display().to_string() });
+    }
+
+    let objects = vault_dir.join(SNAPSHOTS_DIR).join(OBJECTS_DIR);
+    std::fs::create_dir_all(objects.as_path())?;
+    let files = webdav::local_files(vault_dir)?;
+    for (name, hash) in files.iter() {
+        let object = objects.join(hash.as_str());
+        if !object.exists() {
+            vault::atomic_write(object.as_path(), std::fs::read(vault_dir.join(name.as_str()))?.as_slice())?;
+        }
+    }
+
+    // Snapshots taken within a second of each other are kept apart, and in the order they were
+    // taken
+    let newest = list(vault_dir)?.last().map(|snapshot| snapshot.taken + 1).unwrap_or_default();
+    let taken = entry::now().max(newest);
+    let label = label.map(str::trim).filter(|label| !label.is_empty()).map(String::from);
+    let snapshot = Snapshot { id: taken.to_string(), label, taken, files };
+    let p = vault_dir.join(SNAPSHOTS_DIR).join(format!("{}.yaml", snapshot.id));
+    vault::atomic_write(p.as_path(), serde_yaml::to_string(&snapshot)?.as_bytes())?;
+    Ok(snapshot)
+}
+
+/// Rolls the vault in `vault_dir` back to the snapshot with the given id, returning the snapshot
+/// of the vault as it was before, so the rollback can itself be rolled back. Every file of the
+/// snapshot is checked before anything is changed, then files which differ are replaced, and
+/// files which were created since are removed. The header is rolled back too, so the master
+/// password is the one the vault had when the snapshot was taken.
+pub fn rollback(vault_dir: &Path, id: &str) -> Result<Snapshot> {
+    let snapshot = Snapshot::read(vault_dir, id)?;
+    if config::read_or_default()?.with_vault(vault_dir)?.read_only() {
+        return Err(Error::ReadOnly);
+    }
+    vault::recover_staging(vault_dir)?;
+    let _lock = lock::lock(vault_dir)?;
+
+    let objects = vault_dir.join(SNAPSHOTS_DIR).join(OBJECTS_DIR);
+    let mut contents: BTreeMap<&str, PathBuf> = BTreeMap::new();
+    for (name, hash) in snapshot.files.iter() {
+        let object = objects.join(hash.as_str());
+        let intact = std::fs::read(object.as_path()).is_ok_and(|data| webdav::hash(data.as_slice()) == *hash);
+        if !intact {
+            return Err(Error::BadSnapshot { id: id.to_string(), name: name.clone() });
+        }
+        contents.insert(name.as_str(), object);
+    }
+
+    let label = format!("before rolling back to {}", id);
+    let before = take(vault_dir, Some(label.as_str()))?;
+    for name in before.files.keys().filter(|name| !snapshot.files.contains_key(*name)) {
+        let p = vault_dir.join(name.as_str());
+        std::fs::remove_file(p.as_path())?;
+        vault::remove_empty_parents(vault_dir, p.as_path())?;
+    }
+    for (name, object) in contents {
+        if before.files.get(name) != snapshot.files.get(name) {
+            let p = vault_dir.join(name);
+            if let Some(parent) = p.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            vault::atomic_write(p.as_path(), std::fs::read(object)?.as_slice())?;
+        }
+    }
+    vault::remove_index(vault_dir)?;
+    Ok(before)
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use crate::crypto::Kdf;
+    use crate::global::SNAPSHOTS_DIR;
+    use crate::snapshot;
+    use crate::vault::{self, Backend};
+
+    #[test]
+    fn vaults_roll_back_to_snapshots() {
+        let yap_test = String::from(".yap_test_snapshot");
+        let dir = Path::new(yap_test.as_str());
+        let mut vault = vault::create(Some(yap_test.clone()), "asdf", Kdf::default(), Backend::default(), None).unwrap();
+        vault.set_key("github", "first".to_string()).unwrap();
+        vault.set_key("work/email", "password".to_string()).unwrap();
+        let taken = snapshot::create(dir, Some("before cleanup")).unwrap();
+        assert_eq!(taken.label.as_deref(), Some("before cleanup"));
+
+        // Unchanged files are stored once
+        let objects = || std::fs::read_dir(dir.join(SNAPSHOTS_DIR).join("objects")).unwrap().count();
+        let stored = objects();
+        snapshot::create(dir, None).unwrap();
+        assert_eq!(objects(), stored);
+
+        vault.set_key("github", "second".to_string()).unwrap();
+        vault.delete_key("work/email").unwrap();
+        vault.set_key("bank", "password".to_string()).unwrap();
+        let before = snapshot::rollback(dir, taken.id.as_str()).unwrap();
+        assert_eq!(vault.get_key("github").unwrap(), "first");
+        assert_eq!(vault.list_keys("").unwrap(), vec!["github", "work/email"]);
+        assert!(vault.list_trash().unwrap().is_empty());
+
+        // The rollback can be undone, but not to a snapshot whose files were damaged
+        snapshot::rollback(dir, before.id.as_str()).unwrap();
+        assert_eq!(vault.list_keys("").unwrap(), vec!["bank", "github"]);
+        let hash = &taken.files["github"];
+        std::fs::write(dir.join(SNAPSHOTS_DIR).join("objects").join(hash), "garbage").unwrap();
+        assert!(snapshot::rollback(dir, taken.id.as_str()).is_err());
+        assert_eq!(vault.get_key("github").unwrap(), "second");
+        assert_eq!(snapshot::list(dir).unwrap().len(), 4);
+
+        std::fs::remove_dir_all(yap_test).unwrap();
+    }
+}
@@ -0,0 +1,839 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use age::x25519::Recipient;
+use rayon::prelude::*;
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+use tracing::debug;
+use zeroize::Zeroizing;
+
+use crate::activity::{self, Activity};
+use crate::crypto::{Aes256GcmEngine, Kdf, KEY_LEN};
+use crate::entry::{self, Entry, Metadata};
+use crate::error::{Error, Result};
+use crate::global::{ATTACHMENTS_DIR, ATTACHMENT_FILES_DIR, DATABASE_FILE, HEADER_FILE, HISTORY_DIR, QUARANTINE_DIR, REKEY_COMPLETE, REKEY_DIR, TRASH_DIR, TRASH_ENTRY_FILE};
+use crate::header::VaultHeader;
+use crate::stats::{Sizes, Stats};
+use crate::vault::{self, Vault};
+use crate::verify::{Action, Problem, RepairReport, Report};
+
+/// The tables of the database, created when it is first opened. Every row holds when its entry
+/// was deleted, in seconds since the Unix epoch, or 0 while the entry is in the vault, so an
+/// entry is moved into the trash along with its history and attachments by a single update.
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS entries (
+        name TEXT NOT NULL, deleted INTEGER NOT NULL, data BLOB NOT NULL,
+        PRIMARY KEY (name, deleted));
+    CREATE TABLE IF NOT EXISTS history (
+        name TEXT NOT NULL, deleted INTEGER NOT NULL, version INTEGER NOT NULL, data BLOB NOT NULL,
+        PRIMARY KEY (name, deleted, version));
+    CREATE TABLE IF NOT EXISTS attachments (
+        name TEXT NOT NULL, deleted INTEGER NOT NULL, attachment TEXT NOT NULL, data BLOB NOT NULL,
+        PRIMARY KEY (name, deleted, attachment));
+";
+
+/// The tables holding what belongs to an entry, which follows it into and out of the trash
+const TABLES: [&str; 3] = ["entries", "history", "attachments"];
+
+/// Opens the database of the vault in `dir`, creating it and its tables if they don't exist.
+/// Deleted rows are overwritten, so nothing removed from the vault lingers in the file.
+fn open(dir: &Path) -> Result<Connection> {
+    let conn = Connection::open(dir.join(DATABASE_FILE))?;
+    conn.pragma_update(None, "secure_delete", true)?;
+    conn.execute_batch(SCHEMA)?;
+    Ok(conn)
+}
+
+/// Opens the database of the vault in `dir` without changing it, such as one in a backup
+fn open_read_only(dir: &Path) -> Result<Connection> {
+    Ok(Connection::open_with_flags(dir.join(DATABASE_FILE), OpenFlags::SQLITE_OPEN_READ_ONLY)?)
+}
+
+/// Lists the names of the entries in the vault in `vault_dir`, sorted by name, without opening
+/// the vault, see `vault::list_names`
+pub(crate) fn names(vault_dir: &Path) -> Result<Vec<String>> {
+    if !vault_dir.join(DATABASE_FILE).is_file() {
+        return Ok(vec![]);
+    }
+    let conn = open_read_only(vault_dir)?;
+    let mut statement = conn.prepare("SELECT name FROM entries WHERE deleted = 0 ORDER BY name")?;
+    let names = statement.query_map([], |row| row.get(0))?.collect::<rusqlite::Result<Vec<String>>>()?;
+    Ok(names)
+}
+
+/// Item is what a row of the database holds for its entry
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Item {
+    Entry,
+    /// A previous version of the entry, numbered from 1
+    Version(u32),
+    Attachment(String),
+}
+
+/// Row names a row of the database by the name of its entry, when the entry was deleted, or 0,
+/// and what the row holds
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct Row {
+    name: String,
+    deleted: i64,
+    item: Item,
+}
+
+impl Row {
+    fn entry(name: &str) -> Row {
+        Row { name: name.to_string(), deleted: 0, item: Item::Entry }
+    }
+
+    fn version(name: &str, version: u32) -> Row {
+        Row { name: name.to_string(), deleted: 0, item: Item::Version(version) }
+    }
+
+    fn attachment(name: &str, attachment: &str) -> Row {
+        Row { name: name.to_string(), deleted: 0, item: Item::Attachment(attachment.to_string()) }
+    }
+
+    /// Returns the path the row would have as a file in a vault using the files backend, which
+    /// names it in reports and in the quarantine
+    fn path(&self) -> String {
+        match (&self.item, self.deleted) {
+            (Item::Entry, 0) => self.name.clone(),
+            (Item::Version(version), 0) => format!("{}/{}/{}", HISTORY_DIR, self.name, version),
+            (Item::Attachment(attachment), 0) => format!("{}/{}/{}/{}", ATTACHMENTS_DIR, self.name, ATTACHMENT_FILES_DIR, attachment),
+            (Item::Entry, deleted) => format!("{}/{}/.{}/{}", TRASH_DIR, self.name, deleted, TRASH_ENTRY_FILE),
+            (Item::Version(version), deleted) => format!("{}/{}/.{}/{}/{}", TRASH_DIR, self.name, deleted, HISTORY_DIR, version),
+            (Item::Attachment(attachment), deleted) => format!("{}/{}/.{}/{}/{}", TRASH_DIR, self.name, deleted, ATTACHMENTS_DIR, attachment),
+        }
+    }
+
+    /// Returns the row held by a file of a vault using the files backend, given its path relative
+    /// to the vault, the reverse of `path`. None is returned for files which aren't an entry, a
+    /// previous version, or an attachment, such as temporary files and unfinished attachments.
+    fn from_path(file: &str) -> Option<Row> {
+        let row = if let Some((name, deleted, rest)) = vault::trash_item(file) {
+            let item = match rest.split_once('/') {
+                None if rest == TRASH_ENTRY_FILE => Item::Entry,
+                Some((HISTORY_DIR, version)) => Item::Version(version.parse().ok()?),
+                Some((ATTACHMENTS_DIR, attachment)) => Item::Attachment(attachment.to_string()),
+                _ => return None,
+            };
+            Row { name: name.to_string(), deleted, item }
+        } else if let Some(rest) = file.strip_prefix(HISTORY_DIR).and_then(|rest| rest.strip_prefix('/')) {
+            let (name, version) = rest.rsplit_once('/')?;
+            Row::version(name, version.parse().ok()?)
+        } else if let Some(rest) = file.strip_prefix(ATTACHMENTS_DIR).and_then(|rest| rest.strip_prefix('/')) {
+            let (name, attachment) = rest.rsplit_once(format!("/{}/", ATTACHMENT_FILES_DIR).as_str())?;
+            Row::attachment(name, attachment)
+        } else {
+            Row::entry(file)
+        };
+
+        let valid = vault::validate_key(row.name.as_str()).is_ok() && match &row.item {
+            Item::Attachment(attachment) => vault::validate_attachment(attachment.as_str()).is_ok(),
+            _ => true,
+        };
+        Some(row).filter(|_| valid)
+    }
+
+    /// Reads the contents of the row, or None if there is no such row
+    fn read(&self, conn: &Connection) -> Result<Option<Vec<u8>>> {
+        let data = match &self.item {
+            Item::Entry => conn.query_row("SELECT data FROM entries WHERE name = ?1 AND deleted = ?2",
+                params![self.name, self.deleted], |row| row.get(0)),
+            Item::Version(version) => conn.query_row("SELECT data FROM history WHERE name = ?1 AND deleted = ?2 AND version = ?3",
+                params![self.name, self.deleted, version], |row| row.get(0)),
+            Item::Attachment(attachment) => conn.query_row("SELECT data FROM attachments WHERE name = ?1 AND deleted = ?2 AND attachment = ?3",
+                params![self.name, self.deleted, attachment], |row| row.get(0)),
+        };
+        Ok(data.optional()?)
+    }
+
+    /// Writes the contents of the row, replacing the row if it exists
+    fn write(&self, conn: &Connection, data: &[u8]) -> Result<()> {
+        match &self.item {
+            Item::Entry => conn.execute("INSERT OR REPLACE INTO entries (name, deleted, data) VALUES (?1, ?2, ?3)",
+                params![self.name, self.deleted, data])?,
+            Item::Version(version) => conn.execute("INSERT OR REPLACE INTO history (name, deleted, version, data) VALUES (?1, ?2, ?3, ?4)",
+                params![self.name, self.deleted, version, data])?,
+            Item::Attachment(attachment) => conn.execute("INSERT OR REPLACE INTO attachments (name, deleted, attachment, data) VALUES (?1, ?2, ?3, ?4)",
+                params![self.name, self.deleted, attachment, data])?,
+        };
+        Ok(())
+    }
+
+    /// Removes the row, returning whether it existed
+    fn remove(&self, conn: &Connection) -> Result<bool> {
+        let removed = match &self.item {
+            Item::Entry => conn.execute("DELETE FROM entries WHERE name = ?1 AND deleted = ?2", params![self.name, self.deleted])?,
+            Item::Version(version) => conn.execute("DELETE FROM history WHERE name = ?1 AND deleted = ?2 AND version = ?3",
+                params![self.name, self.deleted, version])?,
+            Item::Attachment(attachment) => conn.execute("DELETE FROM attachments WHERE name = ?1 AND deleted = ?2 AND attachment = ?3",
+                params![self.name, self.deleted, attachment])?,
+        };
+        Ok(removed > 0)
+    }
+}
+
+/// Reads every row of the database along with its contents: entries, previous versions, and
+/// attachments, in the vault and in the trash
+fn rows(conn: &Connection) -> Result<Vec<(Row, Vec<u8>)>> {
+    let mut rows = vec![];
+    let mut statement = conn.prepare("SELECT name, deleted, data FROM entries ORDER BY name, deleted")?;
+    for row in statement.query_map([], |row| Ok((Row { name: row.get(0)?, deleted: row.get(1)?, item: Item::Entry }, row.get(2)?)))? {
+        rows.push(row?);
+    }
+    let mut statement = conn.prepare("SELECT name, deleted, version, data FROM history ORDER BY name, deleted, version")?;
+    for row in statement.query_map([], |row| Ok((Row { name: row.get(0)?, deleted: row.get(1)?, item: Item::Version(row.get(2)?) }, row.get(3)?)))? {
+        rows.push(row?);
+    }
+    let mut statement = conn.prepare("SELECT name, deleted, attachment, data FROM attachments ORDER BY name, deleted, attachment")?;
+    for row in statement.query_map([], |row| Ok((Row { name: row.get(0)?, deleted: row.get(1)?, item: Item::Attachment(row.get(2)?) }, row.get(3)?)))? {
+        rows.push(row?);
+    }
+    Ok(rows)
+}
+
+/// Writes the entries of the vault in `vault_dir`, which uses the files backend, into a new
+/// database in `into`, along with their history, attachments, and the trash, see
+/// `vault::migrate`. Files are copied as they are, still encrypted.
+pub(crate) fn from_files(vault_dir: &Path, into: &Path) -> Result<()> {
+    let mut files = vec![];
+    vault::collect_files(vault_dir, "", false, &mut files)?;
+    for dir in [HISTORY_DIR, ATTACHMENTS_DIR, TRASH_DIR] {
+        if vault_dir.join(dir).is_dir() {
+            vault::collect_files(vault_dir.join(dir).as_path(), dir, true, &mut files)?;
+        }
+    }
+
+    let mut conn = open(into)?;
+    let tx = conn.transaction()?;
+    for file in files {
+        match Row::from_path(file.as_str()) {
+            Some(row) => row.write(&tx, std::fs::read(vault_dir.join(Path::new(file.as_str())))?.as_slice())?,
+            None => debug!("{} isn't migrated, since it isn't part of an entry", file),
+        }
+    }
+    Ok(tx.commit()?)
+}
+
+/// Writes the rows of the database of the vault in `vault_dir` into `into`, as the files of a
+/// vault using the files backend, see `Row::path` and `vault::migrate`
+pub(crate) fn to_files(vault_dir: &Path, into: &Path) -> Result<()> {
+    for (row, data) in rows(&open_read_only(vault_dir)?)? {
+        let p = into.join(Path::new(row.path().as_str()));
+        if let Some(parent) = p.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        vault::atomic_write(p.as_path(), data.as_slice())?;
+    }
+    Ok(())
+}
+
+/// Copies the current version of the entry with the given name, if it exists, into the next
+/// version of its history. The ciphertext is copied as-is, so nothing needs to be decrypted.
+fn archive(conn: &Connection, key: &str) -> Result<()> {
+    conn.execute("INSERT INTO history (name, deleted, version, data)
+        SELECT name, 0, (SELECT COALESCE(MAX(version), 0) + 1 FROM history WHERE name = ?1 AND deleted = 0), data
+        FROM entries WHERE name = ?1 AND deleted = 0", params![key])?;
+    Ok(())
+}
+
+/// SqliteVault stores a vault's entries in a single SQLite database, `.entries.db`, along with
+/// their history, their attachments, and the trash. Entries are encrypted the same way as in a
+/// SimpleVault and bound to their names, but every change is made in a single transaction, so an
+/// interrupted write never leaves the vault half changed. The header and the vault's other files
+/// are kept beside the database.
+pub(crate) struct SqliteVault {
+    vault_dir: PathBuf,
+    conn: Connection,
+    engine: Aes256GcmEngine,
+    version: u32,
+    master_key: Option<Zeroizing<[u8; KEY_LEN]>>,
+}
+
+impl SqliteVault {
+    /// Loads a SqliteVault with its key, which has already been derived from the master
+    /// password, the same way as a SimpleVault, see `SimpleVault::load`
+    pub(crate) fn load(vault_dir: PathBuf, key: Zeroizing<[u8; KEY_LEN]>, header: &VaultHeader) -> Result<SqliteVault> {
+        let master_key = header.unwrap_master_key(&key)?;
+        let engine = vault::engine(vault_dir.as_path(), master_key.clone().unwrap_or(key))?;
+        activity::init(vault_dir.as_path(), &engine).ok();
+        let conn = open(vault_dir.as_path())?;
+        Ok(SqliteVault { vault_dir, conn, engine, version: header.version, master_key })
+    }
+
+    /// Loads a SqliteVault with its master key, which a member has decrypted with their age key
+    pub(crate) fn with_master_key(vault_dir: PathBuf, master_key: Zeroizing<[u8; KEY_LEN]>, header: &VaultHeader) -> Result<SqliteVault> {
+        let engine = vault::engine(vault_dir.as_path(), master_key.clone())?;
+        let conn = open(vault_dir.as_path())?;
+        Ok(SqliteVault { vault_dir, conn, engine, version: header.version, master_key: Some(master_key) })
+    }
+
+    fn decrypt(&self, key: &str, data: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+        vault::decrypt_entry(&self.engine, self.version, key, data)
+    }
+
+    fn encrypt(&self, key: &str, plaintext: &[u8]) -> Result<Vec<u8>> {
+        vault::encrypt_entry(&self.engine, self.master_key.is_some(), key, plaintext)
+    }
+
+    /// Reads the ciphertext of the entry with the given name
+    fn read_entry(&self, key: &str) -> Result<Vec<u8>> {
+        vault::validate_key(key)?;
+        Row::entry(key).read(&self.conn)?.ok_or_else(|| Error::PasswordNotFound { name: key.to_string() })
+    }
+
+    /// Returns the previous versions of the entry with the given name, oldest first, still
+    /// encrypted
+    fn versions(&self, key: &str) -> Result<Vec<(u32, Vec<u8>)>> {
+        let mut statement = self.conn.prepare("SELECT version, data FROM history WHERE name = ?1 AND deleted = 0 ORDER BY version")?;
+        let versions = statement.query_map(params![key], |row| Ok((row.get(0)?, row.get(1)?)))?.collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(versions)
+    }
+
+    /// Checks that the contents of a row decrypt and authenticate with the vault's key. Previous
+    /// versions are bound to the name of their entry, like the entry itself.
+    fn check(&self, row: &Row, data: &[u8]) -> Result<()> {
+        match &row.item {
+            Item::Attachment(attachment) => {
+                let aad = vault::attachment_aad(row.name.as_str(), attachment.as_str());
+                self.engine.decrypt_stream(&mut &data[..], &mut std::io::sink(), aad.as_slice()).map(|_| ())
+            }
+            _ => Entry::from_bytes(self.decrypt(row.name.as_str(), data)?.as_slice()).map(|_| ()),
+        }
+    }
+
+    /// Moves a row out of the database into the vault's `.quarantine` directory, as the file it
+    /// would be in a vault using the files backend, see `Row::path`
+    fn quarantine(&self, row: &Row, data: &[u8]) -> Result<()> {
+        let p = self.vault_dir.join(QUARANTINE_DIR).join(Path::new(row.path().as_str()));
+        if let Some(parent) = p.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        vault::atomic_write(p.as_path(), data)?;
+        row.remove(&self.conn)?;
+        Ok(())
+    }
+
+    /// Returns the size in bytes of the contents of a table, of the rows in the vault or of
+    /// those in the trash
+    fn size(&self, table: &str, trash: bool) -> Result<u64> {
+        let sql = format!("SELECT COALESCE(SUM(LENGTH(data)), 0) FROM {} WHERE (deleted > 0) = ?1", table);
+        let size: i64 = self.conn.query_row(sql.as_str(), params![trash], |row| row.get(0))?;
+        Ok(size as u64)
+    }
+
+    /// Rekeys the vault with the master password, deriving its key with the given Kdf, see
+    /// `SimpleVault::rekey_with`
+    fn rekey_with(&mut self, new_pass: &str, kdf: Kdf) -> Result<()> {
+        let reencrypt = self.master_key.is_none();
+        let (header, master_key) = vault::rekeyed_header(self.vault_dir.as_path(), new_pass, kdf, self.master_key.clone())?;
+        self.rewrite(header, master_key, reencrypt)
+    }
+
+    /// Replaces the header, and re-encrypts every row with the new master key if `reencrypt` is
+    /// set. The header and the re-encrypted database are written to a staging directory first,
+    /// and moved over the old ones once it is complete, the same way as in a SimpleVault, see
+    /// `SimpleVault::rewrite`.
+    fn rewrite(&mut self, header: VaultHeader, master_key: Zeroizing<[u8; KEY_LEN]>, reencrypt: bool) -> Result<()> {
+        let staging = self.vault_dir.join(REKEY_DIR);
+        if staging.as_path().exists() {
+            std::fs::remove_dir_all(staging.as_path())?;
+        }
+        std::fs::create_dir(staging.as_path())?;
+
+        let engine = vault::engine(self.vault_dir.as_path(), master_key.clone())?;
+        if reencrypt {
+            let mut conn = open(staging.as_path())?;
+            let tx = conn.transaction()?;
+            for (row, data) in rows(&self.conn)? {
+                let data = match &row.item {
+                    Item::Attachment(attachment) => {
+                        let aad = vault::attachment_aad(row.name.as_str(), attachment.as_str());
+                        let mut rewrapped = vec![];
+                        self.engine.rewrap_stream(&engine, &mut data.as_slice(), &mut rewrapped, aad.as_slice(), aad.as_slice())?;
+                        rewrapped
+                    }
+                    _ => vault::encrypt_entry(&engine, true, row.name.as_str(), self.decrypt(row.name.as_str(), data.as_slice())?.as_slice())?,
+                };
+                row.write(&tx, data.as_slice())?;
+            }
+            tx.commit()?;
+            activity::rewrap(self.vault_dir.as_path(), &self.engine, &engine, staging.as_path())?;
+        }
+        header.write(staging.as_path())?;
+        File::open(staging.join(HEADER_FILE))?.sync_all()?;
+        vault::atomic_write(staging.join(REKEY_COMPLETE).as_path(), &[])?;
+
+        // The database is closed before it is replaced, and opened again afterwards
+        self.conn = Connection::open_in_memory()?;
+        vault::finish_staging(self.vault_dir.as_path(), staging.as_path())?;
+        self.conn = open(self.vault_dir.as_path())?;
+        self.engine = engine;
+        self.version = header.version;
+        self.master_key = Some(master_key);
+        Ok(())
+    }
+}
+
+impl Vault for SqliteVault {
+    fn has_key(&self, key: &str) -> bool {
+        vault::validate_key(key).is_ok() && Row::entry(key).read(&self.conn).is_ok_and(|data| data.is_some())
+    }
+
+    fn get_entry(&self, key: &str) -> Result<Entry> {
+        Entry::from_bytes(self.decrypt(key, self.read_entry(key)?.as_slice())?.as_slice())
+    }
+
+    fn access_entry(&mut self, key: &str) -> Result<Entry> {
+        let mut entry = self.get_entry(key)?;
+        entry.metadata.accessed = entry::now();
+        Row::entry(key).write(&self.conn, self.encrypt(key, entry.to_bytes()?.as_slice())?.as_slice())?;
+        Ok(entry)
+    }
+
+    /// Encrypts and writes the entry, see `SimpleVault::set_entry`. The replaced entry is kept
+    /// in its history in the same transaction.
+    fn set_entry(&mut self, key: &str, entry: &Entry) -> Result<()> {
+        vault::validate_key(key)?;
+        let ciphertext = self.encrypt(key, vault::stamp(entry).to_bytes()?.as_slice())?;
+        let tx = self.conn.transaction()?;
+        archive(&tx, key)?;
+        Row::entry(key).write(&tx, ciphertext.as_slice())?;
+        Ok(tx.commit()?)
+    }
+
+    /// Encrypts the entries in parallel, then writes them in a single transaction, so either
+    /// every entry is written or none are
+    fn set_entries(&mut self, entries: &[(String, Entry)]) -> Result<()> {
+        let mut batch = BTreeMap::new();
+        for (key, entry) in entries {
+            vault::validate_key(key)?;
+            batch.insert(key.as_str(), vault::stamp(entry));
+        }
+
+        let (engine, enveloped) = (&self.engine, self.master_key.is_some());
+        let ciphertexts = batch.par_iter()
+            .map(|(key, entry)| vault::encrypt_entry(engine, enveloped, key, entry.to_bytes()?.as_slice()))
+            .collect::<Result<Vec<_>>>()?;
+
+        let tx = self.conn.transaction()?;
+        for (key, ciphertext) in batch.keys().zip(ciphertexts) {
+            archive(&tx, key)?;
+            Row::entry(key).write(&tx, ciphertext.as_slice())?;
+        }
+        Ok(tx.commit()?)
+    }
+
+    /// Moves the entry into the trash along with its history and attachments, by marking their
+    /// rows with when the entry was deleted
+    fn delete_key(&mut self, key: &str) -> Result<()> {
+        vault::validate_key(key)?;
+        if !self.has_key(key) {
+            return Err(Error::PasswordNotFound { name: key.to_string() });
+        }
+
+        // An entry deleted twice within a second is kept apart from the earlier copy
+        let mut deleted = entry::now();
+        while (Row { name: key.to_string(), deleted, item: Item::Entry }).read(&self.conn)?.is_some() {
+            deleted += 1;
+        }
+        let tx = self.conn.transaction()?;
+        for table in TABLES {
+            tx.execute(format!("UPDATE {} SET deleted = ?1 WHERE name = ?2 AND deleted = 0", table).as_str(), params![deleted, key])?;
+        }
+        Ok(tx.commit()?)
+    }
+
+    /// Renames an entry, re-encrypting it and its history under the new name and rewrapping its
+    /// attachments, see `SimpleVault::rename_key`
+    fn rename_key(&mut self, from: &str, to: &str, force: bool) -> Result<()> {
+        vault::validate_key(from)?;
+        vault::validate_key(to)?;
+        if !self.has_key(from) {
+            return Err(Error::PasswordNotFound { name: from.to_string() });
+        }
+        if from == to {
+            return Ok(());
+        }
+        if !force && self.has_key(to) {
+            return Err(Error::PasswordExists { name: to.to_string() });
+        }
+
+        let entry = self.encrypt(to, self.decrypt(from, self.read_entry(from)?.as_slice())?.as_slice())?;
+        let mut versions = vec![];
+        for (version, data) in self.versions(from)? {
+            versions.push((version, self.encrypt(to, self.decrypt(from, data.as_slice())?.as_slice())?));
+        }
+        let mut attachments = vec![];
+        for attachment in self.list_attachments(from)? {
+            let data = Row::attachment(from, attachment.as_str()).read(&self.conn)?.unwrap_or_default();
+            let (aad, to_aad) = (vault::attachment_aad(from, attachment.as_str()), vault::attachment_aad(to, attachment.as_str()));
+            let mut rewrapped = vec![];
+            self.engine.rewrap_stream(&self.engine, &mut data.as_slice(), &mut rewrapped, aad.as_slice(), to_aad.as_slice())?;
+            attachments.push((attachment, rewrapped));
+        }
+
+        // The history and attachments follow the entry, replacing those of any entry it replaced
+        let tx = self.conn.transaction()?;
+        for table in TABLES {
+            tx.execute(format!("DELETE FROM {} WHERE name IN (?1, ?2) AND deleted = 0", table).as_str(), params![from, to])?;
+        }
+        Row::entry(to).write(&tx, entry.as_slice())?;
+        for (version, data) in versions {
+            Row::version(to, version).write(&tx, data.as_slice())?;
+        }
+        for (attachment, data) in attachments {
+            Row::attachment(to, attachment.as_str()).write(&tx, data.as_slice())?;
+        }
+        Ok(tx.commit()?)
+    }
+
+    fn list_keys(&self, prefix: &str) -> Result<Vec<String>> {
+        let mut statement = self.conn.prepare("SELECT name FROM entries WHERE deleted = 0 ORDER BY name")?;
+        let mut keys = statement.query_map([], |row| row.get(0))?.collect::<rusqlite::Result<Vec<String>>>()?;
+        keys.retain(|key| key.starts_with(prefix));
+        Ok(keys)
+    }
+
+    /// Encrypts an attachment in chunks as it is read, see `Aes256GcmEngine::encrypt_stream`.
+    /// The encrypted attachment is stored in the database as a single value, so unlike in a
+    /// SimpleVault it is held in memory while it is written.
+    fn attach(&mut self, key: &str, attachment: &str, reader: &mut dyn Read) -> Result<u64> {
+        vault::validate_key(key)?;
+        if !self.has_key(key) {
+            return Err(Error::PasswordNotFound { name: key.to_string() });
+        }
+        vault::validate_attachment(attachment)?;
+
+        let mut data = vec![];
+        let aad = vault::attachment_aad(key, attachment);
+        let len = self.engine.encrypt_stream(reader, &mut data, aad.as_slice())?;
+        Row::attachment(key, attachment).write(&self.conn, data.as_slice())?;
+        Ok(len)
+    }
+
+    fn read_attachment(&self, key: &str, attachment: &str, writer: &mut dyn Write) -> Result<u64> {
+        vault::validate_key(key)?;
+        vault::validate_attachment(attachment)?;
+        let data = Row::attachment(key, attachment).read(&self.conn)?
+            .ok_or_else(|| Error::AttachmentNotFound { name: key.to_string(), attachment: attachment.to_string() })?;
+        let aad = vault::attachment_aad(key, attachment);
+        self.engine.decrypt_stream(&mut data.as_slice(), writer, aad.as_slice())
+    }
+
+    fn list_attachments(&self, key: &str) -> Result<Vec<String>> {
+        vault::validate_key(key)?;
+        let mut statement = self.conn.prepare("SELECT attachment FROM attachments WHERE name = ?1 AND deleted = 0 ORDER BY attachment")?;
+        let attachments = statement.query_map(params![key], |row| row.get(0))?.collect::<rusqlite::Result<Vec<String>>>()?;
+        Ok(attachments)
+    }
+
+    fn remove_attachment(&mut self, key: &str, attachment: &str) -> Result<()> {
+        vault::validate_key(key)?;
+        vault::validate_attachment(attachment)?;
+        match Row::attachment(key, attachment).remove(&self.conn)? {
+            true => Ok(()),
+            false => Err(Error::AttachmentNotFound { name: key.to_string(), attachment: attachment.to_string() }),
+        }
+    }
+
+    fn history(&self, key: &str) -> Result<Vec<(u32, Metadata)>> {
+        vault::validate_key(key)?;
+        let mut versions = vec![];
+        for (version, data) in self.versions(key)? {
+            let entry = Entry::from_bytes(self.decrypt(key, data.as_slice())?.as_slice())?;
+            versions.push((version, entry.metadata));
+        }
+        Ok(versions)
+    }
+
+    fn restore(&mut self, key: &str, version: u32) -> Result<()> {
+        vault::validate_key(key)?;
+        let data = Row::version(key, version).read(&self.conn)?
+            .ok_or_else(|| Error::VersionNotFound { name: key.to_string(), version })?;
+        let entry = Entry::from_bytes(self.decrypt(key, data.as_slice())?.as_slice())?;
+        self.set_entry(key, &entry)
+    }
+
+    fn list_trash(&self) -> Result<Vec<(String, i64)>> {
+        let mut statement = self.conn.prepare("SELECT name, deleted FROM entries WHERE deleted > 0 ORDER BY deleted, name")?;
+        let trashed = statement.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?.collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(trashed)
+    }
+
+    /// Moves the entry back out of the trash, along with its history and attachments. An existing
+    /// entry which is replaced is moved into the trash itself.
+    fn restore_trash(&mut self, key: &str, force: bool) -> Result<()> {
+        vault::validate_key(key)?;
+        let deleted: Option<i64> = self.conn.query_row("SELECT MAX(deleted) FROM entries WHERE name = ?1 AND deleted > 0", params![key], |row| row.get(0))?;
+        let Some(deleted) = deleted else {
+            return Err(Error::NotInTrash { name: key.to_string() });
+        };
+        if self.has_key(key) {
+            if !force {
+                return Err(Error::PasswordExists { name: key.to_string() });
+            }
+            self.delete_key(key)?;
+        }
+
+        let tx = self.conn.transaction()?;
+        for table in TABLES {
+            tx.execute(format!("UPDATE {} SET deleted = 0 WHERE name = ?1 AND deleted = ?2", table).as_str(), params![key, deleted])?;
+        }
+        Ok(tx.commit()?)
+    }
+
+    /// Removes entries from the trash for good. Deleted rows are overwritten by SQLite, see
+    /// `open`.
+    fn empty_trash(&mut self, before: Option<i64>) -> Result<usize> {
+        let before = before.unwrap_or(i64::MAX);
+        let tx = self.conn.transaction()?;
+        let removed = tx.execute("DELETE FROM entries WHERE deleted > 0 AND deleted < ?1", params![before])?;
+        for table in &TABLES[1..] {
+            tx.execute(format!("DELETE FROM {} WHERE deleted > 0 AND deleted < ?1", table).as_str(), params![before])?;
+        }
+        tx.commit()?;
+        Ok(removed)
+    }
+
+    /// Entry names are listed straight from the database, so there is no separate index to
+    /// rebuild, and the entries are only counted
+    fn rebuild_index(&mut self) -> Result<usize> {
+        Ok(self.list_keys("")?.len())
+    }
+
+    /// Checks the vault row by row, see `Vault::verify`, after SQLite's own check of the
+    /// database. Rows are named by the paths they would have in a vault using the files backend,
+    /// and quarantined as those files.
+    fn verify(&mut self, quarantine: bool) -> Result<Report> {
+        let mut report = Report::default();
+        vault::verify_header(self.vault_dir.as_path(), self.master_key.is_some(), &mut report);
+        let integrity: String = self.conn.query_row("PRAGMA integrity_check", [], |row| row.get(0))?;
+        if integrity != "ok" {
+            report.add(DATABASE_FILE, Problem::Corrupt, integrity);
+        }
+
+        let mut corrupt = vec![];
+        for (row, data) in rows(&self.conn)? {
+            report.checked += 1;
+            if let Err(e) = self.check(&row, data.as_slice()) {
+                report.add(row.path().as_str(), Problem::Corrupt, e);
+                corrupt.push((report.findings.len() - 1, row, data));
+            }
+        }
+
+        if quarantine {
+            for (finding, row, data) in corrupt {
+                self.quarantine(&row, data.as_slice())?;
+                report.findings[finding].quarantined = true;
+            }
+        }
+        Ok(report)
+    }
+
+    /// Repairs the vault, see `Vault::repair`. Changes to the database are made in transactions,
+    /// so there are no temporary files or separated history to clean up, and only corrupted rows
+    /// are repaired: from the database in `backup`, or otherwise from the entry's latest readable
+    /// previous version. Automatic backups are only taken of vaults using the files backend, see
+    /// `BackupVault`.
+    fn repair(&mut self, backup: Option<&Path>) -> Result<RepairReport> {
+        let mut report = RepairReport::default();
+        let backup = backup.map(open_read_only).transpose()?;
+        for (row, data) in rows(&self.conn)? {
+            if self.check(&row, data.as_slice()).is_ok() {
+                continue;
+            }
+
+            let path = row.path();
+            let copy = match backup.as_ref() {
+                Some(backup) => row.read(backup)?.filter(|copy| self.check(&row, copy.as_slice()).is_ok()),
+                None => None,
+            };
+            if let Some(copy) = copy {
+                row.write(&self.conn, copy.as_slice())?;
+                report.add(path.as_str(), Action::Restored, "from the backup");
+                continue;
+            }
+
+            if row.item == Item::Entry {
+                let mut statement = self.conn.prepare("SELECT version, data FROM history WHERE name = ?1 AND deleted = ?2 ORDER BY version DESC")?;
+                let versions = statement.query_map(params![row.name, row.deleted], |row| Ok((row.get(0)?, row.get(1)?)))?
+                    .collect::<rusqlite::Result<Vec<(u32, Vec<u8>)>>>()?;
+                if let Some((version, previous)) = versions.into_iter().find(|(_, previous)| self.check(&row, previous.as_slice()).is_ok()) {
+                    row.write(&self.conn, previous.as_slice())?;
+                    report.add(path.as_str(), Action::Reverted, format!("to version {}", version));
+                    continue;
+                }
+            }
+
+            self.quarantine(&row, data.as_slice())?;
+            report.add(path.as_str(), Action::Quarantined, "since no readable copy was found");
+        }
+
+        report.indexed = self.rebuild_index()?;
+        Ok(report)
+    }
+
+    /// Recovers an entry from the database in a backup, see `Vault::recover`. The recovered entry
+    /// replaces the current one like any other change, so the current one is kept in its history.
+    fn recover(&mut self, backup: &Path, key: &str) -> Result<()> {
+        vault::validate_key(key)?;
+        let data = match backup.join(DATABASE_FILE).is_file() {
+            true => Row::entry(key).read(&open_read_only(backup)?)?,
+            false => None,
+        };
+        let data = data.ok_or_else(|| Error::PasswordNotFound { name: key.to_string() })?;
+        let entry = Entry::from_bytes(self.decrypt(key, data.as_slice())?.as_slice())?;
+        self.set_entry(key, &entry)
+    }
+
+    fn activity(&self) -> Result<Vec<Activity>> {
+        activity::read(self.vault_dir.as_path(), &self.engine)
+    }
+
+    /// Summarizes the entries, with the sizes of their rows in the database
+    fn stats(&self) -> Result<Stats> {
+        let mut entries = vec![];
+        for key in self.list_keys("")? {
+            let entry = self.get_entry(key.as_str())?;
+            let versions: usize = self.conn.query_row("SELECT COUNT(*) FROM history WHERE name = ?1 AND deleted = 0", params![key], |row| row.get(0))?;
+            entries.push((key, entry.metadata, entry.tags, versions));
+        }
+
+        let mut trash = 0;
+        for table in TABLES {
+            trash += self.size(table, true)?;
+        }
+        let sizes = Sizes {
+            entries: self.size("entries", false)?,
+            history: self.size("history", false)?,
+            attachments: self.size("attachments", false)?,
+            trash,
+        };
+        Ok(Stats::new(entries, sizes))
+    }
+
+    fn rekey(&mut self, new_pass: &str) -> Result<()> {
+        let kdf = VaultHeader::read(self.vault_dir.as_path())?.kdf;
+        self.rekey_with(new_pass, kdf)
+    }
+
+    fn set_kdf(&mut self, kdf: Kdf, pass: &str) -> Result<()> {
+        vault::check_pass(self.vault_dir.as_path(), pass)?;
+        self.rekey_with(pass, kdf)
+    }
+
+    fn add_member(&mut self, name: &str, recipient: &Recipient) -> Result<()> {
+        let master_key = self.master_key.as_ref().ok_or(Error::NoMasterKey)?;
+        let mut header = VaultHeader::read(self.vault_dir.as_path())?;
+        header.wrap_for_member(name, recipient, master_key)?;
+        header.write(self.vault_dir.as_path())
+    }
+
+    fn remove_member(&mut self, name: &str, pass: &str) -> Result<()> {
+        let (header, master_key) = vault::without_member(self.vault_dir.as_path(), name, pass)?;
+        self.rewrite(header, master_key, true)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use crate::crypto::Kdf;
+    use crate::entry::Entry;
+    use crate::error::Error;
+    use crate::global::{DATABASE_FILE, HISTORY_DIR};
+    use crate::header::VaultHeader;
+    use crate::snapshot;
+    use crate::vault::{self, Backend};
+
+    #[test]
+    fn entries_are_stored_in_the_database() {
+        let yap_test = String::from(".yap_test_sqlite");
+        let mut vault = vault::create(Some(yap_test.clone()), "asdf", Kdf::default(), Backend::Sqlite, None).unwrap();
+        assert!(Path::new(yap_test.as_str()).join(DATABASE_FILE).is_file());
+
+        vault.set_key("github", "first".to_string()).unwrap();
+        vault.set_key("github", "second".to_string()).unwrap();
+        vault.set_entries(&[("work/email".to_string(), Entry::default()), ("work/vpn".to_string(), Entry::default())]).unwrap();
+        assert_eq!(vault.get_key("github").unwrap(), "second");
+        assert_eq!(vault.list_keys("work/").unwrap(), vec!["work/email", "work/vpn"]);
+        assert_eq!(vault::list_names(Some(yap_test.clone()), "").unwrap(), vec!["github", "work/email", "work/vpn"]);
+
+        // History, attachments, and the trash
+        vault.attach("github", "codes.txt", &mut "123456".as_bytes()).unwrap();
+        assert_eq!(vault.history("github").unwrap().len(), 1);
+        vault.restore("github", 1).unwrap();
+        assert_eq!(vault.get_key("github").unwrap(), "first");
+        vault.delete_key("github").unwrap();
+        assert!(!vault.has_key("github"));
+        assert_eq!(vault.list_trash().unwrap().len(), 1);
+        vault.restore_trash("github", false).unwrap();
+        let mut codes = vec![];
+        vault.read_attachment("github", "codes.txt", &mut codes).unwrap();
+        assert_eq!(codes, b"123456");
+        assert_eq!(vault.history("github").unwrap().len(), 2);
+
+        // Renamed entries are bound to their new names, along with their attachments
+        vault.rename_key("github", "personal/github", false).unwrap();
+        assert_eq!(vault.get_key("personal/github").unwrap(), "first");
+        assert_eq!(vault.list_attachments("personal/github").unwrap(), vec!["codes.txt"]);
+        vault.rename_key("personal/github", "personal/github", false).unwrap();
+        assert!(matches!(vault.get_entry("github"), Err(Error::PasswordNotFound { .. })));
+        assert!(matches!(vault.get_entry("../github"), Err(Error::BadKeyName { .. })));
+
+        // The vault is reopened with the same backend, and survives a rekey
+        vault.rekey("new").unwrap();
+        drop(vault);
+        let mut vault = vault::load(Some(yap_test.clone()), "new").unwrap();
+        assert_eq!(vault.get_key("personal/github").unwrap(), "first");
+        assert_eq!(vault.verify(false).unwrap().findings.len(), 0);
+
+        std::fs::remove_dir_all(yap_test).unwrap();
+    }
+
+    #[test]
+    fn vaults_migrate_between_backends() {
+        let yap_test = String::from(".yap_test_sqlite_migrate");
+        let dir = Path::new(yap_test.as_str());
+        let mut vault = vault::create(Some(yap_test.clone()), "asdf", Kdf::default(), Backend::Files, None).unwrap();
+        vault.set_key("work/github", "first".to_string()).unwrap();
+        vault.set_key("work/github", "second".to_string()).unwrap();
+        vault.attach("work/github", "codes.txt", &mut "123456".as_bytes()).unwrap();
+        vault.set_key("email", "password".to_string()).unwrap();
+        vault.delete_key("email").unwrap();
+        drop(vault);
+
+        // Everything is moved into the database, and the entry files are removed
+        assert!(!vault::migrate(Some(yap_test.clone()), Backend::Files).unwrap());
+        assert!(vault::migrate(Some(yap_test.clone()), Backend::Sqlite).unwrap());
+        assert!(dir.join(DATABASE_FILE).is_file());
+        assert!(!dir.join("work").exists() && !dir.join(HISTORY_DIR).exists());
+        let check = |backend: Backend| {
+            assert_eq!(VaultHeader::read(dir).unwrap().backend, backend);
+            let vault = vault::load(Some(yap_test.clone()), "asdf").unwrap();
+            assert_eq!(vault.list_keys("").unwrap(), vec!["work/github"]);
+            assert_eq!(vault.get_key("work/github").unwrap(), "second");
+            assert_eq!(vault.history("work/github").unwrap().len(), 1);
+            assert_eq!(vault.list_attachments("work/github").unwrap(), vec!["codes.txt"]);
+            assert_eq!(vault.list_trash().unwrap().len(), 1);
+        };
+        check(Backend::Sqlite);
+
+        // And back out of it again
+        assert!(vault::migrate(Some(yap_test.clone()), Backend::Files).unwrap());
+        assert!(!dir.join(DATABASE_FILE).exists());
+        check(Backend::Files);
+
+        // Each migration can be rolled back to the snapshot taken before it
+        let snapshots = snapshot::list(dir).unwrap();
+        assert_eq!(snapshots.len(), 2);
+        snapshot::rollback(dir, snapshots[1].id.as_str()).unwrap();
+        check(Backend::Sqlite);
+
+        std::fs::remove_dir_all(yap_test).unwrap();
+    }
+}
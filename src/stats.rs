@@ -0,0 +1,135 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::entry::{self, Metadata};
+use crate::error::Result;
+
+/// The ages passwords are grouped by, as the label of each group and the most days since a
+/// password was changed for it to be in the group
+const AGES: &[(&str, i64)] = &[("under 30 days", 30), ("30 to 90 days", 90), ("90 days to a year", 365), ("over a year", i64::MAX)];
+
+/// The number of entries with the most previous versions which are listed
+const MOST_HISTORY: usize = 5;
+
+/// Stats summarizes the entries of a vault, as shown by yap stats
+#[derive(Serialize, Default, PartialEq, Eq, Debug)]
+pub struct Stats {
+    pub entries: usize,
+    /// The number of entries directly in each folder, with "" for entries in no folder
+    pub folders: BTreeMap<String, usize>,
+    /// The number of entries with each tag
+    pub tags: BTreeMap<String, usize>,
+    /// The number of entries in each age group, by how long ago their password was changed, see
+    /// `AGES`. Entries without a modified time are counted as unknown.
+    pub ages: Vec<(String, usize)>,
+    pub sizes: Sizes,
+    pub history: History,
+}
+
+/// Sizes are the bytes taken up by each part of a vault on disk, encrypted
+#[derive(Serialize, Default, PartialEq, Eq, Debug)]
+pub struct Sizes {
+    pub entries: u64,
+    pub history: u64,
+    pub attachments: u64,
+    pub trash: u64,
+}
+
+impl Sizes {
+    pub fn total(&self) -> u64 {
+        self.entries + self.history + self.attachments + self.trash
+    }
+}
+
+/// History counts the previous versions kept of entries
+#[derive(Serialize, Default, PartialEq, Eq, Debug)]
+pub struct History {
+    pub versions: usize,
+    /// The number of entries with at least one previous version
+    pub entries: usize,
+    /// The entries with the most previous versions, with how many each has, most first
+    pub most: Vec<(String, usize)>,
+}
+
+impl Stats {
+    /// Summarizes entries given as their names, metadata, tags, and number of previous versions,
+    /// along with the sizes of the vault's files
+    pub fn new(entries: impl IntoIterator<Item = (String, Metadata, BTreeSet<String>, usize)>, sizes: Sizes) -> Stats {
+        let now = entry::now();
+        let mut ages: Vec<(String, usize)> = AGES.iter().map(|(label, _)| (label.to_string(), 0)).collect();
+        ages.push(("unknown".to_string(), 0));
+        let mut stats = Stats { ages, sizes, ..Stats::default() };
+
+        let mut versions = vec![];
+        for (name, metadata, tags, count) in entries {
+            stats.entries += 1;
+            let folder = name.rsplit_once('/').map(|(folder, _)| folder).unwrap_or_default();
+            *stats.folders.entry(folder.to_string()).or_default() += 1;
+            for tag in tags {
+                *stats.tags.entry(tag).or_default() += 1;
+            }
+
+            let group = match metadata.modified {
+                0 => AGES.len(),
+                modified => AGES.iter().position(|(_, days)| (now - modified) / 86400 < *days).unwrap_or_default(),
+            };
+            stats.ages[group].1 += 1;
+            if count > 0 {
+                versions.push((name, count));
+            }
+        }
+
+        versions.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+        stats.history.versions = versions.iter().map(|(_, count)| count).sum();
+        stats.history.entries = versions.len();
+        stats.history.most = versions.into_iter().take(MOST_HISTORY).collect();
+        stats
+    }
+}
+
+/// Returns the total size in bytes of the files in `dir` and its subdirectories, or zero if it
+/// doesn't exist
+pub(crate) fn dir_size(dir: &Path) -> Result<u64> {
+    if !dir.is_dir() {
+        return Ok(0);
+    }
+
+    let mut size = 0;
+    for file in std::fs::read_dir(dir)? {
+        let file = file?;
+        size += match file.file_type()?.is_dir() {
+            true => dir_size(file.path().as_path())?,
+            false => file.metadata()?.len(),
+        };
+    }
+    Ok(size)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+
+    use crate::entry::{self, Metadata};
+    use crate::stats::{Sizes, Stats};
+
+    #[test]
+    fn entries_are_grouped() {
+        let now = entry::now();
+        let work = BTreeSet::from(["work".to_string()]);
+        let entries = vec![
+            ("github".to_string(), Metadata { modified: now, ..Metadata::default() }, work.clone(), 3),
+            ("work/email".to_string(), Metadata { modified: now - 100 * 86400, ..Metadata::default() }, work, 1),
+            ("work/vpn".to_string(), Metadata::default(), BTreeSet::new(), 0),
+        ];
+
+        let stats = Stats::new(entries, Sizes::default());
+        assert_eq!(stats.entries, 3);
+        assert_eq!(stats.folders, [("".to_string(), 1), ("work".to_string(), 2)].into());
+        assert_eq!(stats.tags, [("work".to_string(), 2)].into());
+        assert_eq!(stats.ages.iter().map(|(_, count)| *count).collect::<Vec<_>>(), vec![1, 0, 1, 0, 1]);
+        assert_eq!((stats.history.versions, stats.history.entries), (4, 2));
+        assert_eq!(stats.history.most[0], ("github".to_string(), 3));
+    }
+}
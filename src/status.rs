@@ -0,0 +1,102 @@
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::config;
+use crate::error::Result;
+use crate::global::LAST_SYNC_FILE;
+use crate::header::VaultHeader;
+use crate::session::{self, SessionState};
+use crate::sync::{self, SyncStatus};
+use crate::vault;
+
+/// VaultStatus summarizes the vault in a store, as shown by yap status. It is read without the
+/// master password, so entries are counted without decrypting anything, see `vault::list_names`.
+#[derive(Serialize, Debug)]
+pub struct VaultStatus {
+    pub store: String,
+    pub backend: &'static str,
+    pub entries: usize,
+    #[serde(skip)]
+    pub session: SessionState,
+    pub unlocked: bool,
+    /// Seconds until the vault's session is locked, if it is unlocked and has a timeout
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub locks_in: Option<u64>,
+    /// When the vault was last synced, in seconds since the Unix epoch
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_sync: Option<i64>,
+    pub remote: bool,
+    /// What a sync would change, if the vault has a remote and it could be reached
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pending: Option<SyncStatus>,
+    /// Why the remote couldn't be reached, if it couldn't
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sync_error: Option<String>,
+}
+
+/// Summarizes the vault in the given store, or the default store if None. Finding the changes a
+/// sync would make needs the remote, so a remote which can't be reached is reported rather than
+/// failing the whole status.
+pub fn status(store: Option<String>) -> Result<VaultStatus> {
+    let vault_dir = vault::get_path_or_default(store.clone())?;
+    let header = VaultHeader::read(vault_dir.as_path())?;
+    let config = config::read_or_default()?.with_vault(vault_dir.as_path())?;
+
+    let entries = vault::list_names(store.clone(), "")?;
+    let session = session::status(store)?;
+    let (unlocked, locks_in) = match session {
+        SessionState::Locked => (false, None),
+        SessionState::Unlocked(secs) => (true, secs),
+    };
+
+    let remote = !config.remote_url().is_empty();
+    let (pending, sync_error) = match remote {
+        true => match sync::status(vault_dir.clone(), config.remote_url(), false, false) {
+            Ok(pending) => (Some(pending), None),
+            Err(e) => (None, Some(e.to_string())),
+        },
+        false => (None, None),
+    };
+
+    Ok(VaultStatus {
+        store: vault_dir.display().to_string(),
+        backend: header.backend.name(),
+        entries: entries.len(),
+        session,
+        unlocked,
+        locks_in,
+        last_sync: last_sync(vault_dir.as_path()),
+        remote,
+        pending,
+        sync_error,
+    })
+}
+
+/// Returns when the vault in `vault_dir` was last synced, if it ever was, see `sync::sync`
+pub fn last_sync(vault_dir: &Path) -> Option<i64> {
+    std::fs::read_to_string(vault_dir.join(LAST_SYNC_FILE)).ok()?.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::crypto::Kdf;
+    use crate::status;
+    use crate::vault::{self, Backend};
+
+    #[test]
+    fn status_counts_entries_without_the_password() {
+        let yap_test = String::from(".yap_test_status");
+        let mut vault = vault::create(Some(yap_test.clone()), "asdf", Kdf::default(), Backend::default(), None).unwrap();
+        vault.set_key("github", "password".to_string()).unwrap();
+        vault.set_key("work/email", "password".to_string()).unwrap();
+        vault.delete_key("github").unwrap();
+
+        let status = status::status(Some(yap_test.clone())).unwrap();
+        assert_eq!(status.entries, 1);
+        assert_eq!(status.backend, "files");
+        assert_eq!(status.last_sync, None);
+
+        std::fs::remove_dir_all(yap_test).unwrap();
+    }
+}
@@ -0,0 +1,460 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::path::PathBuf;
+use std::process::Command;
+
+use clap::ValueEnum;
+use serde::Serialize;
+use tracing::{debug, info};
+
+use crate::activity;
+use crate::entry;
+use crate::error::{Error, Result};
+use crate::global::{ACTIVITY_FILE, ACTIVITY_KEY_FILE, BACKUPS_DIR, CONFIG_FILE, INDEX_FILE, LAST_SYNC_FILE, LOCAL_CONFIG_FILE, LOCK_FILE, QUARANTINE_DIR, SNAPSHOTS_DIR, TOKENS_FILE};
+use crate::lock;
+use crate::vault::{self, Vault};
+use crate::webdav::{self, WebDav};
+
+const REMOTE_NAME: &str = "origin";
+const SYNC_BRANCH: &str = "main";
+
+/// The suffix of the copies of local entries kept when a conflict is resolved by keeping both
+const CONFLICT_SUFFIX: &str = ".conflict";
+
+/// Resolution is which side of a conflict a sync keeps, when an entry was changed both locally
+/// and on the remote since the last sync
+#[derive(ValueEnum, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Resolution {
+    /// Keep the local entry, overwriting the remote one
+    Local,
+    /// Keep the remote entry, overwriting the local one
+    Remote,
+    /// Keep the remote entry, and the local one as `<name>.conflict`, see `keep_both`
+    Both,
+}
+
+/// Remote is somewhere a vault is synced with. Local changes are committed first, then remote
+/// changes are pulled, and finally local changes are pushed.
+pub trait Remote {
+    /// Records the local changes since the last sync, returning a description of them, or None
+    /// if there are none
+    fn commit(&mut self) -> Result<Option<String>>;
+
+    /// Brings changes from the remote into the vault. Conflicts with local changes are resolved
+    /// by keeping the side given by `resolution`, and if there is none the pull fails without
+    /// changing anything.
+    fn pull(&mut self, resolution: Option<Resolution>) -> Result<()>;
+
+    /// Sends local changes to the remote, resolving conflicts in the same way as `pull`
+    fn push(&mut self, resolution: Option<Resolution>) -> Result<()>;
+
+    /// Lists the files a sync would push, pull, or find in conflict, without changing the vault
+    fn status(&mut self) -> Result<SyncStatus>;
+}
+
+/// Opens the remote a vault directory is synced with. `dav://` and `davs://` URLs are synced with
+/// a WebDAV server, and anything else is a git remote.
+pub fn open(dir: PathBuf, remote_url: &str) -> Result<Box<dyn Remote>> {
+    if webdav::is_webdav(remote_url) {
+        Ok(Box::new(WebDav::open(dir, remote_url)?))
+    } else {
+        Ok(Box::new(GitRepo::open(dir, remote_url)?))
+    }
+}
+
+/// GitRepo is a vault directory which is tracked as a git repository. Since entries are stored
+/// encrypted in separate files, the files can be committed and pushed to a remote repository
+/// as-is. Git is invoked as a subprocess, so the user's own git configuration and credentials
+/// are used when talking to the remote.
+pub struct GitRepo {
+    dir: PathBuf,
+}
+
+impl GitRepo {
+    /// Opens the vault directory as a git repository, initializing it if needed, and points
+    /// the remote at `remote_url`. The config file is ignored since it is local to this machine.
+    pub fn open(dir: PathBuf, remote_url: &str) -> Result<GitRepo> {
+        if !dir.as_path().exists() {
+            std::fs::create_dir_all(dir.as_path())?;
+        }
+
+        let repo = GitRepo { dir };
+        if !repo.dir.join(".git").exists() {
+            repo.git(&["init", "--quiet", "--initial-branch", SYNC_BRANCH])?;
+            std::fs::write(repo.dir.join(".gitignore"), format!("{}\n", CONFIG_FILE))?;
+        }
+        // Tokens for `yap serve` are local to the server, and the local config, index, lock file,
+        // activity log, time of the last sync, quarantine, automatic backups, and snapshots to
+        // each copy of the vault. They are kept out of repositories created before them
+        let exclude = format!("{}\n{}\n{}\n{}\n{}\n{}\n{}\n/{}/\n/{}/\n/{}/\n", TOKENS_FILE, LOCAL_CONFIG_FILE, INDEX_FILE, LOCK_FILE, ACTIVITY_FILE, ACTIVITY_KEY_FILE, LAST_SYNC_FILE,
+            QUARANTINE_DIR, BACKUPS_DIR, SNAPSHOTS_DIR);
+        std::fs::write(repo.dir.join(".git/info/exclude"), exclude)?;
+
+        if repo.git(&["remote", "get-url", REMOTE_NAME]).is_ok() {
+            repo.git(&["remote", "set-url", REMOTE_NAME, remote_url])?;
+        } else {
+            repo.git(&["remote", "add", REMOTE_NAME, remote_url])?;
+        }
+
+        Ok(repo)
+    }
+
+    /// Checks whether the given ref exists in the repository
+    fn has_ref(&self, name: &str) -> bool {
+        self.git(&["rev-parse", "--verify", "--quiet", name]).is_ok()
+    }
+
+    /// Runs git with the given arguments inside the vault directory, returning its stdout.
+    fn git(&self, args: &[&str]) -> Result<String> {
+        debug!("running git {}", args.join(" "));
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(self.dir.as_path())
+            .args(args)
+            .output()?;
+
+        if output.status.success() {
+            Ok(String::from_utf8(output.stdout)?)
+        } else {
+            debug!("git {} failed with {}", args.first().unwrap_or(&""), output.status);
+            Err(Error::Git { message: String::from_utf8_lossy(&output.stderr).trim().to_string() })
+        }
+    }
+}
+
+impl Remote for GitRepo {
+    /// Commits every change in the vault, with a message describing which keys were added,
+    /// modified, or deleted. Returns the commit message, or None if there was nothing to commit.
+    fn commit(&mut self) -> Result<Option<String>> {
+        self.git(&["add", "--all"])?;
+
+        let changes = self.git(&["diff", "--cached", "--name-status"])?;
+        if changes.trim().is_empty() {
+            return Ok(None);
+        }
+
+        let message = commit_message(changes.as_str());
+        self.git(&["commit", "--quiet", "--message", message.as_str()])?;
+        Ok(Some(message))
+    }
+
+    /// Fetches the remote branch and rebases local commits on top of it. Conflicting changes are
+    /// resolved by git's ours or theirs strategy options, and if the rebase still conflicts, it
+    /// is aborted so the vault is left as it was before pulling.
+    fn pull(&mut self, resolution: Option<Resolution>) -> Result<()> {
+        self.git(&["fetch", "--quiet", REMOTE_NAME])?;
+
+        let remote_branch = format!("{}/{}", REMOTE_NAME, SYNC_BRANCH);
+        if !self.has_ref(remote_branch.as_str()) {
+            // Nothing has been pushed to the remote yet
+            return Ok(());
+        }
+
+        if !self.has_ref("HEAD") {
+            self.git(&["checkout", "--quiet", "-B", SYNC_BRANCH, remote_branch.as_str()])?;
+            return Ok(());
+        }
+
+        // While rebasing, ours is the remote branch and theirs is the local commits
+        let strategy = match resolution {
+            Some(Resolution::Local) => vec!["--strategy-option", "theirs"],
+            Some(Resolution::Remote | Resolution::Both) => vec!["--strategy-option", "ours"],
+            None => vec![],
+        };
+        let mut args = vec!["rebase", "--quiet"];
+        args.extend(strategy);
+        args.push(remote_branch.as_str());
+
+        if self.git(args.as_slice()).is_err() {
+            let conflicts = self.git(&["diff", "--name-only", "--diff-filter=U"]).unwrap_or_default();
+            self.git(&["rebase", "--abort"])?;
+            return Err(Error::SyncConflict { names: conflicts.lines().map(String::from).collect() });
+        }
+
+        Ok(())
+    }
+
+    /// Pushes local commits to the remote branch. Conflicts were already resolved by pulling.
+    fn push(&mut self, _: Option<Resolution>) -> Result<()> {
+        if self.has_ref("HEAD") {
+            self.git(&["push", "--quiet", REMOTE_NAME, SYNC_BRANCH])?;
+        }
+        Ok(())
+    }
+
+    /// Fetches the remote branch and compares the files changed on each side since the commit
+    /// they share, counting uncommitted changes as local ones. A file changed on both sides is
+    /// a conflict. Fetching only updates the remote-tracking branch, so the vault is unchanged.
+    fn status(&mut self) -> Result<SyncStatus> {
+        self.git(&["fetch", "--quiet", REMOTE_NAME])?;
+        let remote_branch = format!("{}/{}", REMOTE_NAME, SYNC_BRANCH);
+        let (has_head, has_remote) = (self.has_ref("HEAD"), self.has_ref(remote_branch.as_str()));
+
+        let mut local = self.git(&["ls-files", "--others", "--exclude-standard"])?;
+        if has_head {
+            local += self.git(&["diff", "--name-only", "HEAD"])?.as_str();
+        }
+        local += match (has_head, has_remote) {
+            (true, true) => self.git(&["diff", "--name-only", format!("{}...HEAD", remote_branch).as_str()])?,
+            (true, false) => self.git(&["ls-files"])?,
+            (false, _) => String::new(),
+        }.as_str();
+
+        let remote = match (has_head, has_remote) {
+            (true, true) => self.git(&["diff", "--name-only", format!("HEAD...{}", remote_branch).as_str()])?,
+            (false, true) => self.git(&["ls-tree", "-r", "--name-only", remote_branch.as_str()])?,
+            (_, false) => String::new(),
+        };
+
+        let local: BTreeSet<&str> = local.lines().collect();
+        let remote: BTreeSet<&str> = remote.lines().collect();
+        Ok(SyncStatus {
+            push: local.difference(&remote).map(|name| name.to_string()).collect(),
+            pull: remote.difference(&local).map(|name| name.to_string()).collect(),
+            conflicts: local.intersection(&remote).map(|name| name.to_string()).collect(),
+        })
+    }
+}
+
+/// SyncSummary describes what a sync did
+#[derive(Serialize)]
+pub struct SyncSummary {
+    /// A description of the local changes, if there were any. For git remotes, this is the
+    /// message of the commit holding them.
+    pub committed: Option<String>,
+    pub pulled: bool,
+    pub pushed: bool,
+    /// The entries which conflicted, and were resolved by keeping one side or both
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub resolved: Vec<String>,
+}
+
+impl fmt::Display for SyncSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(message) = &self.committed {
+            writeln!(f, "Committed: {}", message)?;
+        }
+        if self.pulled {
+            writeln!(f, "Pulled changes from remote")?;
+        }
+        if self.pushed {
+            writeln!(f, "Pushed changes to remote")?;
+        }
+        if !self.resolved.is_empty() {
+            writeln!(f, "Resolved conflicts in {}", self.resolved.join(", "))?;
+        }
+        write!(f, "Successfully synced vault")
+    }
+}
+
+/// SyncStatus lists the entries a sync would change on each side
+#[derive(Serialize, Default, Debug)]
+pub struct SyncStatus {
+    /// The entries changed locally, which a sync would send to the remote
+    pub push: Vec<String>,
+    /// The entries changed on the remote, which a sync would bring into the vault
+    pub pull: Vec<String>,
+    /// The entries changed on both sides, which need a resolution, see `Resolution`
+    pub conflicts: Vec<String>,
+}
+
+impl fmt::Display for SyncStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.push.is_empty() && self.pull.is_empty() && self.conflicts.is_empty() {
+            return write!(f, "Vault is up to date with remote");
+        }
+
+        let lines: Vec<String> = [("To push", &self.push), ("To pull", &self.pull), ("Conflicts", &self.conflicts)]
+            .into_iter()
+            .filter(|(_, names)| !names.is_empty())
+            .map(|(label, names)| format!("{}: {}", label, names.join(", ")))
+            .collect();
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+/// Syncs the vault directory with the remote at `remote_url`, see `Remote`. When neither `pull`
+/// nor `push` is set, both are done. Keeping both sides of a conflict needs the local entries
+/// to be copied aside first, see `keep_both`.
+pub fn sync(vault_dir: PathBuf, remote_url: &str, pull: bool, push: bool, resolution: Option<Resolution>) -> Result<SyncSummary> {
+    if remote_url.is_empty() {
+        return Err(Error::NoRemote);
+    }
+
+    let (pull, push) = if pull || push { (pull, push) } else { (true, true) };
+    // Opening the remote creates the vault directory when syncing into a new one
+    let mut remote = open(vault_dir.clone(), remote_url)?;
+    let _lock = lock::lock(vault_dir.as_path())?;
+
+    let committed = remote.commit()?;
+    if let Some(message) = committed.as_ref() {
+        info!("committed local changes: {}", message);
+    }
+    if pull {
+        info!("pulling from the remote");
+        remote.pull(resolution)?;
+    }
+    if push {
+        info!("pushing to the remote");
+        remote.push(resolution)?;
+    }
+    // Entries changed by the sync aren't in the index, so it is rebuilt when next needed
+    vault::remove_index(vault_dir.as_path())?;
+    vault::atomic_write(vault_dir.join(LAST_SYNC_FILE).as_path(), entry::now().to_string().as_bytes())?;
+    activity::record(vault_dir.as_path(), "sync", "")?;
+
+    Ok(SyncSummary { committed, pulled: pull, pushed: push, resolved: vec![] })
+}
+
+/// Shows what syncing the vault directory with the remote at `remote_url` would do, without
+/// doing it. As with `sync`, `pull` and `push` limit which sides are shown. Files which aren't
+/// entries, such as the vault header and entry history, are left out.
+pub fn status(vault_dir: PathBuf, remote_url: &str, pull: bool, push: bool) -> Result<SyncStatus> {
+    if remote_url.is_empty() {
+        return Err(Error::NoRemote);
+    }
+
+    let (pull, push) = if pull || push { (pull, push) } else { (true, true) };
+    let mut status = open(vault_dir, remote_url)?.status()?;
+    for names in [&mut status.push, &mut status.pull, &mut status.conflicts] {
+        names.retain(|name| is_entry(name));
+    }
+    if !pull {
+        status.pull.clear();
+    }
+    if !push {
+        status.push.clear();
+    }
+    Ok(status)
+}
+
+/// Copies each conflicting local entry to `<name>.conflict`, so the next sync can take the
+/// remote entry while the local one is kept. Entries are bound to their names, so they are
+/// copied through the vault rather than as files. Conflicting files which aren't entries, such
+/// as the history of an entry, are left to take the remote copy. Returns the names of the copies.
+pub fn keep_both(vault: &mut dyn Vault, conflicts: &[String]) -> Result<Vec<String>> {
+    let mut copies = vec![];
+    for name in conflicts.iter().filter(|name| is_entry(name)) {
+        if !vault.has_key(name) {
+            continue;
+        }
+        let copy = (1..)
+            .map(|i| match i {
+                1 => format!("{}{}", name, CONFLICT_SUFFIX),
+                i => format!("{}{}{}", name, CONFLICT_SUFFIX, i),
+            })
+            .find(|copy| !vault.has_key(copy))
+            .unwrap_or_default();
+
+        let entry = vault.get_entry(name)?;
+        vault.set_entry(copy.as_str(), &entry)?;
+        copies.push(copy);
+    }
+    Ok(copies)
+}
+
+/// Checks whether a file in the vault directory holds an entry, rather than something like the
+/// vault header or the history of an entry, which are kept in dotfiles
+fn is_entry(name: &str) -> bool {
+    !name.split('/').any(|part| part.starts_with('.'))
+}
+
+/// Builds a commit message from the output of `git diff --name-status`. Dotfiles, such as the
+/// vault header, are not keys so they are left out of the message.
+pub(crate) fn commit_message(changes: &str) -> String {
+    let mut added = vec![];
+    let mut modified = vec![];
+    let mut deleted = vec![];
+
+    for line in changes.lines() {
+        let mut fields = line.split('\t');
+        let status = fields.next().unwrap_or_default();
+        let names: Vec<&str> = fields.filter(|name| !name.starts_with('.')).collect();
+
+        match (status.chars().next(), names.as_slice()) {
+            (Some('A'), [name]) => added.push(name.to_string()),
+            (Some('D'), [name]) => deleted.push(name.to_string()),
+            (Some('R'), [from, to]) => modified.push(format!("{} -> {}", from, to)),
+            (_, [name]) => modified.push(name.to_string()),
+            _ => {}
+        }
+    }
+
+    let parts: Vec<String> = [("Add", added), ("Update", modified), ("Delete", deleted)]
+        .into_iter()
+        .filter(|(_, keys)| !keys.is_empty())
+        .map(|(verb, keys)| format!("{} {}", verb, keys.join(", ")))
+        .collect();
+
+    if parts.is_empty() {
+        "Update vault metadata".to_string()
+    } else {
+        parts.join("; ")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+    use std::process::Command;
+    use crate::error::Error;
+    use crate::global::LOCAL_CONFIG_FILE;
+    use crate::sync::{sync, status, commit_message, GitRepo, Resolution};
+
+    #[test]
+    fn commit_message_describes_changes() {
+        let changes = "A\tgithub\nM\temail\nD\told\nA\t.header.yaml\nR100\ta\tb\n";
+        assert_eq!(commit_message(changes), "Add github; Update email, a -> b; Delete old");
+        assert_eq!(commit_message("A\t.gitignore\n"), "Update vault metadata");
+    }
+
+    #[test]
+    fn push_and_pull_between_vaults() {
+        let remote = Path::new(".yap_test_sync_remote");
+        let vault_a = Path::new(".yap_test_sync_a");
+        let vault_b = Path::new(".yap_test_sync_b");
+        std::fs::create_dir_all(remote).unwrap();
+        Command::new("git").arg("-C").arg(remote).args(["init", "--quiet", "--bare"]).status().unwrap();
+        let remote_url = std::fs::canonicalize(remote).unwrap().display().to_string();
+        for vault in [vault_a, vault_b] {
+            GitRepo::open(vault.to_path_buf(), remote_url.as_str()).unwrap();
+            for (key, value) in [("user.name", "yap"), ("user.email", "yap@localhost")] {
+                Command::new("git").arg("-C").arg(vault).args(["config", key, value]).status().unwrap();
+            }
+        }
+
+        std::fs::write(vault_a.join("github"), "ciphertext").unwrap();
+        std::fs::write(vault_a.join(LOCAL_CONFIG_FILE), "keyfile: /keys/vault\n").unwrap();
+        sync(vault_a.to_path_buf(), remote_url.as_str(), false, false, None).unwrap();
+
+        // The local config holds paths on this machine, so it stays behind
+        sync(vault_b.to_path_buf(), remote_url.as_str(), true, false, None).unwrap();
+        assert_eq!(std::fs::read_to_string(vault_b.join("github")).unwrap(), "ciphertext");
+        assert!(!vault_b.join(LOCAL_CONFIG_FILE).exists());
+
+        std::fs::write(vault_b.join("github"), "new ciphertext").unwrap();
+        assert_eq!(status(vault_b.to_path_buf(), remote_url.as_str(), false, false).unwrap().push, ["github"]);
+        sync(vault_b.to_path_buf(), remote_url.as_str(), false, true, None).unwrap();
+        assert_eq!(status(vault_a.to_path_buf(), remote_url.as_str(), false, false).unwrap().pull, ["github"]);
+        sync(vault_a.to_path_buf(), remote_url.as_str(), true, false, None).unwrap();
+        assert_eq!(std::fs::read_to_string(vault_a.join("github")).unwrap(), "new ciphertext");
+
+        // Both vaults change the same entry, which conflicts until one side is chosen
+        std::fs::write(vault_a.join("github"), "ciphertext a").unwrap();
+        sync(vault_a.to_path_buf(), remote_url.as_str(), false, false, None).unwrap();
+        std::fs::write(vault_b.join("github"), "ciphertext b").unwrap();
+        assert_eq!(status(vault_b.to_path_buf(), remote_url.as_str(), false, false).unwrap().conflicts, ["github"]);
+        let conflict = sync(vault_b.to_path_buf(), remote_url.as_str(), false, false, None);
+        assert!(matches!(conflict, Err(Error::SyncConflict { names }) if names == ["github"]));
+        assert_eq!(std::fs::read_to_string(vault_b.join("github")).unwrap(), "ciphertext b");
+
+        sync(vault_b.to_path_buf(), remote_url.as_str(), false, false, Some(Resolution::Local)).unwrap();
+        sync(vault_a.to_path_buf(), remote_url.as_str(), false, false, None).unwrap();
+        assert_eq!(std::fs::read_to_string(vault_a.join("github")).unwrap(), "ciphertext b");
+
+        for dir in [remote, vault_a, vault_b] {
+            std::fs::remove_dir_all(dir).unwrap();
+        }
+    }
+}
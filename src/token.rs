@@ -0,0 +1,183 @@
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::display;
+use crate::entry;
+use crate::error::{Error, Result};
+use crate::global::TOKENS_FILE;
+use crate::server;
+use crate::vault;
+use crate::webdav;
+
+/// The number of seconds in a day, for token expiry given in days
+const DAY: i64 = 24 * 60 * 60;
+
+/// Token is a bearer token clients of `yap serve` authenticate with, limited to what it was
+/// created for. Only the SHA-256 hash of the token is kept, so the tokens file can't be used to
+/// authenticate.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Token {
+    pub name: String,
+    hash: String,
+    /// Whether the token can only list and get entries
+    #[serde(default)]
+    pub read_only: bool,
+    /// The prefixes of the entries the token can use, or every entry if empty. A prefix ending in
+    /// `/` is a folder, and any other prefix is an entry along with the folder of the same name
+    #[serde(default)]
+    pub prefixes: Vec<String>,
+    /// When the token stops working, as seconds since the Unix epoch, or never if None
+    #[serde(default)]
+    pub expires: Option<i64>,
+    pub created: i64,
+}
+
+impl Token {
+    /// Checks whether the token can be used to authenticate
+    pub fn is_expired(&self) -> bool {
+        self.expires.is_some_and(|expires| expires <= entry::now())
+    }
+
+    /// Checks whether the token can use the entry with the given name. Names are compared by
+    /// whole parts, so a token for `work/` can't use `workshop`, and names with parts such as
+    /// `..` never match, since they aren't valid entry names.
+    pub fn allows(&self, name: &str) -> bool {
+        if vault::validate_key(name).is_err() {
+            return false;
+        }
+        self.prefixes.is_empty() || self.prefixes.iter().any(|prefix| within(name, prefix.as_str()))
+    }
+}
+
+/// Checks whether the entry with the given name is the entry or in the folder named by `prefix`
+fn within(name: &str, prefix: &str) -> bool {
+    let folder = prefix.ends_with('/');
+    let prefix: Vec<&str> = prefix.split('/').filter(|part| !part.is_empty()).collect();
+    let parts: Vec<&str> = name.split('/').collect();
+    parts.len() >= prefix.len() + usize::from(folder) && parts.iter().zip(prefix.iter()).all(|(part, prefix)| part == prefix)
+}
+
+/// Tokens are the tokens created for the vault served from a directory, kept in its tokens file.
+/// The file is local to the server, so it is never synced or served.
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct Tokens(Vec<Token>);
+
+impl Tokens {
+    /// Reads the tokens created for the vault in a directory
+    pub fn read(dir: &Path) -> Result<Tokens> {
+        match std::fs::read(dir.join(TOKENS_FILE)) {
+            Ok(contents) => Ok(serde_yaml::from_slice(contents.as_slice())?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Tokens::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn write(&self, dir: &Path) -> Result<()> {
+        std::fs::create_dir_all(dir)?;
+        vault::atomic_write(dir.join(TOKENS_FILE).as_path(), serde_yaml::to_string(self)?.as_bytes())
+    }
+
+    /// Returns the token which hashes to the same value as a token given by a client
+    pub fn find(&self, given: &str) -> Option<&Token> {
+        let hash = webdav::hash(given.as_bytes());
+        self.0.iter().find(|token| ring::constant_time::verify_slices_are_equal(token.hash.as_bytes(), hash.as_bytes()).is_ok())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// Creates a token for the vault in a directory, returning the token itself, which is only ever
+/// shown this once. The token expires after `expires_in` days, if given.
+pub fn create(dir: &Path, name: &str, read_only: bool, prefixes: Vec<String>, expires_in: Option<u32>) -> Result<String> {
+    let mut tokens = Tokens::read(dir)?;
+    if tokens.0.iter().any(|token| token.name == name) {
+        return Err(Error::TokenExists { name: name.to_string() });
+    }
+
+    let secret = server::new_token()?;
+    let created = entry::now();
+    tokens.0.push(Token {
+        name: name.to_string(),
+        hash: webdav::hash(secret.as_bytes()),
+        read_only,
+        prefixes,
+        expires: expires_in.map(|days| created + days as i64 * DAY),
+        created,
+    });
+    tokens.write(dir)?;
+    Ok(secret)
+}
+
+/// Revokes the token with the given name, so it can no longer be used to authenticate
+pub fn revoke(dir: &Path, name: &str) -> Result<()> {
+    let mut tokens = Tokens::read(dir)?;
+    let count = tokens.0.len();
+    tokens.0.retain(|token| token.name != name);
+    if tokens.0.len() == count {
+        return Err(Error::TokenNotFound { name: name.to_string() });
+    }
+    tokens.write(dir)
+}
+
+/// Lists the tokens created for the vault in a directory
+pub fn list(dir: &Path) -> Result<Vec<Token>> {
+    Ok(Tokens::read(dir)?.0)
+}
+
+/// Listing renders tokens in columns along with what each can access and when it expires
+pub struct Listing(pub Vec<Token>);
+
+impl Display for Listing {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let width = self.0.iter().map(|token| token.name.len()).max().unwrap_or_default().max(4);
+        let row = |name: &str, access: &str, expires: &str, prefixes: &str| {
+            format!("{:width$}  {:10}  {:16}  {}", name, access, expires, prefixes)
+        };
+
+        let mut lines = vec![row("NAME", "ACCESS", "EXPIRES", "PREFIXES")];
+        for token in &self.0 {
+            let access = if token.read_only { "read-only" } else { "read-write" };
+            let expires = match token.expires {
+                Some(_) if token.is_expired() => "expired".to_string(),
+                Some(expires) => display::date(expires),
+                None => "never".to_string(),
+            };
+            let prefixes = if token.prefixes.is_empty() { "*".to_string() } else { token.prefixes.join(", ") };
+            lines.push(row(token.name.as_str(), access, expires.as_str(), prefixes.as_str()));
+        }
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use crate::error::Error;
+    use crate::token::{self, Tokens};
+
+    #[test]
+    fn tokens_are_stored_hashed_and_scoped() {
+        let dir = Path::new(".yap_test_token");
+        let secret = token::create(dir, "ci", true, vec!["work/".to_string()], Some(30)).unwrap();
+        assert!(matches!(token::create(dir, "ci", false, vec![], None), Err(Error::TokenExists { .. })));
+        assert!(!std::fs::read_to_string(dir.join(".tokens.yaml")).unwrap().contains(secret.as_str()));
+
+        let tokens = Tokens::read(dir).unwrap();
+        let token = tokens.find(secret.as_str()).unwrap();
+        assert!(token.read_only && !token.is_expired());
+        assert!(token.allows("work/github") && !token.allows("email"));
+        assert!(!token.allows("work") && !token.allows("workshop/github") && !token.allows("work/../bank"));
+        assert!(tokens.find("wrong").is_none());
+
+        token::revoke(dir, "ci").unwrap();
+        assert!(Tokens::read(dir).unwrap().find(secret.as_str()).is_none());
+        assert!(matches!(token::revoke(dir, "ci"), Err(Error::TokenNotFound { .. })));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,292 @@
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::widgets::{Block, Borders, List, ListState, Paragraph, Wrap};
+use ratatui::Frame;
+use zeroize::Zeroizing;
+
+use crate::clipboard;
+use crate::entry::Entry;
+use crate::error::Result;
+use crate::search::Matcher;
+use crate::vault::Vault;
+
+/// The fields which can be edited inline, in the order Tab moves between them
+const EDITABLE_FIELDS: &[&str] = &["password", "username", "url"];
+
+/// The keys shown at the bottom of the screen while browsing
+const HELP: &str = "type to search | ↑↓ select | enter reveal | ^y copy | ^e edit | esc quit";
+
+/// Edit is a field of the selected entry being edited inline
+struct Edit {
+    field: usize,
+    value: Zeroizing<String>,
+}
+
+/// Browser is the state of the terminal interface: the entries matching the search, the
+/// selected entry, and the field being edited, if any. Entries are only decrypted when they are
+/// selected, and secrets are only shown once they are revealed.
+pub struct Browser<'a> {
+    vault: &'a mut dyn Vault,
+    names: Vec<String>,
+    query: String,
+    matches: Vec<usize>,
+    list: ListState,
+    entry: Option<Entry>,
+    revealed: bool,
+    editing: Option<Edit>,
+    status: String,
+    clip_timeout: u64,
+}
+
+impl<'a> Browser<'a> {
+    /// Creates a browser for every entry in the vault, with the first one selected. Copied
+    /// secrets are cleared from the clipboard after `clip_timeout` seconds.
+    pub fn new(vault: &'a mut dyn Vault, clip_timeout: u64) -> Result<Browser<'a>> {
+        let names = vault.list_keys("")?;
+        let mut browser = Browser {
+            vault, names, query: String::new(), matches: vec![], list: ListState::default(),
+            entry: None, revealed: false, editing: None, status: HELP.to_string(), clip_timeout,
+        };
+        browser.search()?;
+        Ok(browser)
+    }
+
+    /// Returns the name of the selected entry
+    fn selected(&self) -> Option<&str> {
+        self.list.selected()
+            .and_then(|i| self.matches.get(i))
+            .map(|i| self.names[*i].as_str())
+    }
+
+    /// Updates the entries matching the search query, and selects the first of them
+    fn search(&mut self) -> Result<()> {
+        let matcher = Matcher::new(self.query.as_str(), false, true)?;
+        self.matches = (0..self.names.len()).filter(|i| matcher.is_match(self.names[*i].as_str())).collect();
+        self.select(0)
+    }
+
+    /// Selects the matching entry at an index, decrypting it to show its fields
+    fn select(&mut self, i: usize) -> Result<()> {
+        self.revealed = false;
+        self.entry = None;
+        if self.matches.is_empty() {
+            self.list.select(None);
+            return Ok(());
+        }
+
+        self.list.select(Some(i.min(self.matches.len() - 1)));
+        if let Some(name) = self.selected().map(|name| name.to_string()) {
+            self.entry = Some(self.vault.get_entry(name.as_str())?);
+        }
+        Ok(())
+    }
+
+    /// Handles a key press, returning true when the browser should exit
+    pub fn handle(&mut self, key: KeyEvent) -> Result<bool> {
+        if self.editing.is_some() {
+            self.handle_edit(key)?;
+            return Ok(false);
+        }
+
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        let selected = self.list.selected().unwrap_or_default();
+        match key.code {
+            KeyCode::Esc if self.query.is_empty() => return Ok(true),
+            KeyCode::Char('c') if ctrl => return Ok(true),
+            KeyCode::Esc => {
+                self.query.clear();
+                self.search()?;
+            }
+            KeyCode::Up => self.select(selected.saturating_sub(1))?,
+            KeyCode::Down => self.select(selected + 1)?,
+            KeyCode::PageUp => self.select(selected.saturating_sub(10))?,
+            KeyCode::PageDown => self.select(selected + 10)?,
+            KeyCode::Enter => self.reveal()?,
+            KeyCode::Char('y') if ctrl => self.copy()?,
+            KeyCode::Char('e') if ctrl => {
+                if let Some(entry) = &self.entry {
+                    self.editing = Some(Edit { field: 0, value: Zeroizing::new(entry.password.clone()) });
+                }
+            }
+            KeyCode::Backspace => {
+                self.query.pop();
+                self.search()?;
+            }
+            KeyCode::Char(c) if !ctrl => {
+                self.query.push(c);
+                self.search()?;
+            }
+            _ => {}
+        }
+        Ok(false)
+    }
+
+    /// Handles a key press while a field is being edited. Enter saves the field, Tab moves to the
+    /// next field, and Esc stops editing without saving.
+    fn handle_edit(&mut self, key: KeyEvent) -> Result<()> {
+        let (Some(edit), Some(entry)) = (self.editing.as_mut(), self.entry.as_mut()) else { return Ok(()) };
+        match key.code {
+            KeyCode::Esc => {
+                self.editing = None;
+                self.status = HELP.to_string();
+            }
+            KeyCode::Tab => {
+                edit.field = (edit.field + 1) % EDITABLE_FIELDS.len();
+                edit.value = Zeroizing::new(entry.field(EDITABLE_FIELDS[edit.field]).unwrap_or_default().to_string());
+            }
+            KeyCode::Enter => {
+                let field = EDITABLE_FIELDS[edit.field];
+                entry.set_field(field, edit.value.to_string());
+                let name = self.names[self.matches[self.list.selected().unwrap_or_default()]].clone();
+                self.vault.set_entry(name.as_str(), entry)?;
+                self.editing = None;
+                self.status = format!("Saved the {} of {}", field, name);
+            }
+            KeyCode::Backspace => {
+                edit.value.pop();
+            }
+            KeyCode::Char(c) => edit.value.push(c),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Shows or hides the secret of the selected entry. Revealing it counts as accessing it.
+    fn reveal(&mut self) -> Result<()> {
+        if let Some(name) = self.selected().map(|name| name.to_string()) {
+            if !self.revealed {
+                self.entry = Some(self.vault.access_entry(name.as_str())?);
+            }
+            self.revealed = !self.revealed;
+        }
+        Ok(())
+    }
+
+    /// Copies the secret of the selected entry to the clipboard, as `get --clip` does
+    fn copy(&mut self) -> Result<()> {
+        let Some(name) = self.selected().map(|name| name.to_string()) else { return Ok(()) };
+        let entry = self.vault.access_entry(name.as_str())?;
+        let field = entry.secret_field();
+
+        clipboard::copy_with_timeout(entry.field(field).unwrap_or_default(), self.clip_timeout)?;
+        self.status = format!("Copied {} of {} to the clipboard", field, name);
+        Ok(())
+    }
+
+    /// Renders the search box, the matching entries, the selected entry, and the status line
+    fn draw(&mut self, frame: &mut Frame) {
+        let [search, body, status] = Layout::vertical([Constraint::Length(3), Constraint::Min(1), Constraint::Length(1)])
+            .areas(frame.area());
+        let [list, details] = Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)]).areas(body);
+
+        frame.render_widget(Paragraph::new(self.query.as_str()).block(Block::default().borders(Borders::ALL).title("Search")), search);
+
+        let names: Vec<&str> = self.matches.iter().map(|i| self.names[*i].as_str()).collect();
+        let names = List::new(names)
+            .block(Block::default().borders(Borders::ALL).title(format!("Entries ({})", self.matches.len())))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(names, list, &mut self.list);
+
+        let text = self.entry.as_ref().map(|entry| self.details(entry)).unwrap_or_default();
+        let title = self.selected().unwrap_or_default().to_string();
+        frame.render_widget(Paragraph::new(text).wrap(Wrap { trim: false }).block(Block::default().borders(Borders::ALL).title(title)), details);
+
+        let status_line = match &self.editing {
+            Some(edit) => format!("{}: {}  (enter save | tab next field | esc cancel)", EDITABLE_FIELDS[edit.field], edit.value.as_str()),
+            None => self.status.clone(),
+        };
+        frame.render_widget(Paragraph::new(status_line), status);
+    }
+
+    /// Renders the fields of an entry, hiding its secret unless it has been revealed
+    fn details(&self, entry: &Entry) -> String {
+        let secret = entry.secret_field();
+        let mut fields = vec![("password", Some(entry.password.as_str())), ("username", entry.username.as_deref()),
+            ("url", entry.url.as_deref()), ("notes", entry.notes.as_deref())];
+        fields.extend(entry.fields.iter().map(|(name, value)| (name.as_str(), Some(value.as_str()))));
+
+        fields.into_iter()
+            .filter_map(|(name, value)| value.filter(|value| !value.is_empty()).map(|value| (name, value)))
+            .map(|(name, value)| match name == secret && !self.revealed {
+                true => format!("{}: {}", name, "•".repeat(8)),
+                false => format!("{}: {}", name, value),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// Runs the terminal interface until the user quits, restoring the terminal afterwards
+pub fn run(vault: &mut dyn Vault, clip_timeout: u64) -> Result<()> {
+    let mut browser = Browser::new(vault, clip_timeout)?;
+    let mut terminal = ratatui::init();
+
+    let result = loop {
+        if let Err(e) = terminal.draw(|frame| browser.draw(frame)) {
+            break Err(e.into());
+        }
+        match event::read() {
+            Ok(Event::Key(key)) if key.kind == KeyEventKind::Press => match browser.handle(key) {
+                Ok(true) => break Ok(()),
+                Ok(false) => {}
+                // Errors such as a missing clipboard are shown rather than ending the session
+                Err(e) => browser.status = e.to_string(),
+            },
+            Ok(_) => {}
+            Err(e) => break Err(e.into()),
+        }
+    };
+
+    ratatui::restore();
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+    use crate::crypto::Kdf;
+    use crate::tui::Browser;
+    use crate::vault::{self, Backend};
+
+    #[test]
+    fn browser_searches_and_edits_entries() {
+        let yap_test = String::from(".yap_test_tui");
+        let mut vault = vault::create(Some(yap_test.clone()), "asdf", Kdf::default(), Backend::default(), None).unwrap();
+        for name in ["email", "work/github", "work/aws"] {
+            vault.set_key(name, format!("{} password", name)).unwrap();
+        }
+
+        let key = |code| KeyEvent::new(code, KeyModifiers::NONE);
+        let mut browser = Browser::new(vault.as_mut(), 45).unwrap();
+        assert_eq!(browser.selected(), Some("email"));
+
+        for c in "WORK".chars() {
+            browser.handle(key(KeyCode::Char(c))).unwrap();
+        }
+        assert_eq!(browser.matches.len(), 2);
+        assert_eq!(browser.selected(), Some("work/aws"));
+        browser.handle(key(KeyCode::Down)).unwrap();
+        browser.handle(key(KeyCode::Down)).unwrap();
+        assert_eq!(browser.selected(), Some("work/github"));
+
+        let entry = browser.entry.clone().unwrap();
+        assert!(!browser.details(&entry).contains("work/github password"));
+        browser.handle(key(KeyCode::Enter)).unwrap();
+        assert!(browser.details(&entry).contains("work/github password"));
+
+        browser.handle(KeyEvent::new(KeyCode::Char('e'), KeyModifiers::CONTROL)).unwrap();
+        browser.handle(key(KeyCode::Tab)).unwrap();
+        for c in "alice".chars() {
+            browser.handle(key(KeyCode::Char(c))).unwrap();
+        }
+        browser.handle(key(KeyCode::Enter)).unwrap();
+        assert!(!browser.handle(key(KeyCode::Esc)).unwrap());
+        assert!(browser.handle(key(KeyCode::Esc)).unwrap());
+
+        assert_eq!(vault.get_entry("work/github").unwrap().username.as_deref(), Some("alice"));
+
+        std::fs::remove_dir_all(yap_test).unwrap();
+    }
+}
@@ -1,69 +1,1897 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use crate::{Error, Result, global};
-use crate::crypto::Aes256GcmEngine;
+use age::x25519::Recipient;
+use rayon::prelude::*;
+use serde::{Serialize, Deserialize};
+use tracing::{debug, info, trace};
+use zeroize::Zeroizing;
+
+use crate::{config, Error, Result, global};
+use crate::activity::{self, Activity, ActivityVault};
+use crate::autobackup::{self, BackupVault};
+use crate::crypto::{self, Aes256GcmEngine, Kdf, KEY_LEN};
+use crate::entry::{self, Entry, Metadata};
+use crate::header::VaultHeader;
+use crate::index::Index;
+use crate::lock::{self, LockingVault};
+use crate::readonly::ReadOnlyVault;
+use crate::remote::{self, RemoteStore, RemoteVault};
+use crate::share;
+use crate::sign::{self, SigningVault};
+use crate::snapshot;
+use crate::sqlite::{self, SqliteVault};
+use crate::stats::{self, Sizes, Stats};
+use crate::verify::{Action, Problem, RepairReport, Report};
+use crate::wipe;
+
+/// Vault is the interface to a store of encrypted entries. Backends may store entries in
+/// different ways, but every backend encrypts entries with a key derived from the master
+/// password and exposes them by name through this trait. Vaults can be handed to other threads,
+/// such as the ones serving the Secret Service.
+pub trait Vault: Send {
+    /// Checks whether an entry with the given name exists in the vault
+    fn has_key(&self, key: &str) -> bool;
+
+    /// Reads and decrypts the entry with the given name
+    fn get_entry(&self, key: &str) -> Result<Entry>;
+
+    /// Reads and decrypts the entry with the given name, recording that it was accessed
+    fn access_entry(&mut self, key: &str) -> Result<Entry>;
+
+    /// Encrypts and writes the entry with the given name, replacing it if it exists
+    fn set_entry(&mut self, key: &str, entry: &Entry) -> Result<()>;
+
+    /// Encrypts and writes several entries at once, like `set_entry`. Either every entry is
+    /// written, or none are. If a name is given more than once, the last entry is kept.
+    fn set_entries(&mut self, entries: &[(String, Entry)]) -> Result<()>;
+
+    /// Deletes the entry with the given name from the vault, moving it into the trash along with
+    /// its history and attachments
+    fn delete_key(&mut self, key: &str) -> Result<()>;
+
+    /// Renames an entry, replacing an existing entry at `to` only if `force` is set
+    fn rename_key(&mut self, from: &str, to: &str, force: bool) -> Result<()>;
+
+    /// Lists the names of all entries which start with `prefix`, sorted by name
+    fn list_keys(&self, prefix: &str) -> Result<Vec<String>>;
+
+    /// Encrypts everything read from `reader` as an attachment of the entry with the given name,
+    /// such as a file, replacing any attachment with the same name. Returns its size in bytes.
+    fn attach(&mut self, key: &str, attachment: &str, reader: &mut dyn Read) -> Result<u64>;
+
+    /// Decrypts an attachment of the entry with the given name into `writer`, returning its size
+    /// in bytes
+    fn read_attachment(&self, key: &str, attachment: &str, writer: &mut dyn Write) -> Result<u64>;
+
+    /// Lists the names of the attachments of the entry with the given name, sorted by name
+    fn list_attachments(&self, key: &str) -> Result<Vec<String>>;
+
+    /// Removes an attachment from the entry with the given name
+    fn remove_attachment(&mut self, key: &str, attachment: &str) -> Result<()>;
+
+    /// Lists the previous versions of the entry with the given name, oldest first
+    fn history(&self, key: &str) -> Result<Vec<(u32, Metadata)>>;
+
+    /// Restores a previous version of the entry with the given name
+    fn restore(&mut self, key: &str, version: u32) -> Result<()>;
+
+    /// Lists the entries in the trash, as their names and when they were deleted in seconds since
+    /// the Unix epoch, oldest first
+    fn list_trash(&self) -> Result<Vec<(String, i64)>>;
+
+    /// Restores the most recently deleted entry with the given name from the trash, replacing an
+    /// existing entry only if `force` is set
+    fn restore_trash(&mut self, key: &str, force: bool) -> Result<()>;
+
+    /// Permanently removes the entries in the trash which were deleted before `before`, in
+    /// seconds since the Unix epoch, or every entry if None. Returns the number removed.
+    fn empty_trash(&mut self, before: Option<i64>) -> Result<usize>;
+
+    /// Rebuilds the index of entry names and timestamps from the entries themselves, returning
+    /// the number of entries
+    fn rebuild_index(&mut self) -> Result<usize>;
+
+    /// Checks that the vault header is well-formed, that every entry, previous version,
+    /// attachment, and entry in the trash decrypts and authenticates with the vault's key, and
+    /// that the index matches the entries on disk. If `quarantine` is set, files which are
+    /// corrupted are moved out of the vault, into its `.quarantine` directory.
+    fn verify(&mut self, quarantine: bool) -> Result<Report>;
+
+    /// Repairs what can be repaired in the vault: temporary files left by interrupted writes are
+    /// removed, history and attachments left behind by an interrupted delete or restore are
+    /// reunited with their entry, corrupted files are restored from `backup`, a directory holding
+    /// an earlier copy of the vault, then from the automatic backups, newest first, or otherwise
+    /// from the entry's history, and the index is rebuilt. Files which can't be repaired are
+    /// quarantined, see `verify`.
+    fn repair(&mut self, backup: Option<&Path>) -> Result<RepairReport>;
+
+    /// Replaces the entry with the given name by its copy in `backup`, a directory holding an
+    /// earlier copy of the vault such as an automatic backup, see `autobackup::AutoBackup`. The
+    /// copy must still decrypt with the vault's key.
+    fn recover(&mut self, backup: &Path, key: &str) -> Result<()>;
+
+    /// Returns the operations recorded in the vault's activity log on this machine, oldest
+    /// first, see `activity::ActivityVault`
+    fn activity(&self) -> Result<Vec<Activity>>;
+
+    /// Re-encrypts every entry with a key derived from a new master password
+    fn rekey(&mut self, new_pass: &str) -> Result<()>;
+
+    /// Derives the vault's key with a different Kdf from now on, such as one calibrated for this
+    /// machine. `pass` is the master password, which the key is derived from.
+    fn set_kdf(&mut self, kdf: Kdf, pass: &str) -> Result<()>;
+
+    /// Gives a member access to the vault with their age public key, replacing any member with
+    /// the same name
+    fn add_member(&mut self, name: &str, recipient: &Recipient) -> Result<()>;
+
+    /// Removes a member from the vault, re-encrypting every entry with a new master key so they
+    /// can't read anything changed after they were removed. `pass` is the master password, which
+    /// the new master key is encrypted with.
+    fn remove_member(&mut self, name: &str, pass: &str) -> Result<()>;
+
+    /// Deletes every entry in the folder, such as `work/` for `work/github` and `work/aws/root`,
+    /// returning the names of the deleted entries.
+    fn delete_folder(&mut self, folder: &str) -> Result<Vec<String>> {
+        let prefix = format!("{}/", folder.trim_end_matches('/'));
+        let keys = self.list_keys(prefix.as_str())?;
+        if keys.is_empty() {
+            return Err(Error::PasswordNotFound { name: prefix });
+        }
+
+        for key in keys.iter() {
+            self.delete_key(key.as_str())?;
+        }
+        Ok(keys)
+    }
+
+    /// Gets the created, modified, and accessed times of the entry with the given name
+    fn metadata(&self, key: &str) -> Result<Metadata> {
+        Ok(self.get_entry(key)?.metadata)
+    }
+
+    /// Gets the tags of the entry with the given name
+    fn tags(&self, key: &str) -> Result<BTreeSet<String>> {
+        Ok(self.get_entry(key)?.tags)
+    }
+
+    /// Summarizes the entries of the vault, see `Stats`. The sizes of its files are only known
+    /// to backends which store them on disk.
+    fn stats(&self) -> Result<Stats> {
+        let mut entries = vec![];
+        for key in self.list_keys("")? {
+            let (metadata, tags, versions) = (self.metadata(key.as_str())?, self.tags(key.as_str())?, self.history(key.as_str())?.len());
+            entries.push((key, metadata, tags, versions));
+        }
+        Ok(Stats::new(entries, Sizes::default()))
+    }
+
+    /// Gets the password of the entry with the given name
+    fn get_key(&self, key: &str) -> Result<String> {
+        Ok(self.get_entry(key)?.password)
+    }
+
+    /// Sets the password of the entry with the given name, keeping its other fields if the entry
+    /// already exists.
+    fn set_key(&mut self, key: &str, value: String) -> Result<()> {
+        let mut entry = if self.has_key(key) { self.get_entry(key)? } else { Entry::default() };
+        entry.password = value;
+        self.set_entry(key, &entry)
+    }
+}
+
+/// Backend identifies how a vault stores its entries. The backend of a new vault is chosen by
+/// the `backend` config setting and recorded in the vault header, so the vault is always opened
+/// with the backend it was created with.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// Each entry is stored in its own file, see SimpleVault
+    #[default]
+    Files,
+    /// Entries are stored in a single SQLite database, see SqliteVault. The database is synced
+    /// as a single file, so changes made on two machines at once conflict as a whole.
+    Sqlite,
+}
+
+impl Backend {
+    /// parse creates a Backend from its name, as used in the `backend` config setting. If the
+    /// name is not a known backend, then a None option is returned.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "files" => Some(Backend::Files),
+            "sqlite" => Some(Backend::Sqlite),
+            _ => None
+        }
+    }
+
+    /// Returns the name of the backend, as used in the `backend` config setting
+    pub fn name(&self) -> &'static str {
+        match self {
+            Backend::Files => "files",
+            Backend::Sqlite => "sqlite",
+        }
+    }
+}
 
 // SimpleVault stores all passwords in separate files
 pub struct SimpleVault {
     vault_dir: PathBuf,
     engine: Aes256GcmEngine,
+    version: u32,
+    master_key: Option<Zeroizing<[u8; KEY_LEN]>>,
+}
+
+impl SimpleVault {
+    /// Loads a SimpleVault with its key, which has already been
+    /// derived from the master password, see `derive_key`. Entries are
+    /// encrypted with the vault's master key if it has one, and otherwise
+    /// with the derived key itself. The key pair of the activity log is
+    /// created the first time the vault is unlocked, see `activity::init`.
+    pub(crate) fn load(vault_dir: PathBuf, key: Zeroizing<[u8; KEY_LEN]>, header: &VaultHeader) -> Result<SimpleVault> {
+        let master_key = header.unwrap_master_key(&key)?;
+        let engine = engine(vault_dir.as_path(), master_key.clone().unwrap_or(key))?;
+        // A vault on a read-only mount can still be read, it just has no log to write to
+        activity::init(vault_dir.as_path(), &engine).ok();
+        Ok(SimpleVault { vault_dir, engine, version: header.version, master_key })
+    }
+
+    /// Loads a SimpleVault with its master key, which a member has decrypted with their age key
+    fn with_master_key(vault_dir: PathBuf, master_key: Zeroizing<[u8; KEY_LEN]>, header: &VaultHeader) -> Result<SimpleVault> {
+        let engine = engine(vault_dir.as_path(), master_key.clone())?;
+        Ok(SimpleVault { vault_dir, engine, version: header.version, master_key: Some(master_key) })
+    }
+
+    /// Returns the associated data authenticated along with the entry with the given name, which
+    /// binds the ciphertext to the entry's name and its format version. A ciphertext moved to
+    /// another entry's file fails to decrypt. Vaults from before the format was versioned use no
+    /// associated data.
+    fn associated_data(version: u8, key: &str) -> Vec<u8> {
+        match version {
+            0 => vec![],
+            version => format!("yap/v{}/{}", version, key).into_bytes(),
+        }
+    }
+
+    /// Decrypts the contents of a file holding the entry with the given name, or one of its
+    /// previous versions. Files written before entries were marked with their format use the
+    /// format recorded in the vault header, and are upgraded the next time they are written.
+    fn decrypt(&self, key: &str, data: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+        decrypt_entry(&self.engine, self.version, key, data)
+    }
+
+    /// Encrypts the contents of the entry with the given name, or one of its previous versions
+    fn encrypt(&self, key: &str, plaintext: &[u8]) -> Result<Vec<u8>> {
+        encrypt_entry(&self.engine, self.master_key.is_some(), key, plaintext)
+    }
+
+    /// Re-encrypts the file at `from`, holding the entry named `from_key`, into `to` for the
+    /// entry named `to_key`
+    fn reencrypt(&self, from: &Path, from_key: &str, to: &Path, to_key: &str) -> Result<()> {
+        let plaintext = self.decrypt(from_key, std::fs::read(from)?.as_slice())?;
+        if let Some(parent) = to.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        atomic_write(to, self.encrypt(to_key, plaintext.as_slice())?.as_slice())
+    }
+
+    /// Reads and decrypts the raw contents of the entry with the given name, which are wiped from
+    /// memory when dropped
+    fn read_plaintext(&self, key: &str) -> Result<Zeroizing<Vec<u8>>> {
+        validate_key(key)?;
+        let p = self.vault_dir.join(Path::new(key));
+        if !p.as_path().exists() {
+            Err(Error::PasswordNotFound { name: key.to_string() })
+        } else {
+            let data = std::fs::read(p.as_path())?;
+            self.decrypt(key, data.as_slice())
+        }
+    }
+
+    /// Encrypts and writes the entry as-is
+    fn write_entry(&mut self, key: &str, entry: &Entry) -> Result<()> {
+        let p = self.vault_dir.join(Path::new(key));
+        if let Some(parent) = p.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let ciphertext = self.encrypt(key, entry.to_bytes()?.as_slice())?;
+        atomic_write(p.as_path(), ciphertext.as_slice())?;
+        self.update_index(|index| {
+            index.insert(key, entry.metadata);
+            index.set_tags(key, &entry.tags);
+        })
+    }
+
+    /// Reads the created, modified, and accessed times of the entry with the given name from the
+    /// entry itself. Entries written before timestamps were recorded use the modification time of
+    /// their file instead.
+    fn entry_metadata(&self, key: &str, entry: &Entry) -> Result<Metadata> {
+        let mut metadata = entry.metadata;
+        if metadata.modified == 0 {
+            let modified = std::fs::metadata(self.vault_dir.join(Path::new(key)))?.modified()?;
+            metadata.modified = modified.duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs() as i64)
+                .unwrap_or_default();
+        }
+        Ok(metadata)
+    }
+
+    /// Reads the vault's index, see `Index`. It is built from the entries if there is none yet,
+    /// or if it can't be decrypted, such as after the master key was changed.
+    fn index(&self) -> Result<Index> {
+        let p = self.vault_dir.join(global::INDEX_FILE);
+        if p.as_path().exists() {
+            let data = std::fs::read(p.as_path())?;
+            let index = self.decrypt(global::INDEX_FILE, data.as_slice())
+                .and_then(|plaintext| Index::from_bytes(plaintext.as_slice()));
+            match index {
+                Ok(index) => return Ok(index),
+                Err(e) => debug!("rebuilding the index, which couldn't be read: {}", e),
+            }
+        }
+
+        // The index is only a cache, so a vault which can't be written to, such as one on a
+        // read-only mount, still lists its entries
+        let index = self.build_index()?;
+        if let Err(e) = self.write_index(&index) {
+            debug!("the rebuilt index couldn't be saved: {}", e);
+        }
+        Ok(index)
+    }
+
+    /// Builds the index by walking the vault directory and decrypting every entry. Files which
+    /// can't be read as entries are still listed, without any times.
+    fn build_index(&self) -> Result<Index> {
+        let mut keys = vec![];
+        collect_files(self.vault_dir.as_path(), "", false, &mut keys)?;
+
+        let mut index = Index::default();
+        for key in keys {
+            let entry = match self.get_entry(key.as_str()) {
+                Ok(entry) => entry,
+                Err(e) => {
+                    debug!("{} couldn't be read as an entry: {}", key, e);
+                    index.insert(key.as_str(), Metadata::default());
+                    continue;
+                }
+            };
+            index.insert(key.as_str(), self.entry_metadata(key.as_str(), &entry).unwrap_or_default());
+            index.set_tags(key.as_str(), &entry.tags);
+        }
+        Ok(index)
+    }
+
+    /// Encrypts and writes the index. The index is bound to the name of its file like an entry.
+    fn write_index(&self, index: &Index) -> Result<()> {
+        let ciphertext = self.encrypt(global::INDEX_FILE, index.to_bytes()?.as_slice())?;
+        atomic_write(self.vault_dir.join(global::INDEX_FILE).as_path(), ciphertext.as_slice())
+    }
+
+    /// Reads the index, applies a change to it, and writes it back
+    fn update_index(&self, change: impl FnOnce(&mut Index)) -> Result<()> {
+        let mut index = self.index()?;
+        change(&mut index);
+        self.write_index(&index)
+    }
+
+    /// Copies the current ciphertext of the entry, if it exists, into the next version of its
+    /// history. The ciphertext is copied as-is, so nothing needs to be decrypted.
+    fn archive(&self, key: &str) -> Result<()> {
+        if !self.has_key(key) {
+            return Ok(());
+        }
+
+        let dir = self.history_dir(key);
+        std::fs::create_dir_all(dir.as_path())?;
+
+        let next = self.versions(key)?.last().copied().unwrap_or_default() + 1;
+        std::fs::copy(self.vault_dir.join(Path::new(key)), dir.join(next.to_string()))?;
+        Ok(())
+    }
+
+    /// Returns the version numbers in the history of the entry with the given name, sorted
+    fn versions(&self, key: &str) -> Result<Vec<u32>> {
+        let dir = self.history_dir(key);
+        if !dir.as_path().exists() {
+            return Ok(vec![]);
+        }
+
+        let mut versions = vec![];
+        for file in std::fs::read_dir(dir)? {
+            if let Ok(version) = file?.file_name().to_string_lossy().parse() {
+                versions.push(version);
+            }
+        }
+        versions.sort_unstable();
+        Ok(versions)
+    }
+
+    /// Encrypts the entries in parallel and writes them to the staging directory, along with the
+    /// current version of each entry they replace, which becomes the next version in its
+    /// history. The staging directory is marked complete once everything is synced to disk.
+    fn stage_entries(&self, staging: &Path, entries: &[(&str, Entry)]) -> Result<()> {
+        if staging.exists() {
+            std::fs::remove_dir_all(staging)?;
+        }
+        std::fs::create_dir(staging)?;
+
+        let ciphertexts = entries.par_iter()
+            .map(|(key, entry)| self.encrypt(key, entry.to_bytes()?.as_slice()))
+            .collect::<Result<Vec<_>>>()?;
+
+        for ((key, _), ciphertext) in entries.iter().zip(ciphertexts) {
+            if self.has_key(key) {
+                let next = self.versions(key)?.last().copied().unwrap_or_default() + 1;
+                let archived = staging.join(global::HISTORY_DIR).join(Path::new(key)).join(next.to_string());
+                if let Some(parent) = archived.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                std::fs::copy(self.vault_dir.join(Path::new(key)), archived)?;
+            }
+
+            let p = staging.join(Path::new(key));
+            if let Some(parent) = p.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            atomic_write(p.as_path(), ciphertext.as_slice())?;
+        }
+
+        atomic_write(staging.join(global::REKEY_COMPLETE).as_path(), &[])
+    }
+
+    /// Returns the directory holding the previous versions of the entry with the given name
+    fn history_dir(&self, key: &str) -> PathBuf {
+        self.vault_dir.join(global::HISTORY_DIR).join(Path::new(key))
+    }
+
+    /// Returns the directory holding the attachments of the entry with the given name, as
+    /// `.attachments/<name>/.files`. Entry names never have parts starting with a `.`, so the
+    /// attachments can't be mistaken for those of an entry in a folder named after this one.
+    fn attachment_dir(&self, key: &str) -> PathBuf {
+        self.vault_dir.join(global::ATTACHMENTS_DIR).join(Path::new(key)).join(global::ATTACHMENT_FILES_DIR)
+    }
+
+    /// Returns the directory holding an entry in the trash, as `.trash/<name>/.<deleted>`, along
+    /// with its history and attachments. Like attachments, the directory starts with a `.` so it
+    /// can't be mistaken for an entry in a folder.
+    fn trash_dir(&self, key: &str, deleted: i64) -> PathBuf {
+        self.vault_dir.join(global::TRASH_DIR).join(Path::new(key)).join(format!(".{}", deleted))
+    }
+
+    /// Returns when each copy of the entry with the given name in the trash was deleted, sorted
+    fn trashed(&self, key: &str) -> Result<Vec<i64>> {
+        let dir = self.vault_dir.join(global::TRASH_DIR).join(Path::new(key));
+        if !dir.as_path().is_dir() {
+            return Ok(vec![]);
+        }
+
+        let mut deleted = vec![];
+        for file in std::fs::read_dir(dir)? {
+            let file = file?;
+            let time = file.file_name().to_string_lossy().strip_prefix('.').and_then(|time| time.parse().ok());
+            if let Some(time) = time.filter(|_| file.path().join(global::TRASH_ENTRY_FILE).is_file()) {
+                deleted.push(time);
+            }
+        }
+        deleted.sort_unstable();
+        Ok(deleted)
+    }
+
+    /// Removes the temporary files left by writes which were interrupted, see `atomic_write`,
+    /// and attachments which were never finished
+    fn remove_temporary(&self, report: &mut RepairReport) -> Result<()> {
+        let mut files = vec![];
+        collect_files(self.vault_dir.as_path(), "", true, &mut files)?;
+        let skipped = [".git/", global::QUARANTINE_DIR, global::BACKUPS_DIR, global::SNAPSHOTS_DIR];
+        for file in files.iter().filter(|file| !skipped.iter().any(|dir| file.starts_with(dir))) {
+            let name = file.rsplit('/').next().unwrap_or_default();
+            if (name.starts_with('.') && name.ends_with(global::TEMP_SUFFIX)) || unfinished_attachment(file.as_str()) {
+                let p = self.vault_dir.join(Path::new(file.as_str()));
+                std::fs::remove_file(p.as_path())?;
+                remove_empty_parents(self.vault_dir.as_path(), p.as_path())?;
+                report.add(file.as_str(), Action::Removed, "");
+            }
+        }
+        Ok(())
+    }
+
+    /// Reunites history and attachments with their entry, which an interrupted delete or restore
+    /// may have left apart, see `delete_key` and `restore_trash`. What belongs to no entry, in the
+    /// vault or in the trash, is quarantined.
+    fn reconcile_trash(&self, report: &mut RepairReport) -> Result<()> {
+        let mut keys = BTreeSet::new();
+        for file in self.stored_files()? {
+            if let Some((key, _)) = attachment_name(file.as_str()).filter(|_| file.starts_with(global::ATTACHMENTS_DIR)) {
+                keys.insert(key.to_string());
+            } else if file.starts_with(global::HISTORY_DIR) {
+                keys.insert(entry_name(file.as_str()).to_string());
+            }
+        }
+
+        // Left behind in the vault by a delete, which moves the entry into the trash first
+        for key in keys.iter().filter(|key| !self.has_key(key)) {
+            let trash = self.trashed(key)?.last().map(|deleted| self.trash_dir(key, *deleted));
+            for (dir, name) in [(self.history_dir(key), global::HISTORY_DIR), (self.attachment_dir(key), global::ATTACHMENTS_DIR)] {
+                if !dir.exists() {
+                    continue;
+                }
+                let from = dir.strip_prefix(self.vault_dir.as_path()).unwrap_or(dir.as_path()).display().to_string();
+                let root = self.vault_dir.join(name);
+                match trash.as_ref().map(|trash| trash.join(name)).filter(|to| !to.exists()) {
+                    Some(to) => {
+                        self.move_into(dir.as_path(), to.as_path(), root.as_path())?;
+                        report.add(from.as_str(), Action::Moved, format!("to {} in the trash", key));
+                    }
+                    None => {
+                        self.move_into(dir.as_path(), self.vault_dir.join(global::QUARANTINE_DIR).join(from.as_str()).as_path(), root.as_path())?;
+                        report.add(from.as_str(), Action::Quarantined, "since it belongs to no entry");
+                    }
+                }
+            }
+        }
+
+        // Left behind in the trash by a restore, which moves the entry out of the trash last, or
+        // the remains of an entry whose trashed copy was lost
+        let mut files = self.stored_files()?;
+        files.retain(|file| file.starts_with(global::TRASH_DIR));
+        let orphans: BTreeSet<(&str, i64)> = files.iter()
+            .filter_map(|file| trash_item(file.as_str()))
+            .map(|(key, deleted, _)| (key, deleted))
+            .filter(|(key, deleted)| !self.trash_dir(key, *deleted).join(global::TRASH_ENTRY_FILE).exists())
+            .collect();
+        let root = self.vault_dir.join(global::TRASH_DIR);
+        for (key, deleted) in orphans {
+            let trash = self.trash_dir(key, deleted);
+            for (to, name) in [(self.history_dir(key), global::HISTORY_DIR), (self.attachment_dir(key), global::ATTACHMENTS_DIR)] {
+                let from = trash.join(name);
+                if !from.exists() {
+                    continue;
+                }
+                let path = from.strip_prefix(self.vault_dir.as_path()).unwrap_or(from.as_path()).display().to_string();
+                if self.has_key(key) && !to.exists() {
+                    self.move_into(from.as_path(), to.as_path(), root.as_path())?;
+                    report.add(path.as_str(), Action::Moved, format!("to {}", key));
+                } else {
+                    self.move_into(from.as_path(), self.vault_dir.join(global::QUARANTINE_DIR).join(path.as_str()).as_path(), root.as_path())?;
+                    report.add(path.as_str(), Action::Quarantined, "since it belongs to no entry");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Lists the files in the vault which are stored along with its entries: their history, their
+    /// attachments, and the trash, as paths relative to the vault
+    fn stored_files(&self) -> Result<Vec<String>> {
+        let mut files = vec![];
+        for dir in [global::HISTORY_DIR, global::ATTACHMENTS_DIR, global::TRASH_DIR] {
+            if self.vault_dir.join(dir).is_dir() {
+                collect_files(self.vault_dir.join(dir).as_path(), dir, true, &mut files)?;
+            }
+        }
+        Ok(files)
+    }
+
+    /// Checks that the file at `p` decrypts and authenticates as the file at `file` in the vault,
+    /// which is an entry, a previous version, an attachment, or any of those in the trash.
+    /// Attachments which were never finished are hidden, and aren't checked, like in listings.
+    fn check_file(&self, p: &Path, file: &str) -> Option<Result<()>> {
+        if let Some((key, attachment)) = attachment_name(file) {
+            let aad = attachment_aad(key, attachment);
+            let checked = File::open(p).map_err(Error::from)
+                .and_then(|f| self.engine.decrypt_stream(&mut BufReader::new(f), &mut std::io::sink(), aad.as_slice()));
+            return Some(checked.map(|_| ()));
+        }
+        if unfinished_attachment(file) {
+            return None;
+        }
+        let checked = std::fs::read(p).map_err(Error::from)
+            .and_then(|data| self.decrypt(entry_name(file), data.as_slice()))
+            .and_then(|plaintext| Entry::from_bytes(plaintext.as_slice()));
+        Some(checked.map(|_| ()))
+    }
+
+    /// Moves a file or directory if it exists, creating the folders it is moved into and removing
+    /// the folders it leaves empty, up to `root`
+    fn move_into(&self, from: &Path, to: &Path, root: &Path) -> Result<()> {
+        if !from.exists() {
+            return Ok(());
+        }
+        if let Some(parent) = to.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::rename(from, to)?;
+        remove_empty_parents(root, from)
+    }
+
+    /// Removes every attachment of the entry with the given name
+    fn remove_attachments(&self, key: &str) -> Result<()> {
+        let dir = self.attachment_dir(key);
+        if dir.as_path().exists() {
+            std::fs::remove_dir_all(dir.as_path())?;
+            remove_empty_parents(self.vault_dir.join(global::ATTACHMENTS_DIR).as_path(), dir.as_path())?;
+        }
+        Ok(())
+    }
+}
+
+impl Vault for SimpleVault {
+    fn has_key(&self, key: &str) -> bool {
+        validate_key(key).is_ok() && self.vault_dir.join(Path::new(key)).is_file()
+    }
+
+    fn get_entry(&self, key: &str) -> Result<Entry> {
+        Entry::from_bytes(self.read_plaintext(key)?.as_slice())
+    }
+
+    /// Reads and decrypts the entry with the given name, recording that it was accessed. This
+    /// should be used when the entry's secrets are shown to the user, rather than when the entry
+    /// is only being updated.
+    fn access_entry(&mut self, key: &str) -> Result<Entry> {
+        let mut entry = self.get_entry(key)?;
+        entry.metadata.accessed = entry::now();
+        self.write_entry(key, &entry)?;
+        Ok(entry)
+    }
+
+    /// Encrypts and writes the entry with the given name, replacing it if it exists. The entry's
+    /// modified time is updated, and its created time is set if it is new. A replaced entry is
+    /// kept in the entry's history. A signature which no longer matches the entry is dropped,
+    /// since the entry was changed by someone who didn't sign it.
+    fn set_entry(&mut self, key: &str, entry: &Entry) -> Result<()> {
+        validate_key(key)?;
+        self.archive(key)?;
+        self.write_entry(key, &stamp(entry))
+    }
+
+    /// Encrypts and writes several entries at once, like `set_entry`, from a single unlock. The
+    /// entries are encrypted in parallel and written to a staging directory along with the
+    /// replaced versions for their history, like a rekey, see `rewrite`. If anything fails
+    /// before the staging directory is complete, it is discarded and the vault is untouched.
+    fn set_entries(&mut self, entries: &[(String, Entry)]) -> Result<()> {
+        let mut batch = BTreeMap::new();
+        for (key, entry) in entries {
+            validate_key(key)?;
+            batch.insert(key.as_str(), stamp(entry));
+        }
+        let batch: Vec<(&str, Entry)> = batch.into_iter().collect();
+        if batch.is_empty() {
+            return Ok(());
+        }
+
+        let staging = self.vault_dir.join(global::BATCH_DIR);
+        if let Err(e) = self.stage_entries(staging.as_path(), batch.as_slice()) {
+            if staging.as_path().exists() {
+                std::fs::remove_dir_all(staging.as_path())?;
+            }
+            return Err(e);
+        }
+        finish_staging(self.vault_dir.as_path(), staging.as_path())?;
+
+        self.update_index(|index| {
+            for (key, entry) in batch.iter() {
+                index.insert(key, entry.metadata);
+                index.set_tags(key, &entry.tags);
+            }
+        })
+    }
+
+    /// Gets the created, modified, and accessed times of the entry with the given name from the
+    /// index, or from the entry itself if it isn't indexed, see `entry_metadata`.
+    fn metadata(&self, key: &str) -> Result<Metadata> {
+        match self.index()?.metadata(key) {
+            Some(metadata) => Ok(metadata),
+            None => self.entry_metadata(key, &self.get_entry(key)?),
+        }
+    }
+
+    /// Gets the tags of the entry with the given name from the index, or from the entry itself if
+    /// it isn't indexed
+    fn tags(&self, key: &str) -> Result<BTreeSet<String>> {
+        let index = self.index()?;
+        match index.metadata(key) {
+            Some(_) => Ok(index.tags(key)),
+            None => Ok(self.get_entry(key)?.tags),
+        }
+    }
+
+    /// Summarizes the entries from the index, with previous versions counted by their files, so
+    /// no entry is decrypted and large vaults are summarized quickly
+    fn stats(&self) -> Result<Stats> {
+        let index = self.index()?;
+        let mut entries = vec![];
+        let mut size = 0;
+        for key in index.names("") {
+            size += std::fs::metadata(self.vault_dir.join(Path::new(key.as_str()))).map(|metadata| metadata.len()).unwrap_or_default();
+            let (metadata, tags, versions) = (index.metadata(key.as_str()).unwrap_or_default(), index.tags(key.as_str()), self.versions(key.as_str())?.len());
+            entries.push((key, metadata, tags, versions));
+        }
+
+        let sizes = Sizes {
+            entries: size,
+            history: stats::dir_size(self.vault_dir.join(global::HISTORY_DIR).as_path())?,
+            attachments: stats::dir_size(self.vault_dir.join(global::ATTACHMENTS_DIR).as_path())?,
+            trash: stats::dir_size(self.vault_dir.join(global::TRASH_DIR).as_path())?,
+        };
+        Ok(Stats::new(entries, sizes))
+    }
+
+    /// Moves the entry into the trash, see `trash_dir`, along with its history and attachments.
+    /// The entry stays encrypted and bound to its name, so nothing needs to be re-encrypted.
+    fn delete_key(&mut self, key: &str) -> Result<()> {
+        validate_key(key)?;
+        if !self.has_key(key) {
+            return Err(Error::PasswordNotFound { name: key.to_string() });
+        }
+
+        // An entry deleted twice within a second is kept apart from the earlier copy
+        let mut deleted = entry::now();
+        while self.trash_dir(key, deleted).exists() {
+            deleted += 1;
+        }
+        let trash = self.trash_dir(key, deleted);
+        let (history, attachments) = (self.vault_dir.join(global::HISTORY_DIR), self.vault_dir.join(global::ATTACHMENTS_DIR));
+        self.move_into(self.vault_dir.join(Path::new(key)).as_path(), trash.join(global::TRASH_ENTRY_FILE).as_path(), self.vault_dir.as_path())?;
+        self.move_into(self.history_dir(key).as_path(), trash.join(global::HISTORY_DIR).as_path(), history.as_path())?;
+        self.move_into(self.attachment_dir(key).as_path(), trash.join(global::ATTACHMENTS_DIR).as_path(), attachments.as_path())?;
+        self.update_index(|index| {
+            index.remove(key);
+        })
+    }
+
+    /// Renames an entry within the vault. Since entries are bound to their names, the entry and
+    /// its history are re-encrypted under the new name. The entry is written under its new name
+    /// before the old one is removed, so it is never lost if yap is interrupted. An existing
+    /// entry at `to` is only replaced if `force` is set.
+    fn rename_key(&mut self, from: &str, to: &str, force: bool) -> Result<()> {
+        validate_key(from)?;
+        validate_key(to)?;
+        if !self.has_key(from) {
+            return Err(Error::PasswordNotFound { name: from.to_string() });
+        }
+        if from == to {
+            return Ok(());
+        }
+        if !force && self.has_key(to) {
+            return Err(Error::PasswordExists { name: to.to_string() });
+        }
+
+        let from_history = self.history_dir(from);
+        let to_history = self.history_dir(to);
+        let versions = self.versions(from)?;
+
+        let from_path = self.vault_dir.join(Path::new(from));
+        let to_path = self.vault_dir.join(Path::new(to));
+        self.reencrypt(from_path.as_path(), from, to_path.as_path(), to)?;
+        std::fs::remove_file(from_path.as_path())?;
+        remove_empty_parents(self.vault_dir.as_path(), from_path.as_path())?;
+
+        // The history follows the entry, replacing the history of any entry it replaced
+        if to_history.as_path().exists() {
+            std::fs::remove_dir_all(to_history.as_path())?;
+        }
+        for version in versions {
+            let file = version.to_string();
+            self.reencrypt(from_history.join(file.as_str()).as_path(), from, to_history.join(file.as_str()).as_path(), to)?;
+        }
+        if from_history.as_path().exists() {
+            std::fs::remove_dir_all(from_history.as_path())?;
+            remove_empty_parents(self.vault_dir.join(global::HISTORY_DIR).as_path(), from_history.as_path())?;
+        }
+
+        // So do the attachments, whose data keys are bound to the entry's name
+        self.remove_attachments(to)?;
+        let (from_attachments, to_attachments) = (self.attachment_dir(from), self.attachment_dir(to));
+        for attachment in self.list_attachments(from)? {
+            std::fs::create_dir_all(to_attachments.as_path())?;
+            let mut reader = File::open(from_attachments.join(attachment.as_str()))?;
+            let mut writer = File::create(to_attachments.join(attachment.as_str()))?;
+            let (aad, to_aad) = (attachment_aad(from, attachment.as_str()), attachment_aad(to, attachment.as_str()));
+            self.engine.rewrap_stream(&self.engine, &mut reader, &mut writer, aad.as_slice(), to_aad.as_slice())?;
+            writer.sync_all()?;
+        }
+        self.remove_attachments(from)?;
+
+        self.update_index(|index| {
+            let tags = index.tags(from);
+            let metadata = index.remove(from).unwrap_or_default();
+            index.insert(to, metadata);
+            index.set_tags(to, &tags);
+        })
+    }
+
+    /// Lists the names of all entries in the vault which start with `prefix`, sorted by name.
+    /// Entries in sub-directories are named by their path relative to the vault, such as
+    /// `work/github`. The names come from the index, so the vault directory isn't walked.
+    fn list_keys(&self, prefix: &str) -> Result<Vec<String>> {
+        Ok(self.index()?.names(prefix))
+    }
+
+    /// Encrypts an attachment in chunks as it is read, see `Aes256GcmEngine::encrypt_stream`, so
+    /// large files are never held in memory. The attachment is written to a hidden file first,
+    /// and only replaces an existing attachment once it is complete.
+    fn attach(&mut self, key: &str, attachment: &str, reader: &mut dyn Read) -> Result<u64> {
+        validate_key(key)?;
+        if !self.has_key(key) {
+            return Err(Error::PasswordNotFound { name: key.to_string() });
+        }
+        validate_attachment(attachment)?;
+
+        let dir = self.attachment_dir(key);
+        std::fs::create_dir_all(dir.as_path())?;
+        let partial = dir.join(format!(".{}", attachment));
+        let mut writer = File::create(partial.as_path())?;
+        let aad = attachment_aad(key, attachment);
+        let written = self.engine.encrypt_stream(reader, &mut writer, aad.as_slice())
+            .and_then(|len| Ok(writer.sync_all().map(|_| len)?));
+        match written {
+            Ok(len) => {
+                std::fs::rename(partial, dir.join(attachment))?;
+                Ok(len)
+            }
+            Err(e) => {
+                std::fs::remove_file(partial)?;
+                Err(e)
+            }
+        }
+    }
+
+    fn read_attachment(&self, key: &str, attachment: &str, writer: &mut dyn Write) -> Result<u64> {
+        validate_key(key)?;
+        validate_attachment(attachment)?;
+        let p = self.attachment_dir(key).join(attachment);
+        if !p.as_path().is_file() {
+            return Err(Error::AttachmentNotFound { name: key.to_string(), attachment: attachment.to_string() });
+        }
+        let aad = attachment_aad(key, attachment);
+        self.engine.decrypt_stream(&mut BufReader::new(File::open(p)?), writer, aad.as_slice())
+    }
+
+    fn list_attachments(&self, key: &str) -> Result<Vec<String>> {
+        validate_key(key)?;
+        let dir = self.attachment_dir(key);
+        if !dir.as_path().is_dir() {
+            return Ok(vec![]);
+        }
+
+        let mut attachments = vec![];
+        for file in std::fs::read_dir(dir)? {
+            let name = file?.file_name().to_string_lossy().to_string();
+            if !name.starts_with('.') {
+                attachments.push(name);
+            }
+        }
+        attachments.sort();
+        Ok(attachments)
+    }
+
+    fn remove_attachment(&mut self, key: &str, attachment: &str) -> Result<()> {
+        validate_key(key)?;
+        validate_attachment(attachment)?;
+        let p = self.attachment_dir(key).join(attachment);
+        if !p.as_path().is_file() {
+            return Err(Error::AttachmentNotFound { name: key.to_string(), attachment: attachment.to_string() });
+        }
+        wipe::wipe_file(p.as_path())?;
+        remove_empty_parents(self.vault_dir.join(global::ATTACHMENTS_DIR).as_path(), p.as_path())
+    }
+
+    /// Lists the previous versions of the entry with the given name, oldest first, along with
+    /// the metadata of each version. Versions are numbered from 1.
+    fn history(&self, key: &str) -> Result<Vec<(u32, Metadata)>> {
+        validate_key(key)?;
+        let mut versions = vec![];
+        for version in self.versions(key)? {
+            let data = std::fs::read(self.history_dir(key).join(version.to_string()))?;
+            let entry = Entry::from_bytes(self.decrypt(key, data.as_slice())?.as_slice())?;
+            versions.push((version, entry.metadata));
+        }
+        Ok(versions)
+    }
+
+    /// Restores a previous version of the entry with the given name. The current version, if
+    /// any, is kept in the history so a restore can itself be undone.
+    fn restore(&mut self, key: &str, version: u32) -> Result<()> {
+        validate_key(key)?;
+        let p = self.history_dir(key).join(version.to_string());
+        if !p.as_path().exists() {
+            return Err(Error::VersionNotFound { name: key.to_string(), version });
+        }
+
+        let data = std::fs::read(p.as_path())?;
+        let entry = Entry::from_bytes(self.decrypt(key, data.as_slice())?.as_slice())?;
+        self.set_entry(key, &entry)
+    }
+
+    fn list_trash(&self) -> Result<Vec<(String, i64)>> {
+        let trash = self.vault_dir.join(global::TRASH_DIR);
+        if !trash.as_path().is_dir() {
+            return Ok(vec![]);
+        }
+
+        let mut files = vec![];
+        collect_files(trash.as_path(), global::TRASH_DIR, true, &mut files)?;
+        let mut trashed: Vec<(String, i64)> = files.iter()
+            .filter_map(|file| trash_item(file.as_str()))
+            .filter(|(_, _, file)| *file == global::TRASH_ENTRY_FILE)
+            .map(|(key, deleted, _)| (key.to_string(), deleted))
+            .collect();
+        trashed.sort_by(|(a, a_deleted), (b, b_deleted)| a_deleted.cmp(b_deleted).then(a.cmp(b)));
+        Ok(trashed)
+    }
+
+    /// Moves the entry back out of the trash, along with its history and attachments. An existing
+    /// entry which is replaced is moved into the trash itself.
+    fn restore_trash(&mut self, key: &str, force: bool) -> Result<()> {
+        validate_key(key)?;
+        let Some(deleted) = self.trashed(key)?.last().copied() else {
+            return Err(Error::NotInTrash { name: key.to_string() });
+        };
+        if self.has_key(key) {
+            if !force {
+                return Err(Error::PasswordExists { name: key.to_string() });
+            }
+            self.delete_key(key)?;
+        }
+
+        let trash = self.trash_dir(key, deleted);
+        let root = self.vault_dir.join(global::TRASH_DIR);
+        self.move_into(trash.join(global::HISTORY_DIR).as_path(), self.history_dir(key).as_path(), root.as_path())?;
+        self.move_into(trash.join(global::ATTACHMENTS_DIR).as_path(), self.attachment_dir(key).as_path(), root.as_path())?;
+        self.move_into(trash.join(global::TRASH_ENTRY_FILE).as_path(), self.vault_dir.join(Path::new(key)).as_path(), root.as_path())?;
+
+        let entry = self.get_entry(key)?;
+        self.update_index(|index| {
+            index.insert(key, entry.metadata);
+            index.set_tags(key, &entry.tags);
+        })
+    }
+
+    /// Removes entries from the trash for good. Their files are overwritten before they are
+    /// removed, see `wipe::wipe_file`.
+    fn empty_trash(&mut self, before: Option<i64>) -> Result<usize> {
+        let root = self.vault_dir.join(global::TRASH_DIR);
+        let mut removed = 0;
+        for (key, deleted) in self.list_trash()? {
+            if before.is_some_and(|before| deleted >= before) {
+                continue;
+            }
+            let trash = self.trash_dir(key.as_str(), deleted);
+            wipe::wipe_dir(trash.as_path())?;
+            remove_empty_parents(root.as_path(), trash.as_path())?;
+            removed += 1;
+        }
+        Ok(removed)
+    }
+
+    fn rebuild_index(&mut self) -> Result<usize> {
+        let index = self.build_index()?;
+        self.write_index(&index)?;
+        Ok(index.len())
+    }
+
+    /// Repairs the vault, see `Vault::repair`. A copy of a corrupted file in a backup is only
+    /// used if it can be read, so a backup taken before the master key changed is never restored
+    /// over the vault. Entries fall back to their latest readable previous version, whose file is
+    /// copied as-is since it is bound to the same name.
+    fn repair(&mut self, backup: Option<&Path>) -> Result<RepairReport> {
+        let mut report = RepairReport::default();
+        self.remove_temporary(&mut report)?;
+        self.reconcile_trash(&mut report)?;
+
+        let mut backups: Vec<(PathBuf, String)> = backup.map(|backup| (backup.to_path_buf(), "from the backup".to_string())).into_iter().collect();
+        for automatic in autobackup::list(self.vault_dir.as_path())?.into_iter().rev() {
+            let dir = autobackup::path(self.vault_dir.as_path(), automatic.id.as_str())?;
+            backups.push((dir, format!("from automatic backup {}", automatic.id)));
+        }
+
+        let mut files = vec![];
+        collect_files(self.vault_dir.as_path(), "", false, &mut files)?;
+        files.extend(self.stored_files()?);
+        for file in files.iter() {
+            let p = self.vault_dir.join(Path::new(file.as_str()));
+            if !matches!(self.check_file(p.as_path(), file.as_str()), Some(Err(_))) {
+                continue;
+            }
+
+            let copy = backups.iter().map(|(backup, from)| (backup.join(Path::new(file.as_str())), from))
+                .find(|(copy, _)| matches!(self.check_file(copy.as_path(), file.as_str()), Some(Ok(()))));
+            if let Some((copy, from)) = copy {
+                atomic_write(p.as_path(), std::fs::read(copy)?.as_slice())?;
+                report.add(file.as_str(), Action::Restored, from);
+                continue;
+            }
+
+            let version = match self.has_key(file.as_str()) {
+                true => self.versions(file.as_str())?.into_iter().rev().find(|version| {
+                    let previous = self.history_dir(file.as_str()).join(version.to_string());
+                    let name = format!("{}/{}/{}", global::HISTORY_DIR, file, version);
+                    matches!(self.check_file(previous.as_path(), name.as_str()), Some(Ok(())))
+                }),
+                false => None,
+            };
+            if let Some(version) = version {
+                let previous = std::fs::read(self.history_dir(file.as_str()).join(version.to_string()))?;
+                atomic_write(p.as_path(), previous.as_slice())?;
+                report.add(file.as_str(), Action::Reverted, format!("to version {}", version));
+                continue;
+            }
+
+            self.move_into(p.as_path(), self.vault_dir.join(global::QUARANTINE_DIR).join(Path::new(file.as_str())).as_path(), self.vault_dir.as_path())?;
+            report.add(file.as_str(), Action::Quarantined, "since no readable copy was found");
+        }
+
+        report.indexed = self.rebuild_index()?;
+        Ok(report)
+    }
+
+    /// Recovers an entry from a backup, see `Vault::recover`. The recovered entry replaces the
+    /// current one like any other change, so the current one is kept in the entry's history.
+    fn recover(&mut self, backup: &Path, key: &str) -> Result<()> {
+        validate_key(key)?;
+        let p = backup.join(Path::new(key));
+        if !p.is_file() {
+            return Err(Error::PasswordNotFound { name: key.to_string() });
+        }
+        let entry = Entry::from_bytes(self.decrypt(key, std::fs::read(p)?.as_slice())?.as_slice())?;
+        self.set_entry(key, &entry)
+    }
+
+    fn activity(&self) -> Result<Vec<Activity>> {
+        activity::read(self.vault_dir.as_path(), &self.engine)
+    }
+
+    /// Checks the vault file by file, see `Vault::verify`. Quarantined files keep their path
+    /// within the vault, so they can be put back by hand, and quarantined entries are dropped
+    /// from the index. A corrupted index is removed instead, since it is rebuilt when next needed.
+    fn verify(&mut self, quarantine: bool) -> Result<Report> {
+        let mut report = Report::default();
+        verify_header(self.vault_dir.as_path(), self.master_key.is_some(), &mut report);
+
+        let mut keys = vec![];
+        collect_files(self.vault_dir.as_path(), "", false, &mut keys)?;
+        let mut entries = BTreeMap::new();
+        for key in keys.iter() {
+            report.checked += 1;
+            match self.get_entry(key.as_str()) {
+                Ok(entry) => {
+                    entries.insert(key.as_str(), entry);
+                }
+                Err(e) => report.add(key.as_str(), Problem::Corrupt, e),
+            }
+        }
+
+        for file in self.stored_files()? {
+            let Some(checked) = self.check_file(self.vault_dir.join(Path::new(file.as_str())).as_path(), file.as_str()) else { continue };
+            report.checked += 1;
+            if let Err(e) = checked {
+                report.add(file.as_str(), Problem::Corrupt, e);
+            }
+        }
+
+        let mut index_corrupt = false;
+        let p = self.vault_dir.join(global::INDEX_FILE);
+        if p.as_path().exists() {
+            let index = std::fs::read(p.as_path()).map_err(Error::from)
+                .and_then(|data| self.decrypt(global::INDEX_FILE, data.as_slice()))
+                .and_then(|plaintext| Index::from_bytes(plaintext.as_slice()));
+            match index {
+                Ok(index) => {
+                    for (key, entry) in entries.iter() {
+                        match index.metadata(key) {
+                            None => report.add(key, Problem::Unindexed, "the entry isn't listed"),
+                            Some(metadata) if metadata != self.entry_metadata(key, entry)? || index.tags(key) != entry.tags => {
+                                report.add(key, Problem::Stale, "the index holds different times or tags")
+                            }
+                            Some(_) => {}
+                        }
+                    }
+                    for key in index.names("").into_iter().filter(|key| !keys.contains(key)) {
+                        report.add(key.as_str(), Problem::Missing, "the entry has no file");
+                    }
+                }
+                Err(e) => {
+                    report.add(global::INDEX_FILE, Problem::Corrupt, e);
+                    index_corrupt = true;
+                }
+            }
+        }
+
+        if !quarantine {
+            return Ok(report);
+        }
+        let mut quarantined = vec![];
+        for finding in report.findings.iter_mut().filter(|finding| finding.problem == Problem::Corrupt && finding.path != global::INDEX_FILE) {
+            let to = self.vault_dir.join(global::QUARANTINE_DIR).join(Path::new(finding.path.as_str()));
+            self.move_into(self.vault_dir.join(Path::new(finding.path.as_str())).as_path(), to.as_path(), self.vault_dir.as_path())?;
+            finding.quarantined = true;
+            quarantined.push(finding.path.clone());
+        }
+        if index_corrupt {
+            remove_index(self.vault_dir.as_path())?;
+        } else if quarantined.iter().any(|file| keys.contains(file)) {
+            self.update_index(|index| {
+                for file in quarantined.iter() {
+                    index.remove(file);
+                }
+            })?;
+        }
+        Ok(report)
+    }
+
+    /// Changes the master password of the vault. The vault's master key is encrypted again with
+    /// a key derived from `new_pass` and a fresh salt, using the same Kdf, so the entries
+    /// themselves don't change. Vaults created before envelope encryption are given a master
+    /// key, and every entry is decrypted and re-encrypted in the current format. Members keep
+    /// their access, since the master key stays the same.
+    fn rekey(&mut self, new_pass: &str) -> Result<()> {
+        let kdf = VaultHeader::read(self.vault_dir.as_path())?.kdf;
+        self.rekey_with(new_pass, kdf)
+    }
+
+    /// Rekeys the vault with the master password, like `rekey`, but derives its key with the
+    /// new Kdf. The password is checked first, since a mistyped one would otherwise become the
+    /// new master password.
+    fn set_kdf(&mut self, kdf: Kdf, pass: &str) -> Result<()> {
+        check_pass(self.vault_dir.as_path(), pass)?;
+        self.rekey_with(pass, kdf)
+    }
+
+    fn add_member(&mut self, name: &str, recipient: &Recipient) -> Result<()> {
+        let master_key = self.master_key.as_ref().ok_or(Error::NoMasterKey)?;
+        let mut header = VaultHeader::read(self.vault_dir.as_path())?;
+        header.wrap_for_member(name, recipient, master_key)?;
+        header.write(self.vault_dir.as_path())
+    }
+
+    /// Removes a member, rotating the master key. The new master key is encrypted for the master
+    /// password and every remaining member, and entries are re-encrypted with it the same way as
+    /// in `rekey`. The salt and verifier are kept, since the master password doesn't change.
+    fn remove_member(&mut self, name: &str, pass: &str) -> Result<()> {
+        let (header, master_key) = without_member(self.vault_dir.as_path(), name, pass)?;
+        self.rewrite(header, master_key, true)
+    }
+}
+
+impl SimpleVault {
+    /// Encrypts the vault's master key with a key derived from the new master password with the
+    /// given Kdf, creating a master key if the vault has none yet. Members keep their access.
+    fn rekey_with(&mut self, new_pass: &str, kdf: Kdf) -> Result<()> {
+        // Entries only need to be re-encrypted if the vault had no master key
+        let reencrypt = self.master_key.is_none();
+        let (header, master_key) = rekeyed_header(self.vault_dir.as_path(), new_pass, kdf, self.master_key.clone())?;
+        self.rewrite(header, master_key, reencrypt)
+    }
+
+    /// Replaces the header, and re-encrypts every entry with the new master key if `reencrypt`
+    /// is set. Previous versions of entries are encrypted too, so they are re-encrypted along
+    /// with the entries.
+    ///
+    /// The new header and any re-encrypted entries are first written to a staging directory, which
+    /// is marked complete once everything has been written and synced to disk. Only then are
+    /// the files moved over the old ones. If yap is interrupted before the staging directory is
+    /// complete, the old vault is untouched, and if it is interrupted while moving files, the
+    /// move is finished the next time the vault is loaded, see `recover_staging`.
+    fn rewrite(&mut self, header: VaultHeader, master_key: Zeroizing<[u8; KEY_LEN]>, reencrypt: bool) -> Result<()> {
+        let staging = self.vault_dir.join(global::REKEY_DIR);
+        if staging.as_path().exists() {
+            std::fs::remove_dir_all(staging.as_path())?;
+        }
+        std::fs::create_dir(staging.as_path())?;
+
+        let engine = engine(self.vault_dir.as_path(), master_key.clone())?;
+        let mut files = vec![];
+        let mut trashed = vec![];
+        if reencrypt {
+            files = self.list_keys("")?;
+            let history = self.vault_dir.join(global::HISTORY_DIR);
+            if history.as_path().exists() {
+                collect_files(history.as_path(), global::HISTORY_DIR, true, &mut files)?;
+            }
+            let trash = self.vault_dir.join(global::TRASH_DIR);
+            if trash.as_path().exists() {
+                collect_files(trash.as_path(), global::TRASH_DIR, true, &mut trashed)?;
+            }
+        }
+
+        // Entries in the trash are re-encrypted along with their history, and their attachments
+        // are rewrapped like any other
+        let (mut trashed_attachments, trashed): (Vec<String>, Vec<String>) = trashed.into_iter()
+            .partition(|file| attachment_name(file.as_str()).is_some());
+        files.extend(trashed.into_iter().filter(|file| {
+            trash_item(file.as_str()).is_some_and(|(_, _, file)| file == global::TRASH_ENTRY_FILE || file.starts_with(global::HISTORY_DIR))
+        }));
+
+        for file in files {
+            let key = entry_name(file.as_str());
+            let data = std::fs::read(self.vault_dir.join(Path::new(file.as_str())))?;
+            let plaintext = self.decrypt(key, data.as_slice())?;
+
+            let p = staging.join(Path::new(file.as_str()));
+            if let Some(parent) = p.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            atomic_write(p.as_path(), encrypt_entry(&engine, true, key, plaintext.as_slice())?.as_slice())?;
+        }
+
+        // Only the data keys of attachments are encrypted with the master key, so the rest of
+        // each attachment is copied as it is
+        let attachments = self.vault_dir.join(global::ATTACHMENTS_DIR);
+        let mut files = vec![];
+        if reencrypt && attachments.as_path().exists() {
+            collect_files(attachments.as_path(), global::ATTACHMENTS_DIR, true, &mut files)?;
+        }
+        files.append(&mut trashed_attachments);
+        for file in files {
+            let Some((key, attachment)) = attachment_name(file.as_str()) else { continue };
+            let p = staging.join(Path::new(file.as_str()));
+            if let Some(parent) = p.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let mut reader = File::open(self.vault_dir.join(Path::new(file.as_str())))?;
+            let mut writer = File::create(p.as_path())?;
+            let aad = attachment_aad(key, attachment);
+            self.engine.rewrap_stream(&engine, &mut reader, &mut writer, aad.as_slice(), aad.as_slice())?;
+            writer.sync_all()?;
+        }
+
+        if reencrypt {
+            activity::rewrap(self.vault_dir.as_path(), &self.engine, &engine, staging.as_path())?;
+        }
+        header.write(staging.as_path())?;
+        File::open(staging.join(global::HEADER_FILE))?.sync_all()?;
+        atomic_write(staging.join(global::REKEY_COMPLETE).as_path(), &[])?;
+
+        finish_staging(self.vault_dir.as_path(), staging.as_path())?;
+        if reencrypt {
+            remove_index(self.vault_dir.as_path())?;
+        }
+        self.engine = engine;
+        self.version = header.version;
+        self.master_key = Some(master_key);
+        Ok(())
+    }
 }
 
-impl SimpleVault {
-    /// Creates a new SimpleVault with the specified store. This may
-    /// overwrite an existing vault.
-    pub(crate) fn create(vault_dir: PathBuf) -> Result<SimpleVault> {
-        if !vault_dir.as_path().exists() {
-            std::fs::create_dir(vault_dir.as_path())?;
+/// Returns a new header for the vault in `vault_dir`, protected by `new_pass` with a fresh salt
+/// and its key derived with the given Kdf, along with the master key encrypted in it. The vault
+/// keeps its master key if it has one, and a new one is created otherwise. Members keep their
+/// access, and a vault protected by a keyfile keeps using it, with a hash for the new salt.
+pub(crate) fn rekeyed_header(vault_dir: &Path, new_pass: &str, kdf: Kdf, master_key: Option<Zeroizing<[u8; KEY_LEN]>>) -> Result<(VaultHeader, Zeroizing<[u8; KEY_LEN]>)> {
+    let current = VaultHeader::read(vault_dir)?;
+    let keyfile = read_keyfile(vault_dir, &current)?;
+    let mut header = VaultHeader::new(kdf, current.backend)?;
+    header.keyfile = keyfile.as_ref().map(|keyfile| crypto::keyfile_hash(header.salt.as_slice(), keyfile));
+    header.members = current.members;
+    let key = vault_key(&header, new_pass, keyfile.as_ref().map(|keyfile| keyfile.as_slice()))?;
+    header.set_verifier(&key)?;
+
+    let master_key = match master_key {
+        Some(master_key) => master_key,
+        None => crypto::new_key()?,
+    };
+    header.wrap_master_key(&key, &master_key)?;
+    Ok((header, master_key))
+}
+
+/// Returns the header of the vault in `vault_dir` without the member with the given name, along
+/// with a new master key encrypted in it for the master password and every remaining member.
+/// The salt and verifier are kept, since the master password doesn't change.
+pub(crate) fn without_member(vault_dir: &Path, name: &str, pass: &str) -> Result<(VaultHeader, Zeroizing<[u8; KEY_LEN]>)> {
+    let mut header = VaultHeader::read(vault_dir)?;
+    if !header.members.iter().any(|member| member.name == name) {
+        return Err(Error::MemberNotFound { name: name.to_string() });
+    }
+    let keyfile = read_keyfile(vault_dir, &header)?;
+    let key = vault_key(&header, pass, keyfile.as_ref().map(|keyfile| keyfile.as_slice()))?;
+    header.verify(&key)?;
+    header.unwrap_master_key(&key)?.ok_or(Error::NoMasterKey)?;
+
+    let master_key = crypto::new_key()?;
+    header.wrap_master_key(&key, &master_key)?;
+    let members = std::mem::take(&mut header.members);
+    for member in members.into_iter().filter(|member| member.name != name) {
+        let recipient = share::recipient(member.recipient.as_str())?;
+        header.wrap_for_member(member.name.as_str(), &recipient, &master_key)?;
+    }
+    Ok((header, master_key))
+}
+
+/// Checks `pass` against the header of the vault in `vault_dir`, such as before the vault is
+/// rekeyed with it, since a mistyped password would otherwise become the new master password
+pub(crate) fn check_pass(vault_dir: &Path, pass: &str) -> Result<()> {
+    let header = VaultHeader::read(vault_dir)?;
+    let keyfile = read_keyfile(vault_dir, &header)?;
+    header.verify(&vault_key(&header, pass, keyfile.as_ref().map(|keyfile| keyfile.as_slice()))?)
+}
+
+/// Adds the problems with the header of the vault in `vault_dir` to the report, see
+/// `Vault::verify`. `enveloped` is set if the vault was opened with a master key, which the
+/// header must then hold.
+pub(crate) fn verify_header(vault_dir: &Path, enveloped: bool, report: &mut Report) {
+    match VaultHeader::read(vault_dir) {
+        Ok(header) => {
+            for problem in header.problems() {
+                report.add(global::HEADER_FILE, Problem::Header, problem);
+            }
+            if enveloped && header.master_key.is_none() {
+                report.add(global::HEADER_FILE, Problem::Header, "the vault's master key is missing");
+            }
+        }
+        Err(e) => report.add(global::HEADER_FILE, Problem::Header, e),
+    }
+}
+
+/// Returns the entry as it is written, with its modified time updated and its created time set
+/// if it is new. A signature which no longer matches the entry is dropped,
+/// since the entry was changed by someone who didn't sign it.
+pub(crate) fn stamp(entry: &Entry) -> Entry {
+    let mut entry = entry.clone();
+    if !sign::intact(&entry) {
+        entry.signature = None;
+    }
+    entry.metadata.modified = entry::now();
+    if entry.metadata.created == 0 {
+        entry.metadata.created = entry.metadata.modified;
+    }
+    entry
+}
+
+/// Checks that the key is a valid entry name. Names may contain `/` to place the entry in a
+/// folder, but every part of the name must be non-empty and must not start with a `.`, so that
+/// entries can't escape the vault directory or be mistaken for the vault's own files. The user's
+/// config file is kept at the root of the default store, so its name isn't allowed there either.
+pub fn validate_key(key: &str) -> Result<()> {
+    let valid = !key.contains('\\') && key != global::CONFIG_FILE
+        && key.split('/').all(|part| !part.is_empty() && !part.starts_with('.'));
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::BadKeyName { name: key.to_string() })
+    }
+}
+
+/// Marks the start of every encrypted entry, followed by the entry's format version
+const ENTRY_MAGIC: &[u8] = b"YAPE";
+
+/// The format version entries are written in, where each entry is encrypted with its own data
+/// key, see `Aes256GcmEngine::seal_enveloped`. Versions 0 and 1 were only ever recorded in the
+/// vault header, see `VaultHeader`.
+const ENTRY_VERSION: u8 = 3;
+
+/// The format version of entries encrypted with the vault's key directly, which is still written
+/// in vaults without a master key until they are rekeyed
+const DIRECT_ENTRY_VERSION: u8 = 2;
+
+/// Returns the engine which encrypts the entries and attachments of the vault in `vault_dir`
+/// with the given key, compressing them first if the compress setting is on
+pub(crate) fn engine(vault_dir: &Path, key: Zeroizing<[u8; KEY_LEN]>) -> Result<Aes256GcmEngine> {
+    let compress = config::read_or_default()?.with_vault(vault_dir)?.compress();
+    Ok(Aes256GcmEngine::with_key(key).with_compression(compress))
+}
+
+/// Encrypts the contents of the entry with the given name, prefixed with the format marker and
+/// version. Entries are encrypted with their own data key if `enveloped` is set, in which case
+/// the engine holds the vault's master key. Large contents are compressed by the engine if it
+/// was asked to, see `engine`.
+pub(crate) fn encrypt_entry(engine: &Aes256GcmEngine, enveloped: bool, key: &str, plaintext: &[u8]) -> Result<Vec<u8>> {
+    let version = if enveloped { ENTRY_VERSION } else { DIRECT_ENTRY_VERSION };
+    let aad = SimpleVault::associated_data(version, key);
+    let ciphertext = match enveloped {
+        true => engine.seal_enveloped(plaintext, aad.as_slice())?,
+        false => engine.encrypt_bytes(plaintext, aad.as_slice())?,
+    };
+    Ok([ENTRY_MAGIC, &[version], ciphertext.as_slice()].concat())
+}
+
+/// Decrypts the contents of the entry with the given name, written by `encrypt_entry`. Contents
+/// written before entries were marked with their format use `version`, the format recorded in
+/// the vault header, and are upgraded the next time they are written.
+pub(crate) fn decrypt_entry(engine: &Aes256GcmEngine, version: u32, key: &str, data: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+    let (version, ciphertext) = match data.strip_prefix(ENTRY_MAGIC) {
+        Some([version, ciphertext @ ..]) => (*version, ciphertext),
+        Some([]) => return Err(Error::NotAnEntry { name: key.to_string() }),
+        None => (version as u8, data),
+    };
+    if data.starts_with(ENTRY_MAGIC) && version != ENTRY_VERSION && version != DIRECT_ENTRY_VERSION {
+        return Err(Error::UnsupportedEntry { name: key.to_string(), version });
+    }
+
+    let aad = SimpleVault::associated_data(version, key);
+    if version == ENTRY_VERSION {
+        if ciphertext.len() < crypto::WRAPPED_KEY_LEN + crypto::OVERHEAD {
+            return Err(Error::NotAnEntry { name: key.to_string() });
+        }
+        engine.open_enveloped(ciphertext, aad.as_slice())
+    } else {
+        if ciphertext.len() < crypto::OVERHEAD {
+            return Err(Error::NotAnEntry { name: key.to_string() });
         }
+        engine.decrypt_bytes(ciphertext, aad.as_slice())
+    }
+}
 
-        // TODO remove testing only
-        let pass = std::env::var("PASS").unwrap();
-        let engine = Aes256GcmEngine::new(pass);
+/// Returns the name of the entry held by a file in the vault, given its path relative to the
+/// vault. Previous versions are kept in the history as `.history/<name>/<version>`, and deleted
+/// entries in the trash, see `trash_item`.
+fn entry_name(file: &str) -> &str {
+    if let Some((key, _, _)) = trash_item(file) {
+        return key;
+    }
+    file.strip_prefix(global::HISTORY_DIR)
+        .and_then(|history| history.strip_prefix('/'))
+        .and_then(|history| history.rsplit_once('/'))
+        .map_or(file, |(name, _)| name)
+}
 
-        Ok(SimpleVault { vault_dir, engine })
+/// Checks that the name of an attachment can be used as the name of its file
+pub(crate) fn validate_attachment(attachment: &str) -> Result<()> {
+    if attachment.is_empty() || attachment.starts_with('.') || attachment.contains(['/', '\\']) {
+        return Err(Error::BadAttachmentName { attachment: attachment.to_string() });
     }
+    Ok(())
+}
+
+/// Returns the associated data which the data key of an attachment is bound to, so the
+/// attachment can't be moved to another entry or renamed
+pub(crate) fn attachment_aad(key: &str, attachment: &str) -> Vec<u8> {
+    format!("yap/attachment/{}/{}", key, attachment).into_bytes()
+}
 
-    pub(crate) fn load(vault_dir: PathBuf) -> Result<SimpleVault> {
-        // TODO remove testing only
-        let pass = std::env::var("PASS").unwrap();
-        let engine = Aes256GcmEngine::new(pass);
+/// Splits the path of a file in the trash, `.trash/<name>/.<deleted>/<file>`, into the name of
+/// the deleted entry, when it was deleted, and the path of the file within its directory in the
+/// trash, such as `entry` or `.history/1`
+pub(crate) fn trash_item(file: &str) -> Option<(&str, i64, &str)> {
+    let rest = file.strip_prefix(global::TRASH_DIR)?.strip_prefix('/')?;
+    let (key, rest) = rest.split_once("/.")?;
+    let (deleted, file) = rest.split_once('/')?;
+    Some((key, deleted.parse().ok()?, file))
+}
 
-        Ok(SimpleVault { vault_dir, engine })
+/// Returns the name of the entry and of the attachment held by a file in the vault, given its
+/// path relative to the vault, or None if it isn't a complete attachment
+fn attachment_name(file: &str) -> Option<(&str, &str)> {
+    if let Some((key, _, file)) = trash_item(file) {
+        let attachment = file.strip_prefix(global::ATTACHMENTS_DIR)?.strip_prefix('/')?;
+        return Some((key, attachment)).filter(|(_, attachment)| !attachment.starts_with('.'));
     }
+    let (key, attachment) = file.strip_prefix(global::ATTACHMENTS_DIR)?
+        .strip_prefix('/')?
+        .rsplit_once(format!("/{}/", global::ATTACHMENT_FILES_DIR).as_str())?;
+    Some((key, attachment)).filter(|(_, attachment)| !attachment.starts_with('.'))
+}
 
-    pub fn get_key(&self, key: &str) -> Result<String> {
-        let p = self.vault_dir.join(Path::new(key));
-        if !p.as_path().exists() {
-            Err(Error::PasswordNotFound { name: key.to_string() })
-        } else {
-            let data = std::fs::read(p.as_path())?;
-            let plaintext = self.engine.decrypt_bytes(data.as_slice())?;
+/// Checks whether a file in the vault, given its path relative to the vault, is an attachment
+/// which was never finished, see `Vault::attach`
+fn unfinished_attachment(file: &str) -> bool {
+    let attachment = file.starts_with(global::ATTACHMENTS_DIR)
+        || trash_item(file).is_some_and(|(_, _, file)| file.starts_with(global::ATTACHMENTS_DIR));
+    attachment && attachment_name(file).is_none()
+}
 
-            Ok(String::from_utf8(plaintext)?)
+/// Removes the now empty folders containing a deleted or moved file, stopping at `root`
+pub(crate) fn remove_empty_parents(root: &Path, p: &Path) -> Result<()> {
+    for dir in p.ancestors().skip(1).take_while(|dir| *dir != root && dir.starts_with(root)) {
+        if std::fs::read_dir(dir)?.next().is_some() {
+            break;
         }
+        std::fs::remove_dir(dir)?;
     }
+    Ok(())
+}
 
-    pub fn set_key(&mut self, key: &str, value: String) -> Result<()> {
-        let p = self.vault_dir.join(Path::new(key));
-        let ciphertext = self.engine.encrypt_bytes(value.as_bytes())?;
-        Ok(std::fs::write(p.as_path(), ciphertext)?)
+/// Removes the index of the vault in the given directory, so it is rebuilt the next time it is
+/// needed. This is done when the vault's files are changed by something other than the vault,
+/// such as a sync.
+pub(crate) fn remove_index(vault_dir: &Path) -> Result<()> {
+    let p = vault_dir.join(global::INDEX_FILE);
+    if p.as_path().exists() {
+        debug!("removing the index of {}", vault_dir.display());
+        std::fs::remove_file(p.as_path())?;
+    }
+    Ok(())
+}
+
+/// Writes the file atomically, so a crash part way through leaves either the old file or the new
+/// one, never a mix of the two. The contents are written to a hidden temporary file in the same
+/// directory and synced to disk, then renamed over the file, and the directory is synced so the
+/// rename itself is kept. A replaced file's permissions are kept.
+pub(crate) fn atomic_write(p: &Path, contents: &[u8]) -> Result<()> {
+    let dir = p.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let name = p.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+    let suffix: String = crypto::new_salt()?[..4].iter().map(|b| format!("{:02x}", b)).collect();
+    let temp = dir.join(format!(".{}.{}{}", name, suffix, global::TEMP_SUFFIX));
+    trace!("writing {} through {}", p.display(), temp.display());
+
+    let written = (|| -> Result<()> {
+        let mut f = File::create(temp.as_path())?;
+        if let Ok(metadata) = std::fs::metadata(p) {
+            f.set_permissions(metadata.permissions())?;
+        }
+        f.write_all(contents)?;
+        f.sync_all()?;
+        Ok(std::fs::rename(temp.as_path(), p)?)
+    })();
+    if let Err(e) = written {
+        let _ = std::fs::remove_file(temp.as_path());
+        return Err(e);
+    }
+
+    #[cfg(unix)]
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+/// Moves a completed staging directory, written by a rekey or a batch of entries, into the
+/// vault. Entries are moved before the header, and the staging directory is only removed once
+/// everything has been moved, so this can safely be run again if it is interrupted.
+pub(crate) fn finish_staging(vault_dir: &Path, staging: &Path) -> Result<()> {
+    let mut files = vec![];
+    collect_files(staging, "", true, &mut files)?;
+    files.retain(|file| file != global::HEADER_FILE && file != global::REKEY_COMPLETE);
+
+    for file in files {
+        let to = vault_dir.join(Path::new(file.as_str()));
+        if let Some(parent) = to.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::rename(staging.join(Path::new(file.as_str())), to)?;
+    }
+
+    let header = staging.join(global::HEADER_FILE);
+    if header.as_path().exists() {
+        std::fs::rename(header, vault_dir.join(global::HEADER_FILE))?;
+    }
+
+    Ok(std::fs::remove_dir_all(staging)?)
+}
+
+/// Finishes an interrupted rekey or batch if its staging directory was completed, or otherwise
+/// discards the incomplete staging directory, leaving the vault as it was before. The staging
+/// directory may still be being written by another yap, so it is only touched under the lock.
+pub(crate) fn recover_staging(vault_dir: &Path) -> Result<()> {
+    const STAGING: [&str; 2] = [global::REKEY_DIR, global::BATCH_DIR];
+    if !STAGING.iter().any(|dir| vault_dir.join(dir).exists()) {
+        return Ok(());
+    }
+
+    let _lock = lock::lock(vault_dir)?;
+    for dir in STAGING {
+        let staging = vault_dir.join(dir);
+        if staging.join(global::REKEY_COMPLETE).exists() {
+            info!("finishing the interrupted write in {}", staging.display());
+            finish_staging(vault_dir, staging.as_path())?;
+        } else if staging.as_path().exists() {
+            info!("discarding the incomplete write in {}", staging.display());
+            std::fs::remove_dir_all(staging)?;
+        }
+    }
+    Ok(())
+}
+
+/// Recursively collects the paths of files in `dir` into `files`, relative to the vault and
+/// prefixed with the path of the parent directory. Unless `hidden` is set, dotfiles and the
+/// config file are skipped, which leaves only the names of entries.
+pub(crate) fn collect_files(dir: &Path, parent: &str, hidden: bool, files: &mut Vec<String>) -> Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !hidden && (name.starts_with('.') || (parent.is_empty() && name == global::CONFIG_FILE)) {
+            continue;
+        }
+
+        let file = if parent.is_empty() { name } else { format!("{}/{}", parent, name) };
+        if entry.file_type()?.is_dir() {
+            collect_files(entry.path().as_path(), file.as_str(), hidden, files)?;
+        } else {
+            files.push(file);
+        }
+    }
+
+    Ok(())
+}
+
+/// Creates a vault in the given store, or the default store if None, protected by `pass`, and by
+/// the contents of a keyfile if one is given. This may overwrite an existing vault, since a new
+/// header with a fresh salt is written.
+pub fn create(store: Option<String>, pass: &str, kdf: Kdf, backend: Backend, keyfile: Option<&[u8]>) -> Result<Box<dyn Vault>> {
+    let remote = open_remote(store.clone())?;
+    let vault_dir = get_path_or_default(store)?;
+    if !vault_dir.as_path().exists() {
+        std::fs::create_dir_all(vault_dir.as_path())?;
+    }
+
+    let mut header = VaultHeader::new(kdf, backend)?;
+    header.keyfile = keyfile.map(|keyfile| crypto::keyfile_hash(header.salt.as_slice(), keyfile));
+    let key = vault_key(&header, pass, keyfile)?;
+    header.set_verifier(&key)?;
+    header.wrap_master_key(&key, &crypto::new_key()?)?;
+    header.write(vault_dir.as_path())?;
+
+    let vault: Box<dyn Vault> = match backend {
+        Backend::Files => Box::new(SimpleVault::load(vault_dir, key, &header)?),
+        Backend::Sqlite => Box::new(SqliteVault::load(vault_dir, key, &header)?),
+    };
+    with_remote(vault, remote)
+}
+
+/// Loads the vault in the given store, or the default store if None, using `pass`. The backend
+/// is chosen by the vault header.
+pub fn load(store: Option<String>, pass: &str) -> Result<Box<dyn Vault>> {
+    let key = derive_key(store.clone(), pass)?;
+    load_with_key(store, key)
+}
+
+/// Derives the key of the vault in the given store from its master password, using the salt and
+/// Kdf stored in the vault header, and the keyfile in the keyfile setting if the vault has one. A
+/// master password change which was interrupted is finished or rolled back first, since it may
+/// replace the header. The key is wiped from memory when dropped.
+pub fn derive_key(store: Option<String>, pass: &str) -> Result<Zeroizing<[u8; KEY_LEN]>> {
+    open_remote(store.clone())?;
+    let vault_dir = get_path_or_default(store)?;
+    recover_staging(vault_dir.as_path())?;
+
+    let header = VaultHeader::read(vault_dir.as_path())?;
+    let keyfile = read_keyfile(vault_dir.as_path(), &header)?;
+    vault_key(&header, pass, keyfile.as_ref().map(|keyfile| keyfile.as_slice()))
+}
+
+/// Derives the key of the vault with the given header from its master password, mixing in the
+/// contents of its keyfile if it has one
+fn vault_key(header: &VaultHeader, pass: &str, keyfile: Option<&[u8]>) -> Result<Zeroizing<[u8; KEY_LEN]>> {
+    let key = header.kdf.derive_key(pass, header.salt.as_slice())?;
+    Ok(match keyfile {
+        Some(keyfile) => crypto::mix_keyfile(&key, keyfile),
+        None => key,
+    })
+}
+
+/// Reads the keyfile of the vault in `vault_dir` from the path in its keyfile setting, which
+/// `yap init` saves in the vault's local config file, checking it against the hash in the header.
+/// None is returned if the vault has no keyfile.
+fn read_keyfile(vault_dir: &Path, header: &VaultHeader) -> Result<Option<Zeroizing<Vec<u8>>>> {
+    let Some(hash) = header.keyfile.as_ref() else { return Ok(None) };
+
+    let p = config::read_or_default()?.with_vault(vault_dir)?.keyfile().ok_or(Error::MissingKeyfile)?;
+    let keyfile = Zeroizing::new(std::fs::read(p.as_path())?);
+    if crypto::keyfile_hash(header.salt.as_slice(), keyfile.as_slice()) != *hash {
+        return Err(Error::WrongKeyfile { path: p.display().to_string() });
+    }
+    Ok(Some(keyfile))
+}
+
+/// Loads the vault in the given store with a key which was already derived, such as one cached
+/// by the agent. The key is checked against the verifier in the vault header, so a wrong master
+/// password is reported here rather than when the first entry is read.
+pub fn load_with_key(store: Option<String>, key: Zeroizing<[u8; KEY_LEN]>) -> Result<Box<dyn Vault>> {
+    let remote = open_remote(store.clone())?;
+    let vault_dir = get_path_or_default(store)?;
+    recover_staging(vault_dir.as_path())?;
+
+    debug!("opening the vault in {}", vault_dir.display());
+    let header = VaultHeader::read(vault_dir.as_path())?;
+    header.verify(&key)?;
+    let vault: Box<dyn Vault> = match header.backend {
+        Backend::Files => Box::new(SimpleVault::load(vault_dir.clone(), key, &header)?),
+        Backend::Sqlite => Box::new(SqliteVault::load(vault_dir.clone(), key, &header)?),
+    };
+    wrap(vault, remote, vault_dir.as_path(), header.backend)
+}
+
+/// Returns the master key of the vault in the given store, checking `pass` against it. This is
+/// the secret recovery shares are split from, see `recovery::split`.
+pub fn master_key(store: Option<String>, pass: &str) -> Result<Zeroizing<[u8; KEY_LEN]>> {
+    let key = derive_key(store.clone(), pass)?;
+    let header = VaultHeader::read(get_path_or_default(store)?.as_path())?;
+    header.verify(&key)?;
+    header.unwrap_master_key(&key)?.ok_or(Error::NoMasterKey)
+}
+
+/// Restores access to the vault in the given store with its master key, such as one recovered
+/// from shares, by encrypting the master key with a key derived from a new master password. The
+/// master key is first checked against an entry, since encrypting a wrong one would lock everyone
+/// out of the vault.
+pub fn recover(store: Option<String>, master_key: Zeroizing<[u8; KEY_LEN]>, new_pass: &str) -> Result<()> {
+    let remote = open_remote(store.clone())?;
+    let vault_dir = get_path_or_default(store)?;
+    recover_staging(vault_dir.as_path())?;
+
+    let header = VaultHeader::read(vault_dir.as_path())?;
+    let vault: Box<dyn Vault> = match header.backend {
+        Backend::Files => Box::new(SimpleVault::with_master_key(vault_dir.clone(), master_key, &header)?),
+        Backend::Sqlite => Box::new(SqliteVault::with_master_key(vault_dir.clone(), master_key, &header)?),
+    };
+    if let Some(name) = vault.list_keys("")?.first() {
+        vault.get_entry(name.as_str()).map_err(|_| Error::WrongRecoveryShares)?;
+    }
+    wrap(vault, remote, vault_dir.as_path(), header.backend)?.rekey(new_pass)
+}
+
+/// Converts the vault in the given store to another backend in place, returning whether it was
+/// converted, which it isn't if it already uses the backend. A snapshot of the vault is taken
+/// first, so the conversion can be rolled back, see `snapshot::rollback`. Entries are moved as
+/// they are stored, still encrypted and bound to their names, so no master password is needed.
+///
+/// The converted entries are written to a staging directory along with the new header, which is
+/// moved into the vault once it is complete, like a rekey, see `SimpleVault::rewrite`. The old
+/// backend's files are only removed once the vault uses the new backend.
+pub fn migrate(store: Option<String>, to: Backend) -> Result<bool> {
+    let vault_dir = get_path_or_default(store)?;
+    recover_staging(vault_dir.as_path())?;
+    let mut header = VaultHeader::read(vault_dir.as_path())?;
+    if header.backend == to {
+        return Ok(false);
+    }
+    if config::read_or_default()?.with_vault(vault_dir.as_path())?.read_only() {
+        return Err(Error::ReadOnly);
+    }
+
+    snapshot::create(vault_dir.as_path(), Some(format!("before migrating to {}", to.name()).as_str()))?;
+    let _lock = lock::lock(vault_dir.as_path())?;
+    let staging = vault_dir.join(global::REKEY_DIR);
+    if staging.as_path().exists() {
+        std::fs::remove_dir_all(staging.as_path())?;
+    }
+    std::fs::create_dir(staging.as_path())?;
+    let converted = match to {
+        Backend::Files => sqlite::to_files(vault_dir.as_path(), staging.as_path()),
+        Backend::Sqlite => sqlite::from_files(vault_dir.as_path(), staging.as_path()),
+    };
+    if let Err(e) = converted {
+        std::fs::remove_dir_all(staging.as_path())?;
+        return Err(e);
     }
+
+    info!("migrating {} to the {} backend", vault_dir.display(), to.name());
+    header.backend = to;
+    header.write(staging.as_path())?;
+    File::open(staging.join(global::HEADER_FILE))?.sync_all()?;
+    atomic_write(staging.join(global::REKEY_COMPLETE).as_path(), &[])?;
+    finish_staging(vault_dir.as_path(), staging.as_path())?;
+
+    match to {
+        Backend::Files => std::fs::remove_file(vault_dir.join(global::DATABASE_FILE))?,
+        Backend::Sqlite => {
+            let mut keys = vec![];
+            collect_files(vault_dir.as_path(), "", false, &mut keys)?;
+            for key in keys {
+                let p = vault_dir.join(Path::new(key.as_str()));
+                std::fs::remove_file(p.as_path())?;
+                remove_empty_parents(vault_dir.as_path(), p.as_path())?;
+            }
+            for dir in [global::HISTORY_DIR, global::ATTACHMENTS_DIR, global::TRASH_DIR] {
+                if vault_dir.join(dir).is_dir() {
+                    std::fs::remove_dir_all(vault_dir.join(dir))?;
+                }
+            }
+            remove_index(vault_dir.as_path())?;
+        }
+    }
+    Ok(true)
 }
 
-pub fn create(store: Option<String>) -> Result<SimpleVault> {
+/// Returns the Kdf which the key of the vault in the given store is derived with
+pub fn kdf(store: Option<String>) -> Result<Kdf> {
+    Ok(VaultHeader::read(get_path_or_default(store)?.as_path())?.kdf)
+}
+
+/// Loads the vault in the given store as a member, with the master key encrypted to the age key
+/// at `identity`, see `Vault::add_member`. None is returned if there is no such key, or it doesn't
+/// belong to a member of the vault, so the master password can be asked for instead.
+pub fn load_as_member(store: Option<String>, identity: &Path) -> Result<Option<Box<dyn Vault>>> {
+    if !identity.exists() {
+        return Ok(None);
+    }
+    let remote = open_remote(store.clone())?;
     let vault_dir = get_path_or_default(store)?;
-    SimpleVault::create(vault_dir)
+    recover_staging(vault_dir.as_path())?;
+
+    let header = VaultHeader::read(vault_dir.as_path())?;
+    if header.members.is_empty() {
+        return Ok(None);
+    }
+    let Some(master_key) = header.unwrap_as_member(&share::read_identity(identity)?)? else { return Ok(None) };
+    let vault: Box<dyn Vault> = match header.backend {
+        Backend::Files => Box::new(SimpleVault::with_master_key(vault_dir.clone(), master_key, &header)?),
+        Backend::Sqlite => Box::new(SqliteVault::with_master_key(vault_dir.clone(), master_key, &header)?),
+    };
+    wrap(vault, remote, vault_dir.as_path(), header.backend).map(Some)
+}
+
+/// Lists the members of the vault in the given store by name, with their public keys
+pub fn members(store: Option<String>) -> Result<Vec<(String, String)>> {
+    let header = VaultHeader::read(get_path_or_default(store)?.as_path())?;
+    Ok(header.members.into_iter().map(|member| (member.name, member.recipient)).collect())
+}
+
+/// Opens the remote vault in the given store, if it is one, bringing its local copy up to date
+/// with the server. The local copy is then used like any other vault directory.
+fn open_remote(store: Option<String>) -> Result<Option<RemoteStore>> {
+    match store.or_else(|| std::env::var(global::STORE_VAR).ok()) {
+        Some(url) if remote::is_remote(url.as_str()) => {
+            let mut remote = RemoteStore::open(url.as_str(), remote::cache_dir(url.as_str())?, remote::token()?);
+            remote.fetch()?;
+            Ok(Some(remote))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Wraps a vault opened from the local copy of a remote vault, so its changes are sent to the
+/// server
+fn with_remote(vault: Box<dyn Vault>, remote: Option<RemoteStore>) -> Result<Box<dyn Vault>> {
+    match remote {
+        Some(remote) => Ok(Box::new(RemoteVault::new(remote, vault)?)),
+        None => Ok(vault),
+    }
+}
+
+/// Wraps a vault opened from a store as its settings ask. Entries about to be replaced or deleted
+/// are backed up unless the backup_count setting is 0, see `BackupVault`, which copies their
+/// files and so only backs up vaults using the files backend. Changes are recorded in the
+/// activity log, see `ActivityVault`. Changes lock the vault directory, see `LockingVault`.
+/// Entries are signed if the sign_as setting is set, see `SigningVault`. Changes to a remote
+/// vault are sent to its server, see `RemoteVault`. The vault can't be changed if the read_only
+/// setting is on, see `ReadOnlyVault`.
+fn wrap(vault: Box<dyn Vault>, remote: Option<RemoteStore>, vault_dir: &Path, backend: Backend) -> Result<Box<dyn Vault>> {
+    let config = config::read_or_default()?.with_vault(vault_dir)?;
+    let vault: Box<dyn Vault> = match (backend, config.backup_count()) {
+        (Backend::Sqlite, _) | (_, 0) => vault,
+        (Backend::Files, keep) => Box::new(BackupVault::new(vault, vault_dir, keep)),
+    };
+    let vault = Box::new(ActivityVault::new(vault, vault_dir));
+    let vault = Box::new(LockingVault::new(vault, vault_dir, Duration::from_secs(config.lock_wait())));
+    let vault: Box<dyn Vault> = match config.sign_as() {
+        Some(author) => Box::new(SigningVault::new(vault, sign::key_path()?.as_path(), author, sign::trusted_authors(&config)?)?),
+        None => vault,
+    };
+
+    let vault = with_remote(vault, remote)?;
+    match config.read_only() {
+        true => Ok(Box::new(ReadOnlyVault::new(vault))),
+        false => Ok(vault),
+    }
+}
+
+/// Checks whether a vault has been initialized in the store
+pub fn exists(store: Option<String>) -> Result<bool> {
+    if let Some(remote) = open_remote(store.clone())? {
+        return Ok(remote.has(global::HEADER_FILE));
+    }
+    Ok(get_path_or_default(store)?.join(global::HEADER_FILE).exists())
 }
 
-pub fn load(store: Option<String>) -> Result<SimpleVault> {
+/// Lists the names of the entries in a vault which start with `prefix`, without opening it. Entry
+/// names aren't encrypted, so this needs no master password, which lets shells complete them.
+pub fn list_names(store: Option<String>, prefix: &str) -> Result<Vec<String>> {
     let vault_dir = get_path_or_default(store)?;
-    SimpleVault::load(vault_dir)
+    let header = VaultHeader::read(vault_dir.as_path())?;
+
+    let mut keys = vec![];
+    match header.backend {
+        Backend::Files => collect_files(vault_dir.as_path(), "", false, &mut keys)?,
+        Backend::Sqlite => keys = sqlite::names(vault_dir.as_path())?,
+    }
+    keys.retain(|key| key.starts_with(prefix));
+    keys.sort();
+    Ok(keys)
 }
 
-/// Method to get a PathBuf to Some(String), or the default dir if None
-fn get_path_or_default(path: Option<String>) -> Result<PathBuf> {
-    Ok(path.map(|p| PathBuf::from(p)).unwrap_or(default_vault_path()?))
+/// Method to get a PathBuf to Some(String), or the default dir if None. The YAP_STORE environment
+/// variable is used when no store is given. The name of a store registered in the config, see
+/// `Configuration::add_store`, is resolved to its path, and the URL of a remote vault to its
+/// local copy, see `RemoteVault`. Anything else is treated as a path.
+pub fn get_path_or_default(path: Option<String>) -> Result<PathBuf> {
+    match path.or_else(|| std::env::var(global::STORE_VAR).ok()) {
+        Some(url) if remote::is_remote(url.as_str()) => remote::cache_dir(url.as_str()),
+        Some(path) => Ok(config::read_or_default()?.store_path(path.as_str()).unwrap_or_else(|| PathBuf::from(path))),
+        None => default_vault_path(),
+    }
 }
 
 /// Returns a PathBuf representing the location of the default
@@ -78,19 +1906,425 @@ fn default_vault_path() -> Result<PathBuf> {
 #[cfg(test)]
 mod test {
     use std::path::Path;
-    use crate::vault;
+    use crate::config::{self, SettingKey};
+    use crate::crypto::{Aes256GcmEngine, Kdf};
+    use crate::entry::Entry;
+    use crate::error::Error;
+    use crate::global::{BATCH_DIR, CONFIG_FILE, HEADER_FILE, HISTORY_DIR, INDEX_FILE, REKEY_COMPLETE, REKEY_DIR, VAULT_CONFIG_FILE};
+    use crate::header::{VaultHeader, FORMAT_VERSION};
+    use crate::share;
+    use crate::vault::{self, Backend};
 
     #[test]
     fn create_and_load_simple_vault() {
-        // TODO remove testing
-        std::env::set_var("PASS", "asdf");
-        let yap_test = String::from(".yap_test");
+        let yap_test = String::from(".yap_test_vault");
         std::fs::create_dir_all(Path::new(yap_test.as_str())).unwrap();
 
-        let simple_vault = vault::create(Some(yap_test.clone()));
+        let simple_vault = vault::create(Some(yap_test.clone()), "asdf", Kdf::default(), Backend::default(), None);
         assert!(simple_vault.is_ok());
+        assert!(Path::new(yap_test.as_str()).join(HEADER_FILE).exists());
 
-        let simple_vault = vault::load(Some(yap_test));
-        assert!(simple_vault.is_ok());
+        let mut simple_vault = simple_vault.unwrap();
+        simple_vault.set_key("test", "password".to_string()).unwrap();
+
+        let simple_vault = vault::load(Some(yap_test.clone()), "asdf").unwrap();
+        assert_eq!(simple_vault.get_key("test").unwrap(), "password");
+
+        let mut simple_vault = simple_vault;
+        let metadata = simple_vault.metadata("test").unwrap();
+        assert!(metadata.created > 0);
+        assert_eq!(metadata.created, metadata.modified);
+        assert_eq!(metadata.accessed, 0);
+        simple_vault.access_entry("test").unwrap();
+        assert!(simple_vault.metadata("test").unwrap().accessed > 0);
+
+        simple_vault.set_key("other", "other password".to_string()).unwrap();
+        assert!(simple_vault.rename_key("test", "other", false).is_err());
+        simple_vault.rename_key("other", "other", false).unwrap();
+        simple_vault.rename_key("other", "moved", false).unwrap();
+        assert_eq!(simple_vault.get_key("moved").unwrap(), "other password");
+        simple_vault.rename_key("moved", "test", true).unwrap();
+        assert_eq!(simple_vault.get_key("test").unwrap(), "other password");
+
+        simple_vault.delete_key("test").unwrap();
+        assert!(!simple_vault.has_key("test"));
+        assert!(simple_vault.delete_key("test").is_err());
+
+        std::fs::remove_dir_all(yap_test).unwrap();
+    }
+
+    #[test]
+    fn keyfiles_are_recorded_per_store() {
+        let stores = [String::from(".yap_test_keyfile_a"), String::from(".yap_test_keyfile_b")];
+        for store in stores.iter() {
+            let dir = Path::new(store.as_str());
+            std::fs::create_dir_all(dir).unwrap();
+            let keyfile = std::fs::canonicalize(dir).unwrap().join("keyfile");
+            std::fs::write(keyfile.as_path(), store.as_bytes()).unwrap();
+            let contents = std::fs::read(keyfile.as_path()).unwrap();
+            vault::create(Some(store.clone()), "asdf", Kdf::default(), Backend::default(), Some(contents.as_slice())).unwrap();
+            config::set_local_key(dir, SettingKey::Keyfile, keyfile.display().to_string().as_str()).unwrap();
+        }
+
+        // Each store opens with its own keyfile, which is kept out of the synced config file
+        for store in stores.iter() {
+            assert!(!Path::new(store.as_str()).join(VAULT_CONFIG_FILE).exists());
+            let mut vault = vault::load(Some(store.clone()), "asdf").unwrap();
+            vault.set_key("github", store.clone()).unwrap();
+            assert_eq!(vault.get_key("github").unwrap(), *store);
+        }
+
+        // Using the other store's keyfile fails
+        let other = std::fs::canonicalize(stores[1].as_str()).unwrap().join("keyfile");
+        config::set_local_key(Path::new(stores[0].as_str()), SettingKey::Keyfile, other.display().to_string().as_str()).unwrap();
+        assert!(matches!(vault::load(Some(stores[0].clone()), "asdf"), Err(Error::WrongKeyfile { .. })));
+
+        stores.iter().for_each(|store| std::fs::remove_dir_all(store).unwrap());
+    }
+
+    #[test]
+    fn list_keys_in_vault() {
+        let yap_test = String::from(".yap_test_vault_list");
+        let mut simple_vault = vault::create(Some(yap_test.clone()), "asdf", Kdf::default(), Backend::default(), None).unwrap();
+
+        for key in ["github", "work/github", "work/aws", "email"] {
+            simple_vault.set_key(key, "password".to_string()).unwrap();
+        }
+
+        assert_eq!(simple_vault.list_keys("").unwrap(), vec!["email", "github", "work/aws", "work/github"]);
+        assert_eq!(simple_vault.list_keys("work/").unwrap(), vec!["work/aws", "work/github"]);
+        assert_eq!(vault::list_names(Some(yap_test.clone()), "work/").unwrap(), vec!["work/aws", "work/github"]);
+
+        for key in ["../escape", "/absolute", "work//github", ".hidden", ""] {
+            assert!(simple_vault.set_key(key, "password".to_string()).is_err());
+        }
+
+        simple_vault.rename_key("work/aws", "personal/aws", false).unwrap();
+        assert_eq!(simple_vault.delete_folder("work").unwrap(), vec!["work/github"]);
+        assert!(!Path::new(yap_test.as_str()).join("work").exists());
+        assert!(simple_vault.delete_folder("work/").is_err());
+        assert_eq!(simple_vault.list_keys("").unwrap(), vec!["email", "github", "personal/aws"]);
+
+        std::fs::remove_dir_all(yap_test).unwrap();
+    }
+
+    #[test]
+    fn rekey_vault() {
+        let yap_test = String::from(".yap_test_vault_rekey");
+        let mut simple_vault = vault::create(Some(yap_test.clone()), "old", Kdf::default(), Backend::default(), None).unwrap();
+
+        simple_vault.set_key("github", "password".to_string()).unwrap();
+        simple_vault.set_key("work/aws", "aws password".to_string()).unwrap();
+        simple_vault.set_key("work/aws", "new aws password".to_string()).unwrap();
+
+        // Entries are encrypted with the vault's master key, so a rekey leaves them untouched
+        let github = std::fs::read(Path::new(yap_test.as_str()).join("github")).unwrap();
+        simple_vault.rekey("new").unwrap();
+        assert_eq!(simple_vault.get_key("github").unwrap(), "password");
+        assert_eq!(std::fs::read(Path::new(yap_test.as_str()).join("github")).unwrap(), github);
+
+        let mut simple_vault = vault::load(Some(yap_test.clone()), "new").unwrap();
+        assert_eq!(simple_vault.get_key("work/aws").unwrap(), "new aws password");
+        simple_vault.restore("work/aws", 1).unwrap();
+        assert_eq!(simple_vault.get_key("work/aws").unwrap(), "aws password");
+
+        assert!(matches!(vault::load(Some(yap_test.clone()), "old"), Err(Error::WrongPassword)));
+
+        // An incomplete rekey is discarded when the vault is loaded
+        let staging = Path::new(yap_test.as_str()).join(REKEY_DIR);
+        std::fs::create_dir(staging.as_path()).unwrap();
+        std::fs::write(staging.join("github"), "garbage").unwrap();
+        let simple_vault = vault::load(Some(yap_test.clone()), "new").unwrap();
+        assert!(!staging.exists());
+        assert_eq!(simple_vault.get_key("github").unwrap(), "password");
+
+        std::fs::remove_dir_all(yap_test).unwrap();
+    }
+
+    #[test]
+    fn members_open_the_vault_until_removed() {
+        let yap_test = String::from(".yap_test_vault_members");
+        let mut owner = vault::create(Some(yap_test.clone()), "owner", Kdf::default(), Backend::default(), None).unwrap();
+        owner.set_key("github", "password".to_string()).unwrap();
+
+        let alice = Path::new(yap_test.as_str()).join(".alice.key");
+        let bob = Path::new(yap_test.as_str()).join(".bob.key");
+        let alice_public = share::recipient(share::public_key(alice.as_path()).unwrap().as_str()).unwrap();
+        let bob_public = share::recipient(share::public_key(bob.as_path()).unwrap().as_str()).unwrap();
+        assert!(vault::load_as_member(Some(yap_test.clone()), alice.as_path()).unwrap().is_none());
+
+        owner.add_member("alice", &alice_public).unwrap();
+        owner.add_member("bob", &bob_public).unwrap();
+        assert_eq!(vault::members(Some(yap_test.clone())).unwrap().len(), 2);
+        let mut as_bob = vault::load_as_member(Some(yap_test.clone()), bob.as_path()).unwrap().unwrap();
+        assert_eq!(as_bob.get_key("github").unwrap(), "password");
+        as_bob.set_key("work/aws", "aws password".to_string()).unwrap();
+
+        // Removing a member rotates the master key, and the remaining members keep their access
+        assert!(matches!(owner.remove_member("bob", "wrong"), Err(Error::WrongPassword)));
+        assert!(matches!(owner.remove_member("carol", "owner"), Err(Error::MemberNotFound { .. })));
+        let github = std::fs::read(Path::new(yap_test.as_str()).join("github")).unwrap();
+        owner.remove_member("bob", "owner").unwrap();
+        assert_ne!(std::fs::read(Path::new(yap_test.as_str()).join("github")).unwrap(), github);
+        assert!(vault::load_as_member(Some(yap_test.clone()), bob.as_path()).unwrap().is_none());
+        assert!(as_bob.get_key("github").is_err());
+
+        let as_alice = vault::load_as_member(Some(yap_test.clone()), alice.as_path()).unwrap().unwrap();
+        assert_eq!(as_alice.get_key("work/aws").unwrap(), "aws password");
+        assert_eq!(vault::load(Some(yap_test.clone()), "owner").unwrap().get_key("github").unwrap(), "password");
+
+        std::fs::remove_dir_all(yap_test).unwrap();
+    }
+
+    #[test]
+    fn index_follows_changes_and_is_rebuilt() {
+        let yap_test = String::from(".yap_test_vault_index");
+        let mut simple_vault = vault::create(Some(yap_test.clone()), "asdf", Kdf::default(), Backend::default(), None).unwrap();
+        simple_vault.set_key("github", "password".to_string()).unwrap();
+        simple_vault.set_key("work/aws", "aws password".to_string()).unwrap();
+        simple_vault.set_key("work/vpn", "vpn password".to_string()).unwrap();
+        simple_vault.rename_key("work/vpn", "work/wifi", false).unwrap();
+        simple_vault.delete_key("github").unwrap();
+
+        let index = Path::new(yap_test.as_str()).join(INDEX_FILE);
+        assert!(index.exists());
+        assert_eq!(simple_vault.list_keys("").unwrap(), vec!["work/aws", "work/wifi"]);
+        let modified = simple_vault.metadata("work/wifi").unwrap().modified;
+        assert!(modified > 0);
+
+        // Vaults without an index, or with entries added by hand, have it rebuilt
+        std::fs::remove_file(index.as_path()).unwrap();
+        assert_eq!(simple_vault.list_keys("work/").unwrap(), vec!["work/aws", "work/wifi"]);
+        assert_eq!(simple_vault.metadata("work/wifi").unwrap().modified, modified);
+        std::fs::copy(Path::new(yap_test.as_str()).join("work/aws"), Path::new(yap_test.as_str()).join("copied")).unwrap();
+        assert_eq!(simple_vault.list_keys("").unwrap().len(), 2);
+        assert_eq!(simple_vault.rebuild_index().unwrap(), 3);
+        assert_eq!(simple_vault.list_keys("").unwrap(), vec!["copied", "work/aws", "work/wifi"]);
+
+        std::fs::write(index.as_path(), "garbage").unwrap();
+        assert_eq!(simple_vault.list_keys("").unwrap().len(), 3);
+
+        std::fs::remove_dir_all(yap_test).unwrap();
+    }
+
+    #[test]
+    fn batches_are_written_whole_or_not_at_all() {
+        let yap_test = String::from(".yap_test_vault_batch");
+        let mut simple_vault = vault::create(Some(yap_test.clone()), "asdf", Kdf::default(), Backend::default(), None).unwrap();
+        simple_vault.set_key("github", "old".to_string()).unwrap();
+
+        let batch = |names: &[&str]| names.iter()
+            .map(|name| (name.to_string(), Entry::new(format!("{} password", name))))
+            .collect::<Vec<_>>();
+        simple_vault.set_entries(batch(&["github", "work/aws", "work/vpn", "work/aws"]).as_slice()).unwrap();
+        assert_eq!(simple_vault.list_keys("").unwrap(), vec!["github", "work/aws", "work/vpn"]);
+        assert_eq!(simple_vault.get_key("github").unwrap(), "github password");
+        assert!(simple_vault.metadata("work/vpn").unwrap().created > 0);
+        assert_eq!(simple_vault.history("github").unwrap().len(), 1);
+        assert!(!Path::new(yap_test.as_str()).join(BATCH_DIR).exists());
+
+        // A bad name anywhere in the batch leaves the vault untouched
+        assert!(simple_vault.set_entries(batch(&["mail", ".hidden"]).as_slice()).is_err());
+        assert!(!simple_vault.has_key("mail"));
+
+        // An interrupted batch is discarded if it wasn't complete, and finished if it was
+        let staging = Path::new(yap_test.as_str()).join(BATCH_DIR);
+        std::fs::create_dir(staging.as_path()).unwrap();
+        std::fs::copy(Path::new(yap_test.as_str()).join("github"), staging.join("copied")).unwrap();
+        let simple_vault = vault::load(Some(yap_test.clone()), "asdf").unwrap();
+        assert!(!simple_vault.has_key("copied") && !staging.exists());
+
+        std::fs::create_dir(staging.as_path()).unwrap();
+        std::fs::copy(Path::new(yap_test.as_str()).join("work/aws"), staging.join("work-aws")).unwrap();
+        std::fs::write(staging.join(REKEY_COMPLETE), "").unwrap();
+        let simple_vault = vault::load(Some(yap_test.clone()), "asdf").unwrap();
+        assert!(simple_vault.has_key("work-aws") && !staging.exists());
+
+        std::fs::remove_dir_all(yap_test).unwrap();
+    }
+
+    #[test]
+    fn history_and_restore() {
+        let yap_test = String::from(".yap_test_vault_history");
+        let mut simple_vault = vault::create(Some(yap_test.clone()), "asdf", Kdf::default(), Backend::default(), None).unwrap();
+
+        simple_vault.set_key("github", "first".to_string()).unwrap();
+        assert!(simple_vault.history("github").unwrap().is_empty());
+
+        simple_vault.set_key("github", "second".to_string()).unwrap();
+        simple_vault.set_key("github", "third".to_string()).unwrap();
+        let versions: Vec<u32> = simple_vault.history("github").unwrap().into_iter().map(|(v, _)| v).collect();
+        assert_eq!(versions, vec![1, 2]);
+
+        simple_vault.restore("github", 1).unwrap();
+        assert_eq!(simple_vault.get_key("github").unwrap(), "first");
+        assert_eq!(simple_vault.history("github").unwrap().len(), 3);
+        assert!(simple_vault.restore("github", 10).is_err());
+
+        simple_vault.rename_key("github", "moved", false).unwrap();
+        assert_eq!(simple_vault.history("moved").unwrap().len(), 3);
+        assert!(simple_vault.history("github").unwrap().is_empty());
+        assert_eq!(simple_vault.list_keys("").unwrap(), vec!["moved"]);
+        simple_vault.restore("moved", 2).unwrap();
+        assert_eq!(simple_vault.get_key("moved").unwrap(), "second");
+
+        std::fs::remove_dir_all(yap_test).unwrap();
+    }
+
+    #[test]
+    fn deleted_entries_are_kept_in_the_trash() {
+        let yap_test = String::from(".yap_test_vault_trash");
+        let dir = Path::new(yap_test.as_str());
+        let mut simple_vault = vault::create(Some(yap_test.clone()), "asdf", Kdf::default(), Backend::default(), None).unwrap();
+        simple_vault.set_key("work/github", "first".to_string()).unwrap();
+        simple_vault.set_key("work/github", "second".to_string()).unwrap();
+        simple_vault.attach("work/github", "codes.txt", &mut "codes".as_bytes()).unwrap();
+
+        simple_vault.delete_key("work/github").unwrap();
+        assert!(simple_vault.list_keys("").unwrap().is_empty());
+        assert!(!dir.join("work").exists() && !dir.join(HISTORY_DIR).join("work").exists());
+        let trashed = simple_vault.list_trash().unwrap();
+        assert_eq!(trashed.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>(), vec!["work/github"]);
+
+        // A second entry with the same name is trashed apart from the first, and restored first
+        simple_vault.set_key("work/github", "other".to_string()).unwrap();
+        simple_vault.delete_key("work/github").unwrap();
+        assert_eq!(simple_vault.list_trash().unwrap().len(), 2);
+        simple_vault.restore_trash("work/github", false).unwrap();
+        assert_eq!(simple_vault.get_key("work/github").unwrap(), "other");
+        assert!(matches!(simple_vault.restore_trash("work/github", false), Err(Error::PasswordExists { .. })));
+        assert!(matches!(simple_vault.restore_trash("missing", false), Err(Error::NotInTrash { .. })));
+
+        // The trash is re-encrypted along with the vault when the master key changes
+        let member = share::public_key(dir.join("member.key").as_path()).unwrap();
+        simple_vault.add_member("bob", &share::recipient(member.as_str()).unwrap()).unwrap();
+        simple_vault.remove_member("bob", "asdf").unwrap();
+
+        simple_vault.restore_trash("work/github", true).unwrap();
+        assert_eq!(simple_vault.get_key("work/github").unwrap(), "second");
+        assert_eq!(simple_vault.history("work/github").unwrap().len(), 1);
+        assert_eq!(simple_vault.list_attachments("work/github").unwrap(), vec!["codes.txt"]);
+        let mut codes = vec![];
+        simple_vault.read_attachment("work/github", "codes.txt", &mut codes).unwrap();
+        assert_eq!(codes, b"codes");
+
+        // The entry replaced by the restore went into the trash, and is gone once it is emptied
+        assert_eq!(simple_vault.list_trash().unwrap().len(), 1);
+        assert_eq!(simple_vault.empty_trash(Some(0)).unwrap(), 0);
+        assert_eq!(simple_vault.empty_trash(None).unwrap(), 1);
+        assert!(simple_vault.list_trash().unwrap().is_empty());
+
+        std::fs::remove_dir_all(yap_test).unwrap();
+    }
+
+    #[test]
+    fn entries_are_bound_to_their_names() {
+        let yap_test = String::from(".yap_test_vault_aad");
+        let dir = Path::new(yap_test.as_str());
+        let mut simple_vault = vault::create(Some(yap_test.clone()), "asdf", Kdf::default(), Backend::default(), None).unwrap();
+
+        simple_vault.set_key("github", "github password".to_string()).unwrap();
+        simple_vault.set_key("email", "email password".to_string()).unwrap();
+        std::fs::copy(dir.join("github"), dir.join("email")).unwrap();
+        assert!(simple_vault.get_key("email").is_err());
+
+        std::fs::remove_dir_all(yap_test).unwrap();
+    }
+
+    #[test]
+    fn names_outside_the_vault_are_rejected() {
+        let yap_test = String::from(".yap_test_vault_escape");
+        let dir = Path::new(yap_test.as_str());
+        let mut simple_vault = vault::create(Some(format!("{}/vault", yap_test)), "asdf", Kdf::default(), Backend::default(), None).unwrap();
+        simple_vault.set_key("work/email", "password".to_string()).unwrap();
+        std::fs::write(dir.join("bank"), "outside").unwrap();
+        std::fs::write(dir.join("vault").join(CONFIG_FILE), "session: none\n").unwrap();
+
+        for name in ["../bank", "work/../../bank", HEADER_FILE, "work/../.header.yaml", CONFIG_FILE] {
+            assert!(!simple_vault.has_key(name));
+            assert!(matches!(simple_vault.get_entry(name), Err(Error::BadKeyName { .. })));
+            assert!(matches!(simple_vault.delete_key(name), Err(Error::BadKeyName { .. })));
+            assert!(matches!(simple_vault.rename_key(name, "moved", true), Err(Error::BadKeyName { .. })));
+            assert!(matches!(simple_vault.history(name), Err(Error::BadKeyName { .. })));
+            assert!(matches!(simple_vault.list_attachments(name), Err(Error::BadKeyName { .. })));
+        }
+        assert!(matches!(simple_vault.set_key(CONFIG_FILE, "password".to_string()), Err(Error::BadKeyName { .. })));
+        assert_eq!(std::fs::read_to_string(dir.join("bank")).unwrap(), "outside");
+        assert_eq!(std::fs::read_to_string(dir.join("vault").join(CONFIG_FILE)).unwrap(), "session: none\n");
+        assert!(dir.join("vault").join(HEADER_FILE).exists());
+        simple_vault.set_key("work/config.yaml", "password".to_string()).unwrap();
+
+        std::fs::remove_dir_all(yap_test).unwrap();
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn files_are_replaced_atomically() {
+        let yap_test = String::from(".yap_test_vault_atomic");
+        let dir = Path::new(yap_test.as_str());
+        std::fs::create_dir_all(dir).unwrap();
+
+        let p = dir.join("settings");
+        vault::atomic_write(p.as_path(), b"first").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(p.as_path(), std::fs::Permissions::from_mode(0o600)).unwrap();
+            vault::atomic_write(p.as_path(), b"second").unwrap();
+            assert_eq!(std::fs::metadata(p.as_path()).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+        vault::atomic_write(p.as_path(), b"third").unwrap();
+        assert_eq!(std::fs::read(p.as_path()).unwrap(), b"third");
+        assert_eq!(std::fs::read_dir(dir).unwrap().count(), 1);
+
+        // A failed write leaves nothing behind
+        assert!(vault::atomic_write(dir.join("missing/settings").as_path(), b"lost").is_err());
+        assert_eq!(std::fs::read_dir(dir).unwrap().count(), 1);
+
+        std::fs::remove_dir_all(yap_test).unwrap();
+    }
+
+    #[test]
+    fn entry_format_versions() {
+        let yap_test = String::from(".yap_test_vault_format");
+        let dir = Path::new(yap_test.as_str());
+        vault::create(Some(yap_test.clone()), "asdf", Kdf::default(), Backend::default(), None).unwrap();
+
+        // Entries written before they were marked with their format use the format recorded in
+        // the vault header, and are upgraded when they are next written
+        let mut header = VaultHeader::read(dir).unwrap();
+        header.version = 0;
+        header.master_key = None;
+        header.write(dir).unwrap();
+        let engine = Aes256GcmEngine::with_key(vault::derive_key(Some(yap_test.clone()), "asdf").unwrap());
+        let legacy = engine.encrypt_bytes(Entry::new("legacy password".to_string()).to_bytes().unwrap().as_slice(), &[]).unwrap();
+        std::fs::write(dir.join("legacy"), legacy.as_slice()).unwrap();
+        std::fs::write(dir.join("copied"), legacy.as_slice()).unwrap();
+
+        let mut simple_vault = vault::load(Some(yap_test.clone()), "asdf").unwrap();
+        assert_eq!(simple_vault.get_key("copied").unwrap(), "legacy password");
+        simple_vault.rekey("asdf").unwrap();
+        assert_eq!(VaultHeader::read(dir).unwrap().version, FORMAT_VERSION);
+        assert!(VaultHeader::read(dir).unwrap().master_key.is_some());
+        assert!(std::fs::read(dir.join("legacy")).unwrap().starts_with(b"YAPE\x03"));
+        assert_eq!(simple_vault.get_key("legacy").unwrap(), "legacy password");
+        std::fs::copy(dir.join("legacy"), dir.join("copied")).unwrap();
+        assert!(simple_vault.get_key("copied").is_err());
+
+        // Files which aren't entries, or were written by a newer version of yap, are reported
+        std::fs::write(dir.join("garbage"), "garbage").unwrap();
+        assert!(matches!(simple_vault.get_key("garbage"), Err(Error::NotAnEntry { .. })));
+        let mut future = std::fs::read(dir.join("legacy")).unwrap();
+        future[4] = 99;
+        std::fs::write(dir.join("future"), future).unwrap();
+        assert!(matches!(simple_vault.get_key("future"), Err(Error::UnsupportedEntry { version: 99, .. })));
+
+        // Large entries are compressed by the engine, without changing their format
+        let notes = "a long note which repeats itself\n".repeat(100);
+        simple_vault.set_entry("note", &Entry::note(notes.clone())).unwrap();
+        let compressed = std::fs::read(dir.join("note")).unwrap();
+        assert!(compressed.starts_with(b"YAPE\x03") && compressed.len() < notes.len() / 4);
+        assert_eq!(simple_vault.get_entry("note").unwrap().notes, Some(notes));
+
+        std::fs::remove_dir_all(yap_test).unwrap();
+    }
+}
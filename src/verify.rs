@@ -0,0 +1,212 @@
+use std::fmt;
+
+use serde::Serialize;
+
+/// Problem is something wrong with a file in the vault, found by `Vault::verify`
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Problem {
+    /// The vault header can't be read, or holds values yap doesn't accept
+    Header,
+    /// The file doesn't decrypt with the vault's key, or was changed since it was written
+    Corrupt,
+    /// The entry is on disk but missing from the index
+    Unindexed,
+    /// The entry is in the index but not on disk
+    Missing,
+    /// The index holds different timestamps or tags than the entry itself
+    Stale,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let problem = match self {
+            Problem::Header => "bad header",
+            Problem::Corrupt => "corrupt",
+            Problem::Unindexed => "not in the index",
+            Problem::Missing => "missing from disk",
+            Problem::Stale => "out of date in the index",
+        };
+        f.write_str(problem)
+    }
+}
+
+/// Finding is a problem with a single file in the vault, named by its path relative to the vault
+#[derive(Serialize)]
+pub struct Finding {
+    pub path: String,
+    pub problem: Problem,
+    /// What exactly is wrong, such as the error decrypting the file
+    pub detail: String,
+    /// Whether the file was moved into the quarantine, see `Vault::verify`
+    pub quarantined: bool,
+}
+
+/// Report holds what verifying a vault found, in the order the files were checked
+#[derive(Serialize, Default)]
+pub struct Report {
+    /// The number of encrypted files which were checked
+    pub checked: usize,
+    pub findings: Vec<Finding>,
+}
+
+impl Report {
+    /// Records a problem with the file at `path`
+    pub(crate) fn add(&mut self, path: &str, problem: Problem, detail: impl ToString) {
+        self.findings.push(Finding { path: path.to_string(), problem, detail: detail.to_string(), quarantined: false });
+    }
+
+    /// Returns the number of findings with the given problem
+    pub fn count(&self, problem: Problem) -> usize {
+        self.findings.iter().filter(|finding| finding.problem == problem).count()
+    }
+}
+
+/// Action is what `Vault::repair` did to fix a file in the vault
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    /// A temporary file left by an interrupted write was removed
+    Removed,
+    /// History or attachments left behind by an interrupted delete or restore were moved to
+    /// where their entry is
+    Moved,
+    /// A corrupted file was replaced by its copy in a backup, or an automatic backup
+    Restored,
+    /// A corrupted entry was replaced by its latest previous version which could be read
+    Reverted,
+    /// A file which couldn't be repaired was moved into the quarantine
+    Quarantined,
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let action = match self {
+            Action::Removed => "removed",
+            Action::Moved => "moved",
+            Action::Restored => "restored",
+            Action::Reverted => "reverted",
+            Action::Quarantined => "quarantined",
+        };
+        f.write_str(action)
+    }
+}
+
+/// Repair is a change `Vault::repair` made to a single file or directory in the vault, named by
+/// its path relative to the vault
+#[derive(Serialize)]
+pub struct Repair {
+    pub path: String,
+    pub action: Action,
+    /// Where the file went or came from, if anywhere
+    pub detail: String,
+}
+
+/// RepairReport holds the repairs made to a vault, in the order they were made
+#[derive(Serialize, Default)]
+pub struct RepairReport {
+    pub repairs: Vec<Repair>,
+    /// The number of entries in the rebuilt index
+    pub indexed: usize,
+}
+
+impl RepairReport {
+    /// Records a repair to the file at `path`
+    pub(crate) fn add(&mut self, path: &str, action: Action, detail: impl ToString) {
+        self.repairs.push(Repair { path: path.to_string(), action, detail: detail.to_string() });
+    }
+
+    /// Returns the number of repairs which took the given action
+    pub fn count(&self, action: Action) -> usize {
+        self.repairs.iter().filter(|repair| repair.action == action).count()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use crate::crypto::Kdf;
+    use crate::global::{HISTORY_DIR, QUARANTINE_DIR};
+    use crate::vault::{self, Backend};
+    use crate::verify::{Action, Problem};
+
+    #[test]
+    fn corrupted_files_are_found_and_quarantined() {
+        let yap_test = String::from(".yap_test_verify");
+        let dir = Path::new(yap_test.as_str());
+        let mut vault = vault::create(Some(yap_test.clone()), "asdf", Kdf::default(), Backend::default(), None).unwrap();
+        vault.set_key("github", "first".to_string()).unwrap();
+        vault.set_key("github", "second".to_string()).unwrap();
+        vault.set_key("work/email", "password".to_string()).unwrap();
+        vault.attach("github", "notes.txt", &mut "notes".as_bytes()).unwrap();
+        let report = vault.verify(false).unwrap();
+        assert_eq!((report.checked, report.findings.len()), (4, 0));
+
+        let mut contents = std::fs::read(dir.join("work/email")).unwrap();
+        let last = contents.len() - 1;
+        contents[last] ^= 1;
+        std::fs::write(dir.join("work/email"), contents).unwrap();
+        std::fs::write(dir.join(HISTORY_DIR).join("github/1"), "garbage").unwrap();
+        std::fs::copy(dir.join("github"), dir.join("copied")).unwrap();
+        let report = vault.verify(false).unwrap();
+        assert_eq!(report.count(Problem::Corrupt), 3);
+        assert!(report.findings.iter().all(|finding| !finding.quarantined));
+
+        // Quarantined entries leave the vault and its index
+        let report = vault.verify(true).unwrap();
+        assert_eq!(report.findings.iter().filter(|finding| finding.quarantined).count(), 3);
+        assert!(dir.join(QUARANTINE_DIR).join("work/email").exists());
+        assert!(!dir.join("work").exists());
+        assert_eq!(vault.list_keys("").unwrap(), vec!["github"]);
+        assert!(vault.verify(false).unwrap().findings.is_empty());
+
+        // The index is compared with the entries on disk
+        std::fs::remove_file(dir.join("github")).unwrap();
+        let report = vault.verify(false).unwrap();
+        assert_eq!((report.findings[0].path.as_str(), report.findings[0].problem), ("github", Problem::Missing));
+
+        std::fs::remove_dir_all(yap_test).unwrap();
+    }
+
+    #[test]
+    fn repairs_restore_what_they_can() {
+        let yap_test = String::from(".yap_test_repair");
+        let dir = Path::new(yap_test.as_str());
+        let mut vault = vault::create(Some(yap_test.clone()), "asdf", Kdf::default(), Backend::default(), None).unwrap();
+        vault.set_key("github", "first".to_string()).unwrap();
+        vault.set_key("github", "second".to_string()).unwrap();
+        vault.set_key("email", "password".to_string()).unwrap();
+        vault.set_key("bank", "password".to_string()).unwrap();
+        let backup = Path::new(".yap_test_repair_backup");
+        std::fs::create_dir_all(backup).unwrap();
+        std::fs::copy(dir.join("bank"), backup.join("bank")).unwrap();
+
+        std::fs::write(dir.join(".github.0123.tmp"), "partial").unwrap();
+        for name in ["github", "email", "bank"] {
+            std::fs::write(dir.join(name), "garbage").unwrap();
+        }
+        // A delete which was interrupted after moving the entry into the trash
+        vault.set_key("old", "password".to_string()).unwrap();
+        vault.set_key("old", "changed".to_string()).unwrap();
+        std::fs::copy(dir.join(HISTORY_DIR).join("old/1"), dir.join("history")).unwrap();
+        vault.delete_key("old").unwrap();
+        std::fs::create_dir_all(dir.join(HISTORY_DIR).join("old")).unwrap();
+        std::fs::rename(dir.join("history"), dir.join(HISTORY_DIR).join("old/1")).unwrap();
+        let trashed = vault.list_trash().unwrap()[0].1;
+        std::fs::remove_dir_all(dir.join(".trash/old").join(format!(".{}", trashed)).join(HISTORY_DIR)).unwrap();
+
+        let report = vault.repair(Some(backup)).unwrap();
+        assert_eq!(report.count(Action::Removed), 1);
+        assert_eq!(report.count(Action::Moved), 1);
+        assert_eq!(vault.get_key("bank").unwrap(), "password");
+        assert_eq!(vault.get_key("github").unwrap(), "first");
+        assert!(dir.join(QUARANTINE_DIR).join("email").exists());
+        assert_eq!(vault.list_keys("").unwrap(), vec!["bank", "github"]);
+        vault.restore_trash("old", false).unwrap();
+        assert_eq!(vault.history("old").unwrap().len(), 1);
+
+        std::fs::remove_dir_all(backup).unwrap();
+        std::fs::remove_dir_all(yap_test).unwrap();
+    }
+}
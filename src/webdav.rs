@@ -0,0 +1,543 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use base64::Engine;
+use ring::digest::{self, SHA256};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::error::{Error, Result};
+use crate::global::{ACTIVITY_FILE, ACTIVITY_KEY_FILE, BACKUPS_DIR, BATCH_DIR, CONFIG_FILE, INDEX_FILE, LAST_SYNC_FILE, LOCAL_CONFIG_FILE, LOCK_FILE, QUARANTINE_DIR, REKEY_DIR, SNAPSHOTS_DIR, SYNC_STATE_FILE, TEMP_SUFFIX, TOKENS_FILE, WEBDAV_PASSWORD_VAR};
+use crate::sync::{self, Remote, Resolution, SyncStatus};
+use crate::vault;
+
+/// The schemes of remote URLs which sync with a WebDAV server, and the schemes they are sent over
+const SCHEMES: &[(&str, &str)] = &[("dav://", "http://"), ("davs://", "https://")];
+
+/// The properties listed for each file on the server
+const PROPFIND_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:propfind xmlns:d="DAV:"><d:prop><d:resourcetype/><d:getetag/></d:prop></d:propfind>"#;
+
+/// The namespace of WebDAV's XML elements
+const DAV_NAMESPACE: &str = "DAV:";
+
+/// Checks whether a remote URL is for a WebDAV server, such as `davs://cloud.example.com/yap`
+pub fn is_webdav(remote_url: &str) -> bool {
+    SCHEMES.iter().any(|(scheme, _)| remote_url.starts_with(scheme))
+}
+
+/// FileState is a file as it was when it was last synced: the SHA-256 hash of its contents, and
+/// the ETag the server gave it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct FileState {
+    hash: String,
+    etag: String,
+}
+
+/// Action is what a sync does with a file, depending on which side changed it since it was last
+/// synced
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Action {
+    /// Only the local file changed, so it is uploaded
+    Upload,
+    /// The local file was deleted, so the remote one is deleted too
+    DeleteRemote,
+    /// Only the remote file changed, so it is downloaded
+    Download,
+    /// The remote file was deleted, so the local one is deleted too
+    DeleteLocal,
+    /// Both sides deleted the file, so it is forgotten
+    Forget,
+    /// Both sides changed the file
+    Conflict,
+}
+
+/// Changes are the actions to take for each changed file, along with the ETag of each remote file
+type Changes = (Vec<(String, Action)>, BTreeMap<String, String>);
+
+/// WebDav syncs a vault directory with a collection on a WebDAV server, such as a Nextcloud
+/// folder. Entries are uploaded as the encrypted files they are stored in, so the server never
+/// sees their contents. The state of each file when it was last synced is kept in the vault, so
+/// changes on each side can be told apart. The server's ETags are used to make sure a file is
+/// only overwritten or deleted if it hasn't changed since it was listed.
+pub struct WebDav {
+    dir: PathBuf,
+    /// The URL of the collection, ending in a slash
+    url: String,
+    /// The path of the collection, which hrefs in listings are relative to
+    path: String,
+    auth: Option<String>,
+    state: BTreeMap<String, FileState>,
+}
+
+impl WebDav {
+    /// Opens the vault directory for syncing with the collection at a `dav://` or `davs://` URL.
+    /// A username and password may be given in the URL, or the password may be left out and
+    /// given by the WEBDAV_PASS environment variable instead.
+    pub fn open(dir: PathBuf, remote_url: &str) -> Result<WebDav> {
+        let (scheme, rest) = SCHEMES.iter()
+            .find_map(|(scheme, http)| remote_url.strip_prefix(scheme).map(|rest| (*http, rest)))
+            .ok_or_else(|| Error::WebDav { message: format!("{} is not a WebDAV URL", remote_url) })?;
+
+        let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+        let (auth, host) = match authority.rsplit_once('@') {
+            Some((credentials, host)) => {
+                let (user, pass) = match credentials.split_once(':') {
+                    Some((user, pass)) => (decode(user), decode(pass)),
+                    None => (decode(credentials), std::env::var(WEBDAV_PASSWORD_VAR).unwrap_or_default()),
+                };
+                let token = base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", user, pass));
+                (Some(format!("Basic {}", token)), host)
+            }
+            None => (None, authority),
+        };
+
+        let path = format!("{}/", path.trim_end_matches('/'));
+        let state = match std::fs::read(dir.join(SYNC_STATE_FILE)) {
+            Ok(state) => serde_yaml::from_slice(state.as_slice())?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(WebDav { dir, url: format!("{}{}{}", scheme, host, path), path: decode(path.as_str()), auth, state })
+    }
+
+    /// Lists the synced files in the vault directory with the hashes of their contents
+    fn local_files(&self) -> Result<BTreeMap<String, String>> {
+        local_files(self.dir.as_path())
+    }
+
+    /// Lists the synced files on the server with their ETags, walking each collection in turn
+    /// since not every server allows listing a whole tree at once
+    fn remote_files(&self) -> Result<BTreeMap<String, String>> {
+        let mut files = BTreeMap::new();
+        let mut collections = vec![String::new()];
+        while let Some(collection) = collections.pop() {
+            let response = self.request("PROPFIND", collection.as_str())
+                .set("Depth", "1")
+                .set("Content-Type", "application/xml; charset=utf-8")
+                .send_string(PROPFIND_BODY);
+
+            let listing = match response {
+                Ok(response) => response.into_string()?,
+                // Nothing has been pushed to the server yet
+                Err(ureq::Error::Status(404, _)) if collection.is_empty() => return Ok(files),
+                Err(e) => return Err(dav_error(e, collection.as_str())),
+            };
+
+            for (name, etag, is_collection) in parse_listing(listing.as_str(), self.path.as_str())? {
+                if name.is_empty() || name == collection.trim_end_matches('/') || !is_synced(name.as_str()) {
+                    continue;
+                }
+                if is_collection {
+                    collections.push(format!("{}/", name));
+                } else {
+                    files.insert(name, etag);
+                }
+            }
+        }
+        Ok(files)
+    }
+
+    /// Works out what to do with each file which changed since the last sync, returning the
+    /// actions along with the ETag of each remote file. A file changed on both sides is only a
+    /// conflict if its contents differ, which is checked by downloading it, so the first sync of
+    /// two copies of the same vault doesn't conflict. Conflicts are resolved by keeping the side
+    /// given by `resolution`, and are an error if there is none.
+    fn changes(&mut self, resolution: Option<Resolution>) -> Result<Changes> {
+        let local = self.local_files()?;
+        let remote = self.remote_files()?;
+
+        let mut changes = vec![];
+        let mut conflicts = vec![];
+        for (name, action) in plan(&self.state, &local, &remote) {
+            if action != Action::Conflict {
+                changes.push((name, action));
+                continue;
+            }
+
+            if let Some(state) = self.identical(name.as_str(), &local, &remote)? {
+                self.state.insert(name, state);
+                continue;
+            }
+
+            // When keeping both, the local entry has already been copied aside, see `keep_both`
+            let action = match (resolution, local.contains_key(name.as_str()), remote.contains_key(name.as_str())) {
+                (None, _, _) => {
+                    conflicts.push(name);
+                    continue;
+                }
+                (Some(Resolution::Local), true, _) => Action::Upload,
+                (Some(Resolution::Local), false, _) => Action::DeleteRemote,
+                (Some(_), _, true) => Action::Download,
+                (Some(_), _, false) => Action::DeleteLocal,
+            };
+            changes.push((name, action));
+        }
+
+        if !conflicts.is_empty() {
+            self.save_state()?;
+            return Err(Error::SyncConflict { names: conflicts });
+        }
+        Ok((changes, remote))
+    }
+
+    /// Checks whether a file changed on both sides ended up with the same contents, returning its
+    /// state if so
+    fn identical(&self, name: &str, local: &BTreeMap<String, String>, remote: &BTreeMap<String, String>) -> Result<Option<FileState>> {
+        let (Some(local_hash), true) = (local.get(name), remote.contains_key(name)) else { return Ok(None) };
+        let (contents, etag) = self.download(name)?;
+        Ok((hash(contents.as_slice()) == *local_hash).then(|| FileState { hash: local_hash.clone(), etag }))
+    }
+
+    /// Downloads a file, returning its contents and ETag
+    fn download(&self, name: &str) -> Result<(Vec<u8>, String)> {
+        let response = self.request("GET", name).call().map_err(|e| dav_error(e, name))?;
+        let etag = response.header("ETag").unwrap_or_default().to_string();
+
+        let mut contents = vec![];
+        response.into_reader().read_to_end(&mut contents)?;
+        Ok((contents, etag))
+    }
+
+    /// Uploads a file, only replacing the remote file if it still has the given ETag, and returns
+    /// its new ETag. Servers which don't return one are asked for it.
+    fn upload(&self, name: &str, contents: &[u8], etag: Option<&String>) -> Result<String> {
+        self.make_collections(name)?;
+
+        let request = match etag {
+            Some(etag) => self.request("PUT", name).set("If-Match", etag.as_str()),
+            None => self.request("PUT", name).set("If-None-Match", "*"),
+        };
+        let response = request.send_bytes(contents).map_err(|e| dav_error(e, name))?;
+        match response.header("ETag") {
+            Some(etag) => Ok(etag.to_string()),
+            None => {
+                let response = self.request("HEAD", name).call().map_err(|e| dav_error(e, name))?;
+                Ok(response.header("ETag").unwrap_or_default().to_string())
+            }
+        }
+    }
+
+    /// Creates the collections a file is stored in, if they don't exist yet
+    fn make_collections(&self, name: &str) -> Result<()> {
+        let mut collection = String::new();
+        for part in name.split('/').rev().skip(1).collect::<Vec<_>>().into_iter().rev() {
+            collection.push_str(part);
+            collection.push('/');
+            match self.request("MKCOL", collection.as_str()).call() {
+                // The collection already exists
+                Ok(_) | Err(ureq::Error::Status(405, _)) => {}
+                Err(e) => return Err(dav_error(e, collection.as_str())),
+            }
+        }
+        Ok(())
+    }
+
+    /// Creates a request for a file in the collection, relative to its URL
+    fn request(&self, method: &str, name: &str) -> ureq::Request {
+        let url = format!("{}{}", self.url, encode(name));
+        debug!("{} {}", method, name);
+        let request = ureq::request(method, url.as_str()).set("User-Agent", "yap");
+        match &self.auth {
+            Some(auth) => request.set("Authorization", auth.as_str()),
+            None => request,
+        }
+    }
+
+    /// Saves the state of each file as of this sync
+    fn save_state(&self) -> Result<()> {
+        if self.dir.exists() {
+            vault::atomic_write(self.dir.join(SYNC_STATE_FILE).as_path(), serde_yaml::to_string(&self.state)?.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+impl Remote for WebDav {
+    /// Describes the local changes since the last sync. Changes are recorded in the sync state
+    /// once they are pushed, so there is nothing to commit.
+    fn commit(&mut self) -> Result<Option<String>> {
+        let local = self.local_files()?;
+        let names: BTreeSet<&String> = local.keys().chain(self.state.keys()).collect();
+        let changes: Vec<String> = names.into_iter()
+            .filter_map(|name| match (local.get(name), self.state.get(name)) {
+                (Some(_), None) => Some(format!("A\t{}", name)),
+                (None, Some(_)) => Some(format!("D\t{}", name)),
+                (Some(hash), Some(known)) if *hash != known.hash => Some(format!("M\t{}", name)),
+                _ => None,
+            })
+            .collect();
+
+        if changes.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(sync::commit_message(changes.join("\n").as_str())))
+    }
+
+    /// Lists the files a sync would upload, download, or find in conflict, without changing the
+    /// files on either side or the sync state
+    fn status(&mut self) -> Result<SyncStatus> {
+        let local = self.local_files()?;
+        let remote = self.remote_files()?;
+
+        let mut status = SyncStatus::default();
+        for (name, action) in plan(&self.state, &local, &remote) {
+            match action {
+                Action::Upload | Action::DeleteRemote => status.push.push(name),
+                Action::Download | Action::DeleteLocal => status.pull.push(name),
+                Action::Conflict if self.identical(name.as_str(), &local, &remote)?.is_none() => status.conflicts.push(name),
+                Action::Conflict | Action::Forget => {}
+            }
+        }
+        Ok(status)
+    }
+
+    /// Downloads the files which only changed on the server, and deletes the local files which
+    /// were deleted on the server
+    fn pull(&mut self, resolution: Option<Resolution>) -> Result<()> {
+        let (changes, _) = self.changes(resolution)?;
+        for (name, action) in changes {
+            match action {
+                Action::Download => {
+                    let (contents, etag) = self.download(name.as_str())?;
+                    let p = self.dir.join(name.as_str());
+                    if let Some(parent) = p.parent() {
+                        std::fs::create_dir_all(parent)?;
+                    }
+                    vault::atomic_write(p.as_path(), contents.as_slice())?;
+                    self.state.insert(name, FileState { hash: hash(contents.as_slice()), etag });
+                }
+                Action::DeleteLocal => {
+                    let p = self.dir.join(name.as_str());
+                    std::fs::remove_file(p.as_path())?;
+                    vault::remove_empty_parents(self.dir.as_path(), p.as_path())?;
+                    self.state.remove(name.as_str());
+                }
+                Action::Forget => {
+                    self.state.remove(name.as_str());
+                }
+                _ => {}
+            }
+        }
+        self.save_state()
+    }
+
+    /// Uploads the files which only changed locally, and deletes the remote files which were
+    /// deleted locally
+    fn push(&mut self, resolution: Option<Resolution>) -> Result<()> {
+        let (changes, remote) = self.changes(resolution)?;
+        for (name, action) in changes {
+            match action {
+                Action::Upload => {
+                    let contents = std::fs::read(self.dir.join(name.as_str()))?;
+                    let etag = self.upload(name.as_str(), contents.as_slice(), remote.get(name.as_str()))?;
+                    self.state.insert(name, FileState { hash: hash(contents.as_slice()), etag });
+                }
+                Action::DeleteRemote => {
+                    let etag = remote.get(name.as_str()).cloned().unwrap_or_default();
+                    match self.request("DELETE", name.as_str()).set("If-Match", etag.as_str()).call() {
+                        Ok(_) | Err(ureq::Error::Status(404, _)) => {}
+                        Err(e) => return Err(dav_error(e, name.as_str())),
+                    }
+                    self.state.remove(name.as_str());
+                }
+                Action::Forget => {
+                    self.state.remove(name.as_str());
+                }
+                _ => {}
+            }
+        }
+        self.save_state()
+    }
+}
+
+/// Decides what to do with each file from the state it was last synced in, and its hash locally
+/// and ETag on the server now. A file missing from one side was deleted there, or never existed.
+fn plan(state: &BTreeMap<String, FileState>, local: &BTreeMap<String, String>, remote: &BTreeMap<String, String>) -> Vec<(String, Action)> {
+    let names: BTreeSet<&String> = state.keys().chain(local.keys()).chain(remote.keys()).collect();
+    names.into_iter()
+        .filter_map(|name| {
+            let known = state.get(name);
+            let local_changed = local.get(name) != known.map(|known| &known.hash);
+            let remote_changed = remote.get(name) != known.map(|known| &known.etag);
+
+            let action = match (local_changed, remote_changed) {
+                (false, false) => return None,
+                (true, false) if local.contains_key(name) => Action::Upload,
+                (true, false) => Action::DeleteRemote,
+                (false, true) if remote.contains_key(name) => Action::Download,
+                (false, true) => Action::DeleteLocal,
+                (true, true) if !local.contains_key(name) && !remote.contains_key(name) => Action::Forget,
+                (true, true) => Action::Conflict,
+            };
+            Some((name.clone(), action))
+        })
+        .collect()
+}
+
+/// Parses a PROPFIND response into the name of each file or collection relative to the
+/// collection at `base`, its ETag, and whether it is a collection
+fn parse_listing(listing: &str, base: &str) -> Result<Vec<(String, String, bool)>> {
+    let doc = roxmltree::Document::parse(listing)
+        .map_err(|e| Error::WebDav { message: format!("unreadable listing: {}", e) })?;
+    let mut files = vec![];
+    for response in doc.descendants().filter(|node| is_dav(node, "response")) {
+        let Some(href) = response.descendants().find(|node| is_dav(node, "href")).and_then(|node| node.text()) else { continue };
+        let etag = response.descendants().find(|node| is_dav(node, "getetag")).and_then(|node| node.text()).unwrap_or_default();
+        let is_collection = response.descendants().any(|node| is_dav(&node, "collection"));
+
+        // Hrefs are usually absolute paths, but may be full URLs
+        let href = match href.split_once("://") {
+            Some((_, rest)) => &rest[rest.find('/').unwrap_or(rest.len())..],
+            None => href,
+        };
+        if let Some(name) = decode(href).strip_prefix(base) {
+            files.push((name.trim_end_matches('/').to_string(), etag.to_string(), is_collection));
+        }
+    }
+    Ok(files)
+}
+
+/// Checks whether an XML node is the WebDAV element with the given name
+fn is_dav(node: &roxmltree::Node, name: &str) -> bool {
+    node.tag_name().namespace() == Some(DAV_NAMESPACE) && node.tag_name().name() == name
+}
+
+/// Lists the synced files in a vault directory with the hashes of their contents, see `is_synced`
+pub(crate) fn local_files(dir: &Path) -> Result<BTreeMap<String, String>> {
+    let mut files = vec![];
+    if dir.exists() {
+        vault::collect_files(dir, "", true, &mut files)?;
+    }
+
+    files.into_iter()
+        .filter(|name| is_synced(name))
+        .map(|name| {
+            let contents = std::fs::read(dir.join(name.as_str()))?;
+            Ok((name, hash(contents.as_slice())))
+        })
+        .collect()
+}
+
+/// Checks whether a file in the vault directory is synced. Git's files, the user's config file
+/// in the default vault, the sync state and time of the last sync, the tokens of `yap serve`, the
+/// index and lock file, the activity log and its key, quarantined files, automatic backups,
+/// snapshots, a rekey or batch in progress, and temporary files left by an interrupted write are
+/// local to this machine.
+pub(crate) fn is_synced(name: &str) -> bool {
+    let top = name.split('/').next().unwrap_or_default();
+    let file = name.rsplit('/').next().unwrap_or_default();
+    let temp = file.starts_with('.') && file.ends_with(TEMP_SUFFIX);
+    !(top.starts_with(".git") || top == REKEY_DIR || top == BATCH_DIR || top == QUARANTINE_DIR || top == BACKUPS_DIR || top == SNAPSHOTS_DIR || name == CONFIG_FILE || name == LOCAL_CONFIG_FILE || name == SYNC_STATE_FILE || name == LAST_SYNC_FILE || name == TOKENS_FILE || name == INDEX_FILE || name == LOCK_FILE
+        || name == ACTIVITY_FILE || name == ACTIVITY_KEY_FILE || temp)
+}
+
+/// Returns the SHA-256 hash of a file's contents as hex
+pub(crate) fn hash(contents: &[u8]) -> String {
+    digest::digest(&SHA256, contents).as_ref().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Percent-encodes a path for a URL, leaving its slashes as they are
+pub(crate) fn encode(path: &str) -> String {
+    path.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => (b as char).to_string(),
+            b => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Decodes a percent-encoded part of a URL. Invalid escapes are left as they are.
+pub(crate) fn decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes.get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], escaped) {
+            (b'%', Some(b)) => {
+                decoded.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                decoded.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(decoded.as_slice()).to_string()
+}
+
+/// Converts an error from the server about a file into a yap error. A failed precondition means
+/// that the file changed on the server after it was listed.
+fn dav_error(e: ureq::Error, name: &str) -> Error {
+    match e {
+        ureq::Error::Status(412, _) => Error::SyncConflict { names: vec![name.to_string()] },
+        ureq::Error::Status(code, response) => Error::WebDav {
+            message: format!("{} {} for {}", code, response.status_text(), response.get_url()),
+        },
+        e => Error::WebDav { message: e.to_string() },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use crate::webdav::{decode, encode, parse_listing, plan, Action, FileState};
+
+    #[test]
+    fn plans_changes_from_sync_state() {
+        let known = |hash: &str, etag: &str| FileState { hash: hash.to_string(), etag: etag.to_string() };
+        let state = BTreeMap::from([
+            ("same".to_string(), known("h1", "e1")),
+            ("edited".to_string(), known("h2", "e2")),
+            ("updated".to_string(), known("h3", "e3")),
+            ("both".to_string(), known("h4", "e4")),
+            ("deleted".to_string(), known("h5", "e5")),
+            ("gone".to_string(), known("h6", "e6")),
+        ]);
+        let local = BTreeMap::from([
+            ("same".to_string(), "h1".to_string()),
+            ("edited".to_string(), "new".to_string()),
+            ("updated".to_string(), "h3".to_string()),
+            ("both".to_string(), "new".to_string()),
+            ("gone".to_string(), "h6".to_string()),
+            ("added".to_string(), "h7".to_string()),
+        ]);
+        let remote = BTreeMap::from([
+            ("same".to_string(), "e1".to_string()),
+            ("edited".to_string(), "e2".to_string()),
+            ("updated".to_string(), "new".to_string()),
+            ("both".to_string(), "new".to_string()),
+            ("deleted".to_string(), "e5".to_string()),
+        ]);
+
+        assert_eq!(plan(&state, &local, &remote), vec![
+            ("added".to_string(), Action::Upload),
+            ("both".to_string(), Action::Conflict),
+            ("deleted".to_string(), Action::DeleteRemote),
+            ("edited".to_string(), Action::Upload),
+            ("gone".to_string(), Action::DeleteLocal),
+            ("updated".to_string(), Action::Download),
+        ]);
+
+        let listing = r#"<?xml version="1.0"?>
+            <d:multistatus xmlns:d="DAV:">
+              <d:response><d:href>/dav/yap/</d:href><d:propstat><d:prop><d:resourcetype><d:collection/></d:resourcetype></d:prop></d:propstat></d:response>
+              <d:response><d:href>/dav/yap/work%20stuff/</d:href><d:propstat><d:prop><d:resourcetype><d:collection/></d:resourcetype></d:prop></d:propstat></d:response>
+              <d:response><d:href>https://example.com/dav/yap/github</d:href><d:propstat><d:prop><d:resourcetype/><d:getetag>"abc"</d:getetag></d:prop></d:propstat></d:response>
+            </d:multistatus>"#;
+        assert_eq!(parse_listing(listing, "/dav/yap/").unwrap(), vec![
+            (String::new(), String::new(), true),
+            ("work stuff".to_string(), String::new(), true),
+            ("github".to_string(), "\"abc\"".to_string(), false),
+        ]);
+        assert_eq!(encode("work stuff/git+hub"), "work%20stuff/git%2Bhub");
+        assert_eq!(decode("work%20stuff/git%2Bhub%"), "work stuff/git+hub%");
+    }
+}
@@ -0,0 +1,173 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+
+use crate::config;
+use crate::error::{Error, Result};
+use crate::global::CONFIG_FILE;
+use crate::header::VaultHeader;
+
+/// Filesystems which write changes to new blocks rather than over the old ones, so overwriting
+/// a file leaves its old contents on disk anyway
+const COPY_ON_WRITE: [&str; 5] = ["btrfs", "zfs", "bcachefs", "f2fs", "nilfs2"];
+
+/// The number of zeros written at a time when overwriting a file
+const WIPE_CHUNK_LEN: usize = 64 * 1024;
+
+/// Overwrites the file with zeros and removes it, so its contents can't be recovered from the
+/// disk afterwards. The overwrite is skipped on filesystems where it wouldn't reach the old
+/// blocks, see `overwrites_in_place`, where the file is only removed. A symlink is only removed,
+/// since overwriting it would overwrite the file it points to, which may be outside the vault.
+/// Nothing is done if the file doesn't exist.
+pub fn wipe_file(p: &Path) -> Result<()> {
+    let Ok(metadata) = std::fs::symlink_metadata(p) else { return Ok(()) };
+
+    if metadata.is_file() && overwrites_in_place(p) {
+        let mut len = metadata.len() as usize;
+        let mut f = OpenOptions::new().write(true).open(p)?;
+        let zeros = vec![0u8; WIPE_CHUNK_LEN];
+        while len > 0 {
+            let n = len.min(WIPE_CHUNK_LEN);
+            f.write_all(&zeros[..n])?;
+            len -= n;
+        }
+        f.sync_all()?;
+    }
+    Ok(std::fs::remove_file(p)?)
+}
+
+/// Wipes every file in the directory, see `wipe_file`, then removes the directory itself.
+/// Returns the number of files wiped.
+pub fn wipe_dir(dir: &Path) -> Result<usize> {
+    let wiped = wipe_contents(dir, &[])?;
+    std::fs::remove_dir(dir)?;
+    Ok(wiped)
+}
+
+/// Wipes every file in the directory except those directly in it named in `keep`, see
+/// `wipe_file`. Symlinks to directories are removed without following them. Returns the number
+/// of files wiped.
+fn wipe_contents(dir: &Path, keep: &[&str]) -> Result<usize> {
+    let mut wiped = 0;
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        if keep.iter().any(|name| entry.file_name() == *name) {
+            continue;
+        }
+
+        if entry.file_type()?.is_dir() {
+            wiped += wipe_dir(entry.path().as_path())?;
+        } else {
+            wipe_file(entry.path().as_path())?;
+            wiped += 1;
+        }
+    }
+    Ok(wiped)
+}
+
+/// Destroys the vault in `vault_dir`, wiping every file in it, including its header, history,
+/// trash, and the git repository it is synced with. Only a directory holding a vault is
+/// destroyed, so a mistyped store can't wipe something else, and a vault with the read_only
+/// setting on isn't destroyed at all. The user's config file is kept, since the default store is
+/// also the directory which holds it, so the directory is only removed if nothing else is left in
+/// it. Returns the number of files wiped.
+pub fn nuke(vault_dir: &Path) -> Result<usize> {
+    VaultHeader::read(vault_dir)?;
+    if config::read_or_default()?.with_vault(vault_dir)?.read_only() {
+        return Err(Error::ReadOnly);
+    }
+
+    let wiped = wipe_contents(vault_dir, &[CONFIG_FILE])?;
+    if std::fs::read_dir(vault_dir)?.next().is_none() {
+        std::fs::remove_dir(vault_dir)?;
+    }
+    Ok(wiped)
+}
+
+/// Checks whether overwriting a file at `p` replaces its contents on disk. This isn't the case on
+/// copy-on-write filesystems, which are looked up in the mount table on Linux. Elsewhere the file
+/// is assumed to be overwritten in place, which costs little if it isn't.
+fn overwrites_in_place(p: &Path) -> bool {
+    filesystem(p).is_none_or(|fs| !COPY_ON_WRITE.contains(&fs.as_str()))
+}
+
+/// Returns the type of the filesystem holding the file, from the mount with the longest path
+/// which contains it
+#[cfg(target_os = "linux")]
+fn filesystem(p: &Path) -> Option<String> {
+    let p = p.canonicalize().ok()?;
+    let mounts = std::fs::read_to_string("/proc/self/mounts").ok()?;
+    mounts.lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let (_, mount, fs) = (fields.next()?, fields.next()?, fields.next()?);
+            // Spaces in mount points are escaped as \040
+            let mount = mount.replace("\\040", " ");
+            Some((mount, fs.to_string())).filter(|(mount, _)| p.starts_with(mount))
+        })
+        .max_by_key(|(mount, _)| mount.len())
+        .map(|(_, fs)| fs)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn filesystem(_p: &Path) -> Option<String> {
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use crate::crypto::Kdf;
+    use crate::error::Error;
+    use crate::global::{CONFIG_FILE, HEADER_FILE, VAULT_CONFIG_FILE};
+    use crate::vault::{self, Backend};
+    use crate::wipe;
+
+    #[test]
+    fn vaults_are_wiped_whole() {
+        let yap_test = String::from(".yap_test_wipe");
+        let dir = Path::new(yap_test.as_str());
+        let mut vault = vault::create(Some(yap_test.clone()), "asdf", Kdf::default(), Backend::default(), None).unwrap();
+        vault.set_key("work/github", "first".to_string()).unwrap();
+        vault.set_key("work/github", "second".to_string()).unwrap();
+        vault.delete_key("work/github").unwrap();
+        assert_eq!(vault.empty_trash(None).unwrap(), 1);
+        assert!(!dir.join("work").exists());
+
+        let other = dir.join("not-a-vault");
+        std::fs::create_dir_all(other.as_path()).unwrap();
+        std::fs::write(other.join("file"), "keep me").unwrap();
+        assert!(wipe::nuke(other.as_path()).is_err());
+        assert!(other.join("file").exists());
+
+        assert!(wipe::nuke(dir).unwrap() >= 2);
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn nuke_keeps_the_config_and_files_outside_the_vault() {
+        let yap_test = String::from(".yap_test_wipe_links");
+        let dir = Path::new(yap_test.as_str());
+        let outside = Path::new(".yap_test_wipe_outside.txt");
+        vault::create(Some(yap_test.clone()), "asdf", Kdf::default(), Backend::default(), None).unwrap();
+        std::fs::write(outside, "outside the vault").unwrap();
+        std::fs::write(dir.join(CONFIG_FILE), "session: none\n").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(std::fs::canonicalize(outside).unwrap(), dir.join("linked")).unwrap();
+
+        std::fs::write(dir.join(VAULT_CONFIG_FILE), "read_only: true\n").unwrap();
+        assert!(matches!(wipe::nuke(dir), Err(Error::ReadOnly)));
+        assert!(dir.join(HEADER_FILE).exists());
+        std::fs::remove_file(dir.join(VAULT_CONFIG_FILE)).unwrap();
+
+        wipe::nuke(dir).unwrap();
+        assert_eq!(std::fs::read_to_string(outside).unwrap(), "outside the vault");
+        assert!(!dir.join("linked").exists());
+        assert!(!dir.join(HEADER_FILE).exists());
+        assert_eq!(std::fs::read_to_string(dir.join(CONFIG_FILE)).unwrap(), "session: none\n");
+
+        std::fs::remove_dir_all(dir).unwrap();
+        std::fs::remove_file(outside).unwrap();
+    }
+}